@@ -0,0 +1,110 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use ll1engine::lexer::{Lexer, RegexTokenMatcher};
+use ll1engine::token_recognizer;
+
+
+const KEYWORDS: [&str; 24] = [
+    "as", "break", "const", "continue", "else", "enum", "false", "fn", "for", "if", "impl", "in",
+    "let", "loop", "match", "mod", "mut", "pub", "return", "struct", "true", "type", "use", "while",
+];
+
+
+fn lexer() -> Lexer {
+    Lexer::new(token_recognizer! {
+        id => "[a-zA-Z_][a-zA-Z0-9_]*",
+        intlit => "[0-9]+",
+        dqstr => r#""[^"]*""#,
+        lparen => r"\(",
+        rparen => r"\)",
+        brace => r"\{|\}",
+        add => r"\+",
+        sub => "-",
+        mul => r"\*",
+        div => "/",
+        semi => ";",
+        comma => ",",
+        eq => "="
+    })
+}
+
+/// 关键字在标识符之前声明， 一样长时关键字优先
+fn keyword_lexer() -> Lexer {
+    let mut rules = KEYWORDS
+        .iter()
+        .map(|kw| (RegexTokenMatcher::new(kw), format!("kw_{}", kw)))
+        .collect::<Vec<_>>();
+
+    rules.extend(
+        [
+            ("[a-zA-Z_][a-zA-Z0-9_]*", "id"),
+            ("[0-9]+", "intlit"),
+            (r"\(", "lparen"),
+            (r"\)", "rparen"),
+            (r"\{", "lbrace"),
+            (r"\}", "rbrace"),
+            ("->", "arrow"),
+            ("==", "eqeq"),
+            ("=", "eq"),
+            (r"\+", "add"),
+            ("<", "lt"),
+            (":", "colon"),
+            (";", "semi"),
+            (",", "comma"),
+        ]
+        .iter()
+        .map(|(pat, name)| (RegexTokenMatcher::new(pat), name.to_string())),
+    );
+
+    Lexer::new(rules)
+}
+
+fn source(lines: usize) -> String {
+    (0..lines)
+        .map(|i| format!("f fn{} (a, b) = {{ x = a * {} + \"s{}\"; fn{}(x, b); }}\n", i, i, i, i))
+        .collect()
+}
+
+fn keyword_source(lines: usize) -> String {
+    (0..lines)
+        .map(|i| format!(
+            "pub fn f{}(x: u32) -> u32 {{ let mut y = x; while y < {} {{ if y == 0 {{ return y; }} else {{ y = y + 1; }} }} y }}\n",
+            i, i
+        ))
+        .collect()
+}
+
+
+fn bench_tokenize(c: &mut Criterion) {
+    let src = source(2000);
+
+    let mut group = c.benchmark_group("tokenize");
+    group.throughput(Throughput::Bytes(src.len() as u64));
+
+    let plain = lexer();
+    group.bench_function("one-by-one", |b| b.iter(|| plain.tokenize_str(&src).unwrap()));
+
+    let compiled = lexer().compile().unwrap();
+    group.bench_function("compiled", |b| b.iter(|| compiled.tokenize_str(&src).unwrap()));
+
+    group.finish();
+}
+
+fn bench_tokenize_keywords(c: &mut Criterion) {
+    let src = keyword_source(1000);
+
+    let mut group = c.benchmark_group("tokenize-keywords");
+    group.throughput(Throughput::Bytes(src.len() as u64));
+
+    let plain = keyword_lexer();
+    group.bench_function("one-by-one", |b| b.iter(|| plain.tokenize_str(&src).unwrap()));
+
+    let compiled = keyword_lexer().compile().unwrap();
+    group.bench_function("compiled", |b| b.iter(|| compiled.tokenize_str(&src).unwrap()));
+
+    group.finish();
+}
+
+
+criterion_group!(benches, bench_tokenize, bench_tokenize_keywords);
+criterion_main!(benches);
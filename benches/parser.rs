@@ -0,0 +1,204 @@
+#![allow(non_snake_case, mixed_script_confusables)]
+
+use criterion::{
+    criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput
+};
+
+use ll1engine::*;
+use ll1engine::gram::Gram;
+use ll1engine::lexer::Lexer;
+use ll1engine::parser::{LL1Parser, ParseEvent, ParseOptions, Token};
+
+
+fn json_gram() -> Gram {
+    declare_nonterminal! {
+        Value,
+        Object,
+        Members,
+        MembersTail,
+        Member,
+        Array,
+        Elems,
+        ElemsTail
+    };
+    declare_terminal! {
+        lbrace,
+        rbrace,
+        lbracket,
+        rbracket,
+        comma,
+        colon,
+        str,
+        num,
+        kw_true,
+        kw_false,
+        kw_null
+    };
+
+    use_epsilon!(ε);
+
+    grammar![json|
+        Value:
+        | Object;
+        | Array;
+        | str;
+        | num;
+        | kw_true;
+        | kw_false;
+        | kw_null;
+
+        Object:
+        | lbrace Members rbrace;
+
+        Members:
+        | Member MembersTail;
+        | ε;
+
+        MembersTail:
+        | comma Member MembersTail;
+        | ε;
+
+        Member:
+        | str colon Value;
+
+        Array:
+        | lbracket Elems rbracket;
+
+        Elems:
+        | Value ElemsTail;
+        | ε;
+
+        ElemsTail:
+        | comma Value ElemsTail;
+        | ε;
+    |]
+}
+
+fn json_lexer() -> Lexer {
+    Lexer::new(token_recognizer! {
+        lbrace => r"\{",
+        rbrace => r"\}",
+        lbracket => r"\[",
+        rbracket => r"\]",
+        comma => ",",
+        colon => ":",
+        str => r#""[^"]*""#,
+        num => r"-?[0-9]+(\.[0-9]+)?",
+        kw_true => "true",
+        kw_false => "false",
+        kw_null => "null"
+    })
+    .compile()
+    .unwrap()
+}
+
+
+/// 一个数组， 每个元素是带嵌套字段的对象
+fn large_json(records: usize) -> String {
+    let records = (0..records)
+        .map(|i| format!(
+            r#"{{"id": {}, "name": "user{}", "active": {}, "score": {}.5, "tags": ["a", "b", null], "addr": {{"city": "c{}", "zip": {}}}}}"#,
+            i, i, i % 2 == 0, i, i, 10000 + i
+        ))
+        .collect::<Vec<_>>();
+
+    format!("[{}]", records.join(", "))
+}
+
+fn deep_nesting(depth: usize) -> String {
+    format!("{}1{}", "[".repeat(depth), "]".repeat(depth))
+}
+
+fn wide_flat(width: usize) -> String {
+    let elems = (0..width).map(|i| i.to_string()).collect::<Vec<_>>();
+
+    format!("[{}]", elems.join(", "))
+}
+
+
+fn inputs() -> Vec<(&'static str, Vec<Token>)> {
+    let lexer = json_lexer();
+
+    vec![
+        ("large-json", large_json(500)),
+        ("deep-nesting", deep_nesting(500)),
+        ("wide-flat", wide_flat(5000)),
+    ]
+    .into_iter()
+    .map(|(name, src)| (name, lexer.tokenize_str(&src).unwrap()))
+    .collect()
+}
+
+/// 语法树的节点数(包括叶子)， 用事件来数， 不用递归遍历
+fn count_nodes(parser: &LL1Parser, tokens: &[Token]) -> usize {
+    let mut nodes = 0;
+
+    parser.parse_events(tokens.to_vec(), &ParseOptions::default(), |event| {
+        if let ParseEvent::EnterRule(_) | ParseEvent::Token(_) = event {
+            nodes += 1;
+        }
+    });
+
+    nodes
+}
+
+
+fn bench_parse(c: &mut Criterion) {
+    let parser = LL1Parser::builder(json_gram()).build().unwrap();
+    let inputs = inputs();
+
+    let mut group = c.benchmark_group("parse-tokens");
+    for (name, tokens) in inputs.iter() {
+        group.throughput(Throughput::Elements(tokens.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), tokens, |b, tokens| {
+            b.iter_batched(
+                || tokens.clone(),
+                |tokens| parser.parse(tokens).unwrap(),
+                BatchSize::LargeInput
+            )
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("parse-nodes");
+    for (name, tokens) in inputs.iter() {
+        group.throughput(Throughput::Elements(count_nodes(&parser, tokens) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), tokens, |b, tokens| {
+            b.iter_batched(
+                || tokens.clone(),
+                |tokens| parser.parse(tokens).unwrap(),
+                BatchSize::LargeInput
+            )
+        });
+    }
+    group.finish();
+}
+
+/// 大量的小输入， 每次解析都要新建节点和符号栈
+fn bench_small_inputs(c: &mut Criterion) {
+    let parser = LL1Parser::builder(json_gram()).build().unwrap();
+    let lexer = json_lexer();
+
+    let docs = (0..200)
+        .map(|i| lexer.tokenize_str(&large_json(i % 3 + 1)).unwrap())
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("parse-small");
+    group.throughput(Throughput::Elements(docs.len() as u64));
+    group.bench_function("200-docs", |b| {
+        b.iter_batched(
+            || docs.clone(),
+            |docs| {
+                for tokens in docs {
+                    parser.parse(tokens).unwrap();
+                }
+            },
+            BatchSize::LargeInput
+        )
+    });
+    group.finish();
+}
+
+
+criterion_group!(benches, bench_parse, bench_small_inputs);
+criterion_main!(benches);
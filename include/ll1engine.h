@@ -0,0 +1,67 @@
+/*
+ * ll1engine C API, built with `--features ffi` (see src/ffi.rs).
+ *
+ * Handles are opaque and released with the matching *_free function.
+ * Strings are NUL-terminated UTF-8; returned strings are owned by the
+ * handle and stay valid until it is freed.
+ * Failed calls return a non-zero ll1_status, see ll1_last_error().
+ */
+
+#ifndef LL1ENGINE_H
+#define LL1ENGINE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define LL1_NONE SIZE_MAX
+
+typedef enum ll1_status {
+    LL1_OK = 0,
+    LL1_NULL_ARG = 1,
+    LL1_INVALID_UTF8 = 2,
+    LL1_GRAMMAR_ERROR = 3,
+    LL1_PATTERN_ERROR = 4,
+    LL1_LEX_ERROR = 5,
+    LL1_OUT_OF_RANGE = 6,
+    LL1_PANIC = 7,
+} ll1_status;
+
+typedef struct ll1_parser ll1_parser;
+typedef struct ll1_lexer ll1_lexer;
+typedef struct ll1_tree ll1_tree;
+
+const char *ll1_last_error(void);
+
+ll1_status ll1_parser_from_dsl(const char *src, ll1_parser **out);
+void ll1_parser_free(ll1_parser *parser);
+
+ll1_lexer *ll1_lexer_new(void);
+ll1_status ll1_lexer_add_rule(ll1_lexer *lexer, const char *name, const char *pattern, int skip);
+void ll1_lexer_free(ll1_lexer *lexer);
+
+/* syntax errors are recovered: LL1_OK plus ll1_tree_error_count() > 0 */
+ll1_status ll1_parse(const ll1_parser *parser, const ll1_lexer *lexer, const char *src, ll1_tree **out);
+void ll1_tree_free(ll1_tree *tree);
+
+size_t ll1_tree_error_count(const ll1_tree *tree);
+const char *ll1_tree_error(const ll1_tree *tree, size_t i);
+
+/* nodes are 0..count in pre-order, 0 is the root */
+size_t ll1_tree_node_count(const ll1_tree *tree);
+const char *ll1_node_name(const ll1_tree *tree, size_t node);
+const char *ll1_node_value(const ll1_tree *tree, size_t node);
+int ll1_node_is_leaf(const ll1_tree *tree, size_t node);
+size_t ll1_node_child_count(const ll1_tree *tree, size_t node);
+size_t ll1_node_child(const ll1_tree *tree, size_t node, size_t i);
+size_t ll1_node_parent(const ll1_tree *tree, size_t node);
+ll1_status ll1_node_loc(const ll1_tree *tree, size_t node, size_t *ln, size_t *col);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
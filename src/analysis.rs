@@ -0,0 +1,352 @@
+//! Incremental Analysis: 编辑语法时只重算受影响的FIRST/FOLLOW/预测集表项
+
+use std::error::Error;
+
+use indexmap::{indexmap, indexset, IndexSet};
+
+use crate::VerboseLv;
+use crate::gram::{
+    _calc_first_sets, _calc_follow_sets, FollSetSym, FollSets, FstSetSym, FstSets,
+    Gram, GramProd, GramSym, GramSymStr, PredSet,
+};
+
+pub struct GramAnalysis {
+    pub(crate) gram: Gram,
+    pub(crate) fstsets: FstSets,
+    pub(crate) follsets: FollSets,
+    pub(crate) predsets: PredSet,
+    pub(crate) verbose: VerboseLv,
+}
+
+impl GramAnalysis {
+    pub fn new(gram: Gram) -> Self {
+        Self::with_verbose(gram, VerboseLv::V0)
+    }
+
+    /// `verbose`为V1时打印集合计算的迭代次数和每次`update_rule`重算的表项数
+    pub fn with_verbose(gram: Gram, verbose: VerboseLv) -> Self {
+        let fstsets = gram.first_sets_with(verbose);
+        let follsets = gram.follow_sets_with(&fstsets, verbose);
+        let predsets = gram.prediction_sets(&fstsets, &follsets);
+
+        Self {
+            gram,
+            fstsets,
+            follsets,
+            predsets,
+            verbose,
+        }
+    }
+
+    pub fn gram(&self) -> &Gram {
+        &self.gram
+    }
+
+    pub fn first_sets(&self) -> &FstSets {
+        &self.fstsets
+    }
+
+    pub fn follow_sets(&self) -> &FollSets {
+        &self.follsets
+    }
+
+    pub fn prediction_sets(&self) -> &PredSet {
+        &self.predsets
+    }
+
+    pub fn into_gram(self) -> Gram {
+        self.gram
+    }
+
+    /// 用`alts`替换`sym`的规则(见`Gram::update_rule`)， 并只重算受影响的表项，
+    /// 返回预测表被重算的非终结符
+    ///
+    /// 表项内容与整体重建一致， 但重算过的集合内部顺序可能不同
+    pub fn update_rule(&mut self, sym: &GramSym, alts: Vec<GramSymStr>) -> IndexSet<GramSym> {
+        let old_start = self.gram.start_sym().cloned();
+        let old_rhs = rhs_syms(self.gram.alts_of(sym));
+
+        self.gram.update_rule(sym, alts);
+
+        // 开始符号变了， $的位置跟着变， 直接重建
+        if self.gram.start_sym() != old_start.as_ref() {
+            *self = Self::with_verbose(std::mem::replace(&mut self.gram, Gram::new("")), self.verbose);
+            return self.predsets.nonterminals().cloned().collect();
+        }
+
+        self.sync_syms();
+
+        // FIRST， 规则删光且不再被引用的`sym`已经不在表里了
+        let fst_dirty = self
+            .first_dirty(sym)
+            .into_iter()
+            .filter(|x| self.fstsets.contains_key(x))
+            .collect::<IndexSet<GramSym>>();
+        for dirty_sym in fst_dirty.iter() {
+            self.fstsets.insert(dirty_sym.clone(), indexset! {});
+        }
+        let prods = self
+            .gram
+            .productions()
+            .filter(|prod| fst_dirty.contains(&prod.lfsym))
+            .cloned()
+            .collect::<IndexSet<GramProd>>();
+        _calc_first_sets(&prods, &mut self.fstsets);
+
+        // FOLLOW
+        let mut seeds = old_rhs;
+        seeds.extend(rhs_syms(self.gram.alts_of(sym)));
+        let foll_dirty = self.follow_dirty(seeds, &fst_dirty);
+        for dirty_sym in foll_dirty.iter() {
+            self.follsets.insert(dirty_sym.clone(), indexset! {});
+        }
+        if let Some(start_sym) = self.gram.start_sym() {
+            if foll_dirty.contains(start_sym) {
+                self.follsets
+                    .get_mut(start_sym)
+                    .unwrap()
+                    .insert(FollSetSym::EndMarker);
+            }
+        }
+        let prods = self
+            .gram
+            .productions()
+            .filter(|prod| rhs_syms(Some(*prod)).iter().any(|x| foll_dirty.contains(x)))
+            .cloned()
+            .collect::<IndexSet<GramProd>>();
+        _calc_follow_sets(&prods, &mut self.follsets, &self.fstsets);
+
+        // 预测集
+        let mut pred_dirty = indexset! { sym.clone() };
+        pred_dirty.extend(fst_dirty.into_iter().filter(|x| x.is_nonterminal()));
+        pred_dirty.extend(foll_dirty);
+
+        for dirty_sym in pred_dirty.iter() {
+            let mut deriv_map = None;
+
+            for prod in self.gram.alts_of(dirty_sym) {
+                let deriv_map = deriv_map.get_or_insert_with(|| indexmap! {});
+                for la in prod.lookahead(&self.fstsets, &self.follsets) {
+                    deriv_map.insert(la, prod.clone());
+                }
+            }
+
+            self.predsets.replace(dirty_sym, deriv_map);
+        }
+
+        verbose!(
+            self.verbose => V1,
+            "{}: update {}, recalc predsets of {} nonterminals",
+            self.gram.name(),
+            sym,
+            pred_dirty.len()
+        );
+
+        pred_dirty
+    }
+
+    /// 重命名非终结符(见`Gram::rename_sym`)， FIRST/FOLLOW/预测集只是换个键， 不用重算
+    pub fn rename_sym(&mut self, old: &GramSym, new: &str) -> Result<GramSym, Box<dyn Error>> {
+        let new_sym = self.gram.rename_sym(old, new)?;
+        let rekey = |sym: GramSym| if &sym == old { new_sym.clone() } else { sym };
+
+        self.fstsets = std::mem::take(&mut self.fstsets)
+            .into_iter()
+            .map(|(sym, fstset)| (rekey(sym), fstset))
+            .collect();
+        self.follsets = std::mem::take(&mut self.follsets)
+            .into_iter()
+            .map(|(sym, follset)| (rekey(sym), follset))
+            .collect();
+        self.predsets.rename_sym(old, &new_sym);
+
+        Ok(new_sym)
+    }
+
+    /// 增删FIRST/FOLLOW中的符号， 与语法当前的符号表一致
+    ///
+    /// 同`Gram::follow_sets`， 只在右边出现的(未定义的)非终结符也有FOLLOW集
+    fn sync_syms(&mut self) {
+        let syms = self.gram.symbols().cloned().collect::<IndexSet<GramSym>>();
+        let nonterms = syms
+            .iter()
+            .filter(|sym| sym.is_nonterminal())
+            .cloned()
+            .collect::<IndexSet<GramSym>>();
+
+        self.fstsets.retain(|sym, _| syms.contains(sym));
+        self.follsets.retain(|sym, _| nonterms.contains(sym));
+
+        for sym in syms.into_iter() {
+            if !self.fstsets.contains_key(&sym) {
+                let fstset = match &sym {
+                    GramSym::NonTerminal(_) => indexset! {},
+                    _ => indexset! { sym.to_fst_set_sym() },
+                };
+                self.fstsets.insert(sym, fstset);
+            }
+        }
+
+        for sym in nonterms.into_iter() {
+            self.follsets.entry(sym).or_insert_with(|| indexset! {});
+        }
+    }
+
+    /// FIRST可能改变的符号： `sym`以及右边(传递地)引用到它的非终结符
+    fn first_dirty(&self, sym: &GramSym) -> IndexSet<GramSym> {
+        let mut dirty = indexset! { sym.clone() };
+
+        loop {
+            let more = self
+                .gram
+                .productions()
+                .filter(|prod| !dirty.contains(&prod.lfsym))
+                .filter(|prod| rhs_syms(Some(*prod)).iter().any(|x| dirty.contains(x)))
+                .map(|prod| prod.lfsym.clone())
+                .collect::<IndexSet<GramSym>>();
+
+            if more.is_empty() {
+                break dirty;
+            }
+            dirty.extend(more);
+        }
+    }
+
+    /// FOLLOW可能改变的非终结符：
+    /// 1. 新旧规则右边的非终结符;
+    /// 2. 后面跟着FIRST改变过的符号的非终结符;
+    /// 3. 以上符号的FOLLOW会(经由可空的尾部)流向的非终结符
+    fn follow_dirty(&self, seeds: IndexSet<GramSym>, fst_dirty: &IndexSet<GramSym>) -> IndexSet<GramSym> {
+        let mut dirty = seeds
+            .into_iter()
+            .filter(|sym| self.follsets.contains_key(sym))
+            .collect::<IndexSet<GramSym>>();
+
+        for prod in self.gram.productions() {
+            if let Some(normal_str) = prod.rhstr.get_normal() {
+                for (i, sym) in normal_str.iter().enumerate() {
+                    if self.follsets.contains_key(sym)
+                        && normal_str[i + 1..].iter().any(|x| fst_dirty.contains(x))
+                    {
+                        dirty.insert(sym.clone());
+                    }
+                }
+            }
+        }
+
+        loop {
+            let mut more = indexset! {};
+
+            for prod in self.gram.productions().filter(|prod| dirty.contains(&prod.lfsym)) {
+                if let Some(normal_str) = prod.rhstr.get_normal() {
+                    for (i, sym) in normal_str.iter().enumerate() {
+                        if self.follsets.contains_key(sym)
+                            && !dirty.contains(sym)
+                            && normal_str[i + 1..].iter().all(|x| self.nullable(x))
+                        {
+                            more.insert(sym.clone());
+                        }
+                    }
+                }
+            }
+
+            if more.is_empty() {
+                break dirty;
+            }
+            dirty.extend(more);
+        }
+    }
+
+    fn nullable(&self, sym: &GramSym) -> bool {
+        self.fstsets
+            .get(sym)
+            .is_some_and(|fstset| fstset.contains(&FstSetSym::Epsilon))
+    }
+}
+
+fn rhs_syms<'a>(prods: impl IntoIterator<Item = &'a GramProd>) -> IndexSet<GramSym> {
+    prods
+        .into_iter()
+        .filter_map(|prod| prod.rhstr.get_normal())
+        .flatten()
+        .filter(|sym| sym.is_nonterminal())
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use indexmap::IndexMap;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::gram::PredSetSym;
+
+    const TERMS: [&str; 3] = ["a", "b", "c"];
+
+    fn nonterm(i: usize) -> GramSym {
+        GramSym::NonTerminal(format!("N{}", i))
+    }
+
+    /// 引用N0..Nn的随机分支， 可能为空
+    fn arb_alts(n: usize) -> impl Strategy<Value = Vec<GramSymStr>> {
+        let sym = prop_oneof![
+            (0..TERMS.len()).prop_map(|i| GramSym::Terminal(TERMS[i].to_string())),
+            (0..n).prop_map(nonterm),
+        ];
+        let rhstr = prop::collection::vec(sym, 0..=3).prop_map(|syms| {
+            if syms.is_empty() { GramSymStr::Epsilon } else { GramSymStr::Str(syms) }
+        });
+
+        prop::collection::vec(rhstr, 0..=3)
+    }
+
+    /// 随机语法和一串规则更新， 更新的可能是新规则， 分支为空时是删除规则
+    fn arb_edits() -> impl Strategy<Value = (Gram, Vec<(GramSym, Vec<GramSymStr>)>)> {
+        (1..5usize)
+            .prop_flat_map(|n| {
+                let rules = prop::collection::vec(
+                    arb_alts(n + 1).prop_filter("empty rule", |alts| !alts.is_empty()),
+                    n
+                );
+                let edits = prop::collection::vec((0..=n, arb_alts(n + 1)), 1..4);
+
+                (rules, edits)
+            })
+            .prop_map(|(rules, edits)| {
+                let mut gram = Gram::new("random");
+                for (i, alts) in rules.into_iter().enumerate() {
+                    for rhstr in alts {
+                        gram.insert_prod(GramProd::new(nonterm(i), rhstr));
+                    }
+                }
+
+                (gram, edits.into_iter().map(|(i, alts)| (nonterm(i), alts)).collect())
+            })
+    }
+
+    fn pred_table(analysis: &GramAnalysis) -> IndexMap<GramSym, IndexMap<PredSetSym, GramProd>> {
+        analysis
+            .prediction_sets()
+            .iter()
+            .map(|(sym, deriv_map)| (sym.clone(), deriv_map.clone()))
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn prop_update_rule((gram, edits) in arb_edits()) {
+            let mut analysis = GramAnalysis::new(gram);
+
+            for (sym, alts) in edits {
+                analysis.update_rule(&sym, alts);
+                let expected = GramAnalysis::new(analysis.gram().clone());
+
+                // IndexMap、 IndexSet的相等不看顺序
+                prop_assert_eq!(analysis.first_sets(), expected.first_sets());
+                prop_assert_eq!(analysis.follow_sets(), expected.follow_sets());
+                prop_assert_eq!(pred_table(&analysis), pred_table(&expected));
+            }
+        }
+    }
+}
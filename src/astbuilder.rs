@@ -0,0 +1,142 @@
+//! AST Builder: 手工合成语法树(测试、宏展开)， 并检查是否符合语法
+//!
+//! ```ignore
+//! let node = tree("Expr", [leaf("num", "1"), leaf("+", "+"), tree("Term", [leaf("num", "2")])]);
+//! let root = build(&gram, node)?;
+//! ```
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use crate::gram::{Gram, GramProd, GramSym};
+use crate::parser::{ASTNode, SrcLoc, Token, AST};
+
+
+/// 合成的token没有源码位置， 统一用(0, 0)
+pub fn leaf(name: &str, value: &str) -> ASTNode {
+    ASTNode::Leaf(Rc::new(Token::new(name, value, SrcLoc::new((0, 0)))))
+}
+
+pub fn tree<I: IntoIterator<Item = ASTNode>>(name: &str, children: I) -> ASTNode {
+    let mut ast = AST::new(&GramSym::NonTerminal(name.to_string()));
+
+    for child in children {
+        ast.insert_node(child);
+    }
+
+    ASTNode::Tree(Rc::new(RefCell::new(ast)))
+}
+
+/// 检查整棵树都符合`gram`， 并给每个节点填上匹配到的产生式的注解
+pub fn build(gram: &Gram, node: ASTNode) -> Result<Rc<RefCell<AST>>, Diagnostics> {
+    let root = match node {
+        ASTNode::Tree(root) => root,
+        ASTNode::Leaf(token) => {
+            let mut diags = Diagnostics::new();
+            diags.push(Diagnostic::error(
+                "ast-leaf-root",
+                &format!("root must be a tree, found leaf `{}`", token.name())
+            ));
+
+            return Err(diags);
+        }
+    };
+
+    let diags = validate(gram, &root.as_ref().borrow());
+    if diags.has_errors() {
+        return Err(diags);
+    }
+
+    fill_attrs(gram, &root);
+
+    Ok(root)
+}
+
+/// 每个节点的子节点序列都要对应它的某个产生式，
+/// 与解析器一致， 推导出ε的非终结符不出现在树上
+pub fn validate(gram: &Gram, ast: &AST) -> Diagnostics {
+    let mut diags = Diagnostics::new();
+    let mut path = vec![];
+
+    validate_(gram, ast, &mut path, &mut diags);
+
+    diags
+}
+
+fn validate_(gram: &Gram, ast: &AST, path: &mut Vec<String>, diags: &mut Diagnostics) {
+    path.push(ast.sym().name().to_string());
+
+    if gram.alts_of(ast.sym()).next().is_none() {
+        diags.push(Diagnostic::error(
+            "ast-unknown-rule",
+            &format!("no rule for `{}` in {}", ast.sym().name(), path.join(" > "))
+        ));
+    }
+    else if matched_prod(gram, ast).is_none() {
+        diags.push(Diagnostic::error(
+            "ast-nonconforming",
+            &format!(
+                "children `{}` match no alternative of `{}` in {}",
+                ast.elem_syms().iter().map(|sym| sym.name()).collect::<Vec<_>>().join(" "),
+                ast.sym().name(),
+                path.join(" > ")
+            )
+        ));
+    }
+
+    for (_sym, node) in ast.elems_vec() {
+        if let ASTNode::Tree(subtree) = node {
+            validate_(gram, &subtree.as_ref().borrow(), path, diags);
+        }
+    }
+
+    path.pop();
+}
+
+fn matched_prod<'a>(gram: &'a Gram, ast: &AST) -> Option<&'a GramProd> {
+    let children = ast
+        .elems_vec()
+        .into_iter()
+        .map(|(_sym, node)| node.clone())
+        .collect::<Vec<ASTNode>>();
+
+    gram.productions().find(|prod| {
+        let rhs = prod.rhstr.get_normal().map_or(&[][..], |normal_str| &normal_str[..]);
+
+        prod.lfsym == *ast.sym() && !prod.is_error_prod() && match_rhs(gram, rhs, &children)
+    })
+}
+
+/// 右边的非终结符如果有ε产生式， 可以不出现
+fn match_rhs(gram: &Gram, rhs: &[GramSym], children: &[ASTNode]) -> bool {
+    match rhs.split_first() {
+        None => children.is_empty(),
+        Some((sym, rest)) => {
+            (children.first().is_some_and(|child| match_node(sym, child))
+                && match_rhs(gram, rest, &children[1..]))
+            || (sym.is_nonterminal() && gram.sym_has_epsilon(sym) && match_rhs(gram, rest, children))
+        }
+    }
+}
+
+/// 叶子要满足终结符的值约束
+fn match_node(sym: &GramSym, node: &ASTNode) -> bool {
+    match node {
+        ASTNode::Leaf(token) => token.matches(sym),
+        ASTNode::Tree(subtree) => subtree.as_ref().borrow().sym() == sym,
+    }
+}
+
+fn fill_attrs(gram: &Gram, ast: &Rc<RefCell<AST>>) {
+    let attrs = matched_prod(gram, &ast.as_ref().borrow()).map(|prod| prod.attrs.clone());
+    if let Some(attrs) = attrs {
+        ast.as_ref().borrow_mut().set_attrs(attrs);
+    }
+
+    for (_sym, node) in ast.as_ref().borrow().elems_vec() {
+        if let ASTNode::Tree(subtree) = node {
+            fill_attrs(gram, subtree);
+        }
+    }
+}
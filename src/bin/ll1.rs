@@ -0,0 +1,71 @@
+//! 命令行工具
+//!
+//! `ll1 fmt [--check] <file>...`: 格式化DSL形式的语法文件(原地改写)，
+//! `--check`只检查， 有文件需要格式化时返回非零；
+//! `ll1 lsp`: 经由stdio提供语法文件的语言服务(需要`lsp` feature)
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::process;
+
+use ll1engine::builder::format_dsl;
+
+
+const USAGE: &str = "usage: ll1 fmt [--check] <file>...\n       ll1 lsp";
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+
+    let res = match args.first().map(|arg| arg.as_str()) {
+        Some("fmt") => fmt(&args[1..]),
+        #[cfg(feature = "lsp")]
+        Some("lsp") => {
+            let stdin = std::io::stdin();
+            ll1engine::lsp::serve(stdin.lock(), std::io::stdout()).map(|()| true)
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    match res {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    }
+}
+
+/// 返回是否所有文件都已经格式化好
+fn fmt(args: &[String]) -> Result<bool, Box<dyn Error>> {
+    let check = args.iter().any(|arg| arg == "--check");
+    let paths = args.iter().filter(|arg| *arg != "--check").collect::<Vec<&String>>();
+
+    if paths.is_empty() {
+        return Err(USAGE.into());
+    }
+
+    let mut tidy = true;
+
+    for path in paths {
+        let src = fs::read_to_string(path)?;
+        let formatted = format_dsl(&src).map_err(|err| format!("{}: {}", path, err))?;
+
+        if formatted == src {
+            continue;
+        }
+
+        if check {
+            println!("{}", path);
+            tidy = false;
+        } else {
+            fs::write(path, formatted)?;
+        }
+    }
+
+    Ok(tidy)
+}
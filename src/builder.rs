@@ -0,0 +1,719 @@
+//! Grammar Builder: 不用DSL宏， 在运行时构建语法
+//!
+//! 规则名就是非终结符， 其余的符号都当作终结符， `ε`或者空的分支表示空串，
+//! 带值约束的终结符写作`id("self")`(见`GramSym::guarded`)，
+//! 加引号的`"+"`总是普通的终结符， 用于不是标识符的token名(`"_"`、 `"$"`也不例外)，
+//! `$`或者`EOF`是输入结束， `_`匹配任意token， `~{semi "}"}`匹配不在集合里的token，
+//! `["a-z"]`是字符类(见`scannerless`， `alt`里直接写`[a-z]`)
+//!
+//! 终结符与非终结符不能重名， `build`时报`sym-collision`错误
+
+use std::error::Error;
+use std::fmt::Write;
+
+use indexmap::{IndexMap, IndexSet};
+use itertools::Itertools;
+
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use crate::error::Trap;
+use crate::gram::*;
+use crate::scannerless::CharClass;
+
+
+const EPSILON_NAME: &str = "ε";
+/// 不是规则名时同`$`
+const EOF_ALIAS: &str = "EOF";
+
+#[derive(Debug, Clone, Default)]
+struct RuleDef {
+    docs: Vec<String>,
+    alts: Vec<(Vec<String>, ProdAttrs)>,
+}
+
+
+/// ```ignore
+/// let gram = GramBuilder::new("expr")
+///     .rule("Expr").alt(["Term", "ExprTail"])
+///     .rule("ExprTail").alt(["add", "Term", "ExprTail"]).alt(["ε"])
+///     .rule("Term").alt(["id"])
+///     .build()?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct GramBuilder {
+    name: String,
+    /// 第一个规则是开始符号
+    rules: IndexMap<String, RuleDef>,
+    cur: Option<String>,
+    meta: GramMeta,
+}
+
+impl GramBuilder {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            rules: IndexMap::new(),
+            cur: None,
+            meta: GramMeta::default(),
+        }
+    }
+
+    pub fn version(mut self, version: &str) -> Self {
+        self.meta.version = Some(version.to_string());
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.meta.description = Some(description.to_string());
+        self
+    }
+
+    /// 关联一个扩展名， 可以带点(`.json`)
+    pub fn extension(mut self, ext: &str) -> Self {
+        self.meta.extensions.push(ext.trim_start_matches('.').to_string());
+        self
+    }
+
+    pub fn mime_type(mut self, mime: &str) -> Self {
+        self.meta.mime_types.push(mime.to_string());
+        self
+    }
+
+    /// 切换到规则`name`(不存在就新建)， 后面的`alt`和`doc`都作用在它上面
+    pub fn rule(mut self, name: &str) -> Self {
+        self.rules.entry(name.to_string()).or_default();
+        self.cur = Some(name.to_string());
+        self
+    }
+
+    pub fn alt<I, S>(mut self, syms: I) -> Self
+    where I: IntoIterator<Item = S>, S: AsRef<str>
+    {
+        let syms = syms.into_iter().map(|sym| sym.as_ref().to_string()).collect_vec();
+        self.cur_rule().alts.push((syms, ProdAttrs::new()));
+        self
+    }
+
+    /// 给当前规则追加一行文档
+    pub fn doc(mut self, line: &str) -> Self {
+        self.cur_rule().docs.push(line.to_string());
+        self
+    }
+
+    /// 给当前规则的最后一个分支加上注解
+    pub fn attr(mut self, key: &str, value: &str) -> Self {
+        let (_, attrs) = self.cur_rule()
+            .alts
+            .last_mut()
+            .expect("call `alt` before `attr`");
+
+        add_prod_attr(attrs, key, value);
+        self
+    }
+
+    fn cur_rule(&mut self) -> &mut RuleDef {
+        let cur = self.cur.as_ref().expect("call `rule` first");
+
+        self.rules.get_mut(cur).unwrap()
+    }
+
+    /// 生成语法并检查， 有错误(没有分支的规则、 LL(1)冲突等)时返回全部诊断
+    pub fn build(&self) -> Result<Gram, Diagnostics> {
+        let (gram, mut diags) = self.assemble();
+
+        if diags.has_errors() {
+            return Err(diags);
+        }
+
+        diags.extend(gram.validate());
+
+        if diags.has_errors() {
+            Err(diags)
+        }
+        else {
+            Ok(gram)
+        }
+    }
+
+    /// 不检查语法本身， 只报告空规则
+    pub(crate) fn assemble(&self) -> (Gram, Diagnostics) {
+        let mut diags = Diagnostics::new();
+        let mut gram = Gram::new(&self.name);
+        *gram.meta_mut() = self.meta.clone();
+
+        for (name, rule) in self.rules.iter() {
+            let lfsym = GramSym::NonTerminal(name.clone());
+
+            if rule.alts.is_empty() {
+                diags.push(Diagnostic::error(
+                    "empty-rule",
+                    &format!("{} has no alternative", lfsym)
+                ));
+            }
+
+            for line in rule.docs.iter() {
+                gram.add_doc(&lfsym, line);
+            }
+
+            for (syms, attrs) in rule.alts.iter() {
+                let mut prod = GramProd::new(lfsym.clone(), self.symstr(syms));
+                prod.attrs = attrs.clone();
+
+                gram.insert_prod(prod);
+            }
+        }
+
+        (gram, diags)
+    }
+
+    fn symstr(&self, syms: &[String]) -> GramSymStr {
+        if syms.is_empty() || syms.iter().any(|sym| sym == EPSILON_NAME) {
+            return GramSymStr::Epsilon;
+        }
+
+        GramSymStr::Str(syms.iter().map(|sym| {
+            if let Some(name) = unquote(sym) {
+                GramSym::Terminal(name.to_string())
+            }
+            else if self.rules.contains_key(sym) {
+                GramSym::NonTerminal(sym.clone())
+            }
+            else if sym == EOF_SYM_NAME || sym == EOF_ALIAS {
+                GramSym::Eof
+            }
+            else if sym == ANY_SYM_NAME {
+                GramSym::Any
+            }
+            else if let Some(names) = sym.strip_prefix("~{").and_then(|names| names.strip_suffix('}')) {
+                GramSym::not_set(names.split_whitespace().map(|name| unquote(name).unwrap_or(name)))
+            }
+            else if let Some((name, value)) = split_guard(sym) {
+                GramSym::guarded(name, value)
+            }
+            else if let Some(class) = CharClass::parse(sym) {
+                GramSym::CharClass(class)
+            }
+            else {
+                GramSym::Terminal(sym.clone())
+            }
+        })
+        .collect_vec())
+    }
+
+    pub fn from_gram(gram: &Gram) -> Self {
+        let mut builder = Self::new(gram.name());
+        builder.meta = gram.meta().clone();
+
+        for (lfsym, prods) in gram.derivation_tree() {
+            let rule = builder.rules.entry(lfsym.name().to_string()).or_default();
+
+            if let Some(doc) = gram.doc(&lfsym) {
+                rule.docs.extend(doc.lines().map(|line| line.to_string()));
+            }
+
+            for prod in prods {
+                let syms = match &prod.rhstr {
+                    GramSymStr::Str(symstr) => {
+                        symstr.iter().map(|sym| dsl_sym(sym, gram)).collect_vec()
+                    },
+                    GramSymStr::Epsilon => vec![EPSILON_NAME.to_string()],
+                };
+
+                rule.alts.push((syms, prod.attrs.clone()));
+            }
+        }
+
+        builder
+    }
+
+    /// 解析`grammar![name| ... |]`形式的文本， 前后的`declare_*!`之类的语句会被忽略
+    pub fn from_dsl(src: &str) -> Result<Self, Box<dyn Error>> {
+        DslReader::new(src)?.read()
+    }
+}
+
+
+impl Gram {
+    /// 生成DSL形式的源码(包括符号的声明)， 符号名需要是合法的标识符
+    pub fn to_dsl(&self) -> String {
+        self.write_dsl(&self.derivation_tree())
+    }
+
+    /// 格式化的DSL源码： 与`to_dsl`相同的排版， 但规则按从开始符号出发、
+    /// 深度优先的引用顺序排列， 不可达的规则保持原顺序放在最后
+    pub fn format_source(&self) -> String {
+        self.write_dsl(&self.topo_rules())
+    }
+
+    fn topo_rules(&self) -> DerivationTree {
+        let mut dt = self.derivation_tree();
+        let mut order = IndexSet::new();
+
+        if let Some(start_sym) = self.start_sym() {
+            visit_rule(start_sym, &dt, &mut order);
+        }
+        order.extend(dt.keys().cloned());
+
+        order
+            .into_iter()
+            .map(|sym| {
+                let prods = dt.remove(&sym).unwrap();
+                (sym, prods)
+            })
+            .collect()
+    }
+
+    fn write_dsl(&self, dt: &DerivationTree) -> String {
+        let mut s = String::new();
+
+        // 终结符按在规则中出现的顺序声明
+        let terms = dt
+            .values()
+            .flatten()
+            .filter_map(|prod| prod.rhstr.get_normal())
+            .flatten()
+            .filter(|sym| {
+                sym.is_terminal()
+                && !sym.is_error()
+                && !sym.is_eof()
+                && !sym.is_wildcard()
+                && !needs_quote(sym, self)
+            })
+            .map(|sym| GramSym::Terminal(sym.token_name().to_string()))
+            .collect::<IndexSet<GramSym>>();
+
+        writeln!(
+            &mut s,
+            "declare_nonterminal! {{\n{}\n}};",
+            dt.keys().map(|sym| format!("    {}", sym.name())).join(",\n")
+        ).unwrap();
+
+        if !terms.is_empty() {
+            writeln!(
+                &mut s,
+                "declare_terminal! {{\n{}\n}};",
+                terms.iter().map(|sym| format!("    {}", sym.name())).join(",\n")
+            ).unwrap();
+        }
+
+        if self.iter().any(|prod| prod.rhstr.is_epsilon()) {
+            writeln!(&mut s, "use_epsilon!({});", EPSILON_NAME).unwrap();
+        }
+
+        if self.iter().any(|prod| prod.is_error_prod()) {
+            writeln!(&mut s, "use_error!({});", ERROR_SYM_NAME).unwrap();
+        }
+
+        writeln!(&mut s, "\ngrammar![{}|", self.name()).unwrap();
+
+        for (lfsym, prods) in dt.iter() {
+            writeln!(&mut s, "    {}:", lfsym.name()).unwrap();
+
+            if let Some(doc) = self.doc(lfsym) {
+                for line in doc.lines() {
+                    writeln!(&mut s, "    //! {}", line).unwrap();
+                }
+            }
+
+            for prod in prods.iter() {
+                for (key, value) in prod.attrs.iter() {
+                    if key == "doc" {
+                        for line in value.lines() {
+                            writeln!(&mut s, "    /// {}", line).unwrap();
+                        }
+                    }
+                    else if value.is_empty() {
+                        writeln!(&mut s, "    #[{}]", key).unwrap();
+                    }
+                    else {
+                        writeln!(&mut s, "    #[{} = {:?}]", key, value).unwrap();
+                    }
+                }
+
+                let symstr = match &prod.rhstr {
+                    GramSymStr::Str(symstr) => symstr.iter().map(|sym| dsl_text(sym, self)).join(" "),
+                    GramSymStr::Epsilon => EPSILON_NAME.to_string(),
+                };
+                writeln!(&mut s, "    | {};", symstr).unwrap();
+            }
+
+            writeln!(&mut s).unwrap();
+        }
+
+        writeln!(&mut s, "|]").unwrap();
+
+        s
+    }
+}
+
+/// 不是标识符， 或者与规则同名的终结符要加引号
+fn needs_quote(sym: &GramSym, gram: &Gram) -> bool {
+    let name = match sym {
+        GramSym::Terminal(name) => name.as_str(),
+        _ => return false,
+    };
+
+    !is_ident(name)
+    || [EPSILON_NAME, EOF_ALIAS, ANY_SYM_NAME].contains(&name)
+    || gram.nonterminals().any(|x| x.name() == name)
+}
+
+fn is_ident(name: &str) -> bool {
+    !name.is_empty()
+    && name
+        .chars()
+        .enumerate()
+        .all(|(i, c)| c == '_' || if i == 0 { c.is_alphabetic() } else { c.is_alphanumeric() })
+}
+
+fn dsl_sym(sym: &GramSym, gram: &Gram) -> String {
+    if let GramSym::NotSet(excluded) = sym {
+        let names = excluded
+            .iter()
+            .map(|name| if is_ident(name) { name.to_string() } else { format!("{:?}", name) })
+            .join(" ");
+
+        format!("~{{{}}}", names)
+    }
+    else if needs_quote(sym, gram) {
+        format!("{:?}", sym.name())
+    }
+    else {
+        sym.name().to_string()
+    }
+}
+
+/// DSL源码里的写法， 只有字符类和`alt`里的写法不同
+fn dsl_text(sym: &GramSym, gram: &Gram) -> String {
+    match sym {
+        GramSym::CharClass(class) => {
+            let class = class.to_string();
+            format!("[{:?}]", &class[1..class.len() - 1])
+        },
+        _ => dsl_sym(sym, gram),
+    }
+}
+
+fn unquote(sym: &str) -> Option<&str> {
+    sym.strip_prefix('"')?.strip_suffix('"')
+}
+
+/// `name("value")` => (name, value)
+fn split_guard(sym: &str) -> Option<(&str, &str)> {
+    sym.strip_suffix("\")")?.split_once("(\"")
+}
+
+fn visit_rule(sym: &GramSym, dt: &DerivationTree, order: &mut IndexSet<GramSym>) {
+    if !order.insert(sym.clone()) {
+        return;
+    }
+
+    for prod in dt[sym].iter() {
+        for rhs_sym in prod.rhstr.get_normal().into_iter().flatten() {
+            if dt.contains_key(rhs_sym) {
+                visit_rule(rhs_sym, dt, order);
+            }
+        }
+    }
+}
+
+/// 格式化DSL源码， 普通的`//`注释不会保留
+pub fn format_dsl(src: &str) -> Result<String, Box<dyn Error>> {
+    let gram = GramBuilder::from_dsl(src)?
+        .build()
+        .map_err(|diags| Trap::new_box_err(&diags.to_string()))?;
+
+    Ok(gram.format_source())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+////// DSL Reader
+
+#[derive(Debug, Clone, PartialEq)]
+enum DslTok {
+    Ident(String),
+    Punct(char),
+    /// 字符串或者数字字面量(字符串已经去掉引号)
+    Lit(String),
+    /// `///`
+    OuterDoc(String),
+    /// `//!`
+    InnerDoc(String),
+}
+
+struct DslReader {
+    toks: Vec<DslTok>,
+    i: usize,
+}
+
+impl DslReader {
+    fn new(src: &str) -> Result<Self, Box<dyn Error>> {
+        let chars = src.chars().collect_vec();
+        let mut toks = vec![];
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+
+            if c.is_whitespace() {
+                i += 1;
+            }
+            else if src_starts_with(&chars, i, "//") {
+                let end = (i..chars.len()).find(|&j| chars[j] == '\n').unwrap_or(chars.len());
+                let line: String = chars[i..end].iter().collect();
+
+                if let Some(doc) = line.strip_prefix("//!") {
+                    toks.push(DslTok::InnerDoc(doc.to_string()));
+                }
+                else if let Some(doc) = line.strip_prefix("///").filter(|doc| !doc.starts_with('/')) {
+                    toks.push(DslTok::OuterDoc(doc.to_string()));
+                }
+
+                i = end;
+            }
+            else if c == '"' {
+                let mut lit = String::new();
+                i += 1;
+
+                loop {
+                    match chars.get(i) {
+                        Some('"') => break,
+                        Some('\\') => {
+                            match chars.get(i + 1) {
+                                Some('n') => lit.push('\n'),
+                                Some('t') => lit.push('\t'),
+                                Some(escaped) => lit.push(*escaped),
+                                None => break,
+                            }
+                            i += 2;
+                        },
+                        Some(c) => {
+                            lit.push(*c);
+                            i += 1;
+                        },
+                        None => return Err(Trap::new_box_err("unterminated string literal")),
+                    }
+                }
+
+                i += 1;
+                toks.push(DslTok::Lit(lit));
+            }
+            else if c.is_ascii_digit() {
+                let end = (i..chars.len())
+                    .find(|&j| !(chars[j].is_ascii_alphanumeric() || chars[j] == '.' || chars[j] == '_'))
+                    .unwrap_or(chars.len());
+
+                toks.push(DslTok::Lit(chars[i..end].iter().collect()));
+                i = end;
+            }
+            else if ":|;[]#!=(){},".contains(c) {
+                toks.push(DslTok::Punct(c));
+                i += 1;
+            }
+            else {
+                let end = (i..chars.len())
+                    .find(|&j| chars[j].is_whitespace() || ":|;[]#!=(){},\"".contains(chars[j]))
+                    .unwrap_or(chars.len());
+
+                toks.push(DslTok::Ident(chars[i..end].iter().collect()));
+                i = end;
+            }
+        }
+
+        Ok(Self { toks, i: 0 })
+    }
+
+    fn peek(&self) -> Option<&DslTok> {
+        self.toks.get(self.i)
+    }
+
+    fn peek_is(&self, offset: usize, punct: char) -> bool {
+        self.toks.get(self.i + offset) == Some(&DslTok::Punct(punct))
+    }
+
+    fn next(&mut self) -> Option<DslTok> {
+        let tok = self.toks.get(self.i).cloned();
+        self.i += 1;
+
+        tok
+    }
+
+    fn expect_punct(&mut self, punct: char) -> Result<(), Box<dyn Error>> {
+        match self.next() {
+            Some(DslTok::Punct(c)) if c == punct => Ok(()),
+            other => Err(Trap::new_box_err(&format!("expect `{}`, found {:?}", punct, other))),
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String, Box<dyn Error>> {
+        match self.next() {
+            Some(DslTok::Ident(ident)) => Ok(ident),
+            other => Err(Trap::new_box_err(&format!("expect identifier, found {:?}", other))),
+        }
+    }
+
+    /// 符号， 加引号的终结符`"+"`， 排除集合`~{semi "}"}`， 字符类`["a-z"]`，
+    /// 或者带值约束的终结符`id("self")`
+    fn expect_sym(&mut self) -> Result<String, Box<dyn Error>> {
+        if self.peek_is(0, '[') {
+            self.i += 1;
+
+            let spec = match self.next() {
+                Some(DslTok::Lit(lit)) => lit,
+                other => return Err(Trap::new_box_err(
+                    &format!("expect literal, found {:?}", other)
+                )),
+            };
+            self.expect_punct(']')?;
+
+            let class = format!("[{}]", spec);
+            if CharClass::parse(&class).is_none() {
+                return Err(Trap::new_box_err(&format!("bad character class {:?}", spec)));
+            }
+
+            return Ok(class);
+        }
+
+        if let Some(DslTok::Lit(lit)) = self.peek() {
+            let quoted = format!("\"{}\"", lit);
+            self.i += 1;
+
+            return Ok(quoted);
+        }
+
+        if self.peek() == Some(&DslTok::Ident("~".to_string())) && self.peek_is(1, '{') {
+            self.i += 2;
+
+            let mut names = vec![];
+            while !self.peek_is(0, '}') {
+                match self.next() {
+                    Some(DslTok::Ident(name)) | Some(DslTok::Lit(name)) => names.push(name),
+                    other => return Err(Trap::new_box_err(
+                        &format!("expect token name, found {:?}", other)
+                    )),
+                }
+            }
+            self.i += 1;
+
+            return Ok(GramSym::not_set(names).name().to_string());
+        }
+
+        let name = self.expect_ident()?;
+
+        if !self.peek_is(0, '(') {
+            return Ok(name);
+        }
+        self.i += 1;
+
+        let value = match self.next() {
+            Some(DslTok::Lit(lit)) => lit,
+            other => return Err(Trap::new_box_err(
+                &format!("expect literal, found {:?}", other)
+            )),
+        };
+        self.expect_punct(')')?;
+
+        Ok(GramSym::guarded(&name, &value).name().to_string())
+    }
+
+    /// `| ]`
+    fn at_end(&self) -> bool {
+        self.peek_is(0, '|') && self.peek_is(1, ']')
+    }
+
+    fn read(mut self) -> Result<GramBuilder, Box<dyn Error>> {
+        // 跳到`grammar![`
+        loop {
+            match self.next() {
+                Some(DslTok::Ident(ident)) if ident == "grammar"
+                && self.peek_is(0, '!') && self.peek_is(1, '[') => {
+                    self.i += 2;
+                    break;
+                },
+                Some(_) => (),
+                None => return Err(Trap::new_box_err("expect `grammar![name| ... |]`")),
+            }
+        }
+
+        let mut builder = GramBuilder::new(&self.expect_ident()?);
+        self.expect_punct('|')?;
+
+        while !self.at_end() {
+            let name = self.expect_ident()?;
+            self.expect_punct(':')?;
+            builder = builder.rule(&name);
+
+            let mut attrs = ProdAttrs::new();
+
+            loop {
+                match self.peek() {
+                    Some(DslTok::InnerDoc(line)) => {
+                        builder = builder.doc(&line.clone());
+                        self.i += 1;
+                    },
+                    Some(DslTok::OuterDoc(line)) => {
+                        add_prod_attr(&mut attrs, "doc", &line.clone());
+                        self.i += 1;
+                    },
+                    Some(DslTok::Punct('#')) => {
+                        self.i += 1;
+                        let inner = self.peek_is(0, '!');
+                        if inner {
+                            self.i += 1;
+                        }
+                        self.expect_punct('[')?;
+
+                        let key = self.expect_ident()?;
+                        let mut value = String::new();
+
+                        if self.peek_is(0, '=') {
+                            self.i += 1;
+
+                            match self.next() {
+                                Some(DslTok::Lit(lit)) => value = lit,
+                                other => return Err(Trap::new_box_err(
+                                    &format!("expect literal, found {:?}", other)
+                                )),
+                            }
+                        }
+                        self.expect_punct(']')?;
+
+                        if inner && key == "doc" {
+                            builder = builder.doc(&value);
+                        }
+                        else {
+                            add_prod_attr(&mut attrs, &key, &value);
+                        }
+                    },
+                    Some(DslTok::Punct('|')) if !self.at_end() => {
+                        self.i += 1;
+
+                        let mut syms = vec![];
+                        while !self.peek_is(0, ';') {
+                            syms.push(self.expect_sym()?);
+                        }
+                        self.i += 1;
+
+                        if syms.is_empty() {
+                            return Err(Trap::new_box_err(
+                                &format!("empty alternative for {}, use `{}`", name, EPSILON_NAME)
+                            ));
+                        }
+
+                        builder = builder.alt(syms);
+                        for (key, value) in attrs.drain(..) {
+                            builder = builder.attr(&key, &value);
+                        }
+                    },
+                    _ => break,
+                }
+            }
+        }
+
+        Ok(builder)
+    }
+}
+
+fn src_starts_with(chars: &[char], i: usize, pat: &str) -> bool {
+    pat.chars().enumerate().all(|(j, c)| chars.get(i + j) == Some(&c))
+}
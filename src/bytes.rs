@@ -0,0 +1,265 @@
+//! Byte Lexer: 按字节规则切分`&[u8]`输入， 用于基于行的二进制协议和文本/二进制混合的格式
+//!
+//! token的名字是规则名， 值是字节的文本形式(非UTF-8的部分转义)，
+//! 原始字节和字节偏移在附加值`ByteToken`里， 位置`SrcLoc`的行号固定为0， 列号是字节偏移
+//!
+//! ```ignore
+//! let lexer = ByteLexer::new(vec![
+//!     (BytePattern::literal(b"GET"), "get"),
+//!     (BytePattern::regex(r"[0-9]+"), "num"),
+//!     (BytePattern::LengthPrefixed(2), "blob"),
+//!     (BytePattern::literal(b"\r\n"), "crlf"),
+//! ]);
+//! let tokens = lexer.tokenize(&data)?;
+//! let span = tokens[0].payload::<ByteToken>().unwrap().span;
+//! ```
+
+use std::{convert::TryFrom, error::Error, fmt, sync::Arc};
+
+use regex::bytes::Regex;
+
+use crate::{
+    error::TrapCode,
+    lexer::MatchPolicy,
+    parser::{SrcLoc, Token},
+};
+
+
+/// 输入里的字节区间`[start, end)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ByteSpan {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl ByteSpan {
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+impl fmt::Display for ByteSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}..{:#x}", self.start, self.end)
+    }
+}
+
+
+/// 字节token的附加值
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ByteToken {
+    pub bytes: Vec<u8>,
+    pub span: ByteSpan,
+}
+
+impl ByteToken {
+    /// 去掉`LengthPrefixed`的长度前缀后的内容
+    pub fn body(&self, prefix_width: usize) -> &[u8] {
+        &self.bytes[prefix_width.min(self.bytes.len())..]
+    }
+}
+
+
+#[derive(Clone)]
+pub enum BytePattern {
+    /// 只匹配开头的字节正则， 不按Unicode匹配
+    Regex(Regex),
+    /// 固定长度的任意字节
+    Fixed(usize),
+    /// 宽度为n(不超过8)的大端长度前缀， 后面跟着那么多字节， token包括前缀
+    LengthPrefixed(usize),
+    /// 满足条件的最长字节序列
+    While(Arc<dyn Fn(u8) -> bool + Send + Sync>),
+}
+
+impl fmt::Debug for BytePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Regex(pat) => write!(f, "Regex({:?})", pat.as_str()),
+            Self::Fixed(n) => write!(f, "Fixed({})", n),
+            Self::LengthPrefixed(n) => write!(f, "LengthPrefixed({})", n),
+            Self::While(_) => write!(f, "While"),
+        }
+    }
+}
+
+impl BytePattern {
+    /// patstr必须是合法的正则， 否则panic
+    pub fn regex(patstr: &str) -> Self {
+        Self::Regex(Regex::new(&format!("^(?-u:{})", patstr)).unwrap())
+    }
+
+    pub fn literal(bytes: &[u8]) -> Self {
+        let patstr = bytes
+            .iter()
+            .map(|b| format!("\\x{:02x}", b))
+            .collect::<String>();
+
+        Self::regex(&patstr)
+    }
+
+    pub fn while_<F: Fn(u8) -> bool + Send + Sync + 'static>(f: F) -> Self {
+        Self::While(Arc::new(f))
+    }
+
+    /// 匹配bytes的开头， 返回匹配的长度
+    pub fn fetch(&self, bytes: &[u8]) -> Option<usize> {
+        match self {
+            Self::Regex(pat) => pat.find(bytes).map(|mat| mat.end()),
+            Self::Fixed(n) => Some(*n).filter(|n| *n <= bytes.len()),
+            Self::LengthPrefixed(width) => {
+                if *width == 0 || *width > 8 || bytes.len() < *width {
+                    return None;
+                }
+
+                let len = bytes[..*width]
+                    .iter()
+                    .fold(0u64, |len, b| len << 8 | *b as u64);
+
+                usize::try_from(len)
+                    .ok()
+                    .and_then(|len| len.checked_add(*width))
+                    .filter(|total| *total <= bytes.len())
+            }
+            Self::While(f) => Some(bytes.iter().take_while(|b| f(**b)).count()),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct ByteRule {
+    name: String,
+    pattern: BytePattern,
+    /// 匹配后丢弃， 比如分隔符
+    skip: bool,
+}
+
+impl ByteRule {
+    pub fn new(pattern: BytePattern, name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            pattern,
+            skip: false,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn pattern(&self) -> &BytePattern {
+        &self.pattern
+    }
+
+    pub fn is_skip(&self) -> bool {
+        self.skip
+    }
+
+    fn make_token(&self, bytes: &[u8], start: usize) -> Token {
+        let span = ByteSpan { start, end: start + bytes.len() };
+
+        Token::new(&self.name, &bytes_text(bytes), SrcLoc::new((0, start)))
+            .with_payload(ByteToken { bytes: bytes.to_vec(), span })
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct ByteLexer {
+    rules: Vec<ByteRule>,
+    policy: MatchPolicy,
+}
+
+impl ByteLexer {
+    /// 规则的声明顺序就是优先级
+    pub fn new(rules: Vec<(BytePattern, &str)>) -> Self {
+        Self {
+            rules: rules
+                .into_iter()
+                .map(|(pattern, name)| ByteRule::new(pattern, name))
+                .collect(),
+            policy: MatchPolicy::default(),
+        }
+    }
+
+    pub fn rules(&self) -> &[ByteRule] {
+        &self.rules
+    }
+
+    pub fn policy(&self) -> MatchPolicy {
+        self.policy
+    }
+
+    pub fn with_policy(mut self, policy: MatchPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_skip(mut self, name: &str) -> Self {
+        for rule in self.rules.iter_mut().filter(|rule| rule.name == name) {
+            rule.skip = true;
+        }
+        self
+    }
+
+    /// 按当前策略匹配bytes的开头， 返回(规则， 匹配长度)
+    pub fn fetch(&self, bytes: &[u8]) -> Option<(&ByteRule, usize)> {
+        let mut candidates = self
+            .rules
+            .iter()
+            .filter_map(|rule| rule.pattern.fetch(bytes).filter(|len| *len > 0).map(|len| (rule, len)));
+
+        match self.policy {
+            MatchPolicy::FirstDeclared => candidates.next(),
+            MatchPolicy::LongestMatch => {
+                candidates.fold(None, |longest, (rule, len)| match longest {
+                    Some((_, longest_len)) if longest_len >= len => longest,
+                    _ => Some((rule, len)),
+                })
+            }
+        }
+    }
+
+    pub fn tokenize(&self, bytes: &[u8]) -> Result<Vec<Token>, Box<dyn Error>> {
+        let mut tokens = vec![];
+        let mut pos = 0usize;
+
+        while pos < bytes.len() {
+            let rest = &bytes[pos..];
+
+            let len = match self.fetch(rest) {
+                Some((rule, len)) => {
+                    if !rule.skip {
+                        tokens.push(rule.make_token(&rest[..len], pos));
+                    }
+                    len
+                },
+                None => {
+                    return Err(TrapCode::UnrecognizedToken(&format!(
+                        "Unrecognized bytes `{}` at {:#x}",
+                        bytes_text(&rest[..rest.len().min(16)]),
+                        pos
+                    )).emit_box_err());
+                }
+            };
+
+            pos += len;
+        }
+
+        Ok(tokens)
+    }
+}
+
+
+/// UTF-8的字节原样作为文本， 否则按ASCII转义
+pub fn bytes_text(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.escape_ascii().to_string(),
+    }
+}
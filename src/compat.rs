@@ -0,0 +1,279 @@
+//! Grammar Compatibility: 检查新版本的语法是否仍然接受旧版本接受的所有输入
+//!
+//! 语言包含是不可判定的， 这里用生成的语料近似： 每个产生式至少一个用到它的最短句子，
+//! 再加上有限深度内穷举的句子， 逐个交给对方的解析器
+
+use std::fmt;
+
+use indexmap::{indexmap, IndexMap, IndexSet};
+use itertools::Itertools;
+
+use crate::analysis::GramAnalysis;
+use crate::gram::{Gram, GramProd, GramSym, GramSymStr};
+use crate::parser::{LL1Parser, SrcLoc, Token};
+
+
+/// 默认的穷举深度和句子数上限
+const SAMPLE_DEPTH: usize = 8;
+const SAMPLE_LIMIT: usize = 2000;
+
+
+#[derive(Debug, Clone, Default)]
+pub struct SubsetReport {
+    /// 检查过的句子数
+    pub checked: usize,
+    /// self接受而other拒绝的句子(终结符串)
+    pub counterexamples: Vec<Vec<GramSym>>,
+}
+
+impl SubsetReport {
+    pub fn holds(&self) -> bool {
+        self.counterexamples.is_empty()
+    }
+}
+
+impl fmt::Display for SubsetReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "checked {} sentences, {} rejected",
+            self.checked,
+            self.counterexamples.len()
+        )?;
+
+        for sentence in self.counterexamples.iter() {
+            writeln!(f, "  rejected: {}", sentence.iter().map(|sym| sym.name()).join(" "))?;
+        }
+
+        Ok(())
+    }
+}
+
+
+impl Gram {
+    /// 近似地判断self接受的语言是否是other的子集
+    pub fn accepts_subset_of(&self, other: &Gram) -> bool {
+        self.check_subset_of(other, SAMPLE_DEPTH, SAMPLE_LIMIT).holds()
+    }
+
+    /// 同`accepts_subset_of`， 但给出反例。 空句子无法作为输入， 不参与检查
+    pub fn check_subset_of(&self, other: &Gram, depth: usize, limit: usize) -> SubsetReport {
+        // 冲突按默认的规则裁决， 和other实际的解析器一致
+        let parser = LL1Parser::from_analysis(GramAnalysis::new(other.clone()));
+        let mut report = SubsetReport::default();
+
+        for sentence in self.sample_sentences(depth, limit) {
+            if sentence.is_empty() {
+                continue;
+            }
+
+            let tokens = sentence
+                .iter()
+                .enumerate()
+                .map(|(i, sym)| Token::from_sym(sym, SrcLoc::new((1, i))))
+                .collect_vec();
+
+            report.checked += 1;
+            if parser.parse(tokens).is_err() {
+                report.counterexamples.push(sentence);
+            }
+        }
+
+        report
+    }
+
+    /// 生成语法接受的句子： 先是覆盖每个产生式的最短句子，
+    /// 然后是推导树高度不超过`depth`的句子， 总数不超过`limit`。
+    /// 错误产生式不参与生成
+    pub fn sample_sentences(&self, depth: usize, limit: usize) -> Vec<Vec<GramSym>> {
+        let mut sentences = IndexSet::new();
+
+        sentences.extend(self.covering_sentences());
+
+        if let Some(start_sym) = self.start_sym() {
+            let mut memo = indexmap! {};
+            let enumerated = self.enumerate(start_sym, depth, limit, &mut memo);
+
+            sentences.extend(enumerated);
+        }
+
+        sentences.into_iter().take(limit).collect()
+    }
+
+    fn normal_prods(&self) -> impl Iterator<Item = &GramProd> {
+        self.productions().filter(|prod| !prod.is_error_prod())
+    }
+
+    /// 每个非终结符能推导出的最短终结符串
+    fn shortest_yields(&self) -> IndexMap<GramSym, Vec<GramSym>> {
+        let mut shortest: IndexMap<GramSym, Vec<GramSym>> = indexmap! {};
+
+        loop {
+            let mut stable = true;
+
+            for prod in self.normal_prods() {
+                let candidate = match &prod.rhstr {
+                    GramSymStr::Epsilon => Some(vec![]),
+                    GramSymStr::Str(normal_str) => {
+                        yield_of(normal_str, &shortest)
+                    }
+                };
+
+                if let Some(candidate) = candidate {
+                    let shorter = shortest
+                        .get(&prod.lfsym)
+                        .is_none_or(|known| candidate.len() < known.len());
+
+                    if shorter {
+                        shortest.insert(prod.lfsym.clone(), candidate);
+                        stable = false;
+                    }
+                }
+            }
+
+            if stable {
+                break shortest;
+            }
+        }
+    }
+
+    /// 对每个产生式`A -> γ`， 取`S =>* u A v`中最短的上下文， 得到`u γ v`的最短句子
+    fn covering_sentences(&self) -> Vec<Vec<GramSym>> {
+        let shortest = self.shortest_yields();
+        let start_sym = match self.start_sym() {
+            Some(start_sym) => start_sym.clone(),
+            None => return vec![],
+        };
+
+        // 非终结符 => (前缀, 后缀)
+        let mut contexts: IndexMap<GramSym, (Vec<GramSym>, Vec<GramSym>)>
+        = indexmap! { start_sym => (vec![], vec![]) };
+
+        loop {
+            let mut stable = true;
+
+            for prod in self.normal_prods() {
+                let (prefix, suffix) = match contexts.get(&prod.lfsym) {
+                    Some(context) => context.clone(),
+                    None => continue,
+                };
+                let normal_str = match prod.rhstr.get_normal() {
+                    Some(normal_str) => normal_str,
+                    None => continue,
+                };
+
+                for (i, sym) in normal_str.iter().enumerate() {
+                    if sym.is_terminal() {
+                        continue;
+                    }
+
+                    let before = yield_of(&normal_str[..i], &shortest);
+                    let after = yield_of(&normal_str[i + 1..], &shortest);
+
+                    if let (Some(before), Some(after)) = (before, after) {
+                        let sym_prefix = [prefix.clone(), before].concat();
+                        let sym_suffix = [after, suffix.clone()].concat();
+
+                        let shorter = contexts.get(sym).is_none_or(|(known_prefix, known_suffix)| {
+                            sym_prefix.len() + sym_suffix.len() < known_prefix.len() + known_suffix.len()
+                        });
+
+                        if shorter {
+                            contexts.insert(sym.clone(), (sym_prefix, sym_suffix));
+                            stable = false;
+                        }
+                    }
+                }
+            }
+
+            if stable {
+                break;
+            }
+        }
+
+        self.normal_prods()
+            .filter_map(|prod| {
+                let (prefix, suffix) = contexts.get(&prod.lfsym)?;
+                let body = match &prod.rhstr {
+                    GramSymStr::Epsilon => vec![],
+                    GramSymStr::Str(normal_str) => yield_of(normal_str, &shortest)?,
+                };
+
+                Some([prefix.clone(), body, suffix.clone()].concat())
+            })
+            .collect()
+    }
+
+    /// 推导树高度不超过`depth`的句子， 最多`limit`个
+    fn enumerate(
+        &self,
+        sym: &GramSym,
+        depth: usize,
+        limit: usize,
+        memo: &mut IndexMap<(GramSym, usize), Vec<Vec<GramSym>>>,
+    ) -> Vec<Vec<GramSym>>
+    {
+        if sym.is_terminal() {
+            return vec![vec![sym.clone()]];
+        }
+        if depth == 0 {
+            return vec![];
+        }
+        if let Some(sentences) = memo.get(&(sym.clone(), depth)) {
+            return sentences.clone();
+        }
+
+        let mut sentences = IndexSet::new();
+
+        for prod in self.alts_of(sym).filter(|prod| !prod.is_error_prod()) {
+            let normal_str = match &prod.rhstr {
+                GramSymStr::Epsilon => {
+                    sentences.insert(vec![]);
+                    continue;
+                }
+                GramSymStr::Str(normal_str) => normal_str,
+            };
+
+            let mut partials = vec![vec![]];
+            for rhs_sym in normal_str.iter() {
+                let tails = self.enumerate(rhs_sym, depth - 1, limit, memo);
+
+                partials = partials
+                    .iter()
+                    .cartesian_product(tails.iter())
+                    .map(|(head, tail)| [head.clone(), tail.clone()].concat())
+                    .take(limit)
+                    .collect();
+
+                if partials.is_empty() {
+                    break;
+                }
+            }
+
+            sentences.extend(partials);
+            if sentences.len() >= limit {
+                break;
+            }
+        }
+
+        let sentences = sentences.into_iter().take(limit).collect_vec();
+        memo.insert((sym.clone(), depth), sentences.clone());
+
+        sentences
+    }
+}
+
+/// 符号串的最短终结符串， 有符号还推不出终结符串时为None
+fn yield_of(normal_str: &[GramSym], shortest: &IndexMap<GramSym, Vec<GramSym>>) -> Option<Vec<GramSym>> {
+    let mut yields = vec![];
+
+    for sym in normal_str.iter() {
+        if sym.is_terminal() {
+            yields.push(sym.clone());
+        } else {
+            yields.extend(shortest.get(sym)?.iter().cloned());
+        }
+    }
+
+    Some(yields)
+}
@@ -0,0 +1,438 @@
+//! Corpus Runner: 批量解析合法/非法样例， 检查结果是否符合预期
+//!
+//! 非法样例`foo.src`旁边可以放一个`foo.src.expected`， 每行一个期望出现的错误码
+//! (比如`unexpected-token`， 词法错误是`lex-error`)， 没有就只要求解析失败
+//!
+//! 开启`with_annotations("//")`后， 非法样例里可以用注释写出期望的诊断，
+//! `^`指向上面最近一行(不是注释的)的那一列， 严重程度之后的代码和消息都可以省略，
+//! 消息只要是诊断消息的一部分就行：
+//!
+//! ```text
+//! let x = 1
+//! let y = 2
+//! //^ error[unmatched-token]: a <semi> expected
+//! ```
+//!
+//! 有这样的注释时， 诊断要和注释一一对应， 没有写出的错误也算失败。
+//! 注释所在的行在词法分析前被清空， 不影响行号
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+
+use crate::diagnostic::{Diagnostic, Diagnostics, Severity};
+use crate::error::Trap;
+use crate::lexer::Lexer;
+use crate::parser::{LL1Parser, ParseOptions, SrcFileInfo, SrcLoc};
+
+
+const EXPECTED_EXT: &str = "expected";
+
+
+pub struct CorpusRunner<'a> {
+    parser: &'a LL1Parser,
+    lexer: &'a Lexer,
+    options: ParseOptions,
+    /// 期望注释的注释开头
+    annotation: Option<String>,
+}
+
+impl<'a> CorpusRunner<'a> {
+    pub fn new(parser: &'a LL1Parser, lexer: &'a Lexer) -> Self {
+        Self {
+            parser,
+            lexer,
+            options: ParseOptions::default(),
+            annotation: None,
+        }
+    }
+
+    /// 比如开启恢复， 以便收集到所有的错误码
+    pub fn with_options(mut self, options: &ParseOptions) -> Self {
+        self.options = options.clone();
+        self
+    }
+
+    /// 识别以`prefix`开头、 紧接着`^`的期望注释， `prefix`是样例语言的行注释(比如`//`、 `#`)
+    pub fn with_annotations(mut self, prefix: &str) -> Self {
+        self.annotation = Some(prefix.to_string());
+        self
+    }
+
+    /// `valid_dir`下的样例都应该解析成功， `invalid_dir`下的都应该失败
+    pub fn run<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        valid_dir: P,
+        invalid_dir: Q,
+    ) -> Result<CorpusReport, Box<dyn Error>>
+    {
+        let mut report = CorpusReport::default();
+
+        for path in samples(valid_dir.as_ref())? {
+            report.results.push(self.check(path, true)?);
+        }
+        for path in samples(invalid_dir.as_ref())? {
+            report.results.push(self.check(path, false)?);
+        }
+
+        Ok(report)
+    }
+
+    fn check(&self, path: PathBuf, valid: bool) -> Result<SampleResult, Box<dyn Error>> {
+        let mut srcfile = SrcFileInfo::new(path.clone())?;
+        let mut expectations = vec![];
+
+        if let (Some(prefix), false) = (&self.annotation, valid) {
+            let (srcstr, parsed) = strip_annotations(&srcfile, prefix)?;
+
+            srcfile = SrcFileInfo::from_srcstr(path.clone(), srcstr);
+            expectations = parsed;
+        }
+
+        let diags = match self.lexer.tokenize(&srcfile) {
+            Ok(tokens) => self.parser.parse_diag(tokens, &self.options).1,
+            Err(err) => {
+                let mut diags = Diagnostics::new();
+                diags.push(Diagnostic::error("lex-error", &err.to_string()));
+
+                diags
+            },
+        };
+        let codes = diags.errors().map(|diag| diag.code.clone()).unique().collect_vec();
+
+        let outcome = if valid {
+            if codes.is_empty() {
+                SampleOutcome::Pass
+            } else {
+                SampleOutcome::UnexpectedFailure(codes)
+            }
+        }
+        else if codes.is_empty() {
+            SampleOutcome::UnexpectedSuccess
+        }
+        else if let Some(outcome) = match_expectations(expectations, &diags) {
+            outcome
+        }
+        else {
+            let expected = expected_codes(&path)?;
+
+            if expected.iter().all(|code| codes.contains(code)) {
+                SampleOutcome::Pass
+            } else {
+                SampleOutcome::WrongCodes { expected, actual: codes }
+            }
+        };
+
+        Ok(SampleResult { path, valid, outcome })
+    }
+}
+
+/// 目录下的样例文件， 按文件名排序
+fn samples(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut paths = vec![];
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_file() && path.extension().is_none_or(|ext| ext != EXPECTED_EXT) {
+            paths.push(path);
+        }
+    }
+
+    paths.sort();
+
+    Ok(paths)
+}
+
+fn expected_codes(path: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut expected_path = path.as_os_str().to_owned();
+    expected_path.push(".");
+    expected_path.push(EXPECTED_EXT);
+
+    let expected_path = PathBuf::from(expected_path);
+    if !expected_path.exists() {
+        return Ok(vec![]);
+    }
+
+    Ok(fs::read_to_string(expected_path)?
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect())
+}
+
+
+/// 期望注释： 在`loc`处有一条这样的诊断
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expectation {
+    pub loc: SrcLoc,
+    pub severity: Severity,
+    pub code: Option<String>,
+    /// 诊断消息的一部分， 为空时不检查
+    pub msg: String,
+}
+
+impl Expectation {
+    fn matches(&self, diag: &Diagnostic) -> bool {
+        diag.loc.as_ref() == Some(&self.loc)
+        && diag.severity == self.severity
+        && self.code.as_ref().is_none_or(|code| *code == diag.code)
+        && diag.msg.contains(&self.msg)
+    }
+}
+
+impl fmt::Display for Expectation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.severity)?;
+
+        if let Some(code) = &self.code {
+            write!(f, "[{}]", code)?;
+        }
+        if !self.msg.is_empty() {
+            write!(f, ": {}", self.msg)?;
+        }
+
+        write!(f, " at {}", self.loc)
+    }
+}
+
+/// 清空期望注释所在的行， 返回清空后的源码和注释
+fn strip_annotations(srcfile: &SrcFileInfo, prefix: &str) -> Result<(String, Vec<Expectation>), Box<dyn Error>> {
+    let config = srcfile.config();
+    let mut lines = vec![];
+    let mut expectations = vec![];
+    // 注释指向的行
+    let mut target = None;
+
+    for (i, line) in srcfile.get_srcstr().split('\n').enumerate() {
+        let ln = i + config.first_line();
+        let caret = line
+            .trim_start()
+            .strip_prefix(prefix)
+            .filter(|rest| rest.starts_with('^'))
+            .map(|rest| line.len() - rest.len());
+
+        let caret = match caret {
+            Some(caret) => caret,
+            None => {
+                lines.push(line);
+                target = Some(ln);
+                continue;
+            }
+        };
+
+        let bad = |reason: &str| {
+            Trap::new_box_err(&format!("bad annotation at {}:{}: {}", srcfile.get_path().display(), ln, reason))
+        };
+
+        let target = target.ok_or_else(|| bad("no line above"))?;
+        let rest = line[caret..].trim_start_matches('^');
+
+        let (head, msg) = rest.split_once(':').unwrap_or((rest, ""));
+        let head = head.trim();
+        let (severity, code) = match head.split_once('[') {
+            Some((severity, code)) => {
+                let code = code.strip_suffix(']').ok_or_else(|| bad("unclosed `[`"))?;
+                (severity.trim(), Some(code.trim().to_string()))
+            }
+            None => (head, None),
+        };
+        let severity = match severity {
+            "error" => Severity::Error,
+            "warning" => Severity::Warning,
+            "note" => Severity::Note,
+            _ => return Err(bad(&format!("unknown severity `{}`", severity))),
+        };
+
+        expectations.push(Expectation {
+            loc: SrcLoc::new((target, line[..caret].chars().count() + config.first_col())),
+            severity,
+            code,
+            msg: msg.trim().to_string(),
+        });
+        lines.push("");
+    }
+
+    Ok((lines.join("\n"), expectations))
+}
+
+/// 没有期望注释时为None， 每条注释用掉一个诊断， 剩下的错误算作多出来的
+fn match_expectations(expectations: Vec<Expectation>, diags: &Diagnostics) -> Option<SampleOutcome> {
+    if expectations.is_empty() {
+        return None;
+    }
+
+    let mut used = vec![false; diags.len()];
+    let mut missing = vec![];
+
+    for expectation in expectations {
+        match diags.iter().enumerate().position(|(i, diag)| !used[i] && expectation.matches(diag)) {
+            Some(i) => used[i] = true,
+            None => missing.push(expectation),
+        }
+    }
+
+    let unexpected = diags
+        .iter()
+        .zip(used)
+        .filter(|(diag, used)| !used && diag.is_error())
+        .map(|(diag, _)| diag.to_string())
+        .collect_vec();
+
+    if missing.is_empty() && unexpected.is_empty() {
+        Some(SampleOutcome::Pass)
+    }
+    else {
+        Some(SampleOutcome::Mismatch { missing, unexpected })
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SampleOutcome {
+    Pass,
+    /// 合法样例解析失败， 附带错误码
+    UnexpectedFailure(Vec<String>),
+    /// 非法样例解析成功
+    UnexpectedSuccess,
+    /// 非法样例失败了， 但是没有出现期望的错误码
+    WrongCodes { expected: Vec<String>, actual: Vec<String> },
+    /// 诊断和期望注释对不上
+    Mismatch { missing: Vec<Expectation>, unexpected: Vec<String> },
+}
+
+#[derive(Debug, Clone)]
+pub struct SampleResult {
+    pub path: PathBuf,
+    /// 是否来自合法样例目录
+    pub valid: bool,
+    pub outcome: SampleOutcome,
+}
+
+impl SampleResult {
+    pub fn passed(&self) -> bool {
+        self.outcome == SampleOutcome::Pass
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CorpusReport {
+    pub results: Vec<SampleResult>,
+}
+
+impl CorpusReport {
+    pub fn is_ok(&self) -> bool {
+        self.results.iter().all(|result| result.passed())
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &SampleResult> {
+        self.results.iter().filter(|result| !result.passed())
+    }
+
+    /// (valid, passed)格子里的样例数
+    pub fn count(&self, valid: bool, passed: bool) -> usize {
+        self.results
+            .iter()
+            .filter(|result| result.valid == valid && result.passed() == passed)
+            .count()
+    }
+}
+
+impl fmt::Display for CorpusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<10}{:>8}{:>8}", "", "pass", "fail")?;
+        writeln!(f, "{:<10}{:>8}{:>8}", "valid", self.count(true, true), self.count(true, false))?;
+        writeln!(f, "{:<10}{:>8}{:>8}", "invalid", self.count(false, true), self.count(false, false))?;
+
+        for result in self.failures() {
+            let reason = match &result.outcome {
+                SampleOutcome::Pass => unreachable!(),
+                SampleOutcome::UnexpectedFailure(codes) => {
+                    format!("unexpected failure: {}", codes.join(", "))
+                },
+                SampleOutcome::UnexpectedSuccess => "unexpected success".to_string(),
+                SampleOutcome::WrongCodes { expected, actual } => {
+                    format!("expected {}, got {}", expected.join(", "), actual.join(", "))
+                },
+                SampleOutcome::Mismatch { missing, unexpected } => {
+                    missing
+                        .iter()
+                        .map(|expectation| format!("missing {}", expectation))
+                        .chain(unexpected.iter().map(|diag| format!("unexpected {}", diag)))
+                        .join("; ")
+                },
+            };
+
+            writeln!(f, "  FAIL {}: {}", result.path.display(), reason)?;
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::builder::GramBuilder;
+    use crate::lexer::RegexTokenMatcher;
+
+    #[test]
+    fn test_expectation_comments() {
+        let gram = GramBuilder::from_dsl("grammar![lang| S: | Stmt S; | ε; Stmt: | id eq id semi; | error semi; |]")
+            .unwrap()
+            .build()
+            .unwrap();
+        let parser = LL1Parser::builder(gram).build().unwrap();
+        let lexer = Lexer::new(vec![
+            (RegexTokenMatcher::new("[a-z]+"), "id".to_string()),
+            (RegexTokenMatcher::new("="), "eq".to_string()),
+            (RegexTokenMatcher::new(";"), "semi".to_string()),
+            (RegexTokenMatcher::new(r"\s+"), "ws".to_string()),
+        ])
+        .with_skip("ws");
+
+        let root = std::env::temp_dir().join(format!("ll1engine-corpus-{}", std::process::id()));
+        let (valid, invalid) = (root.join("valid"), root.join("invalid"));
+        fs::create_dir_all(&valid).unwrap();
+        fs::create_dir_all(&invalid).unwrap();
+
+        fs::write(valid.join("ok.src"), "a = b;\n").unwrap();
+        // 两处错误都写出来了
+        fs::write(invalid.join("a.src"), "a = b;\nc = = d;\n  //^ error[unmatched-token]\ne e;\n//^ error: a <eq> expected\n").unwrap();
+        // 第一处指错了列， 第二处没写
+        fs::write(invalid.join("b.src"), "c = = d;\n   //^ error\ne e;\n").unwrap();
+
+        let options = ParseOptions { error_prods: true, ..Default::default() };
+        let runner = CorpusRunner::new(&parser, &lexer).with_options(&options).with_annotations("//");
+        let report = runner.run(&valid, &invalid);
+
+        // 上面没有可以指向的行
+        fs::write(invalid.join("c.src"), "//^ error\n").unwrap();
+        let bad = runner.run(&valid, &invalid);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(bad.unwrap_err().to_string().contains("no line above"));
+        let report = report.unwrap();
+
+        assert_eq!(report.results.len(), 3);
+        assert!(report.results[0].passed() && report.results[1].passed());
+        assert_eq!(report.count(false, false), 1);
+
+        match &report.results[2].outcome {
+            SampleOutcome::Mismatch { missing, unexpected } => {
+                assert_eq!(missing.len(), 1);
+                assert_eq!(missing[0].loc, SrcLoc::new((1, 6)));
+                assert_eq!(unexpected.len(), 2);
+                assert!(unexpected[0].contains("Unmatched token<eq>: = (1, 5)"));
+                assert!(unexpected[1].contains("Unmatched token<id>: e (3, 3)"));
+            }
+            outcome => panic!("{:?}", outcome),
+        }
+        assert!(report.to_string().contains("missing error at (1, 6)"));
+    }
+}
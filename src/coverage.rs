@@ -0,0 +1,115 @@
+//! Grammar Coverage: 在一批输入上统计哪些产生式被用到过
+
+use std::{
+    cell::RefCell,
+    fmt,
+    rc::Rc,
+};
+
+use indexmap::IndexMap;
+
+use crate::{
+    error::ParseError,
+    gram::GramProd,
+    parser::{LL1Parser, ParseOptions, Token, AST},
+};
+
+
+/// 用它来解析测试语料， 然后看`report().uncovered()`
+pub struct CoverageRecorder<'a> {
+    parser: &'a LL1Parser,
+    hits: IndexMap<GramProd, usize>,
+    parses: usize,
+}
+
+impl<'a> CoverageRecorder<'a> {
+    pub fn new(parser: &'a LL1Parser) -> Self {
+        Self {
+            parser,
+            hits: IndexMap::new(),
+            parses: 0,
+        }
+    }
+
+    /// 正常地解析， 顺便记录用到的产生式(出错之前的部分也算)
+    pub fn parse(&mut self, tokens: Vec<Token>, options: &ParseOptions)
+    -> (Rc<RefCell<AST>>, Vec<ParseError>)
+    {
+        let mut machine = self.parser.machine(options).with_coverage();
+        machine.run_all(tokens);
+
+        for (prod, n) in machine.take_coverage().unwrap() {
+            *self.hits.entry(prod).or_default() += n;
+        }
+        self.parses += 1;
+
+        machine.finish()
+    }
+
+    pub fn report(&self) -> CoverageReport {
+        let prods = self.parser
+            .gram()
+            .productions()
+            .map(|prod| (prod.clone(), self.hits.get(prod).cloned().unwrap_or(0)))
+            .collect();
+
+        CoverageReport {
+            prods,
+            parses: self.parses,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct CoverageReport {
+    /// 语法里全部的产生式， 按定义顺序
+    prods: IndexMap<GramProd, usize>,
+    parses: usize,
+}
+
+impl CoverageReport {
+    pub fn hits(&self, prod: &GramProd) -> usize {
+        self.prods.get(prod).cloned().unwrap_or(0)
+    }
+
+    pub fn covered(&self) -> Vec<&GramProd> {
+        self.prods.iter().filter(|(_, n)| **n > 0).map(|(prod, _)| prod).collect()
+    }
+
+    pub fn uncovered(&self) -> Vec<&GramProd> {
+        self.prods.iter().filter(|(_, n)| **n == 0).map(|(prod, _)| prod).collect()
+    }
+
+    /// 被覆盖的产生式的比例， 空语法算作1
+    pub fn ratio(&self) -> f64 {
+        if self.prods.is_empty() {
+            return 1.0;
+        }
+
+        self.covered().len() as f64 / self.prods.len() as f64
+    }
+
+    pub fn parses(&self) -> usize {
+        self.parses
+    }
+}
+
+impl fmt::Display for CoverageReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "coverage: {}/{} productions ({:.1}%) over {} parses",
+            self.covered().len(),
+            self.prods.len(),
+            self.ratio() * 100.0,
+            self.parses
+        )?;
+
+        for prod in self.uncovered() {
+            writeln!(f, "  uncovered: {}", prod)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+//! Diagnostics: 语法检查和解析共用的诊断信息(Error/Warning/Note)
+
+use std::fmt;
+use std::slice;
+use std::vec;
+
+use crate::error::{ParseError, ParseErrorKind};
+use crate::parser::SrcLoc;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Note => write!(f, "note"),
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// 机器可读的规则名， 比如`unused-nonterminal`
+    pub code: String,
+    pub msg: String,
+    pub loc: Option<SrcLoc>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &str, msg: &str) -> Self {
+        Self {
+            severity,
+            code: code.to_string(),
+            msg: msg.to_string(),
+            loc: None,
+            notes: vec![],
+        }
+    }
+
+    pub fn error(code: &str, msg: &str) -> Self {
+        Self::new(Severity::Error, code, msg)
+    }
+
+    pub fn warning(code: &str, msg: &str) -> Self {
+        Self::new(Severity::Warning, code, msg)
+    }
+
+    pub fn note(code: &str, msg: &str) -> Self {
+        Self::new(Severity::Note, code, msg)
+    }
+
+    pub fn with_loc(mut self, loc: SrcLoc) -> Self {
+        self.loc = Some(loc);
+        self
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.msg)?;
+
+        if let Some(loc) = &self.loc {
+            write!(f, " at {}", loc)?;
+        }
+
+        for note in self.notes.iter() {
+            write!(f, "\n  = {}", note)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(err: &ParseError) -> Self {
+        let code = match err.kind() {
+            ParseErrorKind::EmptyTokens => "empty-tokens",
+            ParseErrorKind::EmptyGram => "empty-gram",
+            ParseErrorKind::UnexpectedToken => "unexpected-token",
+            ParseErrorKind::UnmatchedToken => "unmatched-token",
+            ParseErrorKind::UnfinishedProd => "unfinished-production",
+            ParseErrorKind::TokensRemain => "tokens-remain",
+        };
+
+        let mut diag = Self::error(code, err.msg());
+        diag.loc = err.loc();
+        if !err.backtrace().is_empty() {
+            diag.notes.push(format!("in: {}", err.backtrace_str()));
+        }
+        diag.notes.extend(err.hints().iter().map(|hint| format!("help: {}", hint)));
+
+        diag
+    }
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    diags: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, diag: Diagnostic) {
+        self.diags.push(diag);
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, Diagnostic> {
+        self.diags.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.diags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diags.is_empty()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diags.iter().any(|diag| diag.is_error())
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diags.iter().filter(|diag| diag.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diags.iter().filter(|diag| diag.severity == Severity::Warning)
+    }
+}
+
+impl Extend<Diagnostic> for Diagnostics {
+    fn extend<I: IntoIterator<Item = Diagnostic>>(&mut self, iter: I) {
+        self.diags.extend(iter);
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.diags.into_iter()
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for diag in self.diags.iter() {
+            writeln!(f, "{}", diag)?;
+        }
+
+        Ok(())
+    }
+}
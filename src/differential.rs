@@ -0,0 +1,288 @@
+//! Differential Testing: 把同样的token流交给两个解析器， 报告接受/拒绝不一致或者语法树形状不同的输入，
+//! 用来守护核心算法的重构(新旧预测表、 不同的选项、 别的后端)
+//!
+//! ```ignore
+//! let report = new_parser.diff_against(&old_parser, sample_inputs(&gram, 8, 2000));
+//! for divergence in report.divergences.iter() {
+//!     println!("{}", divergence);
+//! }
+//!
+//! // 任意两个后端
+//! let report = Differential::new(
+//!     |tokens| parser.parse(tokens).map_err(|err| err.to_string()),
+//!     |tokens| other.parse(tokens).map_err(|err| err.to_string()),
+//! )
+//! .with_shrink(true)
+//! .run(inputs);
+//! ```
+//!
+//! 树按形状比较(见`AST`的`PartialEq`)， 不比较位置和注解。 一方panic算作拒绝
+
+use std::{
+    cell::RefCell,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    rc::Rc,
+};
+
+use itertools::Itertools;
+
+use crate::{
+    gram::Gram,
+    parser::{ASTNode, LL1Parser, SrcLoc, Token, AST},
+    shrink::shrink,
+};
+
+
+/// 接受时是语法树， 拒绝时是原因
+pub type Outcome = Result<Rc<RefCell<AST>>, String>;
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DivergenceKind {
+    /// 左边接受， 右边拒绝
+    LeftOnly { right_error: String },
+    /// 右边接受， 左边拒绝
+    RightOnly { left_error: String },
+    /// 都接受， 但树不同： 第一个不同的节点的路径(符号名)和两边的样子
+    TreeShape { path: Vec<String>, left: String, right: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    /// 开启了`with_shrink`时是缩小后的输入
+    pub tokens: Vec<Token>,
+    pub kind: DivergenceKind,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "input `{}`: ", self.tokens.iter().map(|token| token.value()).join(" "))?;
+
+        match &self.kind {
+            DivergenceKind::LeftOnly { right_error } => write!(f, "only left accepts, right: {}", right_error),
+            DivergenceKind::RightOnly { left_error } => write!(f, "only right accepts, left: {}", left_error),
+            DivergenceKind::TreeShape { path, left, right } => {
+                write!(f, "trees differ at {}: {} vs {}", path.join(" > "), left, right)
+            }
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct DiffReport {
+    pub checked: usize,
+    pub divergences: Vec<Divergence>,
+}
+
+impl DiffReport {
+    pub fn agrees(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+
+pub struct Differential<L, R> {
+    left: L,
+    right: R,
+    shrink: bool,
+}
+
+impl<L, R> Differential<L, R>
+where L: Fn(Vec<Token>) -> Outcome, R: Fn(Vec<Token>) -> Outcome
+{
+    pub fn new(left: L, right: R) -> Self {
+        Self { left, right, shrink: false }
+    }
+
+    /// 把每个不一致的输入缩小到最小(见`shrink`)， 缩成一样的只报告一次，
+    /// 缩小后的分歧种类可能和原来不同
+    pub fn with_shrink(mut self, shrink: bool) -> Self {
+        self.shrink = shrink;
+        self
+    }
+
+    pub fn check(&self, tokens: &[Token]) -> Option<Divergence> {
+        let kind = compare(run_guarded(&self.left, tokens), run_guarded(&self.right, tokens))?;
+
+        Some(Divergence { tokens: tokens.to_vec(), kind })
+    }
+
+    pub fn run<I: IntoIterator<Item = Vec<Token>>>(&self, inputs: I) -> DiffReport {
+        let mut report = DiffReport::default();
+
+        for tokens in inputs {
+            report.checked += 1;
+
+            let divergence = match self.check(&tokens) {
+                Some(divergence) => divergence,
+                None => continue,
+            };
+
+            if self.shrink {
+                let shrunk = shrink(&tokens, |tokens| self.check(tokens).is_some());
+
+                // 不同的输入常常缩成同一个
+                let duplicate = report.divergences.iter().any(|each| same_tokens(&each.tokens, &shrunk.tokens));
+                if !duplicate {
+                    report.divergences.extend(self.check(&shrunk.tokens));
+                }
+            }
+            else {
+                report.divergences.push(divergence);
+            }
+        }
+
+        report
+    }
+}
+
+
+impl LL1Parser {
+    /// 和`other`比较， self是左边
+    pub fn diff_against<I: IntoIterator<Item = Vec<Token>>>(&self, other: &LL1Parser, inputs: I) -> DiffReport {
+        Differential::new(
+            |tokens| self.parse(tokens).map_err(|err| err.to_string()),
+            |tokens| other.parse(tokens).map_err(|err| err.to_string()),
+        )
+        .run(inputs)
+    }
+}
+
+
+/// `Gram::sample_sentences`生成的句子， 再加上每个句子去掉最后一个token(通常被拒绝)的版本
+pub fn sample_inputs(gram: &Gram, depth: usize, limit: usize) -> Vec<Vec<Token>> {
+    let sentences = gram
+        .sample_sentences(depth, limit)
+        .into_iter()
+        .filter(|sentence| !sentence.is_empty())
+        .map(|sentence| {
+            sentence
+                .iter()
+                .enumerate()
+                .map(|(i, sym)| Token::from_sym(sym, SrcLoc::new((1, i))))
+                .collect_vec()
+        })
+        .collect_vec();
+
+    let truncated = sentences
+        .iter()
+        .filter(|tokens| tokens.len() > 1)
+        .map(|tokens| tokens[..tokens.len() - 1].to_vec())
+        .collect_vec();
+
+    sentences.into_iter().chain(truncated).collect()
+}
+
+
+fn same_tokens(x: &[Token], y: &[Token]) -> bool {
+    x.len() == y.len() && x.iter().zip(y).all(|(x, y)| x.name() == y.name() && x.value() == y.value())
+}
+
+fn run_guarded<F: Fn(Vec<Token>) -> Outcome>(f: &F, tokens: &[Token]) -> Outcome {
+    panic::catch_unwind(AssertUnwindSafe(|| f(tokens.to_vec()))).unwrap_or_else(|_| Err("panicked".to_string()))
+}
+
+fn compare(left: Outcome, right: Outcome) -> Option<DivergenceKind> {
+    match (left, right) {
+        (Ok(_), Err(right_error)) => Some(DivergenceKind::LeftOnly { right_error }),
+        (Err(left_error), Ok(_)) => Some(DivergenceKind::RightOnly { left_error }),
+        (Err(_), Err(_)) => None,
+        (Ok(left), Ok(right)) => {
+            let (path, left, right) = first_difference(&left, &right)?;
+            Some(DivergenceKind::TreeShape { path, left, right })
+        }
+    }
+}
+
+/// 从根往下找不同的节点， 不递归
+fn first_difference(left: &Rc<RefCell<AST>>, right: &Rc<RefCell<AST>>) -> Option<(Vec<String>, String, String)> {
+    let mut stack = vec![(left.clone(), right.clone(), vec![])];
+
+    while let Some((left, right, mut path)) = stack.pop() {
+        let (left, right) = (left.as_ref().borrow(), right.as_ref().borrow());
+        path.push(left.sym().name().to_string());
+
+        if left.sym() != right.sym() || left.elem_syms() != right.elem_syms() {
+            return Some((path, shape(&left), shape(&right)));
+        }
+
+        for ((_, left_node), (_, right_node)) in left.elems_vec().into_iter().zip(right.elems_vec()).rev() {
+            match (left_node, right_node) {
+                (ASTNode::Tree(left_tree), ASTNode::Tree(right_tree)) => {
+                    stack.push((left_tree.clone(), right_tree.clone(), path.clone()));
+                }
+                (ASTNode::Leaf(left_token), ASTNode::Leaf(right_token)) => {
+                    if left_token.name() != right_token.name() || left_token.value() != right_token.value() {
+                        return Some((path, left_token.to_string(), right_token.to_string()));
+                    }
+                }
+                _ => return Some((path, shape(&left), shape(&right))),
+            }
+        }
+    }
+
+    None
+}
+
+/// 节点和它的子节点的符号， 比如`[Expr] -> <id> [ExprTail]`
+fn shape(tree: &AST) -> String {
+    format!("{} -> {}", tree.sym(), tree.elem_syms().iter().join(" "))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::builder::GramBuilder;
+
+    fn parser(dsl: &str) -> LL1Parser {
+        LL1Parser::builder(GramBuilder::from_dsl(dsl).unwrap().build().unwrap()).build().unwrap()
+    }
+
+    #[test]
+    fn test_divergences() {
+        let base = parser("grammar![e| E: | id Tail; Tail: | plus id Tail; | ε; |]");
+        // 语言相同， 树的形状不同
+        let reshaped = parser("grammar![e| E: | id Tail; Tail: | plus Rhs; | ε; Rhs: | id Tail; |]");
+        // 最多一个`plus`
+        let narrowed = parser("grammar![e| E: | id Tail; Tail: | plus id; | ε; |]");
+        let inputs = sample_inputs(base.gram(), 6, 100);
+
+        let report = base.diff_against(&base, inputs.clone());
+        assert_eq!(report.checked, inputs.len());
+        assert!(report.agrees());
+
+        let report = base.diff_against(&reshaped, inputs.clone());
+        assert!(!report.agrees());
+        for divergence in report.divergences.iter() {
+            match &divergence.kind {
+                DivergenceKind::TreeShape { path, left, right } => {
+                    assert_eq!(path, &["E", "Tail"]);
+                    assert!(left.starts_with("[Tail] -> <plus> <id>"), "{}", left);
+                    assert_eq!(right, "[Tail] -> <plus> [Rhs]");
+                }
+                kind => panic!("{:?}", kind),
+            }
+        }
+
+        // 两边都拒绝的不算
+        let report = base.diff_against(&narrowed, inputs.clone());
+        assert!(!report.agrees());
+        assert!(report.divergences.iter().all(|divergence| {
+            matches!(divergence.kind, DivergenceKind::LeftOnly { .. })
+            && divergence.tokens.iter().filter(|token| token.name() == "plus").count() >= 2
+        }));
+
+        // 缩小后都是同一个最短的输入
+        let report = Differential::new(
+            |tokens| base.parse(tokens).map_err(|err| err.to_string()),
+            |tokens| narrowed.parse(tokens).map_err(|err| err.to_string()),
+        )
+        .with_shrink(true)
+        .run(inputs);
+        assert_eq!(report.divergences.len(), 1);
+        assert!(report.divergences[0].to_string().starts_with("input `id plus id plus id`: only left accepts, right: Tokens remains"));
+    }
+}
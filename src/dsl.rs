@@ -1,10 +1,10 @@
 ////////////////////////////////////////////////////////////////////////////////
-//// Grammar DSL
-//// 效果差强人意，代价大概是
-//// ```
-//// #![allow(non_snake_case)]
-//// #![allow(unused_variables)]
-////```
+////// Grammar DSL
+////// 效果差强人意，代价大概是
+////// ```
+////// #![allow(non_snake_case)]
+////// #![allow(unused_variables)]
+//////```
 
 
 #[macro_export]
@@ -48,17 +48,58 @@ macro_rules! use_epsilon {
     };
 }
 
+/// 错误产生式(`A -> error α`)的开头符号， 同样限制只能叫`error`
+#[macro_export]
+macro_rules! use_error {
+    ($name:ident) => {
+        debug_assert!(
+            stringify!($name) == $crate::gram::ERROR_SYM_NAME,
+            "error sym just should be `error`"
+        );
+        declare_terminal!($name);
+    };
+}
+
+/// 规则里的输入结束`$`， 名字随意
+#[macro_export]
+macro_rules! use_eof {
+    ($name:ident) => {
+        let $name = $crate::gram::GramSym::Eof;
+    };
+}
+
+/// 匹配任意token的通配符`_`， 名字随意
+#[macro_export]
+macro_rules! use_any {
+    ($name:ident) => {
+        let $name = $crate::gram::GramSym::Any;
+    };
+}
+
 /// 创建一个规则
 /// 第一个产生式默认是入口的根语法
+/// 产生式前面可以加注解: `#[deprecated]`, `#[prec=3]`, `#[deprecated="use f()"]`，
+/// `///`文档注释会作为产生式的`doc`注解， 规则名后面的`//!`文档注释是整条规则的文档，
+/// 终结符后面可以跟值约束: `| id("self") dot id ;`
 #[macro_export]
 macro_rules! grammar {
     [$gram_name:ident|
-         $($name:ident : $(| $($gramsym:ident)+ ;)+ )+
+         $($name:ident :
+            $(#![doc = $rule_doc:literal])*
+            $(
+                $(#[$attr_key:ident $(= $attr_val:literal)?])*
+                | $($gramsym:ident $(($guard:literal))?)+ ;
+            )+
+         )+
     |] =>
     {
         {
             let mut _grammar = $crate::gram::Gram::new(stringify!($gram_name));
             $(
+                $(
+                    _grammar.add_doc(&$name, $rule_doc);
+                )*
+
                 $(
                     let mut gram_str_vec = Vec::<$crate::gram::GramSym>::new();
                     let mut has_epsilon = false;
@@ -67,7 +108,7 @@ macro_rules! grammar {
                         if stringify!($gramsym) == "ε" {
                             has_epsilon = true;
                         } else {
-                            gram_str_vec.push($gramsym.clone());
+                            gram_str_vec.push($crate::__gram_sym!($gramsym $(($guard))?));
                         }
                     )+
 
@@ -77,12 +118,15 @@ macro_rules! grammar {
                         $crate::gram::GramSymStr::Str(gram_str_vec)
                      };
 
-                    _grammar.insert_prod(
-                        $crate::gram::GramProd {
-                            lfsym: $name.clone(),
-                            rhstr: gramsymstr
-                        }
-                    );
+                    let mut _prod = $crate::gram::GramProd::new($name.clone(), gramsymstr);
+                    $(
+                        _prod.add_attr(
+                            stringify!($attr_key),
+                            $crate::__prod_attr_val!($($attr_val)?)
+                        );
+                    )*
+
+                    _grammar.insert_prod(_prod);
                 )+
             )+
 
@@ -92,6 +136,31 @@ macro_rules! grammar {
 }
 
 
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __gram_sym {
+    ($sym:ident) => {
+        $sym.clone()
+    };
+
+    ($sym:ident ($guard:literal)) => {
+        $crate::gram::GramSym::guarded(&$sym.name(), $guard)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __prod_attr_val {
+    () => {
+        ""
+    };
+
+    ($val:literal) => {
+        &$val.to_string()
+    };
+}
+
+
 #[macro_export]
 macro_rules! First {
     ($name:ident | $($sym:ident)+) => {
@@ -105,7 +174,7 @@ macro_rules! First {
                 let fstsym = if sym_s == "ε" {
                     $crate::gram::FstSetSym::Epsilon
                 } else {
-                    $crate::gram::FstSetSym::Sym(sym_s.to_string())
+                    $crate::gram::FstSetSym::Sym($crate::gram::GramSym::Terminal(sym_s.to_string()))
                 };
 
                 _set.insert(fstsym);
@@ -129,7 +198,7 @@ macro_rules! Follow {
                 let fstsym = if sym_s == "NUL" {
                     $crate::gram::FollSetSym::EndMarker
                 } else {
-                    $crate::gram::FollSetSym::Sym(sym_s.to_string())
+                    $crate::gram::FollSetSym::Sym($crate::gram::GramSym::Terminal(sym_s.to_string()))
                 };
 
                 _set.insert(fstsym);
@@ -141,7 +210,7 @@ macro_rules! Follow {
 }
 
 ////////////////////////////////////////////////////////////////////////////////
-//// Lex DFA Map
+////// Lex DFA Map
 
 #[macro_export]
 macro_rules! lexdfamap {
@@ -180,7 +249,7 @@ macro_rules! token_recognizer {
 
             $(
                 _vec.push((
-                    RegexTokenMatcher::new($patstr),
+                    $crate::lexer::RegexTokenMatcher::new($patstr),
                     stringify!($token_name).to_string()
                 ));
             )*
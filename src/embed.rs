@@ -0,0 +1,355 @@
+//! Grammar Embedding: 在build.rs里检查语法并预先算好预测表， 生成可以`include!`的常量，
+//! 运行时直接装载， 不再解析DSL或者计算FIRST/FOLLOW集
+//!
+//! ```ignore
+//! // build.rs (ll1engine作为build-dependencies)
+//! fn main() {
+//!     let out_dir = std::env::var("OUT_DIR").unwrap();
+//!     ll1engine::embed::compile("lang.gram", &out_dir).unwrap();
+//! }
+//!
+//! // src/main.rs
+//! include!(concat!(env!("OUT_DIR"), "/lang.rs"));
+//! let parser = LL1Parser::from_compiled(&LANG);
+//! ```
+
+use std::{
+    error::Error,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{
+    analysis::GramAnalysis,
+    builder::GramBuilder,
+    error::Trap,
+    gram::{FollSetSym, FstSetSym, Gram, GramProd, GramSym, GramSymStr, PredSet, PredSetSym},
+    intern::Interner,
+    parser::LL1Parser,
+    scannerless::CharClass,
+    VerboseLv,
+};
+
+
+/// 表里代表ε(FIRST集)或者结束符(FOLLOW集、 预测表)的符号编号
+pub const END: usize = usize::MAX;
+
+/// (左部， 右部， 注解)， 右部为空就是ε
+pub type CompiledProd = (usize, &'static [usize], &'static [(&'static str, &'static str)]);
+
+
+/// 生成的常量里的符号， 同`GramSym`
+#[derive(Debug, Clone, Copy)]
+pub enum CompiledSym {
+    Terminal(&'static str),
+    NonTerminal(&'static str),
+    Eof,
+    Any,
+    NotSet(&'static [&'static str]),
+    /// (token名, 值)
+    Guarded(&'static str, &'static str),
+    /// (是否取反, 区间)
+    CharClass(bool, &'static [(char, char)]),
+}
+
+impl CompiledSym {
+    pub fn to_gram_sym(&self) -> GramSym {
+        match *self {
+            Self::Terminal(name) => GramSym::Terminal(name.to_string()),
+            Self::NonTerminal(name) => GramSym::NonTerminal(name.to_string()),
+            Self::Eof => GramSym::Eof,
+            Self::Any => GramSym::Any,
+            Self::NotSet(names) => GramSym::not_set(names),
+            Self::Guarded(name, value) => GramSym::guarded(name, value),
+            Self::CharClass(negated, ranges) => GramSym::CharClass(CharClass { negated, ranges: ranges.to_vec() }),
+        }
+    }
+}
+
+
+/// 生成的常量， 符号都用`syms`里的下标表示
+#[derive(Debug, Clone, Copy)]
+pub struct CompiledGram {
+    pub name: &'static str,
+    pub syms: &'static [CompiledSym],
+    /// 顺序同`Gram::productions`
+    pub prods: &'static [CompiledProd],
+    pub docs: &'static [(usize, &'static str)],
+    pub first: &'static [(usize, &'static [usize])],
+    pub follow: &'static [(usize, &'static [usize])],
+    /// (非终结符， 向前看符号， 产生式的下标)
+    pub table: &'static [(usize, usize, usize)],
+}
+
+impl CompiledGram {
+    pub fn sym(&self, i: usize) -> GramSym {
+        self.syms[i].to_gram_sym()
+    }
+
+    fn prod(&self, i: usize) -> GramProd {
+        let (lfsym, rhs, attrs) = self.prods[i];
+        let rhstr = if rhs.is_empty() {
+            GramSymStr::Epsilon
+        }
+        else {
+            GramSymStr::Str(rhs.iter().map(|sym| self.sym(*sym)).collect())
+        };
+
+        let mut prod = GramProd::new(self.sym(lfsym), rhstr);
+        prod.attrs = attrs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        prod
+    }
+
+    pub fn gram(&self) -> Gram {
+        let mut gram = Gram::new(self.name);
+
+        for i in 0..self.prods.len() {
+            gram.insert_prod(self.prod(i));
+        }
+
+        for (sym, doc) in self.docs.iter() {
+            for line in doc.lines() {
+                gram.add_doc(&self.sym(*sym), line);
+            }
+        }
+
+        gram
+    }
+
+    /// 生成时已经算好的分析结果
+    pub fn analysis(&self) -> GramAnalysis {
+        let fstsets = self
+            .first
+            .iter()
+            .map(|(sym, set)| {
+                let set = set
+                    .iter()
+                    .map(|i| match *i {
+                        END => FstSetSym::Epsilon,
+                        i => FstSetSym::Sym(self.sym(i)),
+                    })
+                    .collect();
+
+                (self.sym(*sym), set)
+            })
+            .collect();
+
+        let follsets = self
+            .follow
+            .iter()
+            .map(|(sym, set)| {
+                let set = set
+                    .iter()
+                    .map(|i| match *i {
+                        END => FollSetSym::EndMarker,
+                        i => FollSetSym::Sym(self.sym(i)),
+                    })
+                    .collect();
+
+                (self.sym(*sym), set)
+            })
+            .collect();
+
+        let mut predsets = PredSet::default();
+        for (lfsym, la, prod) in self.table.iter() {
+            let la = match *la {
+                END => PredSetSym::EndMarker,
+                la => PredSetSym::Sym(self.sym(la)),
+            };
+
+            predsets.insert(&self.sym(*lfsym), la, self.prod(*prod));
+        }
+
+        GramAnalysis {
+            gram: self.gram(),
+            fstsets,
+            follsets,
+            predsets,
+            verbose: VerboseLv::V0,
+        }
+    }
+
+    /// 语法里全部的符号名
+    pub fn interner(&self) -> Interner {
+        let mut interner = Interner::new();
+
+        for i in 0..self.syms.len() {
+            interner.intern(&self.sym(i).name());
+        }
+
+        interner
+    }
+}
+
+
+impl LL1Parser {
+    pub fn from_compiled(compiled: &CompiledGram) -> Self {
+        Self::from_analysis(compiled.analysis())
+    }
+}
+
+
+/// 读取DSL写的语法文件， 检查通过后在`out_dir`下生成`<文件名>.rs`，
+/// 里面是名为`<文件名的大写>`的`CompiledGram`常量， 返回生成的文件
+///
+/// 给build.rs用， 同时输出`cargo:rerun-if-changed`
+pub fn compile<P: AsRef<Path>, Q: AsRef<Path>>(gram_path: P, out_dir: Q) -> Result<PathBuf, Box<dyn Error>> {
+    let gram_path = gram_path.as_ref();
+    let src = fs::read_to_string(gram_path)?;
+
+    let gram = GramBuilder::from_dsl(&src)?
+        .build()
+        .map_err(|diags| Trap::new_box_err(&format!("{}:\n{}", gram_path.display(), diags)))?;
+
+    let stem = gram_path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| Trap::new_box_err(&format!("bad grammar path {}", gram_path.display())))?;
+
+    let const_name = stem
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect::<String>();
+
+    let out_path = out_dir.as_ref().join(format!("{}.rs", stem));
+    fs::write(&out_path, generate(&gram, &const_name, &gram_path.display().to_string()))?;
+
+    println!("cargo:rerun-if-changed={}", gram_path.display());
+
+    Ok(out_path)
+}
+
+
+/// 生成`pub const <const_name>: CompiledGram`的源码， 语法需要已经检查过
+pub fn generate(gram: &Gram, const_name: &str, source: &str) -> String {
+    let analysis = GramAnalysis::new(gram.clone());
+
+    let mut syms: IndexSet<GramSym> = gram.syms().into_iter().collect();
+    let mut index = |sym: &GramSym| -> usize { syms.insert_full(sym.clone()).0 };
+
+    let prods = gram.productions().cloned().collect::<Vec<GramProd>>();
+    let prod_index: IndexMap<&GramProd, usize> = prods.iter().enumerate().map(|(i, prod)| (prod, i)).collect();
+
+    let mut out = String::new();
+    let w = &mut out;
+
+    writeln!(w, "// generated by ll1engine::embed from {}, do not edit", source).unwrap();
+    writeln!(w, "#[allow(dead_code)]").unwrap();
+    writeln!(w, "pub const {}: ll1engine::embed::CompiledGram = ll1engine::embed::CompiledGram {{", const_name).unwrap();
+    writeln!(w, "    name: {:?},", gram.name()).unwrap();
+
+    let mut body = String::new();
+
+    writeln!(body, "    prods: &[").unwrap();
+    for prod in prods.iter() {
+        let rhs = match &prod.rhstr {
+            GramSymStr::Str(normal_str) => normal_str.iter().map(&mut index).collect(),
+            GramSymStr::Epsilon => vec![],
+        };
+        let attrs = prod
+            .attrs
+            .iter()
+            .map(|(key, value)| format!("({:?}, {:?})", key, value))
+            .collect::<Vec<String>>();
+
+        writeln!(
+            body,
+            "        ({}, &{:?}, &[{}]),",
+            index(&prod.lfsym),
+            rhs,
+            attrs.join(", ")
+        ).unwrap();
+    }
+    writeln!(body, "    ],").unwrap();
+
+    writeln!(body, "    docs: &[").unwrap();
+    for sym in gram.derivation_tree().keys() {
+        if let Some(doc) = gram.doc(sym) {
+            writeln!(body, "        ({}, {:?}),", index(sym), doc).unwrap();
+        }
+    }
+    writeln!(body, "    ],").unwrap();
+
+    writeln!(body, "    first: &[").unwrap();
+    for (sym, set) in analysis.first_sets().iter() {
+        let set = set
+            .iter()
+            .map(|fst| match fst {
+                FstSetSym::Sym(sym) => index_literal(index(sym)),
+                FstSetSym::Epsilon => index_literal(END),
+            })
+            .collect::<Vec<String>>();
+
+        writeln!(body, "        ({}, &[{}]),", index(sym), set.join(", ")).unwrap();
+    }
+    writeln!(body, "    ],").unwrap();
+
+    writeln!(body, "    follow: &[").unwrap();
+    for (sym, set) in analysis.follow_sets().iter() {
+        let set = set
+            .iter()
+            .map(|foll| match foll {
+                FollSetSym::Sym(sym) => index_literal(index(sym)),
+                FollSetSym::EndMarker => index_literal(END),
+            })
+            .collect::<Vec<String>>();
+
+        writeln!(body, "        ({}, &[{}]),", index(sym), set.join(", ")).unwrap();
+    }
+    writeln!(body, "    ],").unwrap();
+
+    writeln!(body, "    table: &[").unwrap();
+    for (lfsym, deriv_map) in analysis.prediction_sets().iter() {
+        for (la, prod) in deriv_map.iter() {
+            let la = match la {
+                PredSetSym::Sym(sym) => index_literal(index(sym)),
+                PredSetSym::EndMarker => index_literal(END),
+            };
+
+            writeln!(body, "        ({}, {}, {}),", index(lfsym), la, prod_index[prod]).unwrap();
+        }
+    }
+    writeln!(body, "    ],").unwrap();
+
+    // 符号表最后才完整
+    writeln!(w, "    syms: &[").unwrap();
+    for sym in syms.iter() {
+        writeln!(w, "        {},", compiled_sym(sym)).unwrap();
+    }
+    writeln!(w, "    ],").unwrap();
+
+    out.push_str(&body);
+    out.push_str("};\n");
+
+    out
+}
+
+fn compiled_sym(sym: &GramSym) -> String {
+    match sym {
+        GramSym::Terminal(name) => format!("ll1engine::embed::CompiledSym::Terminal({:?})", name),
+        GramSym::NonTerminal(name) => format!("ll1engine::embed::CompiledSym::NonTerminal({:?})", name),
+        GramSym::Eof => "ll1engine::embed::CompiledSym::Eof".to_string(),
+        GramSym::Any => "ll1engine::embed::CompiledSym::Any".to_string(),
+        GramSym::NotSet(names) => format!("ll1engine::embed::CompiledSym::NotSet(&{:?})", names),
+        GramSym::Guarded(name, value) => format!("ll1engine::embed::CompiledSym::Guarded({:?}, {:?})", name, value),
+        GramSym::CharClass(class) => {
+            format!("ll1engine::embed::CompiledSym::CharClass({}, &{:?})", class.negated, class.ranges)
+        },
+    }
+}
+
+fn index_literal(i: usize) -> String {
+    if i == END {
+        "ll1engine::embed::END".to_string()
+    }
+    else {
+        i.to_string()
+    }
+}
@@ -1,6 +1,14 @@
 use std::error::Error;
 use std::fmt;
 
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use crate::gram::{GramSym, PredSetSym};
+use crate::parser::{SrcLoc, Token};
+
+
+/// 用户输入(语法、 源码、 序列化数据)引起的失败都通过它返回， panic只留给内部不变量
+pub type LlResult<T> = Result<T, Box<dyn Error>>;
+
 
 #[derive(Debug)]
 pub struct Trap {
@@ -29,15 +37,56 @@ impl Trap {
 }
 
 
+/// `LL1ParserBuilder::build`检查语法失败， 带着全部的诊断(包括警告)
+#[derive(Debug, Clone)]
+pub struct GrammarError {
+    diags: Diagnostics,
+}
+
+impl GrammarError {
+    pub fn new(diags: Diagnostics) -> Self {
+        Self { diags }
+    }
+
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diags
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diags.errors()
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diags.warnings()
+    }
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.diags)
+    }
+}
+
+impl Error for GrammarError {}
+
+
 #[derive(Debug)]
 pub enum TrapCode<'a> {
-    AmbigousLLRule(&'a str)
+    AmbigousLLRule(&'a str),
+    UnrecognizedToken(&'a str),
+    /// 重命名后的名字已被占用
+    SymCollision(&'a str),
+    /// 语法为空或者引用了未定义的非终结符， 没法计算预测表
+    MalformedGram(&'a str),
 }
 
 impl<'a> TrapCode<'a> {
     pub fn emit_box_err(&self) -> Box<dyn Error> {
         match self {
-            Self::AmbigousLLRule(msg) => {
+            Self::AmbigousLLRule(msg)
+            | Self::UnrecognizedToken(msg)
+            | Self::SymCollision(msg)
+            | Self::MalformedGram(msg) => {
                 Trap::new_box_err(
                     msg
                 )
@@ -52,3 +101,111 @@ impl<'a> TrapCode<'a> {
         }
     }
 }
+
+
+////////////////////////////////////////////////////////////////////////////////
+////// Parse Error
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    EmptyTokens,
+    /// 语法没有产生式
+    EmptyGram,
+    /// 没有可以预测的产生式
+    UnexpectedToken,
+    /// 终结符不匹配
+    UnmatchedToken,
+    UnfinishedProd,
+    TokensRemain,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    kind: ParseErrorKind,
+    msg: String,
+    /// 出错处的token， 输入耗尽时为None； 装箱让`Result<_, ParseError>`不至于太大
+    token: Option<Box<Token>>,
+    /// 出错处可以接受的向前看符号
+    expected: Vec<PredSetSym>,
+    /// 类似"did you mean `return`?"的提示
+    hints: Vec<String>,
+    /// 出错时还在展开的非终结符， 从外到内
+    backtrace: Vec<GramSym>,
+}
+
+impl ParseError {
+    pub fn new(
+        kind: ParseErrorKind,
+        msg: &str,
+        token: Option<Token>,
+        expected: Vec<PredSetSym>,
+    ) -> Self {
+        Self {
+            kind,
+            msg: msg.to_string(),
+            token: token.map(Box::new),
+            expected,
+            hints: vec![],
+            backtrace: vec![],
+        }
+    }
+
+    pub fn kind(&self) -> &ParseErrorKind {
+        &self.kind
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    pub fn token(&self) -> Option<&Token> {
+        self.token.as_deref()
+    }
+
+    pub fn loc(&self) -> Option<SrcLoc> {
+        self.token.as_ref().map(|token| token.loc())
+    }
+
+    pub fn expected(&self) -> &[PredSetSym] {
+        &self.expected
+    }
+
+    pub fn hints(&self) -> &[String] {
+        &self.hints
+    }
+
+    pub fn add_hint(&mut self, hint: &str) {
+        self.hints.push(hint.to_string());
+    }
+
+    pub fn backtrace(&self) -> &[GramSym] {
+        &self.backtrace
+    }
+
+    pub fn set_backtrace(&mut self, backtrace: Vec<GramSym>) {
+        self.backtrace = backtrace;
+    }
+
+    /// 形如`File > FnDef > Block > Stmt`
+    pub fn backtrace_str(&self) -> String {
+        self.backtrace.iter().map(|sym| sym.name()).collect::<Vec<_>>().join(" > ")
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.msg)?;
+
+        if !self.backtrace.is_empty() {
+            write!(f, "\n  in: {}", self.backtrace_str())?;
+        }
+
+        for hint in self.hints.iter() {
+            write!(f, "\n  help: {}", hint)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for ParseError {}
@@ -0,0 +1,98 @@
+//! 事件模式(SAX风格)的解析， 不建树， 内存只和嵌套深度有关；
+//! 丢弃模式的解析， 指定的子树用完就扔
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    rc::Rc,
+};
+
+use crate::{
+    error::ParseError,
+    parser::{LL1ParseMachine, LL1Parser, ParseEvent, ParseOptions, Token, AST},
+};
+
+
+impl LL1Parser {
+    /// 把解析过程依次交给callback， Result: Errors
+    pub fn parse_events<F>(&self, tokens: Vec<Token>, options: &ParseOptions, callback: F)
+    -> Vec<ParseError>
+    where F: FnMut(ParseEvent)
+    {
+        let mut machine = self.machine(options).with_sink(callback);
+
+        for token in tokens {
+            if machine.feed(token).is_err() {
+                break;
+            }
+        }
+
+        machine.finish().1
+    }
+
+    /// 丢弃模式： `names`里的非终结符的子树完成后马上交给callback， 然后从树上摘掉，
+    /// 只需要汇总结果时， 树占用的内存只和嵌套深度有关(已读入的token仍然保留)。
+    /// Result: <ASTRoot, Errors>， 返回的树里不再有这些子树
+    pub fn parse_discard<I, F>(&self, tokens: I, options: &ParseOptions, names: &[&str], callback: F)
+    -> (Rc<RefCell<AST>>, Vec<ParseError>)
+    where I: IntoIterator<Item = Token>, F: FnMut(Rc<RefCell<AST>>)
+    {
+        let mut machine = self.machine(options).with_discard(names, callback);
+
+        for token in tokens {
+            if machine.feed(token).is_err() {
+                break;
+            }
+        }
+
+        machine.finish()
+    }
+
+    /// 惰性的事件迭代器， 按需从tokens里取token
+    pub fn events<I>(&self, tokens: I, options: &ParseOptions) -> ParseEvents<'_, I::IntoIter>
+    where I: IntoIterator<Item = Token>
+    {
+        let queue = Rc::new(RefCell::new(VecDeque::new()));
+        let sink_queue = queue.clone();
+
+        let machine = self.machine(options).with_sink(move |event| {
+            sink_queue.as_ref().borrow_mut().push_back(event)
+        });
+
+        ParseEvents {
+            machine: Some(machine),
+            tokens: tokens.into_iter(),
+            queue,
+        }
+    }
+}
+
+
+pub struct ParseEvents<'a, I> {
+    machine: Option<LL1ParseMachine<'a>>,
+    tokens: I,
+    queue: Rc<RefCell<VecDeque<ParseEvent>>>,
+}
+
+impl<'a, I: Iterator<Item = Token>> Iterator for ParseEvents<'a, I> {
+    type Item = ParseEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.queue.as_ref().borrow_mut().pop_front() {
+                return Some(event);
+            }
+
+            let machine = self.machine.as_mut()?;
+
+            match self.tokens.next() {
+                Some(token) if !machine.is_failed() => {
+                    let _ = machine.feed(token);
+                },
+                _ => {
+                    self.machine.take().unwrap().finish();
+                }
+            }
+        }
+    }
+}
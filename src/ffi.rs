@@ -0,0 +1,517 @@
+//! C FFI: 通过稳定的C ABI提供语法装载、 解析和AST遍历， 供C/C++/Python宿主嵌入，
+//! 需要开启`ffi`特性， 头文件是`include/ll1engine.h`
+//!
+//! ```none
+//! cargo rustc --lib --release --features ffi --crate-type cdylib
+//! ```
+//!
+//! 句柄都是不透明指针， 用对应的`*_free`释放； 字符串都是UTF-8、 以NUL结尾，
+//! 返回的字符串归句柄所有， 句柄释放前有效。 出错的调用返回非零的`FfiStatus`，
+//! 错误信息用`ll1_last_error`取(每个线程一份)。
+//!
+//! 解析的结果展平成节点表， 节点用下标表示， 0是根， 没有的节点是`LL1_NONE`
+
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+
+use crate::{
+    builder::GramBuilder,
+    error::LlResult,
+    lexer::{Lexer, RegexTokenMatcher},
+    parser::{FlatNode, LL1Parser, ParseOptions, AST},
+};
+
+
+/// 不存在的节点
+pub const LL1_NONE: usize = usize::MAX;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FfiStatus {
+    Ok = 0,
+    NullArg = 1,
+    InvalidUtf8 = 2,
+    /// DSL解析失败或者语法不良构
+    GrammarError = 3,
+    /// 非法的正则
+    PatternError = 4,
+    /// 源码里有不能识别的字符
+    LexError = 5,
+    OutOfRange = 6,
+    /// Rust代码panic了， 没有穿过ABI边界
+    Panic = 7,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+type FfiResult<T> = Result<T, (FfiStatus, String)>;
+
+
+pub struct FfiParser {
+    parser: LL1Parser,
+}
+
+pub struct FfiLexer {
+    rules: Vec<(RegexTokenMatcher, String)>,
+    skip: Vec<String>,
+    lexer: Lexer,
+}
+
+pub struct FfiTree {
+    nodes: Vec<FlatNode>,
+    names: Vec<CString>,
+    values: Vec<Option<CString>>,
+    errors: Vec<CString>,
+}
+
+impl FfiLexer {
+    /// 编译失败时保留原来的词法分析器
+    fn rebuild(&mut self) -> LlResult<()> {
+        let mut lexer = Lexer::new(self.rules.clone());
+        for name in self.skip.iter() {
+            lexer = lexer.with_skip(name);
+        }
+
+        self.lexer = lexer.compile()?;
+        Ok(())
+    }
+}
+
+impl FfiTree {
+    fn new(root: &AST) -> Self {
+        let nodes = root.flatten();
+        let names = nodes.iter().map(|node| cstring(&node.name)).collect();
+        let values = nodes.iter().map(|node| node.value.as_deref().map(cstring)).collect();
+
+        Self { nodes, names, values, errors: vec![] }
+    }
+
+    fn node(&self, node: usize) -> Option<&FlatNode> {
+        self.nodes.get(node)
+    }
+}
+
+
+fn cstring(s: &str) -> CString {
+    CString::new(s.replace('\0', "")).unwrap()
+}
+
+fn set_last_error(msg: &str) {
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(cstring(msg)));
+}
+
+unsafe fn read_str<'a>(s: *const c_char) -> FfiResult<&'a str> {
+    if s.is_null() {
+        return Err((FfiStatus::NullArg, "null string argument".to_string()));
+    }
+
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|err| (FfiStatus::InvalidUtf8, err.to_string()))
+}
+
+unsafe fn read_ref<'a, T>(handle: *const T) -> FfiResult<&'a T> {
+    handle.as_ref().ok_or_else(|| (FfiStatus::NullArg, "null handle".to_string()))
+}
+
+/// 捕获panic并记录错误信息
+fn guard<F: FnOnce() -> FfiResult<()>>(f: F) -> FfiStatus {
+    let res = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let msg = payload
+            .downcast_ref::<&str>()
+            .map(|msg| msg.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "panic".to_string());
+
+        Err((FfiStatus::Panic, msg))
+    });
+
+    match res {
+        Ok(()) => FfiStatus::Ok,
+        Err((status, msg)) => {
+            set_last_error(&msg);
+            status
+        }
+    }
+}
+
+unsafe fn write_out<T>(out: *mut *mut T, value: T) -> FfiResult<()> {
+    if out.is_null() {
+        return Err((FfiStatus::NullArg, "null out pointer".to_string()));
+    }
+
+    *out = Box::into_raw(Box::new(value));
+    Ok(())
+}
+
+
+/// 当前线程最后一次出错的信息， 没有时返回NULL， 下一次出错前有效
+#[no_mangle]
+pub extern "C" fn ll1_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |msg| msg.as_ptr()))
+}
+
+/// 从`grammar![name| ... |]`形式的DSL文本构建解析器
+///
+/// # Safety
+///
+/// `src`是以NUL结尾的字符串， `out`可写
+#[no_mangle]
+pub unsafe extern "C" fn ll1_parser_from_dsl(src: *const c_char, out: *mut *mut FfiParser) -> FfiStatus {
+    guard(|| {
+        let src = read_str(src)?;
+        let gram = GramBuilder::from_dsl(src)
+            .map_err(|err| (FfiStatus::GrammarError, err.to_string()))?
+            .build()
+            .map_err(|diags| (FfiStatus::GrammarError, diags.to_string()))?;
+        let parser = LL1Parser::try_new(gram).map_err(|err| (FfiStatus::GrammarError, err.to_string()))?;
+
+        write_out(out, FfiParser { parser })
+    })
+}
+
+/// # Safety
+///
+/// `parser`是`ll1_parser_from_dsl`返回的句柄或者NULL， 只能释放一次
+#[no_mangle]
+pub unsafe extern "C" fn ll1_parser_free(parser: *mut FfiParser) {
+    if !parser.is_null() {
+        drop(Box::from_raw(parser));
+    }
+}
+
+/// 没有规则的词法分析器
+#[no_mangle]
+pub extern "C" fn ll1_lexer_new() -> *mut FfiLexer {
+    Box::into_raw(Box::new(FfiLexer {
+        rules: vec![],
+        skip: vec![],
+        lexer: Lexer::new(vec![]),
+    }))
+}
+
+/// 追加一条token规则， 声明顺序就是优先级， `skip`非零时匹配的token被丢弃(空白、 注释)
+///
+/// # Safety
+///
+/// `lexer`是`ll1_lexer_new`返回的句柄， `name`和`pattern`是以NUL结尾的字符串
+#[no_mangle]
+pub unsafe extern "C" fn ll1_lexer_add_rule(
+    lexer: *mut FfiLexer,
+    name: *const c_char,
+    pattern: *const c_char,
+    skip: c_int,
+) -> FfiStatus {
+    guard(|| {
+        let lexer = lexer.as_mut().ok_or_else(|| (FfiStatus::NullArg, "null handle".to_string()))?;
+        let name = read_str(name)?;
+        let pattern = read_str(pattern)?;
+
+        let matcher = RegexTokenMatcher::try_new(pattern)
+            .map_err(|err| (FfiStatus::PatternError, err.to_string()))?;
+
+        lexer.rules.push((matcher, name.to_string()));
+        if skip != 0 {
+            lexer.skip.push(name.to_string());
+        }
+
+        // 规则太多， 编译不下时撤销这条规则
+        if let Err(err) = lexer.rebuild() {
+            lexer.rules.pop();
+            if skip != 0 {
+                lexer.skip.pop();
+            }
+            return Err((FfiStatus::PatternError, err.to_string()));
+        }
+
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// `lexer`是`ll1_lexer_new`返回的句柄或者NULL， 只能释放一次
+#[no_mangle]
+pub unsafe extern "C" fn ll1_lexer_free(lexer: *mut FfiLexer) {
+    if !lexer.is_null() {
+        drop(Box::from_raw(lexer));
+    }
+}
+
+/// 切分并解析`src`， 语法错误会被恢复， 仍然返回`Ok`和一棵树， 错误用`ll1_tree_error`取
+///
+/// # Safety
+///
+/// `parser`和`lexer`是有效的句柄， `src`是以NUL结尾的字符串， `out`可写
+#[no_mangle]
+pub unsafe extern "C" fn ll1_parse(
+    parser: *const FfiParser,
+    lexer: *const FfiLexer,
+    src: *const c_char,
+    out: *mut *mut FfiTree,
+) -> FfiStatus {
+    guard(|| {
+        let parser = read_ref(parser)?;
+        let lexer = read_ref(lexer)?;
+        let src = read_str(src)?;
+
+        let tokens = lexer
+            .lexer
+            .tokenize_str(src)
+            .map_err(|err| (FfiStatus::LexError, err.to_string()))?;
+        let (root, errors) = parser.parser.parse_with(tokens, &ParseOptions::default());
+
+        let mut tree = FfiTree::new(&root.as_ref().borrow());
+        tree.errors = errors.iter().map(|err| cstring(&err.to_string())).collect();
+
+        write_out(out, tree)
+    })
+}
+
+/// # Safety
+///
+/// `tree`是`ll1_parse`返回的句柄或者NULL， 只能释放一次
+#[no_mangle]
+pub unsafe extern "C" fn ll1_tree_free(tree: *mut FfiTree) {
+    if !tree.is_null() {
+        drop(Box::from_raw(tree));
+    }
+}
+
+/// # Safety
+///
+/// `tree`是有效的句柄
+#[no_mangle]
+pub unsafe extern "C" fn ll1_tree_error_count(tree: *const FfiTree) -> usize {
+    tree.as_ref().map_or(0, |tree| tree.errors.len())
+}
+
+/// 第`i`个语法错误的信息， 越界时返回NULL
+///
+/// # Safety
+///
+/// `tree`是有效的句柄
+#[no_mangle]
+pub unsafe extern "C" fn ll1_tree_error(tree: *const FfiTree, i: usize) -> *const c_char {
+    tree.as_ref()
+        .and_then(|tree| tree.errors.get(i))
+        .map_or(ptr::null(), |msg| msg.as_ptr())
+}
+
+/// 节点总数， 节点的下标是`0..count`
+///
+/// # Safety
+///
+/// `tree`是有效的句柄
+#[no_mangle]
+pub unsafe extern "C" fn ll1_tree_node_count(tree: *const FfiTree) -> usize {
+    tree.as_ref().map_or(0, |tree| tree.nodes.len())
+}
+
+/// 非终结符名或者token名， 节点不存在时返回NULL
+///
+/// # Safety
+///
+/// `tree`是有效的句柄
+#[no_mangle]
+pub unsafe extern "C" fn ll1_node_name(tree: *const FfiTree, node: usize) -> *const c_char {
+    tree.as_ref()
+        .and_then(|tree| tree.names.get(node))
+        .map_or(ptr::null(), |name| name.as_ptr())
+}
+
+/// 叶子的token值， 子树或者节点不存在时返回NULL
+///
+/// # Safety
+///
+/// `tree`是有效的句柄
+#[no_mangle]
+pub unsafe extern "C" fn ll1_node_value(tree: *const FfiTree, node: usize) -> *const c_char {
+    tree.as_ref()
+        .and_then(|tree| tree.values.get(node))
+        .and_then(|value| value.as_ref())
+        .map_or(ptr::null(), |value| value.as_ptr())
+}
+
+/// 叶子返回1， 子树返回0， 节点不存在时返回-1
+///
+/// # Safety
+///
+/// `tree`是有效的句柄
+#[no_mangle]
+pub unsafe extern "C" fn ll1_node_is_leaf(tree: *const FfiTree, node: usize) -> c_int {
+    tree.as_ref()
+        .and_then(|tree| tree.node(node))
+        .map_or(-1, |node| node.value.is_some() as c_int)
+}
+
+/// # Safety
+///
+/// `tree`是有效的句柄
+#[no_mangle]
+pub unsafe extern "C" fn ll1_node_child_count(tree: *const FfiTree, node: usize) -> usize {
+    tree.as_ref()
+        .and_then(|tree| tree.node(node))
+        .map_or(0, |node| node.children.len())
+}
+
+/// 第`i`个子节点， 越界时返回`LL1_NONE`
+///
+/// # Safety
+///
+/// `tree`是有效的句柄
+#[no_mangle]
+pub unsafe extern "C" fn ll1_node_child(tree: *const FfiTree, node: usize, i: usize) -> usize {
+    tree.as_ref()
+        .and_then(|tree| tree.node(node))
+        .and_then(|node| node.children.get(i).cloned())
+        .unwrap_or(LL1_NONE)
+}
+
+/// 根节点或者节点不存在时返回`LL1_NONE`
+///
+/// # Safety
+///
+/// `tree`是有效的句柄
+#[no_mangle]
+pub unsafe extern "C" fn ll1_node_parent(tree: *const FfiTree, node: usize) -> usize {
+    tree.as_ref()
+        .and_then(|tree| tree.node(node))
+        .and_then(|node| node.parent)
+        .unwrap_or(LL1_NONE)
+}
+
+/// 节点的位置(行、 列从1开始)， 没有token的子树返回`OutOfRange`
+///
+/// # Safety
+///
+/// `tree`是有效的句柄， `ln`和`col`可写
+#[no_mangle]
+pub unsafe extern "C" fn ll1_node_loc(tree: *const FfiTree, node: usize, ln: *mut usize, col: *mut usize) -> FfiStatus {
+    guard(|| {
+        let tree = read_ref(tree)?;
+        if ln.is_null() || col.is_null() {
+            return Err((FfiStatus::NullArg, "null out pointer".to_string()));
+        }
+
+        let loc = tree
+            .node(node)
+            .and_then(|node| node.loc.as_ref())
+            .ok_or_else(|| (FfiStatus::OutOfRange, format!("node {} has no location", node)))?;
+
+        *ln = loc.ln;
+        *col = loc.col;
+
+        Ok(())
+    })
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    unsafe fn text<'a>(s: *const c_char) -> &'a str {
+        assert!(!s.is_null());
+        CStr::from_ptr(s).to_str().unwrap()
+    }
+
+    #[test]
+    fn test_ffi_errors() {
+        unsafe {
+            let lexer = ll1_lexer_new();
+
+            let status = ll1_lexer_add_rule(lexer, c("lp").as_ptr(), c("(").as_ptr(), 0);
+            assert_eq!(status, FfiStatus::PatternError);
+            assert!(text(ll1_last_error()).contains("regex"));
+
+            let status = ll1_lexer_add_rule(ptr::null_mut(), c("id").as_ptr(), c("[a-z]+").as_ptr(), 0);
+            assert_eq!(status, FfiStatus::NullArg);
+            assert_eq!(text(ll1_last_error()), "null handle");
+
+            let mut parser = ptr::null_mut();
+            let status = ll1_parser_from_dsl(c("grammar![bad| S: | a; | a b; |]").as_ptr(), &mut parser);
+            assert_eq!(status, FfiStatus::GrammarError);
+            assert!(parser.is_null());
+            assert!(text(ll1_last_error()).contains("ll1-conflict"));
+
+            let mut tree = ptr::null_mut();
+            let status = ll1_parse(ptr::null(), lexer, c("x").as_ptr(), &mut tree);
+            assert_eq!(status, FfiStatus::NullArg);
+            assert!(tree.is_null());
+
+            // 空句柄上的查询不会崩溃
+            assert_eq!(ll1_tree_node_count(ptr::null()), 0);
+            assert!(ll1_node_name(ptr::null(), 0).is_null());
+            assert_eq!(ll1_node_child(ptr::null(), 0, 0), LL1_NONE);
+
+            ll1_lexer_free(lexer);
+        }
+    }
+
+    #[test]
+    fn test_ffi_traverse() {
+        unsafe {
+            let mut parser = ptr::null_mut();
+            let status = ll1_parser_from_dsl(c("grammar![assign| S: | id eq num; |]").as_ptr(), &mut parser);
+            assert_eq!(status, FfiStatus::Ok);
+
+            let lexer = ll1_lexer_new();
+            for (name, pattern, skip) in [("id", "[a-z]+", 0), ("eq", "=", 0), ("num", "[0-9]+", 0), ("ws", r"\s+", 1)] {
+                assert_eq!(ll1_lexer_add_rule(lexer, c(name).as_ptr(), c(pattern).as_ptr(), skip), FfiStatus::Ok);
+            }
+
+            let mut tree = ptr::null_mut();
+            assert_eq!(ll1_parse(parser, lexer, c("x = 42").as_ptr(), &mut tree), FfiStatus::Ok);
+            assert_eq!(ll1_tree_error_count(tree), 0);
+            assert!(ll1_tree_error(tree, 0).is_null());
+
+            assert_eq!(text(ll1_node_name(tree, 0)), "S");
+            assert_eq!(ll1_node_is_leaf(tree, 0), 0);
+            assert_eq!(ll1_node_parent(tree, 0), LL1_NONE);
+            assert!(ll1_node_value(tree, 0).is_null());
+            assert_eq!(ll1_node_child_count(tree, 0), 3);
+            assert_eq!(ll1_node_child(tree, 0, 3), LL1_NONE);
+
+            let leaves = (0..3).map(|i| ll1_node_child(tree, 0, i)).collect::<Vec<usize>>();
+            for (leaf, (name, value)) in leaves.iter().zip([("id", "x"), ("eq", "="), ("num", "42")]) {
+                assert_eq!(ll1_node_parent(tree, *leaf), 0);
+                assert_eq!(ll1_node_is_leaf(tree, *leaf), 1);
+                assert_eq!(text(ll1_node_name(tree, *leaf)), name);
+                assert_eq!(text(ll1_node_value(tree, *leaf)), value);
+            }
+
+            let (mut ln, mut col) = (0, 0);
+            assert_eq!(ll1_node_loc(tree, leaves[2], &mut ln, &mut col), FfiStatus::Ok);
+            assert_eq!((ln, col), (1, 5));
+
+            let count = ll1_tree_node_count(tree);
+            assert_eq!(count, 4);
+            assert_eq!(ll1_node_is_leaf(tree, count), -1);
+            assert_eq!(ll1_node_loc(tree, count, &mut ln, &mut col), FfiStatus::OutOfRange);
+
+            // 有语法错误时仍然返回树
+            let mut bad_tree = ptr::null_mut();
+            assert_eq!(ll1_parse(parser, lexer, c("x 42").as_ptr(), &mut bad_tree), FfiStatus::Ok);
+            assert_eq!(ll1_tree_error_count(bad_tree), 1);
+            assert!(!text(ll1_tree_error(bad_tree, 0)).is_empty());
+
+            ll1_tree_free(bad_tree);
+            ll1_tree_free(tree);
+            ll1_lexer_free(lexer);
+            ll1_parser_free(parser);
+        }
+    }
+}
@@ -1,52 +1,137 @@
 //! Meta Grammar Processor
 
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::fmt;
 use std::fmt::Write;
-use std::error::Error;
+use std::hash::{Hash, Hasher};
 
 use indexmap::{IndexMap, IndexSet, indexmap, indexset};
 use itertools::Itertools;
 
-use crate::{VERBOSE, VerboseLv, error::TrapCode};
+use crate::error::{LlResult, Trap, TrapCode};
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use crate::scannerless::CharClass;
+use crate::VerboseLv;
 
 ////////////////////////////////////////////////////////////////////////////////
-//// Grammar Symbol
+////// Grammar Symbol
+
+/// yacc风格的错误恢复终结符， 只出现在错误产生式`A -> error α`的开头
+pub const ERROR_SYM_NAME: &str = "error";
+
+/// `GramSym::Eof`在DSL里的写法
+pub const EOF_SYM_NAME: &str = "$";
+
+/// `GramSym::Any`在DSL里的写法
+pub const ANY_SYM_NAME: &str = "_";
 
 #[derive(Hash, PartialEq, Eq, Clone, Debug)]
 pub enum GramSym {
     Terminal(String),
     NonTerminal(String),
+    /// 输入结束`$`， 可以写在规则里， 对应的向前看符号就是`PredSetSym::EndMarker`
+    Eof,
+    /// 通配符`_`， 匹配任意一个token
+    Any,
+    /// `~{semi rbrace}`， 匹配不在集合里的任意token， 用来写"跳过直到..."这样的规则
+    NotSet(Vec<String>),
+    /// 带值约束的终结符`name("value")`: (token名, 值)， 只匹配值恰好是`value`的`name` token，
+    /// 向前看时优先于不带约束的`name`
+    Guarded(String, String),
+    /// 字符类`[a-z_]`， 匹配值是类里一个字符的token， 见`scannerless`
+    CharClass(CharClass),
 }
 
 impl GramSym {
-    pub fn name(&self) -> &str {
+    /// 特殊的终结符返回DSL里的写法
+    pub fn name(&self) -> Cow<'_, str> {
         match self {
-            Self::Terminal(name) => name,
-            Self::NonTerminal(name) => name,
+            Self::Terminal(name) | Self::NonTerminal(name) => Cow::Borrowed(name),
+            Self::Eof => Cow::Borrowed(EOF_SYM_NAME),
+            Self::Any => Cow::Borrowed(ANY_SYM_NAME),
+            Self::NotSet(names) => Cow::Owned(format!("~{{{}}}", names.join(" "))),
+            Self::Guarded(name, value) => Cow::Owned(format!("{}(\"{}\")", name, value)),
+            Self::CharClass(class) => Cow::Owned(class.to_string()),
         }
     }
 
     pub fn is_terminal(&self) -> bool {
+        !self.is_nonterminal()
+    }
+
+    pub fn is_nonterminal(&self) -> bool {
+        matches!(self, Self::NonTerminal(_))
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Self::Terminal(name) if name == ERROR_SYM_NAME)
+    }
+
+    pub fn guarded(name: &str, value: &str) -> Self {
+        Self::Guarded(name.to_string(), value.to_string())
+    }
+
+    /// 值约束: (token名, 值)
+    pub fn guard(&self) -> Option<(&str, &str)> {
         match self {
-            Self::Terminal(_) => true,
-            Self::NonTerminal(_) => false,
+            Self::Guarded(name, value) => Some((name, value)),
+            _ => None,
         }
     }
 
-    pub fn is_nonterminal(&self) -> bool {
-        !self.is_terminal()
+    /// 去掉值约束后的token名
+    pub fn token_name(&self) -> Cow<'_, str> {
+        match self {
+            Self::Guarded(name, _value) => Cow::Borrowed(name),
+            _ => self.name(),
+        }
+    }
+
+    pub fn not_set<I, S>(names: I) -> Self
+    where I: IntoIterator<Item = S>, S: AsRef<str>
+    {
+        Self::NotSet(names.into_iter().map(|name| name.as_ref().to_string()).collect())
+    }
+
+    pub fn is_eof(&self) -> bool {
+        matches!(self, Self::Eof)
+    }
+
+    pub fn is_any(&self) -> bool {
+        matches!(self, Self::Any)
+    }
+
+    /// `~{...}`排除的token名
+    pub fn excluded(&self) -> Option<&[String]> {
+        match self {
+            Self::NotSet(names) => Some(names),
+            _ => None,
+        }
+    }
+
+    pub fn is_char_class(&self) -> bool {
+        matches!(self, Self::CharClass(_))
+    }
+
+    /// `_`、 `~{...}`和字符类， 向前看时排在精确匹配的终结符之后
+    pub fn is_wildcard(&self) -> bool {
+        matches!(self, Self::Any | Self::NotSet(_) | Self::CharClass(_))
     }
 
     pub fn to_fst_set_sym(&self) -> FstSetSym {
-        FstSetSym::Sym(self.name().to_string())
+        FstSetSym::Sym(self.clone())
     }
 
     pub fn to_foll_set_sym(&self) -> FollSetSym {
-        FollSetSym::Sym(self.name().to_string())
+        FollSetSym::Sym(self.clone())
     }
 
     pub fn to_pred_set_sym(&self) -> PredSetSym {
-        PredSetSym::Sym(self.name().to_string())
+        match self {
+            Self::Eof => PredSetSym::EndMarker,
+            _ => PredSetSym::Sym(self.clone()),
+        }
     }
 }
 
@@ -64,7 +149,7 @@ impl fmt::Display for GramSym {
 
 
 ////////////////////////////////////////////////////////////////////////////////
-//// Grammar Symbol String
+////// Grammar Symbol String
 
 #[derive(Hash, PartialEq, Eq, Clone, Debug)]
 pub enum GramSymStr {
@@ -74,17 +159,11 @@ pub enum GramSymStr {
 
 impl GramSymStr {
     pub fn is_normal(&self) -> bool {
-        match self {
-            Self::Str(_) => true,
-            _ => false,
-        }
+        matches!(self, Self::Str(_))
     }
 
     pub fn is_epsilon(&self) -> bool {
-        match self {
-            Self::Epsilon => true,
-            _ => false,
-        }
+        matches!(self, Self::Epsilon)
     }
 
     pub fn get_normal(&self) -> Option<&Vec<GramSym>> {
@@ -121,12 +200,64 @@ impl fmt::Display for GramSymStr {
 
 
 ////////////////////////////////////////////////////////////////////////////////
-//// Grammar Production (derivation branch)
+////// Grammar Production (derivation branch)
+
+/// 产生式上的注解， 比如`#[deprecated]`, `#[collapse]`, `#[prec=3]`，
+/// 没有值的注解存为空串
+pub type ProdAttrs = IndexMap<String, String>;
+
+pub(crate) fn add_prod_attr(attrs: &mut ProdAttrs, key: &str, value: &str) {
+    let value = if key == "doc" { doc_line(value) } else { value };
+
+    match attrs.get_mut(key) {
+        Some(existed) => {
+            existed.push('\n');
+            existed.push_str(value);
+        },
+        None => {
+            attrs.insert(key.to_string(), value.to_string());
+        }
+    }
+}
 
-#[derive(Hash, PartialEq, Eq, Clone)]
+#[derive(Clone)]
 pub struct GramProd {
     pub lfsym: GramSym,
-    pub rhstr: GramSymStr
+    pub rhstr: GramSymStr,
+    /// 不参与比较和Hash
+    pub attrs: ProdAttrs,
+}
+
+impl GramProd {
+    /// 把两边出现的`old`换成`new`
+    pub(crate) fn rename_sym(mut self, old: &GramSym, new: &GramSym) -> Self {
+        if &self.lfsym == old {
+            self.lfsym = new.clone();
+        }
+
+        if let GramSymStr::Str(normal_str) = &mut self.rhstr {
+            for sym in normal_str.iter_mut().filter(|sym| *sym == old) {
+                *sym = new.clone();
+            }
+        }
+
+        self
+    }
+}
+
+impl PartialEq for GramProd {
+    fn eq(&self, other: &Self) -> bool {
+        self.lfsym == other.lfsym && self.rhstr == other.rhstr
+    }
+}
+
+impl Eq for GramProd {}
+
+impl Hash for GramProd {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.lfsym.hash(state);
+        self.rhstr.hash(state);
+    }
 }
 
 impl fmt::Display for GramProd {
@@ -142,41 +273,71 @@ impl fmt::Debug for GramProd {
 }
 
 impl GramProd {
+    pub fn new(lfsym: GramSym, rhstr: GramSymStr) -> Self {
+        Self {
+            lfsym,
+            rhstr,
+            attrs: indexmap! {},
+        }
+    }
+
+    pub fn with_attr(mut self, key: &str, value: &str) -> Self {
+        self.attrs.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// 重复的注解按行拼接， 用于多行的文档注释(`#[doc]`)
+    pub fn add_attr(&mut self, key: &str, value: &str) {
+        add_prod_attr(&mut self.attrs, key, value)
+    }
+
+    pub fn attr(&self, key: &str) -> Option<&str> {
+        self.attrs.get(key).map(|value| value.as_str())
+    }
+
+    pub fn has_attr(&self, key: &str) -> bool {
+        self.attrs.contains_key(key)
+    }
+
+    /// 形如`A -> error α`的错误产生式
+    pub fn is_error_prod(&self) -> bool {
+        match self.rhstr.get_normal() {
+            Some(normal) => normal.first().is_some_and(|sym| sym.is_error()),
+            None => false,
+        }
+    }
+
     pub fn lookahead(&self, fstsets: &FstSets, follsets: &FollSets) -> IndexSet<PredSetSym> {
         let mut res = indexset! {};
 
         match &self.rhstr {
             GramSymStr::Str(rhsym_vec) => {
-                let fstsym = rhsym_vec.first().unwrap();
-                let thefstset = fstsets.get(fstsym).unwrap();
+                // 集合不是由这个语法算出来时缺少的项当作空集
+                // 前面的符号能推出ε时继续看后面的， 整个串都能推出ε时加上Follow集
+                for rhsym in rhsym_vec.iter() {
+                    let thefstset = fstsets.get(rhsym).into_iter().flatten();
+
+                    res.extend(thefstset.clone().filter_map(|fstsym| fstsym.to_pred_set_sym()));
+
+                    if !thefstset.into_iter().any(|fstsym| fstsym.is_epsilon()) {
+                        return res;
+                    }
+                }
+
+                let thefollset
+                = follsets.get(&self.lfsym).into_iter().flatten();
 
                 res.extend(
-                    thefstset
-                    .iter()
-                    .map(|fstsym| {
-                        if let Some(predsetsym) = fstsym.to_pred_set_sym() {
-                            vec![predsetsym]
-                        }
-                        else {
-                            let thefollset
-                            = follsets.get(&self.lfsym).unwrap();
-
-                            thefollset
-                            .into_iter()
-                            .map(|follsym| follsym.to_pred_set_sym() )
-                            .collect_vec()
-                        }
-                    })
-                    .flatten()
+                thefollset
+                .map(|follsym| follsym.to_pred_set_sym())
                 );
             },
             GramSymStr::Epsilon => {
                 let thefollset
-                = follsets.get(&self.lfsym).unwrap();
+                = follsets.get(&self.lfsym).into_iter().flatten();
 
                 res.extend(
                 thefollset
-                .into_iter()
                 .map(|follsym| follsym.to_pred_set_sym())
                 );
             }
@@ -188,12 +349,27 @@ impl GramProd {
 
 
 ////////////////////////////////////////////////////////////////////////////////
-//// Grammar
+////// Grammar
+
+/// 语法的描述信息， 不参与解析， `GrammarRegistry`按扩展名和MIME类型挑选语法
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GramMeta {
+    pub version: Option<String>,
+    pub description: Option<String>,
+    /// 不带点， 比如`json`
+    pub extensions: Vec<String>,
+    /// 比如`application/json`
+    pub mime_types: Vec<String>,
+}
+
 
 #[derive(Debug, Clone)]
 pub struct Gram {
     name: String,
     prods: IndexSet<GramProd>,
+    /// 非终结符的文档
+    docs: IndexMap<GramSym, String>,
+    meta: GramMeta,
 }
 
 impl Gram {
@@ -201,6 +377,8 @@ impl Gram {
         Self {
             name: name.to_string(),
             prods: indexset! {},
+            docs: indexmap! {},
+            meta: GramMeta::default(),
         }
     }
 
@@ -208,15 +386,119 @@ impl Gram {
         &self.name
     }
 
+    pub fn meta(&self) -> &GramMeta {
+        &self.meta
+    }
+
+    pub fn meta_mut(&mut self) -> &mut GramMeta {
+        &mut self.meta
+    }
+
     // move method
     pub fn extend_gram(&mut self, income_gram: Gram) {
-        self.extend(income_gram.into_iter());
+        self.extend(income_gram);
     }
 
     pub fn insert_prod(&mut self, prod: GramProd) {
         self.prods.insert(prod);
     }
 
+    /// 用`alts`替换`sym`的全部分支， 规则保持在原来的位置(新规则追加在最后)，
+    /// 未改动的分支保留其属性； `alts`为空就是删除这条规则
+    pub fn update_rule(&mut self, sym: &GramSym, alts: Vec<GramSymStr>) {
+        let old_prods = std::mem::take(&mut self.prods);
+        let new_prods = alts
+            .into_iter()
+            .map(|rhstr| {
+                let prod = GramProd::new(sym.clone(), rhstr);
+
+                match old_prods.get(&prod) {
+                    Some(old_prod) => old_prod.clone(),
+                    None => prod,
+                }
+            })
+            .collect_vec();
+
+        let mut new_prods = Some(new_prods);
+        for prod in old_prods.into_iter() {
+            if &prod.lfsym == sym {
+                if let Some(new_prods) = new_prods.take() {
+                    self.prods.extend(new_prods);
+                }
+            } else {
+                self.prods.insert(prod);
+            }
+        }
+
+        if let Some(new_prods) = new_prods {
+            self.prods.extend(new_prods);
+        }
+    }
+
+    /// 重命名非终结符， 产生式两边的引用和规则文档跟着改， 规则的位置(包括开始符号)不变；
+    /// `old`不是已有的非终结符， 或者`new`已经是某个符号的名字时报错
+    pub fn rename_sym(&mut self, old: &GramSym, new: &str) -> LlResult<GramSym> {
+        if !self.nonterminals().any(|sym| sym == old) {
+            return Err(Trap::new_box_err(&format!("{} is not a nonterminal of {}", old, self.name)));
+        }
+        if let Some(sym) = self.symbols().find(|sym| sym.name() == new) {
+            return Err(TrapCode::SymCollision(&format!("{} already exists", sym)).emit_box_err());
+        }
+
+        let new_sym = GramSym::NonTerminal(new.to_string());
+
+        self.prods = std::mem::take(&mut self.prods)
+            .into_iter()
+            .map(|prod| prod.rename_sym(old, &new_sym))
+            .collect();
+
+        self.docs = std::mem::take(&mut self.docs)
+            .into_iter()
+            .map(|(sym, doc)| if &sym == old { (new_sym.clone(), doc) } else { (sym, doc) })
+            .collect();
+
+        Ok(new_sym)
+    }
+
+    pub fn docs(&self) -> &IndexMap<GramSym, String> {
+        &self.docs
+    }
+
+    pub fn doc(&self, sym: &GramSym) -> Option<&str> {
+        self.docs.get(sym).map(|doc| doc.as_str())
+    }
+
+    /// 追加一行文档
+    pub fn add_doc(&mut self, sym: &GramSym, line: &str) {
+        let doc = self.docs.entry(sym.clone()).or_default();
+
+        if !doc.is_empty() {
+            doc.push('\n');
+        }
+        doc.push_str(doc_line(line));
+    }
+
+    /// 给已有的产生式加上注解， 产生式不存在时返回false
+    pub fn set_prod_attr(&mut self, prod: &GramProd, key: &str, value: &str) -> bool {
+        match self.prods.get(prod) {
+            Some(existed) => {
+                let updated = existed.clone().with_attr(key, value);
+                self.prods.replace(updated);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// 标记为`#[deprecated]`， 解析时匹配到会给出警告
+    pub fn deprecate(&mut self, prod: &GramProd, note: &str) -> bool {
+        self.set_prod_attr(prod, "deprecated", note)
+    }
+
+    pub fn deprecation(&self, prod: &GramProd) -> Option<&str> {
+        self.prods.get(prod).and_then(|prod| prod.attr("deprecated"))
+    }
+
     pub fn get_prod_index(&self, prod_ind: usize) -> Option<&GramProd> {
         self.prods.get_index(prod_ind)
     }
@@ -252,14 +534,14 @@ impl Gram {
             all_syms.insert(prod.lfsym.clone());
 
             if let GramSymStr::Str(normal_str) = &prod.rhstr {
-                all_syms.extend(normal_str.clone().into_iter());
+                all_syms.extend(normal_str.clone());
             }
         }
 
         all_syms.into_iter().collect()
     }
 
-    pub fn iter(&self) -> indexmap::set::Iter<GramProd> {
+    pub fn iter(&self) -> indexmap::set::Iter<'_, GramProd> {
         self.prods.iter()
     }
 
@@ -284,6 +566,65 @@ impl Gram {
             .any(|prod| prod.lfsym == *sym && prod.rhstr == GramSymStr::Epsilon)
     }
 
+    /// 每个非终结符最多取第一条错误产生式
+    pub fn error_prods(&self) -> IndexMap<GramSym, GramProd> {
+        let mut error_prods = indexmap! {};
+
+        for prod in self.prods.iter().filter(|prod| prod.is_error_prod()) {
+            error_prods
+                .entry(prod.lfsym.clone())
+                .or_insert_with(|| prod.clone());
+        }
+
+        error_prods
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+    //// Reflection: 不拷贝地遍历语法， 顺序就是定义的顺序
+
+    pub fn productions(&self) -> impl Iterator<Item = &GramProd> {
+        self.prods.iter()
+    }
+
+    /// 产生式在`productions`里的位置
+    pub fn prod_index(&self, prod: &GramProd) -> Option<usize> {
+        self.prods.get_index_of(prod)
+    }
+
+    /// 有产生式的非终结符(去重)
+    pub fn nonterminals(&self) -> impl Iterator<Item = &GramSym> {
+        self.prods
+            .iter()
+            .map(|prod| &prod.lfsym)
+            .collect::<IndexSet<&GramSym>>()
+            .into_iter()
+    }
+
+    /// 产生式右边出现过的终结符(去重)
+    pub fn terminals(&self) -> impl Iterator<Item = &GramSym> {
+        self.symbols().filter(|sym| sym.is_terminal())
+    }
+
+    /// 左右两边出现过的所有符号(去重)
+    pub fn symbols(&self) -> impl Iterator<Item = &GramSym> {
+        let mut syms = IndexSet::new();
+
+        for prod in self.prods.iter() {
+            syms.insert(&prod.lfsym);
+
+            if let GramSymStr::Str(normal_str) = &prod.rhstr {
+                syms.extend(normal_str.iter());
+            }
+        }
+
+        syms.into_iter()
+    }
+
+    /// `sym`的各个分支
+    pub fn alts_of<'a>(&'a self, sym: &'a GramSym) -> impl Iterator<Item = &'a GramProd> {
+        self.prods.iter().filter(move |prod| &prod.lfsym == sym)
+    }
+
     /// 性能上可能应该需要一个Iterator Wrapper
     pub fn find_prod(&self, lfs: &GramSym)
     -> Vec<GramProd>
@@ -295,6 +636,12 @@ impl Gram {
 }
 
 
+/// 文档注释展开后每行前面会带一个空格
+fn doc_line(line: &str) -> &str {
+    line.strip_prefix(' ').unwrap_or(line)
+}
+
+
 impl Extend<GramProd> for Gram {
     fn extend<I: IntoIterator<Item = GramProd>>(&mut self, iter: I) {
         for item in iter {
@@ -313,27 +660,26 @@ impl std::iter::IntoIterator for Gram {
 }
 
 ////////////////////////////////////////////////////////////////////////////////
-//// First Sets
+////// First Sets
 
+/// 顺序是确定的: 非终结符按定义的顺序， 集合内按计算时加入的顺序
 pub type FstSets = IndexMap<GramSym, IndexSet<FstSetSym>>;
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
 pub enum FstSetSym {
-    Sym(String),
+    /// 终结符
+    Sym(GramSym),
     Epsilon,
 }
 
 impl FstSetSym {
     pub fn is_epsilon(&self) -> bool {
-        match self {
-            Self::Epsilon => true,
-            _ => false,
-        }
+        matches!(self, Self::Epsilon)
     }
 
     pub fn to_pred_set_sym(&self) -> Option<PredSetSym> {
         match self {
             Self::Epsilon => None,
-            Self::Sym(value) => Some(PredSetSym::Sym(value.clone())),
+            Self::Sym(sym) => Some(sym.to_pred_set_sym()),
         }
     }
 }
@@ -341,7 +687,7 @@ impl FstSetSym {
 impl fmt::Display for FstSetSym {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Sym(value) => write!(f, "{}", value),
+            Self::Sym(sym) => write!(f, "{}", sym.name()),
             Self::Epsilon => write!(f, "ε"),
         }
     }
@@ -349,9 +695,9 @@ impl fmt::Display for FstSetSym {
 
 pub fn display_fstsets(fst_sets: &FstSets) {
     for (lhs_sym, prodset) in fst_sets.iter() {
-        print!("{}: \n", lhs_sym);
+        println!("{}: ", lhs_sym);
 
-        print!("{}\n",
+        println!("{}",
             prodset.iter().map(|x| format!("| {}", x)).collect_vec().join("\n")
         );
 
@@ -361,34 +707,29 @@ pub fn display_fstsets(fst_sets: &FstSets) {
 
 impl Gram {
     pub fn first_sets(&self) -> FstSets {
+        self.first_sets_with(VerboseLv::V0)
+    }
+
+    /// 同`first_sets`， `verbose`为V1时打印额外的迭代次数
+    pub fn first_sets_with(&self, verbose: VerboseLv) -> FstSets {
         let mut first_sets = self
             .syms()
             .into_iter()
             .map(|sym| match &sym {
                 GramSym::NonTerminal(_) => (sym.clone(), indexset! {}),
-                GramSym::Terminal(_) => (
-                    sym.clone(),
-                    indexset! { FstSetSym::Sym(sym.name().to_string()) },
-                ),
+                _ => (sym.clone(), indexset! { sym.to_fst_set_sym() }),
             })
             .collect();
 
-        let mut round = 0usize;
-        loop {
-            round += 1;
-            if _calc_first_sets_turn(&self.prods, &mut first_sets) {
-                break;
-            }
-        }
+        let revisits = _calc_first_sets(&self.prods, &mut first_sets);
 
-        if round > 2 {
-            if VERBOSE.with(|verbose| verbose.clone()) >= VerboseLv::V1 {
-                println!(
-                    "{}: calc firstsets additional rounds: {}",
-                    self.name(),
-                    round - 1
-                );
-            }
+        if revisits > 0 {
+            verbose!(
+                verbose => V1,
+                "{}: calc firstsets additional visits: {}",
+                self.name(),
+                revisits
+            );
         }
 
         // filter terminal entry
@@ -406,29 +747,38 @@ impl Gram {
 ///       从求取First(Y1)开始,如果Y1的某个产生式有ε,就继续求取First(Y2)......,
 ///       如果整个串都已经耗尽了,就把ε也加入.
 ///```
-fn _calc_first_sets_turn(
+///
+/// 用工作表迭代到不动点: X的First集变大时， 只重新计算右部含有X的产生式，
+/// 很长的推导链也只需线性次访问， 返回重新访问产生式的次数
+pub(crate) fn _calc_first_sets(
     productions: &IndexSet<GramProd>,
     first_sets: &mut IndexMap<GramSym, IndexSet<FstSetSym>>,
-) -> bool {
-    let mut stable = true;
+) -> usize {
+    let mut users: IndexMap<&GramSym, Vec<usize>> = indexmap! {};
+    for (i, prod) in productions.iter().enumerate() {
+        for sym in prod.rhstr.get_normal().into_iter().flatten() {
+            users.entry(sym).or_default().push(i);
+        }
+    }
+
+    let mut worklist = (0..productions.len()).collect::<VecDeque<usize>>();
+    let mut queued = vec![true; productions.len()];
+    let mut revisits = 0;
+
+    while let Some(i) = worklist.pop_front() {
+        queued[i] = false;
 
-    for prod in productions.iter() {
+        let prod = &productions[i];
         let x = &prod.lfsym;
-        let str = &prod.rhstr;
-        let mut x_first_set = first_sets.get(x).unwrap().clone();
-        let x_first_set_old_size = x_first_set.len();
+        let mut x_first_set = indexset! {};
 
-        match str {
+        match &prod.rhstr {
             GramSymStr::Epsilon => {
                 x_first_set.insert(FstSetSym::Epsilon);
             }
 
             GramSymStr::Str(normal_str) => {
                 match x {
-                    GramSym::Terminal(_) => {
-                        x_first_set.insert(FstSetSym::Sym(x.name().to_string()));
-                    }
-
                     GramSym::NonTerminal(_) => {
                         let mut str_iter = normal_str.iter();
 
@@ -441,8 +791,9 @@ fn _calc_first_sets_turn(
                                 break;
                             }
 
+                            // ε只在整个串都能推出ε时加入
                             let cur_sym_first_set = first_sets.get(cur_sym).unwrap();
-                            x_first_set.extend(cur_sym_first_set.clone().into_iter());
+                            x_first_set.extend(cur_sym_first_set.iter().filter(|x| !x.is_epsilon()).cloned());
 
                             if cur_sym_first_set.contains(&FstSetSym::Epsilon) {
                                 // continue
@@ -451,33 +802,44 @@ fn _calc_first_sets_turn(
                             }
                         }
                     }
+
+                    _ => {
+                        x_first_set.insert(x.to_fst_set_sym());
+                    }
                 }
             }
         }
 
-        if x_first_set_old_size < x_first_set.len() {
-            stable = false;
-        }
-
         // 更新first_sets
-        first_sets
-            .get_mut(x)
-            .unwrap()
-            .extend(x_first_set.into_iter());
+        let x_first_set_old = first_sets.get_mut(x).unwrap();
+        let x_first_set_old_size = x_first_set_old.len();
+        x_first_set_old.extend(x_first_set);
+
+        if x_first_set_old_size < x_first_set_old.len() {
+            for j in users.get(x).into_iter().flatten() {
+                if !queued[*j] {
+                    queued[*j] = true;
+                    worklist.push_back(*j);
+                    revisits += 1;
+                }
+            }
+        }
     }
 
-    stable
+    revisits
 }
 
 
 ////////////////////////////////////////////////////////////////////////////////
-//// Follow Sets
+////// Follow Sets
 
+/// 顺序同`FstSets`
 pub type FollSets = IndexMap<GramSym, IndexSet<FollSetSym>>;
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
 pub enum FollSetSym {
-    Sym(String),
+    /// 终结符
+    Sym(GramSym),
     EndMarker,
 }
 
@@ -485,14 +847,14 @@ impl FollSetSym {
     pub fn to_fst_set_sym(&self) -> Option<FstSetSym> {
         match self {
             Self::EndMarker => None,
-            Self::Sym(value) => Some(FstSetSym::Sym(value.clone())),
+            Self::Sym(sym) => Some(FstSetSym::Sym(sym.clone())),
         }
     }
 
     pub fn to_pred_set_sym(&self) -> PredSetSym {
         match self {
             Self::EndMarker => PredSetSym::EndMarker,
-            Self::Sym(value) => PredSetSym::Sym(value.clone()),
+            Self::Sym(sym) => sym.to_pred_set_sym(),
         }
     }
 }
@@ -500,7 +862,7 @@ impl FollSetSym {
 impl fmt::Display for FollSetSym {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Sym(value) => write!(f, "{}", value),
+            Self::Sym(sym) => write!(f, "{}", sym.name()),
             Self::EndMarker => write!(f, "$"),
         }
     }
@@ -508,9 +870,9 @@ impl fmt::Display for FollSetSym {
 
 pub fn display_follsets(foll_sets: &FollSets) {
     for (lhs_sym, prodset) in foll_sets.iter() {
-        print!("{}: \n", lhs_sym);
+        println!("{}: ", lhs_sym);
 
-        print!("{}\n",
+        println!("{}",
             prodset.iter().map(|x| format!("| {}", x)).collect_vec().join("\n")
         );
 
@@ -520,32 +882,31 @@ pub fn display_follsets(foll_sets: &FollSets) {
 
 impl Gram {
     pub fn follow_sets(&self, first_sets: &FstSets) -> FollSets {
+        self.follow_sets_with(first_sets, VerboseLv::V0)
+    }
+
+    /// 同`follow_sets`， `verbose`为V1时打印额外的迭代次数
+    pub fn follow_sets_with(&self, first_sets: &FstSets, verbose: VerboseLv) -> FollSets {
+        // 未定义的非终结符也有(不完整的)Follow集， 由`validate`报告
         let mut foll_sets = self
             .nonterm_syms()
             .into_iter()
+            .chain(self.syms().into_iter().filter(|sym| sym.is_nonterminal()))
             .map(|sym| (sym.clone(), indexset! {}))
             .collect::<FollSets>();
 
-        foll_sets
-            .get_mut(self.start_sym().unwrap())
-            .unwrap()
-            .insert(FollSetSym::EndMarker);
-
-        let mut round = 0usize;
-        loop {
-            round += 1;
-            if _calc_follow_sets_turn(&self.prods, &mut foll_sets, first_sets) {
-                break;
-            }
+        if let Some(start_sym) = self.start_sym() {
+            foll_sets[start_sym].insert(FollSetSym::EndMarker);
         }
-        if round > 2 {
-            if VERBOSE.with(|verbose| verbose.clone()) >= VerboseLv::V1 {
-                println!(
-                    "{}: calc followsets additional rounds: {}",
-                    self.name(),
-                    round - 1
-                );
-            }
+
+        let revisits = _calc_follow_sets(&self.prods, &mut foll_sets, first_sets);
+        if revisits > 0 {
+            verbose!(
+                verbose => V1,
+                "{}: calc followsets additional visits: {}",
+                self.name(),
+                revisits
+            );
         }
         foll_sets
     }
@@ -558,105 +919,118 @@ impl Gram {
 ///    then everything in FOLLOW(A) is in FOLLOW(B).
 ///    如同计算First一样迭代，如果整个串都已经耗尽了,就把ε也加入Follow(B).
 /// ```
-fn _calc_follow_sets_turn(
+///
+/// 和First集一样用工作表: A的Follow集变大时， 只重新计算左部是A的产生式
+pub(crate) fn _calc_follow_sets(
     productions: &IndexSet<GramProd>,
     follow_sets: &mut FollSets,
     first_sets: &FstSets,
-) -> bool {
-    let mut stable = true;
+) -> usize {
+    let mut alts: IndexMap<&GramSym, Vec<usize>> = indexmap! {};
+    for (i, prod) in productions.iter().enumerate() {
+        alts.entry(&prod.lfsym).or_default().push(i);
+    }
+
+    let mut worklist = (0..productions.len()).collect::<VecDeque<usize>>();
+    let mut queued = vec![true; productions.len()];
+    let mut revisits = 0;
 
-    for prod in productions.iter() {
+    while let Some(n) = worklist.pop_front() {
+        queued[n] = false;
+
+        let prod = &productions[n];
         let x = &prod.lfsym;
         let str = &prod.rhstr;
+
+        if !(str.is_normal() && x.is_nonterminal()) {
+            continue;
+        }
+
         let x_follow_set = follow_sets.get(x).unwrap().clone();
+        let normal_str = str.get_normal().unwrap();
+        let lastpos = normal_str.len() - 1;
+        for (i, str_x) in normal_str.iter().enumerate().rev() {
+            if str_x.is_terminal() {
+                continue;
+            }
 
-        if str.is_normal() && x.is_nonterminal() {
-            let normal_str = str.get_normal().unwrap();
-            let lastpos = normal_str.len() - 1;
-            for (i, str_x) in normal_str.iter().enumerate().rev() {
-                if str_x.is_terminal() {
-                    continue;
-                }
+            let mut here_set = indexset! {};
+
+            // apply follow rule 2+
+            let mut j = i;
+            while j < lastpos {
+                let next_first = first_sets.get(&normal_str[j + 1]).unwrap();
+                here_set.extend(
+                    next_first
+                        .iter()
+                        .filter(|x| !x.is_epsilon())
+                        .map(|x| match x {
+                            FstSetSym::Sym(value) => FollSetSym::Sym(value.clone()),
+                            _ => unreachable!(),
+                        }),
+                );
 
-                if !follow_sets.contains_key(str_x) {
-                    assert!(false, "get {} None", str_x);
+                // 没有epsilon转换就停止穿透
+                if !next_first.iter().any(|x| x.is_epsilon()) {
+                    break;
                 }
+                j += 1;
+            }
+            // apply follow rule 3
+            if (i + 1..normal_str.len())
+                .map(|j| &normal_str[j])
+                .all(|x| first_sets.get(x).unwrap().contains(&FstSetSym::Epsilon))
+            {
+                here_set.extend(x_follow_set.iter().cloned());
+            }
 
-                let mut here_set = follow_sets.get(str_x).unwrap().clone();
-                let here_set_old_size = here_set.len();
-
-                // apply follow rule 2+
-                let mut j = i;
-                while j < lastpos {
-                    let next_first = first_sets.get(&normal_str[j + 1]).unwrap();
-                    here_set.extend(
-                        next_first
-                            .clone()
-                            .into_iter()
-                            .filter(|x| !x.is_epsilon())
-                            .map(|x| match x {
-                                FstSetSym::Sym(value) => FollSetSym::Sym(value),
-                                _ => unreachable!(),
-                            }),
-                    );
-
-                    // 没有epsilon转换就停止穿透
-                    if !next_first.iter().any(|x| x.is_epsilon()) {
-                        break;
-                    }
-                    j += 1;
-                }
-                // apply follow rule 3
-                if (i + 1..normal_str.len())
-                    .map(|j| &normal_str[j])
-                    .all(|x| first_sets.get(x).unwrap().contains(&FstSetSym::Epsilon))
-                {
-                    here_set.extend(x_follow_set.clone().into_iter());
-
-                    if stable && here_set_old_size < here_set.len() {
-                        stable = false;
+            // rewrite
+            let str_x_follow_set = follow_sets.get_mut(str_x).unwrap();
+            let old_size = str_x_follow_set.len();
+            str_x_follow_set.extend(here_set);
+
+            if old_size < str_x_follow_set.len() {
+                for k in alts.get(str_x).into_iter().flatten() {
+                    if !queued[*k] {
+                        queued[*k] = true;
+                        worklist.push_back(*k);
+                        revisits += 1;
                     }
                 }
-
-                // rewrite
-                follow_sets
-                    .get_mut(str_x)
-                    .unwrap()
-                    .extend(here_set.into_iter());
             }
         }
     }
 
-    stable
+    revisits
 }
 
 ////////////////////////////////////////////////////////////////////////////////
-//// Predication Sets
+////// Predication Sets
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
 pub enum PredSetSym {
-    Sym(String),
+    /// 终结符， 不会是`GramSym::Eof`
+    Sym(GramSym),
     EndMarker,
 }
 
 impl PredSetSym {
     pub fn is_sym(&self) -> bool {
-        match self {
-            Self::Sym(_) => true,
-            _ => false,
-        }
+        matches!(self, Self::Sym(_))
     }
 }
 
 impl fmt::Display for PredSetSym {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Sym(value) => write!(f, "{}", value),
+            Self::Sym(sym) => write!(f, "{}", sym.name()),
             Self::EndMarker => write!(f, "$"),
         }
     }
 }
 
+/// 非终结符按定义的顺序， 向前看符号按产生式的顺序
+#[derive(Default)]
 pub struct PredSet {
     predsets: IndexMap<GramSym, IndexMap<PredSetSym, GramProd>>
 }
@@ -675,6 +1049,57 @@ impl PredSet {
             None
         }
     }
+
+    /// lfsym处所有可以接受的向前看符号
+    pub fn expected(&self, lfsym: &GramSym) -> Vec<PredSetSym> {
+        match self.predsets.get(lfsym) {
+            Some(deriv_pred_set) => deriv_pred_set.keys().cloned().collect_vec(),
+            None => vec![],
+        }
+    }
+
+    pub fn iter(&self) -> indexmap::map::Iter<'_, GramSym, IndexMap<PredSetSym, GramProd>> {
+        self.predsets.iter()
+    }
+
+    /// lfsym的(向前看符号， 产生式)表
+    pub fn entries(&self, lfsym: &GramSym) -> impl Iterator<Item = (&PredSetSym, &GramProd)> {
+        self.predsets.get(lfsym).into_iter().flat_map(|deriv_map| deriv_map.iter())
+    }
+
+    pub fn nonterminals(&self) -> impl Iterator<Item = &GramSym> {
+        self.predsets.keys()
+    }
+
+    pub(crate) fn rename_sym(&mut self, old: &GramSym, new: &GramSym) {
+        self.predsets = std::mem::take(&mut self.predsets)
+            .into_iter()
+            .map(|(lfsym, deriv_map)| {
+                let deriv_map = deriv_map
+                    .into_iter()
+                    .map(|(la, prod)| (la, prod.rename_sym(old, new)))
+                    .collect();
+
+                (if &lfsym == old { new.clone() } else { lfsym }, deriv_map)
+            })
+            .collect();
+    }
+
+    pub(crate) fn insert(&mut self, lfsym: &GramSym, la: PredSetSym, prod: GramProd) {
+        self.predsets.entry(lfsym.clone()).or_default().insert(la, prod);
+    }
+
+    /// 替换lfsym的整张表， 位置不变； `None`(lfsym已经没有规则)就删掉
+    pub(crate) fn replace(&mut self, lfsym: &GramSym, deriv_map: Option<IndexMap<PredSetSym, GramProd>>) {
+        match deriv_map {
+            Some(deriv_map) => {
+                self.predsets.insert(lfsym.clone(), deriv_map);
+            }
+            None => {
+                self.predsets.shift_remove(lfsym);
+            }
+        }
+    }
 }
 
 impl fmt::Display for PredSet {
@@ -705,12 +1130,11 @@ impl Gram {
                 deriv_map.extend(
                 prodset
                     .into_iter()
-                    .map(|prod| {
+                    .flat_map(|prod| {
                         prod.lookahead(fstsets, follsets)
                         .into_iter()
                         .map(move |la| (la, prod.clone()))
                     })
-                    .flatten()
                 );
 
                 (sym, deriv_map)
@@ -723,7 +1147,7 @@ impl Gram {
 
 
 ////////////////////////////////////////////////////////////////////////////////
-//// Derivation Tree
+////// Derivation Tree
 
 pub type DerivationTree = IndexMap<GramSym, IndexSet<GramProd>>;
 
@@ -744,7 +1168,7 @@ impl Gram {
 }
 
 pub fn display_dt(dt: &DerivationTree, fstsets: &FstSets, follsets: &FollSets)
--> Result<String, Box<dyn Error>> {
+-> LlResult<String> {
     let mut s = String::new();
 
     for (lfsym, deriv_prods) in dt.into_iter() {
@@ -768,7 +1192,7 @@ pub fn display_dt(dt: &DerivationTree, fstsets: &FstSets, follsets: &FollSets)
 }
 
 ////////////////////////////////////////////////////////////////////////////////
-//// Grammar Check
+////// Grammar Check
 
 impl Gram {
     pub fn duplicate_dt(&self, fstsets: &FstSets, follsets: &FollSets) -> DerivationTree {
@@ -798,15 +1222,15 @@ impl Gram {
         dup_dt
     }
 
-    pub(crate) fn do_check1(&self, fstsets: &FstSets, follsets: &FollSets) -> Result<(), Box<dyn Error>> {
-        let dup_dt = self.duplicate_dt(&fstsets, &follsets);
+    pub(crate) fn do_check1(&self, fstsets: &FstSets, follsets: &FollSets) -> LlResult<()> {
+        let dup_dt = self.duplicate_dt(fstsets, follsets);
 
         let mut s = String::new();
 
-        if dup_dt.len() > 0 {
+        if !dup_dt.is_empty() {
             writeln!(&mut s, "Duplicated LL Rule:\n")?;
 
-            writeln!(&mut s, "{}", display_dt(&dup_dt, &fstsets, &follsets)?)?;
+            writeln!(&mut s, "{}", display_dt(&dup_dt, fstsets, follsets)?)?;
 
             return Err(TrapCode::AmbigousLLRule(&s).emit_box_err())
         }
@@ -814,7 +1238,124 @@ impl Gram {
         Ok(())
     }
 
-    pub fn do_check(&self) -> Result<(), Box<dyn Error>> {
+    /// 既用作终结符(按token名)又用作非终结符的名字
+    pub fn sym_collisions(&self) -> Vec<String> {
+        let nonterminals = self
+            .symbols()
+            .filter(|sym| sym.is_nonterminal())
+            .map(|sym| sym.name().to_string())
+            .collect::<IndexSet<String>>();
+
+        self.term_syms()
+            .iter()
+            .map(|sym| sym.token_name().to_string())
+            .filter(|name| nonterminals.contains(name))
+            .unique()
+            .collect()
+    }
+
+    /// 收集语法本身的问题:
+    /// 终结符与非终结符重名， 未定义的非终结符， LL(1)冲突是错误； 没有被引用的非终结符是警告
+    pub fn validate(&self) -> Diagnostics {
+        let mut diags = Diagnostics::new();
+
+        for name in self.sym_collisions() {
+            diags.push(Diagnostic::error(
+                "sym-collision",
+                &format!("{} is used as both terminal and nonterminal", name)
+            ));
+        }
+
+        let lfsyms: IndexSet<GramSym> = self.prods
+            .iter()
+            .map(|prod| prod.lfsym.clone())
+            .collect();
+
+        let mut used = indexset! {};
+        for prod in self.prods.iter() {
+            if let GramSymStr::Str(normal_str) = &prod.rhstr {
+                used.extend(normal_str.iter().filter(|sym| sym.is_nonterminal()).cloned());
+            }
+        }
+
+        for sym in used.difference(&lfsyms) {
+            diags.push(Diagnostic::error(
+                "undefined-nonterminal",
+                &format!("{} is used but has no production", sym)
+            ));
+        }
+
+        for sym in lfsyms.difference(&used) {
+            if Some(sym) != self.start_sym() {
+                diags.push(Diagnostic::warning(
+                    "unused-nonterminal",
+                    &format!("{} is never used", sym)
+                ));
+            }
+        }
+
+        // 有未定义的符号时没法计算Follow集
+        if diags.has_errors() {
+            return diags;
+        }
+
+        let fstsets = self.first_sets();
+        let follsets = self.follow_sets(&fstsets);
+
+        for (deriv_sym, dup_items) in self.duplicate_dt(&fstsets, &follsets) {
+            let mut diag = Diagnostic::error(
+                "ll1-conflict",
+                &format!("ambiguous LL(1) rule for {}", deriv_sym)
+            );
+
+            for prod in dup_items {
+                let laset = prod.lookahead(&fstsets, &follsets);
+                diag = diag.with_note(&format!(
+                    "{} | {}", prod, laset.iter().join(" ")
+                ));
+            }
+
+            diags.push(diag);
+        }
+
+        diags
+    }
+
+    /// 能计算预测表的最低要求： 至少有一个产生式， 引用的非终结符都有定义
+    pub fn check_well_formed(&self) -> LlResult<()> {
+        if self.start_sym().is_none() {
+            return Err(TrapCode::MalformedGram(&format!("{} has no production", self.name)).emit_box_err());
+        }
+
+        let lfsyms = self.nonterm_syms().into_iter().collect::<IndexSet<GramSym>>();
+        let undefined = self
+            .syms()
+            .into_iter()
+            .filter(|sym| sym.is_nonterminal() && !lfsyms.contains(sym))
+            .map(|sym| sym.to_string())
+            .collect_vec();
+
+        if !undefined.is_empty() {
+            return Err(TrapCode::MalformedGram(&format!(
+                "undefined nonterminal: {}",
+                undefined.join(", ")
+            )).emit_box_err());
+        }
+
+        Ok(())
+    }
+
+    pub fn do_check(&self) -> LlResult<()> {
+        self.check_well_formed()?;
+
+        let collisions = self.sym_collisions();
+        if !collisions.is_empty() {
+            return Err(TrapCode::SymCollision(&format!(
+                "used as both terminal and nonterminal: {}",
+                collisions.join(", ")
+            )).emit_box_err());
+        }
+
         let fstsets = self.first_sets();
         let follsets = self.follow_sets(&fstsets);
 
@@ -832,7 +1373,183 @@ impl Gram {
 mod test {
     use indexmap::{IndexMap, IndexSet, indexmap, indexset};
     use itertools::Itertools;
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_deep_chain_first_follow_sets() {
+        // A0 -> A1 x0, A1 -> A2 x1, ..., A{n-1} -> y
+        let depth = 100_000;
+        let nonterm = |i: usize| GramSym::NonTerminal(format!("A{}", i));
+        let term = |name: String| GramSym::Terminal(name);
+
+        let mut gram = Gram::new("chain");
+        for i in 0..depth - 1 {
+            gram.insert_prod(GramProd::new(
+                nonterm(i),
+                GramSymStr::Str(vec![nonterm(i + 1), term(format!("x{}", i))]),
+            ));
+        }
+        gram.insert_prod(GramProd::new(
+            nonterm(depth - 1),
+            GramSymStr::Str(vec![term("y".to_string())]),
+        ));
+
+        let fstsets = gram.first_sets();
+        assert_eq!(fstsets[&nonterm(0)], indexset! { term("y".to_string()).to_fst_set_sym() });
+
+        let follsets = gram.follow_sets(&fstsets);
+        assert_eq!(follsets[&nonterm(1)], indexset! { term("x0".to_string()).to_foll_set_sym() });
+        assert_eq!(
+            follsets[&nonterm(depth - 1)],
+            indexset! { term(format!("x{}", depth - 2)).to_foll_set_sym() }
+        );
+    }
+
+
+    const TERMS: [&str; 3] = ["a", "b", "c"];
+
+    /// 1~4个非终结符N0..， 每个1~3个产生式， 右部是0~3个符号
+    fn arb_gram() -> impl Strategy<Value = Gram> {
+        (1..=4usize)
+            .prop_flat_map(|n| {
+                let sym = prop_oneof![
+                    (0..TERMS.len()).prop_map(|i| GramSym::Terminal(TERMS[i].to_string())),
+                    (0..n).prop_map(|i| GramSym::NonTerminal(format!("N{}", i))),
+                ];
+                let rhstr = prop::collection::vec(sym, 0..=3).prop_map(|syms| {
+                    if syms.is_empty() { GramSymStr::Epsilon } else { GramSymStr::Str(syms) }
+                });
+
+                prop::collection::vec(prop::collection::vec(rhstr, 1..=3), n)
+            })
+            .prop_map(|alts| {
+                let mut gram = Gram::new("random");
+                for (i, rhstrs) in alts.into_iter().enumerate() {
+                    for rhstr in rhstrs {
+                        gram.insert_prod(GramProd::new(GramSym::NonTerminal(format!("N{}", i)), rhstr));
+                    }
+                }
+                gram
+            })
+    }
 
-    use super::Gram;
+    /// 按定义算符号串的First集
+    fn str_first(syms: &[GramSym], fstsets: &FstSets) -> IndexSet<FstSetSym> {
+        let mut res = indexset! {};
+
+        for sym in syms {
+            let fstset = &fstsets[sym];
+            res.extend(fstset.iter().filter(|x| !x.is_epsilon()).cloned());
+
+            if !fstset.contains(&FstSetSym::Epsilon) {
+                return res;
+            }
+        }
+        res.insert(FstSetSym::Epsilon);
+
+        res
+    }
+
+    fn rhs_syms(prod: &GramProd) -> Vec<GramSym> {
+        prod.rhstr.get_normal().cloned().unwrap_or_default()
+    }
+
+    proptest! {
+        #[test]
+        fn prop_first_sets(gram in arb_gram()) {
+            let fstsets = gram.first_sets();
+
+            for sym in gram.syms() {
+                if sym.is_terminal() {
+                    prop_assert_eq!(&fstsets[&sym], &indexset! { sym.to_fst_set_sym() });
+                }
+            }
+
+            // First(A)是A的全部产生式右部的First集的并
+            for (lfsym, prods) in gram.derivation_tree() {
+                let expected = prods
+                    .iter()
+                    .flat_map(|prod| str_first(&rhs_syms(prod), &fstsets))
+                    .collect::<IndexSet<FstSetSym>>();
+
+                prop_assert_eq!(&fstsets[&lfsym].iter().cloned().collect::<IndexSet<_>>(), &expected, "First({})", lfsym);
+            }
+        }
+
+        #[test]
+        fn prop_follow_sets(gram in arb_gram()) {
+            let fstsets = gram.first_sets();
+            let follsets = gram.follow_sets(&fstsets);
+
+            let mut expected: IndexMap<GramSym, IndexSet<FollSetSym>> = indexmap! {};
+            expected.entry(gram.start_sym().unwrap().clone()).or_default().insert(FollSetSym::EndMarker);
+
+            // A -> αBβ: First(β)去掉ε， β能推出ε时再加上Follow(A)
+            for prod in gram.prods.iter() {
+                let syms = rhs_syms(prod);
+
+                for (i, sym) in syms.iter().enumerate().filter(|(_, sym)| sym.is_nonterminal()) {
+                    let rest = str_first(&syms[i + 1..], &fstsets);
+                    let here = expected.entry(sym.clone()).or_default();
+
+                    here.extend(rest.iter().flat_map(|x| match x {
+                        FstSetSym::Sym(value) => Some(FollSetSym::Sym(value.clone())),
+                        FstSetSym::Epsilon => None,
+                    }));
+                    if rest.contains(&FstSetSym::Epsilon) {
+                        here.extend(follsets[&prod.lfsym].iter().cloned());
+                    }
+                }
+            }
+
+            for (sym, follset) in follsets.iter() {
+                let expected = expected.get(sym).cloned().unwrap_or_default();
+                prop_assert_eq!(follset, &expected, "Follow({})", sym);
+            }
+        }
+
+        #[test]
+        fn prop_prediction_sets(gram in arb_gram()) {
+            let fstsets = gram.first_sets();
+            let follsets = gram.follow_sets(&fstsets);
+            let predsets = gram.prediction_sets(&fstsets, &follsets);
+
+            let ll1 = !gram.validate().iter().any(|diag| diag.code == "ll1-conflict");
+            let candidates = TERMS
+                .iter()
+                .map(|name| PredSetSym::Sym(GramSym::Terminal(name.to_string())))
+                .chain(Some(PredSetSym::EndMarker))
+                .collect_vec();
+
+            for (lfsym, prods) in gram.derivation_tree() {
+                // 向前看集合是First(α)， α能推出ε时再加上Follow(A)
+                for prod in prods.iter() {
+                    let first = str_first(&rhs_syms(prod), &fstsets);
+                    let mut expected = first.iter().filter_map(|x| x.to_pred_set_sym()).collect::<IndexSet<_>>();
+                    if first.contains(&FstSetSym::Epsilon) {
+                        expected.extend(follsets[&lfsym].iter().map(|x| x.to_pred_set_sym()));
+                    }
+
+                    prop_assert_eq!(prod.lookahead(&fstsets, &follsets), expected, "{}", prod);
+                }
+
+                // 有预测的向前看符号恰好是各个产生式的向前看集合的并， LL(1)时它们互不相交
+                for la in candidates.iter() {
+                    let owners = prods
+                        .iter()
+                        .filter(|prod| prod.lookahead(&fstsets, &follsets).contains(la))
+                        .collect_vec();
+
+                    prop_assert_eq!(predsets.predict(&lfsym, la.clone()).is_some(), !owners.is_empty());
+                    if ll1 {
+                        prop_assert!(owners.len() <= 1, "{} has {} predictions on {}", lfsym, owners.len(), la);
+                        prop_assert_eq!(predsets.predict(&lfsym, la.clone()), owners.first().cloned());
+                    }
+                }
+            }
+        }
+    }
 }
 
@@ -0,0 +1,66 @@
+//! Grammar Documentation: 从语法本身生成参考文档
+
+use std::fmt::Write;
+
+use itertools::Itertools;
+
+use crate::gram::*;
+
+
+impl Gram {
+    /// Markdown格式的语法参考， 每个非终结符一节，
+    /// 包括规则的文档和各个分支(以及分支上的文档和废弃说明)
+    pub fn to_markdown(&self) -> String {
+        let mut s = String::new();
+
+        writeln!(&mut s, "# {}\n", self.name()).unwrap();
+
+        for (lfsym, prods) in self.derivation_tree() {
+            writeln!(&mut s, "## {}\n", lfsym.name()).unwrap();
+
+            if let Some(doc) = self.doc(&lfsym) {
+                writeln!(&mut s, "{}\n", doc).unwrap();
+            }
+
+            for prod in prods.iter() {
+                write!(&mut s, "- {}", markdown_symstr(&prod.rhstr)).unwrap();
+
+                if let Some(doc) = prod.attr("doc") {
+                    write!(&mut s, ": {}", doc.lines().join(" ")).unwrap();
+                }
+
+                if let Some(note) = prod.attr("deprecated") {
+                    if note.is_empty() {
+                        write!(&mut s, " *(deprecated)*").unwrap();
+                    } else {
+                        write!(&mut s, " *(deprecated: {})*", note).unwrap();
+                    }
+                }
+
+                writeln!(&mut s).unwrap();
+            }
+
+            writeln!(&mut s).unwrap();
+        }
+
+        s
+    }
+}
+
+/// 非终结符链接到对应的小节， 终结符用代码格式
+fn markdown_symstr(symstr: &GramSymStr) -> String {
+    match symstr {
+        GramSymStr::Epsilon => "*ε*".to_string(),
+        GramSymStr::Str(syms) => {
+            syms.iter()
+                .map(|sym| {
+                    if sym.is_terminal() {
+                        format!("`{}`", sym.name())
+                    } else {
+                        format!("[{}](#{})", sym.name(), sym.name().to_lowercase())
+                    }
+                })
+                .join(" ")
+        }
+    }
+}
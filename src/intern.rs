@@ -0,0 +1,170 @@
+//! Symbol Interning: 符号名只存一份， 比较时先比指针
+//!
+//! 每个文法可以有自己的`Interner`， 需要跨组件比较时(比如词法器产生的token和
+//! 另一处构建的文法)用进程级的`registry()`， 同名的符号拿到的是同一个`Arc`
+
+use std::{
+    borrow::Borrow,
+    fmt,
+    hash::{Hash, Hasher},
+    sync::{Arc, OnceLock, RwLock},
+};
+
+use indexmap::IndexSet;
+
+use crate::{
+    gram::Gram,
+    parser::Token,
+};
+
+
+/// 驻留后的符号名， 可以在线程间传递， 生命期和产生它的`Interner`无关
+#[derive(Clone)]
+pub struct Symbol(Arc<str>);
+
+impl Symbol {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// 来自同一个`Interner`(或者`Registry`)的同名符号一定指针相同
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other) || self.0 == other.0
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl Borrow<str> for Symbol {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Symbol({:?})", &*self.0)
+    }
+}
+
+
+/// 单个文法(或者单个组件)自己的符号表
+#[derive(Debug, Default, Clone)]
+pub struct Interner {
+    syms: IndexSet<Symbol>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(sym) = self.syms.get(name) {
+            return sym.clone();
+        }
+
+        let sym = Symbol(Arc::from(name));
+        self.syms.insert(sym.clone());
+
+        sym
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.syms.get(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.syms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.syms.is_empty()
+    }
+
+    pub fn iter(&self) -> indexmap::set::Iter<'_, Symbol> {
+        self.syms.iter()
+    }
+}
+
+
+/// 进程级的符号表， 符号只增不减， 一直活到进程结束
+#[derive(Debug, Default)]
+pub struct Registry {
+    interner: RwLock<Interner>,
+}
+
+impl Registry {
+    pub fn intern(&self, name: &str) -> Symbol {
+        if let Some(sym) = self.get(name) {
+            return sym;
+        }
+
+        self.interner.write().unwrap().intern(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.interner.read().unwrap().get(name).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.interner.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+pub fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+    REGISTRY.get_or_init(Registry::default)
+}
+
+
+impl Gram {
+    /// 文法里所有符号名组成的符号表
+    pub fn interner(&self) -> Interner {
+        let mut interner = Interner::new();
+
+        for sym in self.syms() {
+            interner.intern(&sym.name());
+        }
+
+        interner
+    }
+
+    /// 把文法里所有符号名登记到进程级的符号表
+    pub fn register_syms(&self) {
+        for sym in self.syms() {
+            registry().intern(&sym.name());
+        }
+    }
+}
+
+
+impl Token {
+    /// token名在进程级符号表中的符号
+    pub fn name_sym(&self) -> Symbol {
+        registry().intern(self.name())
+    }
+}
@@ -0,0 +1,149 @@
+//! AST Interpreter: 给每个非终结符注册处理函数， 自底向上求值，
+//! 子节点的值(或者token)按顺序交给父节点的处理函数， 推导出ε的子节点不在树里，
+//! 所以参数可能比产生式的右部少
+//!
+//! ```ignore
+//! let interp = Interp::new()
+//!     .on("Num", |_node, args| Ok(args[0].token().unwrap().value().parse::<i64>()?))
+//!     .on("Add", |_node, args| Ok(args[0].value().unwrap() + args[2].value().unwrap()));
+//!
+//! let value = interp.eval(&root)?;
+//! ```
+
+use std::{cell::RefCell, rc::Rc};
+
+use indexmap::IndexMap;
+use itertools::Itertools;
+
+use crate::{
+    error::{LlResult, Trap},
+    gram::Gram,
+    parser::{ASTNode, Token, AST},
+};
+
+
+/// 处理函数收到的子节点： 终结符是token， 非终结符是求出的值
+#[derive(Debug, Clone)]
+pub enum Arg<V> {
+    Token(Rc<Token>),
+    Value(V),
+}
+
+impl<V> Arg<V> {
+    pub fn token(&self) -> Option<&Token> {
+        match self {
+            Self::Token(token) => Some(token),
+            Self::Value(_) => None,
+        }
+    }
+
+    pub fn value(&self) -> Option<&V> {
+        match self {
+            Self::Token(_) => None,
+            Self::Value(value) => Some(value),
+        }
+    }
+
+    pub fn into_value(self) -> Option<V> {
+        match self {
+            Self::Token(_) => None,
+            Self::Value(value) => Some(value),
+        }
+    }
+}
+
+
+type Handler<'a, V> = Box<dyn Fn(&AST, Vec<Arg<V>>) -> LlResult<V> + 'a>;
+
+pub struct Interp<'a, V> {
+    handlers: IndexMap<String, Handler<'a, V>>,
+    /// 没有注册处理函数的非终结符用它
+    fallback: Option<Handler<'a, V>>,
+}
+
+impl<'a, V> Default for Interp<'a, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, V> Interp<'a, V> {
+    pub fn new() -> Self {
+        Self {
+            handlers: IndexMap::new(),
+            fallback: None,
+        }
+    }
+
+    /// 注册非终结符`name`的处理函数， 同名的会被替换
+    pub fn on<F>(mut self, name: &str, handler: F) -> Self
+    where
+        F: Fn(&AST, Vec<Arg<V>>) -> LlResult<V> + 'a
+    {
+        self.handlers.insert(name.to_string(), Box::new(handler));
+        self
+    }
+
+    pub fn with_fallback<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&AST, Vec<Arg<V>>) -> LlResult<V> + 'a
+    {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// 语法里没有处理函数的非终结符， 求值前检查用， 有fallback时总是为空
+    pub fn missing_handlers(&self, gram: &Gram) -> Vec<String> {
+        if self.fallback.is_some() {
+            return vec![];
+        }
+
+        gram.nonterm_syms()
+            .into_iter()
+            .map(|sym| sym.name().to_string())
+            .unique()
+            .filter(|name| !self.handlers.contains_key(name))
+            .collect()
+    }
+
+    pub fn eval(&self, root: &Rc<RefCell<AST>>) -> LlResult<V> {
+        self.eval_tree(root, &mut vec![])
+    }
+
+    fn eval_tree(&self, tree: &Rc<RefCell<AST>>, path: &mut Vec<String>) -> LlResult<V> {
+        let tree = tree.as_ref().borrow();
+        let name = tree.sym().name();
+        path.push(name.to_string());
+
+        let handler = match self.handlers.get(name.as_ref()).or(self.fallback.as_ref()) {
+            Some(handler) => handler,
+            None => {
+                let loc = tree
+                    .first_token()
+                    .map(|token| format!(" at {}", token.loc()))
+                    .unwrap_or_default();
+
+                return Err(Trap::new_box_err(&format!(
+                    "no handler for nonterminal `{}`{}, in {}; registered: {}",
+                    name,
+                    loc,
+                    path.join(" > "),
+                    self.handlers.keys().join(", ")
+                )));
+            }
+        };
+
+        let mut args = vec![];
+        for (_sym, child) in tree.elems_vec() {
+            args.push(match child {
+                ASTNode::Leaf(token) => Arg::Token(token.clone()),
+                ASTNode::Tree(subtree) => Arg::Value(self.eval_tree(subtree, path)?),
+            });
+        }
+
+        let value = handler(&tree, args)?;
+        path.pop();
+
+        Ok(value)
+    }
+}
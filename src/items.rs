@@ -0,0 +1,143 @@
+//! 开始规则是一串item的重复时， 逐个产出解析完成的item
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    rc::Rc,
+};
+
+use indexmap::IndexSet;
+
+use crate::{
+    error::ParseError,
+    gram::GramSym,
+    parser::{LL1ParseMachine, LL1Parser, ParseOptions, Token, AST},
+};
+
+
+impl LL1Parser {
+    /// 比如`Prog: Stmts; Stmts: Stmt Stmts | ε`， 每个`Stmt`解析完就马上产出它的子树，
+    /// 出错后产出错误并结束
+    pub fn parse_items<I>(&self, tokens: I) -> ParseItems<'_, I::IntoIter>
+    where I: IntoIterator<Item = Token>
+    {
+        let machine = self.machine(&ParseOptions::default());
+        let cursor = machine.root().clone();
+
+        ParseItems {
+            spine: self.list_spine(),
+            machine: Some(machine),
+            tokens: tokens.into_iter(),
+            cursor,
+            pos: 0,
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// 开始符号， 以及沿着产生式最右边递归下去的列表规则
+    fn list_spine(&self) -> IndexSet<GramSym> {
+        let mut spine = IndexSet::new();
+        spine.extend(self.gram().start_sym().cloned());
+
+        let mut i = 0;
+        while let Some(sym) = spine.get_index(i).cloned() {
+            for prod in self.gram().alts_of(&sym) {
+                if let Some(last) = prod.rhstr.get_normal().and_then(|symstr| symstr.last()) {
+                    if last.is_nonterminal() && self.is_list_sym(last) {
+                        spine.insert(last.clone());
+                    }
+                }
+            }
+
+            i += 1;
+        }
+
+        spine
+    }
+
+    /// `L -> ... L`
+    fn is_list_sym(&self, sym: &GramSym) -> bool {
+        self.gram().alts_of(sym).any(|prod| {
+            prod.rhstr.get_normal().and_then(|symstr| symstr.last()) == Some(sym)
+        })
+    }
+}
+
+
+pub struct ParseItems<'a, I> {
+    spine: IndexSet<GramSym>,
+    machine: Option<LL1ParseMachine<'a>>,
+    tokens: I,
+    /// 当前所在的最深的列表节点， 以及下一个要检查的子节点
+    cursor: Rc<RefCell<AST>>,
+    pos: usize,
+    queue: VecDeque<Result<Rc<RefCell<AST>>, ParseError>>,
+}
+
+impl<'a, I> ParseItems<'a, I> {
+    /// 沿着列表节点往下找已经匹配完的item， 遇到未完成的就停下
+    fn collect(&mut self, finished: bool) {
+        loop {
+            let node = match self.cursor.as_ref().borrow().elems_vec().get(self.pos) {
+                Some((_, node)) => node.get_ast().cloned(),
+                None => break,
+            };
+
+            let tree = match node {
+                Some(tree) => tree,
+                None => {  // 列表上的分隔符之类
+                    self.pos += 1;
+                    continue;
+                },
+            };
+
+            if self.spine.contains(tree.as_ref().borrow().sym()) {
+                self.cursor = tree;
+                self.pos = 0;
+                continue;
+            }
+
+            if !finished && self.machine.as_ref().is_some_and(|machine| machine.is_open(&tree)) {
+                break;
+            }
+
+            self.queue.push_back(Ok(tree));
+            self.pos += 1;
+        }
+    }
+}
+
+impl<'a, I: Iterator<Item = Token>> Iterator for ParseItems<'a, I> {
+    type Item = Result<Rc<RefCell<AST>>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.queue.pop_front() {
+                return Some(item);
+            }
+
+            let machine = self.machine.as_mut()?;
+
+            match self.tokens.next() {
+                Some(token) => {
+                    if let Err(err) = machine.feed(token) {
+                        self.machine = None;
+                        self.queue.push_back(Err(err));
+                    }
+                    else {
+                        self.collect(false);
+                    }
+                },
+                None => {
+                    let (_root, errors) = self.machine.take().unwrap().finish();
+
+                    if errors.is_empty() {
+                        self.collect(true);
+                    }
+
+                    self.queue.extend(errors.into_iter().map(Err));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1026 @@
+//! Regex Lexer: 按token规则把源代码切分成Token序列， 配合`token_recognizer!`使用
+
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use indexmap::IndexMap;
+use itertools::Either;
+use regex::{Regex, RegexSet};
+use regex_automata::{
+    hybrid::{
+        dfa::{Cache, DFA},
+        LazyStateID,
+    },
+    Anchored, Input,
+};
+
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use crate::error::{LlResult, Trap, TrapCode};
+use crate::parser::{LocConfig, Payload, SrcFileInfo, SrcLoc, Token, BOM};
+
+
+////////////////////////////////////////////////////////////////////////////////
+////// Token Matcher
+
+#[derive(Debug, Clone)]
+pub struct RegexTokenMatcher {
+    patstr: String,
+    pat: Regex,
+}
+
+impl RegexTokenMatcher {
+    /// patstr必须是合法的正则， 否则panic
+    pub fn new(patstr: &str) -> Self {
+        Self::build(patstr, false)
+    }
+
+    /// 模式来自用户输入时用这个， 不合法的正则报错
+    pub fn try_new(patstr: &str) -> LlResult<Self> {
+        Self::try_build(patstr, false)
+    }
+
+    fn build(patstr: &str, case_insensitive: bool) -> Self {
+        Self::try_build(patstr, case_insensitive).unwrap()
+    }
+
+    fn try_build(patstr: &str, case_insensitive: bool) -> LlResult<Self> {
+        // 只匹配开头， 标志写在模式里， 这样编译到RegexSet时也能保留
+        let flags = if case_insensitive { "?i" } else { "?" };
+        let pat = Regex::new(&format!("^({}:{})", flags, patstr))?;
+
+        Ok(Self {
+            patstr: patstr.to_string(),
+            pat,
+        })
+    }
+
+    pub fn patstr(&self) -> &str {
+        &self.patstr
+    }
+
+    /// 匹配text的开头， 返回匹配的长度(bytes)， lazy时取最短的匹配
+    pub fn fetch_tok(&self, text: &str, lazy: bool) -> Option<usize> {
+        if lazy {
+            self.pat.shortest_match(text)
+        }
+        else {
+            self.pat.find(text).map(|mat| mat.end())
+        }
+    }
+
+    pub fn is_full_match(&self, text: &str) -> bool {
+        self.pat.find(text).is_some_and(|mat| mat.end() == text.len())
+    }
+
+    /// 如果模式本身就是一个字面串， 返回这个字面串
+    pub fn literal(&self) -> Option<&str> {
+        if regex::escape(&self.patstr) == self.patstr {
+            Some(&self.patstr)
+        }
+        else {
+            None
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+////// Token Rule
+
+/// 从匹配到的文本计算token的附加值
+#[derive(Clone)]
+pub struct PayloadFn(Arc<PayloadCompute>);
+
+type PayloadCompute = dyn Fn(&str) -> Option<Payload> + Send + Sync;
+
+impl fmt::Debug for PayloadFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PayloadFn")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenRule {
+    name: String,
+    matcher: RegexTokenMatcher,
+    /// 取最短的匹配， 默认取正则本身的(贪婪)匹配
+    lazy: bool,
+    case_insensitive: bool,
+    /// 匹配后丢弃， 比如注释
+    skip: bool,
+    payload: Option<PayloadFn>,
+}
+
+impl TokenRule {
+    pub fn new(matcher: RegexTokenMatcher, name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            matcher,
+            lazy: false,
+            case_insensitive: false,
+            skip: false,
+            payload: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn matcher(&self) -> &RegexTokenMatcher {
+        &self.matcher
+    }
+
+    pub fn is_lazy(&self) -> bool {
+        self.lazy
+    }
+
+    pub fn is_case_insensitive(&self) -> bool {
+        self.case_insensitive
+    }
+
+    pub fn is_skip(&self) -> bool {
+        self.skip
+    }
+
+    pub fn has_payload(&self) -> bool {
+        self.payload.is_some()
+    }
+
+    fn make_token(&self, value: &str, loc: SrcLoc) -> Token {
+        let mut token = Token::new(self.name(), value, loc);
+
+        if let Some(PayloadFn(payload_fn)) = &self.payload {
+            token.set_payload(payload_fn(value));
+        }
+
+        token
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+////// Lexer
+
+/// 多条规则都能匹配时的取舍
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchPolicy {
+    /// 取最长的匹配， 一样长时取先声明的
+    #[default]
+    LongestMatch,
+    /// 取第一条能匹配的规则
+    FirstDeclared,
+}
+
+#[derive(Debug, Clone)]
+pub struct Lexer {
+    rules: Vec<TokenRule>,
+    policy: MatchPolicy,
+    /// 所有规则编译到一起的自动机
+    compiled: Option<CompiledRules>,
+}
+
+/// 每个位置只扫描一遍就知道哪些规则能匹配， 字面串规则不用再跑一遍正则就知道匹配长度
+#[derive(Debug, Clone)]
+struct CompiledRules {
+    set: RegexSet,
+    /// 和规则一一对应， 模式是字面串时为匹配的长度(bytes)
+    literal_lens: Vec<Option<usize>>,
+}
+
+impl Lexer {
+    /// rules通常由`token_recognizer!`生成， 规则的声明顺序就是优先级
+    pub fn new(rules: Vec<(RegexTokenMatcher, String)>) -> Self {
+        Self {
+            rules: rules
+                .into_iter()
+                .map(|(matcher, name)| TokenRule::new(matcher, &name))
+                .collect(),
+            policy: MatchPolicy::default(),
+            compiled: None,
+        }
+    }
+
+    pub fn rules(&self) -> &[TokenRule] {
+        &self.rules
+    }
+
+    pub fn policy(&self) -> MatchPolicy {
+        self.policy
+    }
+
+    pub fn with_policy(mut self, policy: MatchPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_lazy(mut self, name: &str) -> Self {
+        for rule in self.rules.iter_mut().filter(|rule| rule.name == name) {
+            rule.lazy = true;
+        }
+        self
+    }
+
+    /// 匹配`name`的token附带`f`从文本计算出的值(返回None就不附带)，
+    /// 之后用`Token::payload::<T>()`取出， 语义动作不必再解析一遍文本
+    pub fn with_payload<T, F>(mut self, name: &str, f: F) -> Self
+    where
+        T: Any + Send + Sync,
+        F: Fn(&str) -> Option<T> + Send + Sync + 'static,
+    {
+        let payload_fn = PayloadFn(Arc::new(move |text: &str| {
+            f(text).map(|value| Arc::new(value) as Payload)
+        }));
+
+        for rule in self.rules.iter_mut().filter(|rule| rule.name == name) {
+            rule.payload = Some(payload_fn.clone());
+        }
+        self
+    }
+
+    pub fn with_skip(mut self, name: &str) -> Self {
+        for rule in self.rules.iter_mut().filter(|rule| rule.name == name) {
+            rule.skip = true;
+        }
+        self
+    }
+
+    pub fn with_case_insensitive(mut self, name: &str) -> Self {
+        for rule in self.rules.iter_mut().filter(|rule| rule.name == name) {
+            rule.case_insensitive = true;
+            rule.matcher = RegexTokenMatcher::build(rule.matcher.patstr(), true);
+        }
+        self.compiled = None;
+        self
+    }
+
+    /// 所有的规则都不区分大小写
+    pub fn with_all_case_insensitive(mut self) -> Self {
+        for rule in self.rules.iter_mut() {
+            rule.case_insensitive = true;
+            rule.matcher = RegexTokenMatcher::build(rule.matcher.patstr(), true);
+        }
+        self.compiled = None;
+        self
+    }
+
+    /// 把所有规则编译成一个自动机， 之后的tokenize都复用它；
+    /// 修改大小写设置后需要重新编译。 自动机超过正则库的大小限制时报错
+    pub fn compile(mut self) -> LlResult<Self> {
+        let pats = self.rules.iter().map(|rule| rule.matcher.pat.as_str());
+        let set = RegexSet::new(pats)
+            .map_err(|err| Trap::new_box_err(&format!("can't compile token rules: {}", err)))?;
+
+        let literal_lens = self
+            .rules
+            .iter()
+            .map(|rule| if rule.case_insensitive { None } else { literal_len(rule.matcher.patstr()) })
+            .collect();
+
+        self.compiled = Some(CompiledRules { set, literal_lens });
+        Ok(self)
+    }
+
+    pub fn is_compiled(&self) -> bool {
+        self.compiled.is_some()
+    }
+
+    /// 模式是标识符样子的字面串的规则(通常是关键字)， token名 => 字面串
+    pub fn keywords(&self) -> IndexMap<String, String> {
+        self.rules
+            .iter()
+            .filter_map(|rule| rule.matcher.literal().map(|literal| (rule.name.clone(), literal.to_string())))
+            .filter(|(_name, literal)| is_ident(literal))
+            .collect()
+    }
+
+    /// 按当前策略匹配text的开头， 返回(规则， 匹配长度)
+    pub fn fetch(&self, text: &str) -> Option<(&TokenRule, usize)> {
+        self.pick(self.candidates(text))
+            .map(|(i, len)| (&self.rules[i], len))
+    }
+
+    /// 能匹配text开头的(规则序号， 匹配长度)， 按声明顺序
+    fn candidates<'a>(&'a self, text: &'a str) -> impl Iterator<Item = (usize, usize)> + 'a {
+        // (规则序号， 已知的匹配长度)
+        let matched = match &self.compiled {
+            // matches按声明顺序给出
+            Some(compiled) => Either::Left(
+                compiled.set.matches(text).into_iter().map(move |i| (i, compiled.literal_lens[i]))
+            ),
+            None => Either::Right((0..self.rules.len()).map(|i| (i, None))),
+        };
+
+        matched.filter_map(move |(i, literal_len)| {
+            let rule = &self.rules[i];
+
+            literal_len
+                .or_else(|| rule.matcher.fetch_tok(text, rule.lazy))
+                .filter(|len| *len > 0)
+                .map(|len| (i, len))
+        })
+    }
+
+    fn pick(&self, mut candidates: impl Iterator<Item = (usize, usize)>) -> Option<(usize, usize)> {
+        match self.policy {
+            MatchPolicy::FirstDeclared => candidates.next(),
+            MatchPolicy::LongestMatch => {
+                candidates.fold(None, |longest, (i, len)| match longest {
+                    Some((_, longest_len)) if longest_len >= len => longest,
+                    _ => Some((i, len)),
+                })
+            }
+        }
+    }
+
+    pub fn tokenize(&self, srcfile: &SrcFileInfo) -> Result<Vec<Token>, Box<dyn Error>> {
+        self.tokenize_(srcfile, None)
+    }
+
+    fn tokenize_(&self, srcfile: &SrcFileInfo, mut usage: Option<&mut Vec<RuleUsage>>)
+    -> Result<Vec<Token>, Box<dyn Error>>
+    {
+        let srcstr = srcfile.get_srcstr();
+        let mut tokens = vec![];
+        let mut pos = 0usize;
+        // SrcFileInfo按char计算位置
+        let mut offset = 0usize;
+
+        while pos < srcstr.len() {
+            let rest = &srcstr[pos..];
+
+            let fetched = match usage.as_mut() {
+                Some(usage) => self.fetch_recording(rest, usage),
+                None => self.fetch(rest),
+            };
+
+            let len = match fetched {
+                Some((rule, len)) => {
+                    if !rule.skip {
+                        tokens.push(rule.make_token(&rest[..len], srcfile.offset2srcloc(offset)));
+                    }
+                    len
+                },
+                None => {
+                    let c = rest.chars().next().unwrap();
+
+                    // 没有规则匹配的空白直接跳过
+                    if !c.is_whitespace() {
+                        return Err(TrapCode::UnrecognizedToken(&format!(
+                            "Unrecognized token `{}` at {}",
+                            rest.chars().take_while(|c| !c.is_whitespace()).collect::<String>(),
+                            srcfile.offset2srcloc(offset)
+                        )).emit_box_err());
+                    }
+                    c.len_utf8()
+                }
+            };
+
+            offset += rest[..len].chars().count();
+            pos += len;
+        }
+
+        Ok(tokens)
+    }
+
+    pub fn tokenize_str(&self, srcstr: &str) -> Result<Vec<Token>, Box<dyn Error>> {
+        self.tokenize(&SrcFileInfo::from_srcstr(PathBuf::new(), srcstr.to_string()))
+    }
+
+    /// 边读边切分， 不需要把整个输入读进内存， 见`TokenReader`
+    pub fn tokenize_reader<R: BufRead>(&self, reader: R) -> TokenReader<'_, R> {
+        TokenReader {
+            lexer: self,
+            reader,
+            buf: String::new(),
+            start: 0,
+            last_newline: None,
+            partial: vec![],
+            max_buffer: DEFAULT_MAX_BUFFER,
+            probe: Probe::new(self),
+            eof: false,
+            failed: false,
+            ln: LocConfig::default().first_line(),
+            col: LocConfig::default().first_col(),
+            config: LocConfig::default(),
+            fresh: true,
+        }
+    }
+
+    /// 检查两条规则能否匹配同样的文本:
+    /// 被先声明的规则完全覆盖的规则永远不会被匹配到， 是警告；
+    /// 靠声明顺序消除的歧义是提示。
+    ///
+    /// 只检查字面串规则， 两条都不是字面串的规则(比如`[a-z]+`和`[a-c]+`)即使重叠也不会报告，
+    /// 这种情况用`LexerUsage::diagnose`在语料上统计
+    pub fn diagnose(&self) -> Diagnostics {
+        let mut diags = Diagnostics::new();
+
+        for (i, rule) in self.rules.iter().enumerate() {
+            let literal = match rule.matcher.literal() {
+                Some(literal) => literal,
+                None => continue,
+            };
+
+            for (j, other) in self.rules.iter().enumerate() {
+                if i == j || !other.matcher.is_full_match(literal) {
+                    continue;
+                }
+
+                if j < i {
+                    diags.push(Diagnostic::warning(
+                        "token-shadowed",
+                        &format!(
+                            "token `{}` is shadowed by `{}` declared before it and never matches",
+                            rule.name, other.name
+                        )
+                    ));
+                }
+                else {
+                    diags.push(Diagnostic::note(
+                        "token-overlap",
+                        &format!(
+                            "`{}` also matches token `{}`, resolved by declaration order",
+                            other.name, rule.name
+                        )
+                    ));
+                }
+            }
+        }
+
+        diags
+    }
+}
+
+
+/// 模式只匹配一个字面串(元字符都被转义)时， 返回它的长度(bytes)
+fn literal_len(patstr: &str) -> Option<usize> {
+    const META: &str = r".+*?()|[]{}^$#&-~\";
+    let mut len = 0;
+    let mut chars = patstr.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            // `\<`、 `\d`这样的转义不是字面量
+            '\\' => {
+                let escaped = chars.next().filter(|escaped| META.contains(*escaped))?;
+                len += escaped.len_utf8();
+            }
+            '.' | '+' | '*' | '?' | '(' | ')' | '|' | '[' | ']' | '{' | '}' | '^' | '$' => return None,
+            c => len += c.len_utf8(),
+        }
+    }
+
+    Some(len)
+}
+
+/// 字母或`_`开头， 只含字母、 数字和`_`
+pub fn is_ident(text: &str) -> bool {
+    let mut chars = text.chars();
+
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+    && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+////// Rule Usage
+
+/// 一条token规则在语料上的使用情况
+#[derive(Debug, Clone, Default)]
+pub struct RuleUsage {
+    /// 实际切分出的token数
+    pub matched: usize,
+    /// 能匹配的位置数(不管最后有没有被选中)
+    pub candidate: usize,
+    /// 能匹配却被别的规则抢走时， 抢走它的规则和次数
+    pub lost_to: IndexMap<String, usize>,
+}
+
+impl Lexer {
+    fn fetch_recording(&self, text: &str, usage: &mut [RuleUsage]) -> Option<(&TokenRule, usize)> {
+        let candidates = self.candidates(text).collect::<Vec<_>>();
+        let picked = self.pick(candidates.iter().cloned());
+
+        if let Some((winner, _)) = picked {
+            usage[winner].matched += 1;
+
+            for (i, _) in candidates.iter() {
+                usage[*i].candidate += 1;
+
+                if *i != winner {
+                    *usage[*i].lost_to.entry(self.rules[winner].name.clone()).or_default() += 1;
+                }
+            }
+        }
+
+        picked.map(|(i, len)| (&self.rules[i], len))
+    }
+}
+
+
+/// 在一批输入上统计每条token规则的使用情况， 找出死规则
+pub struct LexerUsage<'a> {
+    lexer: &'a Lexer,
+    /// 和lexer的规则一一对应
+    usage: Vec<RuleUsage>,
+}
+
+impl<'a> LexerUsage<'a> {
+    pub fn new(lexer: &'a Lexer) -> Self {
+        Self {
+            lexer,
+            usage: vec![RuleUsage::default(); lexer.rules.len()],
+        }
+    }
+
+    pub fn tokenize(&mut self, srcfile: &SrcFileInfo) -> Result<Vec<Token>, Box<dyn Error>> {
+        self.lexer.tokenize_(srcfile, Some(&mut self.usage))
+    }
+
+    pub fn tokenize_str(&mut self, srcstr: &str) -> Result<Vec<Token>, Box<dyn Error>> {
+        self.tokenize(&SrcFileInfo::from_srcstr(PathBuf::new(), srcstr.to_string()))
+    }
+
+    /// 同名的规则合在一起算
+    pub fn usage(&self, name: &str) -> Option<RuleUsage> {
+        let mut found = None;
+
+        for (rule, usage) in self.lexer.rules.iter().zip(self.usage.iter()) {
+            if rule.name != name {
+                continue;
+            }
+
+            let total = found.get_or_insert_with(RuleUsage::default);
+            total.matched += usage.matched;
+            total.candidate += usage.candidate;
+            for (other, n) in usage.lost_to.iter() {
+                *total.lost_to.entry(other.clone()).or_default() += n;
+            }
+        }
+
+        found
+    }
+
+    /// 从来没有匹配过任何文本的规则
+    pub fn unused(&self) -> Vec<&TokenRule> {
+        self.rules_where(|usage| usage.candidate == 0)
+    }
+
+    /// 能匹配， 但每次都被别的规则抢走的规则
+    pub fn shadowed(&self) -> Vec<&TokenRule> {
+        self.rules_where(|usage| usage.candidate > 0 && usage.matched == 0)
+    }
+
+    fn rules_where<F: Fn(&RuleUsage) -> bool>(&self, pred: F) -> Vec<&TokenRule> {
+        self.lexer.rules
+            .iter()
+            .zip(self.usage.iter())
+            .filter(|(_, usage)| pred(usage))
+            .map(|(rule, _)| rule)
+            .collect()
+    }
+
+    pub fn diagnose(&self) -> Diagnostics {
+        let mut diags = Diagnostics::new();
+
+        for rule in self.unused() {
+            diags.push(Diagnostic::warning(
+                "token-unused",
+                &format!("token `{}` never matched in the corpus", rule.name)
+            ));
+        }
+
+        for (rule, usage) in self.lexer.rules.iter().zip(self.usage.iter()) {
+            if !(usage.candidate > 0 && usage.matched == 0) {
+                continue;
+            }
+
+            let mut diag = Diagnostic::warning(
+                "token-always-shadowed",
+                &format!(
+                    "token `{}` matched {} times but always lost to other tokens",
+                    rule.name, usage.candidate
+                )
+            );
+
+            for (other, n) in usage.lost_to.iter() {
+                diag = diag.with_note(&format!("lost to `{}` {} times", other, n));
+            }
+
+            diags.push(diag);
+        }
+
+        diags
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+////// Streaming Lexer
+
+/// `TokenReader`缓冲区的默认上限
+pub const DEFAULT_MAX_BUFFER: usize = 1 << 20;
+
+/// 每次最多读入的字节数， 遇到换行就停
+const CHUNK: usize = 8 * 1024;
+
+/// 分块读入， 游标之前是已经切分的部分， 攒到一半时再整体前移。
+/// 切分前保证缓冲区里有一整行或者至少`CHUNK`字节(除非输入已经结束)；
+/// 只要还有规则能匹配从游标到缓冲区末尾的整段文本， token就可能更长(比如跨行的注释)，
+/// 先读入更多再重新匹配， 结果和一次读入全部输入的`tokenize_str`相同。
+/// 一个token(或者没有规则能匹配的文本)超过缓冲区上限时报错
+pub struct TokenReader<'a, R> {
+    lexer: &'a Lexer,
+    reader: R,
+    buf: String,
+    /// 还没切分的部分的开头
+    start: usize,
+    /// 缓冲区里最后一个换行符的位置
+    last_newline: Option<usize>,
+    /// 读入时被截断的UTF-8字符
+    partial: Vec<u8>,
+    max_buffer: usize,
+    /// 规则编译不成DFA时为None， 退回到匹配到缓冲区末尾才读入更多
+    probe: Option<Probe>,
+    eof: bool,
+    failed: bool,
+    /// 游标处的位置
+    ln: usize,
+    col: usize,
+    config: LocConfig,
+    /// 还没读入过， 开头的BOM要去掉
+    fresh: bool,
+}
+
+impl<'a, R: BufRead> TokenReader<'a, R> {
+    /// 要在读取之前设置
+    pub fn with_loc_config(mut self, config: LocConfig) -> Self {
+        self.ln = config.first_line();
+        self.col = config.first_col();
+        self.config = config;
+        self
+    }
+
+    /// 缓冲区里还没切分的部分的上限(bytes)， token要比它短
+    pub fn with_max_buffer(mut self, max_buffer: usize) -> Self {
+        self.max_buffer = max_buffer.max(1);
+        self
+    }
+
+    fn rest(&self) -> &str {
+        &self.buf[self.start..]
+    }
+
+    /// 剩下的部分已经够判断下一个token
+    fn is_ready(&self) -> bool {
+        self.eof
+        || self.last_newline.is_some_and(|pos| pos >= self.start)
+        || self.rest().len() >= CHUNK.min(self.max_buffer)
+    }
+
+    /// 读不到更多了: 输入结束或者缓冲区已满
+    fn is_exhausted(&self) -> bool {
+        self.eof || self.rest().len() >= self.max_buffer
+    }
+
+    fn fill(&mut self) -> Result<(), Box<dyn Error>> {
+        // 已经切分的部分过半时整体前移， 每个字节平均只移动常数次
+        if self.start > 0 && self.start * 2 >= self.buf.len() {
+            if let Some(probe) = self.probe.as_mut() {
+                probe.shift(self.start);
+            }
+            self.buf.drain(..self.start);
+            self.last_newline = self.last_newline.and_then(|pos| pos.checked_sub(self.start));
+            self.start = 0;
+        }
+
+        let limit = CHUNK.min(self.max_buffer.saturating_sub(self.rest().len())).max(1);
+        let read = (&mut self.reader).take(limit as u64).read_until(b'\n', &mut self.partial)?;
+
+        // 只转换完整的字符， 被截断的留到下次
+        let valid = match std::str::from_utf8(&self.partial) {
+            Ok(text) => text.len(),
+            Err(err) if err.error_len().is_none() && read > 0 => err.valid_up_to(),
+            Err(err) => return Err(Box::new(io::Error::new(io::ErrorKind::InvalidData, err))),
+        };
+
+        let text = std::str::from_utf8(&self.partial[..valid]).unwrap();
+        let text = match self.fresh {
+            true => text.strip_prefix(BOM).unwrap_or(text),
+            false => text,
+        };
+        self.fresh = self.fresh && valid == 0;
+
+        if let Some(pos) = text.rfind('\n') {
+            self.last_newline = Some(self.buf.len() + pos);
+        }
+        self.buf.push_str(text);
+        self.partial.drain(..valid);
+
+        if read == 0 {
+            self.eof = true;
+        }
+
+        Ok(())
+    }
+
+    /// 读入更多后下一个token是否可能不同: `len`是现在匹配到的长度
+    fn can_extend(&mut self, len: Option<usize>) -> bool {
+        match self.probe.as_mut() {
+            Some(probe) => probe.is_alive(&self.buf, self.start),
+            None => match len {
+                Some(len) => len == self.rest().len(),
+                None => !self.rest().starts_with(char::is_whitespace),
+            },
+        }
+    }
+
+    fn advance(&mut self, len: usize) -> SrcLoc {
+        let loc = SrcLoc::new((self.ln, self.col));
+        let end = self.start + len;
+        let mut chars = self.buf[self.start..end].chars().peekable();
+
+        while let Some(c) = chars.next() {
+            let next = chars.peek().copied().or_else(|| self.buf[end..].chars().next());
+
+            if self.config.is_line_break(c, next) {
+                self.ln += 1;
+                self.col = self.config.first_col();
+            }
+            else {
+                self.col = self.config.next_col(self.col, c);
+            }
+        }
+        self.start = end;
+
+        loc
+    }
+
+    fn unrecognized(&self) -> Box<dyn Error> {
+        TrapCode::UnrecognizedToken(&format!(
+            "Unrecognized token `{}` at {}",
+            self.rest().chars().take_while(|c| !c.is_whitespace()).take(80).collect::<String>(),
+            SrcLoc::new((self.ln, self.col))
+        )).emit_box_err()
+    }
+}
+
+/// 每条规则一个lazy DFA， 判断从游标开始的文本读入更多后是否还可能被某条规则匹配，
+/// 语义和`Regex`一样是leftmost-first， 非贪婪的部分匹配到就不再继续
+struct Probe {
+    dfas: Vec<(DFA, Cache)>,
+    /// 从`start`走到`end`后每条规则的状态， 不能再匹配下去时为None
+    start: usize,
+    end: usize,
+    states: Vec<Option<LazyStateID>>,
+}
+
+impl Probe {
+    fn new(lexer: &Lexer) -> Option<Self> {
+        let dfas = lexer.rules
+            .iter()
+            .map(|rule| {
+                let dfa = DFA::new(rule.matcher.pat.as_str()).ok()?;
+                let cache = dfa.create_cache();
+
+                Some((dfa, cache))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            states: vec![None; dfas.len()],
+            dfas,
+            start: usize::MAX,
+            end: 0,
+        })
+    }
+
+    /// `buf[start..]`后面再接上一些文本时是否还可能被某条规则匹配，
+    /// 同一个`start`接着上次走到的地方继续。
+    /// 遇到DFA处理不了的情况(比如Unicode的`\b`碰到非ASCII字符)当作不能
+    fn is_alive(&mut self, buf: &str, start: usize) -> bool {
+        let usable = |sid: &LazyStateID| !sid.is_dead() && !sid.is_quit();
+
+        if start != self.start {
+            let input = Input::new(&buf[start..]).anchored(Anchored::Yes);
+
+            for ((dfa, cache), state) in self.dfas.iter_mut().zip(self.states.iter_mut()) {
+                *state = dfa.start_state_forward(cache, &input).ok().filter(usable);
+            }
+            self.start = start;
+            self.end = start;
+        }
+
+        let bytes = &buf.as_bytes()[self.end..];
+
+        for ((dfa, cache), state) in self.dfas.iter_mut().zip(self.states.iter_mut()) {
+            for byte in bytes.iter() {
+                let sid = match state {
+                    Some(sid) => *sid,
+                    None => break,
+                };
+
+                *state = dfa.next_state(cache, sid, *byte).ok().filter(usable);
+            }
+        }
+        self.end = buf.len();
+
+        self.states.iter().any(Option::is_some)
+    }
+
+    /// 缓冲区前移了`n`字节
+    fn shift(&mut self, n: usize) {
+        match self.start.checked_sub(n) {
+            Some(start) => {
+                self.start = start;
+                self.end -= n;
+            }
+            None => self.start = usize::MAX,
+        }
+    }
+}
+
+impl<'a, R: BufRead> Iterator for TokenReader<'a, R> {
+    type Item = Result<Token, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        loop {
+            if !self.is_ready() || self.rest().is_empty() {
+                if self.eof {
+                    return None;
+                }
+
+                if let Err(err) = self.fill() {
+                    self.failed = true;
+                    return Some(Err(err));
+                }
+                continue;
+            }
+
+            let lexer = self.lexer;
+            let fetched = lexer.fetch(self.rest());
+
+            if !self.eof && self.can_extend(fetched.map(|(_, len)| len)) {
+                if self.is_exhausted() {
+                    self.failed = true;
+
+                    return Some(Err(Trap::new_box_err(&format!(
+                        "token at {} is longer than {} bytes",
+                        SrcLoc::new((self.ln, self.col)),
+                        self.max_buffer
+                    ))));
+                }
+
+                if let Err(err) = self.fill() {
+                    self.failed = true;
+                    return Some(Err(err));
+                }
+                continue;
+            }
+
+            match fetched {
+                Some((rule, len)) => {
+                    let value = self.rest()[..len].to_string();
+                    let loc = self.advance(len);
+
+                    if !rule.is_skip() {
+                        return Some(Ok(rule.make_token(&value, loc)));
+                    }
+                },
+                None => {
+                    let c = self.rest().chars().next().unwrap();
+
+                    if c.is_whitespace() {
+                        self.advance(c.len_utf8());
+                    }
+                    else {
+                        self.failed = true;
+
+                        return Some(Err(self.unrecognized()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Cursor};
+
+    use super::*;
+
+    fn rules(pats: &[(&str, &str)]) -> Vec<(RegexTokenMatcher, String)> {
+        pats.iter().map(|(pat, name)| (RegexTokenMatcher::new(pat), name.to_string())).collect()
+    }
+
+    #[test]
+    fn test_compiled_same_tokens() {
+        let pats = [
+            ("if", "kw_if"),
+            ("[a-z]+", "id"),
+            (r"\+\+", "incr"),
+            (r"\+", "add"),
+            (r"a|ab", "alt"),
+            (r"/\*.*?\*/", "comment"),
+            ("[0-9]+", "num"),
+        ];
+        let src = "if ab ifx ++ + a1 /* x */ y /* z */";
+
+        for policy in [MatchPolicy::LongestMatch, MatchPolicy::FirstDeclared] {
+            let plain = Lexer::new(rules(&pats)).with_policy(policy);
+            let compiled = Lexer::new(rules(&pats)).with_policy(policy).compile().unwrap();
+
+            let expected = plain.tokenize_str(src).unwrap();
+            let tokens = compiled.tokenize_str(src).unwrap();
+
+            assert_eq!(
+                tokens.iter().map(|token| (token.name(), token.value())).collect::<Vec<_>>(),
+                expected.iter().map(|token| (token.name(), token.value())).collect::<Vec<_>>(),
+            );
+        }
+
+        assert_eq!(literal_len(r"\+\+"), Some(2));
+        assert_eq!(literal_len(r"\<"), None);
+        assert_eq!(literal_len("a|ab"), None);
+    }
+
+    #[test]
+    fn test_compile_too_large() {
+        let pats = (0..5).map(|i| (r"\w{100}", format!("r{}", i))).collect::<Vec<_>>();
+        let pats = pats.iter().map(|(pat, name)| (*pat, name.as_str())).collect::<Vec<_>>();
+
+        assert!(Lexer::new(rules(&pats)).compile().is_err());
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        assert!(RegexTokenMatcher::try_new("(").unwrap_err().to_string().contains("regex parse error"));
+        assert!(RegexTokenMatcher::try_new(r"\p{Nope}").is_err());
+        assert_eq!(RegexTokenMatcher::try_new("[a-z]+").unwrap().fetch_tok("ab1", false), Some(2));
+    }
+
+    #[test]
+    fn test_tokenize_reader_long_line() {
+        let lexer = Lexer::new(rules(&[("[a-zé]+", "id"), (",", "comma"), (r"/\*[\s\S]*?\*/", "comment")]));
+        // 一整行跨过很多次读入， 读入的边界落在token和多字节字符中间
+        let src = format!("\u{feff}{}\n/* a\n b */ x", "éé,abc,/* x y */ ".repeat(2000));
+
+        let expected = lexer.tokenize_str(&src).unwrap();
+        let tokens = lexer
+            .tokenize_reader(BufReader::with_capacity(7, Cursor::new(src.as_bytes())))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            tokens.iter().map(|token| (token.value(), token.loc())).collect::<Vec<_>>(),
+            expected.iter().map(|token| (token.value(), token.loc())).collect::<Vec<_>>(),
+        );
+
+        // 没有规则能匹配时不会把剩下的输入都读进来
+        let src = format!("abc, {}", "#".repeat(100_000));
+        let res = lexer
+            .tokenize_reader(Cursor::new(src.as_bytes()))
+            .with_max_buffer(64)
+            .collect::<Vec<_>>();
+
+        assert_eq!(res.len(), 3);
+        assert!(res[2].is_err());
+    }
+
+    #[test]
+    fn test_tokenize_reader_multiline_tokens() {
+        // 较短的规则能匹配跨行token的开头， 只读到一行时不能就此切分
+        let lexer = Lexer::new(rules(&[
+            (r"/\*[\s\S]*?\*/", "comment"),
+            ("/", "div"),
+            (r"\*", "mul"),
+            ("[a-z]+", "id"),
+            ("\"[^\"]*\"", "str"),
+        ]));
+
+        for src in ["a /* x\n y */ b", "a / b * \"x\ny\nz\" /* \n\n */ c", "a /* x\n y", "/*\n*"] {
+            let expected = lexer.tokenize_str(src).unwrap();
+            let tokens = lexer
+                .tokenize_reader(BufReader::with_capacity(1, Cursor::new(src.as_bytes())))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            assert_eq!(
+                tokens.iter().map(|token| (token.name(), token.value(), token.loc())).collect::<Vec<_>>(),
+                expected.iter().map(|token| (token.name(), token.value(), token.loc())).collect::<Vec<_>>(),
+                "{:?}", src
+            );
+        }
+    }
+}
@@ -1,21 +1,100 @@
+/// 调试输出， 开启`tracing`特性时作为tracing事件(V1: debug, V2: trace)，
+/// 否则按级别打印。 写作`verbose!(lv => V2, ...)`， 和`lv`比较(比如`ParseOptions::verbose`)
+macro_rules! verbose {
+    (@ $cur:expr, $lv:ident, $level:ident, $($arg:tt)+) => {
+        // 开启`tracing`时级别交给subscriber过滤
+        #[cfg(feature = "tracing")]
+        {
+            let _ = $cur;
+            tracing::$level!($($arg)+);
+        }
+
+        #[cfg(not(feature = "tracing"))]
+        if $cur >= $crate::VerboseLv::$lv {
+            println!($($arg)+);
+        }
+    };
+    ($cur:expr => $lv:ident) => {
+        #[cfg(not(feature = "tracing"))]
+        if $cur >= $crate::VerboseLv::$lv {
+            println!();
+        }
+    };
+    ($cur:expr => V1, $($arg:tt)+) => { verbose!(@ $cur, V1, debug, $($arg)+) };
+    ($cur:expr => V2, $($arg:tt)+) => { verbose!(@ $cur, V2, trace, $($arg)+) };
+}
 
 pub mod dsl;
 pub mod gram;
 pub mod parser;
+pub mod lexer;
 pub mod error;
+pub mod diagnostic;
+pub mod repair;
+pub mod gramdoc;
+pub mod railroad;
+pub mod event;
+pub mod items;
+pub mod intern;
+pub mod stats;
+pub mod builder;
+pub mod coverage;
+pub mod analysis;
+pub mod compat;
+pub mod corpus;
+pub mod astbuilder;
+pub mod refactor;
+pub mod lookahead;
+pub mod transform;
+pub mod provenance;
+pub mod resolve;
+pub mod scannerless;
+pub mod bytes;
+pub mod embed;
+pub mod serial;
+pub mod lint;
+pub mod tokens;
+pub mod session;
+pub mod scope;
+pub mod interp;
+pub mod trace;
+pub mod treesitter;
+pub mod migrate;
+pub mod schema;
+pub mod xml;
+pub mod tabular;
+pub mod redact;
+pub mod registry;
+pub mod batch;
+pub mod sarif;
+pub mod suppress;
+pub mod shrink;
+pub mod differential;
+#[cfg(feature = "async")]
+pub mod stream;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+// 不需要额外的依赖， 测试时总是编译
+#[cfg(any(feature = "ffi", test))]
+pub mod ffi;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "nodejs")]
+pub mod nodejs;
+#[cfg(feature = "watch")]
+pub mod watch;
+
+pub use error::LlResult;
 
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
 pub enum VerboseLv {
+    #[default]
     V0,
     V1,
     V2
 }
 
-thread_local! {
-    pub static VERBOSE: VerboseLv = VerboseLv::V0
-}
-
 
 #[cfg(test)]
 mod tests {
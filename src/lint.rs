@@ -0,0 +1,259 @@
+//! Grammar Lint: 不影响正确性的风格和健壮性问题， 每条规则的严重程度可以单独配置
+//!
+//! ```ignore
+//! let config = LintConfig::default()
+//!     .with_level(LintRule::SingleUse, None)
+//!     .with_level(LintRule::SimilarTerminals, Some(Severity::Error));
+//! for diag in gram.lint(&config).iter() {
+//!     println!("{}", diag);
+//! }
+//! ```
+
+use indexmap::{IndexMap, IndexSet};
+use itertools::Itertools;
+
+use crate::{
+    diagnostic::{Diagnostic, Diagnostics, Severity},
+    gram::{Gram, GramSym, GramSymStr},
+    repair::edit_distance,
+};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LintRule {
+    /// 只被引用一次的非终结符， 可以内联
+    SingleUse,
+    /// 过长的单产生式链`A -> B, B -> C, ...`
+    DeepChain,
+    /// 两个分支只差一个符号， 可以提取成可选的规则
+    OptionalVariant,
+    /// 名字很像的终结符， 可能是笔误
+    SimilarTerminals,
+}
+
+impl LintRule {
+    pub const ALL: [Self; 4] = [
+        Self::SingleUse,
+        Self::DeepChain,
+        Self::OptionalVariant,
+        Self::SimilarTerminals,
+    ];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::SingleUse => "single-use-nonterminal",
+            Self::DeepChain => "deep-rule-chain",
+            Self::OptionalVariant => "optional-variant",
+            Self::SimilarTerminals => "similar-terminals",
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct LintConfig {
+    /// 规则的严重程度， None或者不在表里就是关闭
+    pub levels: IndexMap<LintRule, Option<Severity>>,
+    /// 单产生式链超过这个长度时报告
+    pub max_chain_depth: usize,
+    /// 终结符名字的编辑距离不超过它时认为是笔误， 太短(不到4个字符)的名字不检查
+    pub max_typo_distance: usize,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        Self {
+            levels: LintRule::ALL
+                .iter()
+                .map(|rule| (*rule, Some(Severity::Warning)))
+                .collect(),
+            max_chain_depth: 3,
+            max_typo_distance: 1,
+        }
+    }
+}
+
+impl LintConfig {
+    pub fn with_level(mut self, rule: LintRule, level: Option<Severity>) -> Self {
+        self.levels.insert(rule, level);
+        self
+    }
+
+    pub fn with_max_chain_depth(mut self, depth: usize) -> Self {
+        self.max_chain_depth = depth;
+        self
+    }
+
+    pub fn with_max_typo_distance(mut self, distance: usize) -> Self {
+        self.max_typo_distance = distance;
+        self
+    }
+
+    pub fn level(&self, rule: LintRule) -> Option<Severity> {
+        self.levels.get(&rule).copied().flatten()
+    }
+}
+
+
+impl Gram {
+    pub fn lint(&self, config: &LintConfig) -> Diagnostics {
+        let mut diags = Diagnostics::new();
+
+        for rule in LintRule::ALL.iter() {
+            let severity = match config.level(*rule) {
+                Some(severity) => severity,
+                None => continue,
+            };
+
+            let found = match rule {
+                LintRule::SingleUse => self.lint_single_use(),
+                LintRule::DeepChain => self.lint_deep_chain(config.max_chain_depth),
+                LintRule::OptionalVariant => self.lint_optional_variant(),
+                LintRule::SimilarTerminals => self.lint_similar_terminals(config.max_typo_distance),
+            };
+
+            diags.extend(found.into_iter().map(|(msg, notes)| {
+                let mut diag = Diagnostic::new(severity, rule.code(), &msg);
+                diag.notes = notes;
+                diag
+            }));
+        }
+
+        diags
+    }
+
+    fn lint_single_use(&self) -> Vec<(String, Vec<String>)> {
+        let mut uses: IndexMap<&GramSym, Vec<&GramSym>> = IndexMap::new();
+
+        for prod in self.productions() {
+            for sym in prod.rhstr.get_normal().into_iter().flatten() {
+                if sym.is_nonterminal() {
+                    uses.entry(sym).or_default().push(&prod.lfsym);
+                }
+            }
+        }
+
+        uses.into_iter()
+            .filter(|(sym, users)| {
+                users.len() == 1 && users[0] != *sym && Some(*sym) != self.start_sym()
+            })
+            .map(|(sym, users)| {
+                (format!("{} is used only once, in {}", sym, users[0]), vec![])
+            })
+            .collect()
+    }
+
+    fn lint_deep_chain(&self, max_depth: usize) -> Vec<(String, Vec<String>)> {
+        let mut units: IndexMap<&GramSym, Vec<&GramSym>> = IndexMap::new();
+
+        for prod in self.productions() {
+            if let Some([sym]) = prod.rhstr.get_normal().map(|normal_str| &normal_str[..]) {
+                if sym.is_nonterminal() {
+                    units.entry(&prod.lfsym).or_default().push(sym);
+                }
+            }
+        }
+
+        let targets = units.values().flatten().copied().collect::<IndexSet<&GramSym>>();
+
+        // 只从链的开头报告
+        units
+            .keys()
+            .filter(|sym| !targets.contains(*sym))
+            .filter_map(|sym| {
+                let chain = longest_chain(&units, sym, &mut IndexSet::new());
+
+                if chain.len() - 1 > max_depth {
+                    Some((
+                        format!("{} reaches {} through {} unit productions", sym, chain.last().unwrap(), chain.len() - 1),
+                        vec![chain.iter().join(" -> ")],
+                    ))
+                }
+                else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn lint_optional_variant(&self) -> Vec<(String, Vec<String>)> {
+        let mut found = vec![];
+
+        for (lfsym, prods) in self.derivation_tree() {
+            let alts = prods
+                .iter()
+                .map(|prod| match &prod.rhstr {
+                    GramSymStr::Str(normal_str) => normal_str.clone(),
+                    GramSymStr::Epsilon => vec![],
+                })
+                .collect_vec();
+
+            for (long, short) in alts.iter().cartesian_product(alts.iter()) {
+                if long.len() != short.len() + 1 || long.len() < 2 {
+                    continue;
+                }
+
+                let extra = (0..long.len()).find(|i| {
+                    long[..*i] == short[..*i] && long[i + 1..] == short[*i..]
+                });
+
+                if let Some(i) = extra {
+                    found.push((
+                        format!("alternatives of {} differ only in optional {}", lfsym, long[i]),
+                        vec![
+                            format!("{} -> {}", lfsym, long.iter().join(" ")),
+                            format!("{} -> {}", lfsym, if short.is_empty() { "ε".to_string() } else { short.iter().join(" ") }),
+                        ],
+                    ));
+                }
+            }
+        }
+
+        found
+    }
+
+    fn lint_similar_terminals(&self, max_distance: usize) -> Vec<(String, Vec<String>)> {
+        let names = self
+            .term_syms()
+            .into_iter()
+            .filter(|sym| !sym.is_error() && !sym.is_eof() && !sym.is_wildcard())
+            .map(|sym| sym.token_name().to_string())
+            .unique()
+            .collect_vec();
+
+        names
+            .iter()
+            .tuple_combinations()
+            .filter(|(x, y)| {
+                x.chars().count().min(y.chars().count()) >= 4
+                && edit_distance(&x.to_lowercase(), &y.to_lowercase()) <= max_distance
+            })
+            .map(|(x, y)| (format!("terminals `{}` and `{}` look alike", x, y), vec![]))
+            .collect()
+    }
+}
+
+/// 从`sym`沿单产生式能走到的最长的链(包括两端)， 遇到环就停下
+fn longest_chain<'a>(
+    units: &IndexMap<&'a GramSym, Vec<&'a GramSym>>,
+    sym: &'a GramSym,
+    visiting: &mut IndexSet<&'a GramSym>,
+) -> Vec<&'a GramSym>
+{
+    visiting.insert(sym);
+
+    let mut longest = vec![];
+    for next in units.get(sym).into_iter().flatten() {
+        if !visiting.contains(*next) {
+            let chain = longest_chain(units, next, visiting);
+
+            if chain.len() > longest.len() {
+                longest = chain;
+            }
+        }
+    }
+
+    visiting.shift_remove(sym);
+
+    [vec![sym], longest].concat()
+}
@@ -0,0 +1,176 @@
+//! Lookahead Usage: 预测表的哪些决策只靠FIRST集， 哪些要经由ε借助FOLLOW集，
+//! 后者在语法改动时容易出现意料之外的冲突
+
+use std::{
+    cell::RefCell,
+    fmt,
+    rc::Rc,
+};
+
+use indexmap::{IndexMap, IndexSet};
+use itertools::Itertools;
+
+use crate::{
+    error::ParseError,
+    gram::{FollSets, FstSets, Gram, GramSym, GramSymStr, PredSetSym},
+    parser::{LL1Parser, ParseOptions, Token, AST},
+};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LaSource {
+    First,
+    /// 产生式可以推出ε， 向前看符号来自左边符号的FOLLOW集
+    Follow,
+}
+
+/// 预测表中来自FOLLOW集的表项
+pub(crate) fn follow_entries(gram: &Gram, fstsets: &FstSets, follsets: &FollSets)
+-> IndexSet<(GramSym, PredSetSym)>
+{
+    let mut entries = IndexSet::new();
+
+    for prod in gram.productions() {
+        let first = match &prod.rhstr {
+            GramSymStr::Str(normal_str) => fstsets
+                .get(&normal_str[0])
+                .into_iter()
+                .flatten()
+                .filter_map(|fstsym| fstsym.to_pred_set_sym())
+                .collect::<IndexSet<PredSetSym>>(),
+            GramSymStr::Epsilon => IndexSet::new(),
+        };
+
+        for la in prod.lookahead(fstsets, follsets) {
+            if !first.contains(&la) {
+                entries.insert((prod.lfsym.clone(), la));
+            }
+        }
+    }
+
+    entries
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct RuleLookahead {
+    /// 只靠FIRST集预测的向前看符号
+    pub first: Vec<PredSetSym>,
+    /// 要借助FOLLOW集的向前看符号
+    pub follow: Vec<PredSetSym>,
+    /// 解析时实际做出的两类决策的次数
+    pub first_hits: usize,
+    pub follow_hits: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LookaheadReport {
+    pub rules: IndexMap<GramSym, RuleLookahead>,
+}
+
+impl LookaheadReport {
+    /// 有决策依赖FOLLOW集的规则
+    pub fn follow_dependent(&self) -> impl Iterator<Item = (&GramSym, &RuleLookahead)> {
+        self.rules.iter().filter(|(_, rule)| !rule.follow.is_empty())
+    }
+}
+
+impl fmt::Display for LookaheadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<20}{:>8}{:>8}{:>12}{:>12}", "rule", "first", "follow", "first hits", "follow hits")?;
+
+        for (sym, rule) in self.rules.iter() {
+            writeln!(
+                f,
+                "{:<20}{:>8}{:>8}{:>12}{:>12}",
+                sym.name(),
+                rule.first.len(),
+                rule.follow.len(),
+                rule.first_hits,
+                rule.follow_hits
+            )?;
+
+            if !rule.follow.is_empty() {
+                writeln!(f, "  via FOLLOW: {}", rule.follow.iter().join(" "))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+impl LL1Parser {
+    /// 预测表项`(lfsym, la)`来自哪里， 没有这一项时为None
+    pub fn lookahead_source(&self, lfsym: &GramSym, la: &PredSetSym) -> Option<LaSource> {
+        self.predict_prod(lfsym, la.clone())?;
+
+        if self.follow_entries.contains(&(lfsym.clone(), la.clone())) {
+            Some(LaSource::Follow)
+        } else {
+            Some(LaSource::First)
+        }
+    }
+
+    /// 静态的报告， 只有预测表的内容
+    pub fn lookahead_report(&self) -> LookaheadReport {
+        let mut report = LookaheadReport::default();
+
+        for (lfsym, deriv_map) in self.prediction_sets().iter() {
+            let rule = report.rules.entry(lfsym.clone()).or_default();
+
+            for la in deriv_map.keys() {
+                match self.lookahead_source(lfsym, la) {
+                    Some(LaSource::Follow) => rule.follow.push(la.clone()),
+                    _ => rule.first.push(la.clone()),
+                }
+            }
+        }
+
+        report
+    }
+}
+
+
+/// 解析语料， 统计实际做出的决策有多少依赖FOLLOW集
+pub struct LookaheadRecorder<'a> {
+    parser: &'a LL1Parser,
+    hits: IndexMap<(GramSym, PredSetSym), usize>,
+}
+
+impl<'a> LookaheadRecorder<'a> {
+    pub fn new(parser: &'a LL1Parser) -> Self {
+        Self {
+            parser,
+            hits: IndexMap::new(),
+        }
+    }
+
+    pub fn parse(&mut self, tokens: Vec<Token>, options: &ParseOptions)
+    -> (Rc<RefCell<AST>>, Vec<ParseError>)
+    {
+        let mut machine = self.parser.machine(options).with_lookahead_hits();
+        machine.run_all(tokens);
+
+        for (entry, n) in machine.take_lookahead_hits().unwrap() {
+            *self.hits.entry(entry).or_default() += n;
+        }
+
+        machine.finish()
+    }
+
+    pub fn report(&self) -> LookaheadReport {
+        let mut report = self.parser.lookahead_report();
+
+        for ((lfsym, la), n) in self.hits.iter() {
+            let rule = report.rules.entry(lfsym.clone()).or_default();
+
+            match self.parser.lookahead_source(lfsym, la) {
+                Some(LaSource::Follow) => rule.follow_hits += n,
+                _ => rule.first_hits += n,
+            }
+        }
+
+        report
+    }
+}
@@ -0,0 +1,422 @@
+//! Language Server: DSL形式的语法文件(`.gram`)的LSP服务， 经由stdio通信
+//!
+//! 支持跳转到非终结符的定义、 悬停显示FIRST/FOLLOW集、 语法诊断(包括LL(1)冲突)
+//! 和非终结符重命名。 只同步完整的文本(`TextDocumentSyncKind::Full`)
+
+use std::error::Error;
+use std::io::{BufRead, Write};
+
+use indexmap::{indexmap, IndexMap};
+use itertools::Itertools;
+use serde_json::{json, Value};
+
+use crate::builder::GramBuilder;
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::error::Trap;
+use crate::gram::{FollSets, FstSets, Gram, GramSym};
+
+
+/// DslReader里的标点， 其余的非空白字符组成标识符
+const PUNCTS: &str = ":|;[]#!=(){},";
+
+
+/// 同一行内的范围， 行和列都从0开始， 列按UTF-16计
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Range {
+    fn contains(&self, line: usize, col: usize) -> bool {
+        self.line == line && self.start <= col && col <= self.end
+    }
+
+    fn to_json(self) -> Value {
+        json!({
+            "start": { "line": self.line, "character": self.start },
+            "end": { "line": self.line, "character": self.end },
+        })
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Lexeme {
+    Ident(String),
+    Punct(char),
+}
+
+pub struct GramDocument {
+    lexemes: Vec<(Lexeme, Range)>,
+    /// 非终结符 => 定义处(`Name:`)
+    defs: IndexMap<String, Range>,
+    /// DSL有语法错误时为None
+    gram: Option<Gram>,
+    diags: Vec<Diagnostic>,
+    /// 有未定义的符号时没法计算
+    sets: Option<(FstSets, FollSets)>,
+}
+
+impl GramDocument {
+    pub fn new(text: &str) -> Self {
+        let lexemes = scan(text);
+
+        let mut defs = indexmap! {};
+        for ((lexeme, range), (next, _)) in lexemes.iter().tuple_windows() {
+            if let (Lexeme::Ident(name), Lexeme::Punct(':')) = (lexeme, next) {
+                defs.entry(name.clone()).or_insert(*range);
+            }
+        }
+
+        // 有冲突的语法也要保留， 以便悬停时显示FIRST/FOLLOW集
+        let (gram, diags) = match GramBuilder::from_dsl(text) {
+            Ok(builder) => {
+                let (gram, mut diags) = builder.assemble();
+                diags.extend(gram.validate());
+
+                (Some(gram), diags.into_iter().collect_vec())
+            },
+            Err(err) => (None, vec![Diagnostic::error("dsl-syntax", &err.to_string())]),
+        };
+
+        let sets = match &gram {
+            Some(gram) if !diags.iter().any(|diag| diag.code == "undefined-nonterminal") => {
+                let fstsets = gram.first_sets();
+                let follsets = gram.follow_sets(&fstsets);
+
+                Some((fstsets, follsets))
+            },
+            _ => None,
+        };
+
+        Self { lexemes, defs, gram, diags, sets }
+    }
+
+    pub fn gram(&self) -> Option<&Gram> {
+        self.gram.as_ref()
+    }
+
+    /// 光标处的非终结符和它的范围
+    fn nonterminal_at(&self, line: usize, col: usize) -> Option<(&str, Range)> {
+        self.lexemes.iter().find_map(|(lexeme, range)| match lexeme {
+            Lexeme::Ident(name) if range.contains(line, col) && self.defs.contains_key(name) => {
+                Some((name.as_str(), *range))
+            },
+            _ => None,
+        })
+    }
+
+    pub fn definition(&self, line: usize, col: usize) -> Option<Range> {
+        let (name, _range) = self.nonterminal_at(line, col)?;
+
+        self.defs.get(name).cloned()
+    }
+
+    /// Markdown: 规则文档和FIRST/FOLLOW集
+    pub fn hover(&self, line: usize, col: usize) -> Option<(String, Range)> {
+        let (name, range) = self.nonterminal_at(line, col)?;
+        let sym = GramSym::NonTerminal(name.to_string());
+        let mut sections = vec![format!("`{}`", sym)];
+
+        if let Some(doc) = self.gram().and_then(|gram| gram.doc(&sym)) {
+            sections.push(doc.to_string());
+        }
+
+        if let Some((fstsets, follsets)) = &self.sets {
+            if let Some(fstset) = fstsets.get(&sym) {
+                sections.push(format!("FIRST: `{{{}}}`", fstset.iter().join(", ")));
+            }
+            if let Some(follset) = follsets.get(&sym) {
+                sections.push(format!("FOLLOW: `{{{}}}`", follset.iter().join(", ")));
+            }
+        }
+
+        Some((sections.join("\n\n"), range))
+    }
+
+    /// 要改写的所有出现处(包括声明)
+    pub fn rename(&self, line: usize, col: usize, new_name: &str) -> Result<Vec<Range>, String> {
+        let (name, _range) = self
+            .nonterminal_at(line, col)
+            .ok_or_else(|| "only nonterminals can be renamed".to_string())?;
+
+        if !is_ident(new_name) {
+            return Err(format!("`{}` is not a valid symbol name", new_name));
+        }
+        let taken = self
+            .lexemes
+            .iter()
+            .any(|(lexeme, _)| *lexeme == Lexeme::Ident(new_name.to_string()));
+        if new_name != name && taken {
+            return Err(format!("`{}` is already used", new_name));
+        }
+
+        Ok(self
+            .lexemes
+            .iter()
+            .filter(|(lexeme, _)| *lexeme == Lexeme::Ident(name.to_string()))
+            .map(|(_, range)| *range)
+            .collect())
+    }
+
+    /// 语法检查的结果， 定位到消息里提到的第一个非终结符
+    pub fn diagnostics(&self) -> Vec<(Range, Diagnostic)> {
+        self.diags
+            .iter()
+            .map(|diag| (self.locate(&diag.msg), diag.clone()))
+            .collect()
+    }
+
+    fn locate(&self, msg: &str) -> Range {
+        let mentioned = msg
+            .split_once('[')
+            .and_then(|(_, rest)| rest.split_once(']'))
+            .map(|(name, _)| name.to_string());
+
+        mentioned
+            .and_then(|name| {
+                self.defs.get(&name).cloned().or_else(|| {
+                    self.lexemes
+                        .iter()
+                        .find(|(lexeme, _)| *lexeme == Lexeme::Ident(name.clone()))
+                        .map(|(_, range)| *range)
+                })
+            })
+            .unwrap_or(Range { line: 0, start: 0, end: 0 })
+    }
+}
+
+fn is_ident(name: &str) -> bool {
+    !name.is_empty()
+    && !name.starts_with(|c: char| c.is_ascii_digit())
+    && !name.chars().any(|c| c.is_whitespace() || PUNCTS.contains(c) || c == '"')
+}
+
+/// 跳过注释和字符串， 切出标识符和标点
+fn scan(text: &str) -> Vec<(Lexeme, Range)> {
+    let mut lexemes = vec![];
+
+    for (line, linestr) in text.lines().enumerate() {
+        let chars = linestr.chars().collect_vec();
+        let mut col = 0;
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+
+            if c == '/' && chars.get(i + 1) == Some(&'/') {
+                break;
+            }
+            else if c == '"' {
+                col += c.len_utf16();
+                i += 1;
+
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\\' && i + 1 < chars.len() {
+                        col += chars[i].len_utf16();
+                        i += 1;
+                    }
+                    col += chars[i].len_utf16();
+                    i += 1;
+                }
+                if i < chars.len() {
+                    col += 1;
+                    i += 1;
+                }
+            }
+            else if c.is_whitespace() {
+                col += c.len_utf16();
+                i += 1;
+            }
+            else if PUNCTS.contains(c) {
+                lexemes.push((Lexeme::Punct(c), Range { line, start: col, end: col + 1 }));
+                col += 1;
+                i += 1;
+            }
+            else {
+                let start = col;
+                let mut ident = String::new();
+
+                while i < chars.len()
+                && !(chars[i].is_whitespace() || PUNCTS.contains(chars[i]) || chars[i] == '"') {
+                    ident.push(chars[i]);
+                    col += chars[i].len_utf16();
+                    i += 1;
+                }
+
+                lexemes.push((Lexeme::Ident(ident), Range { line, start, end: col }));
+            }
+        }
+    }
+
+    lexemes
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+////// JSON-RPC
+
+/// 处理请求直到收到`exit`或者输入结束
+pub fn serve<R: BufRead, W: Write>(mut input: R, mut output: W) -> Result<(), Box<dyn Error>> {
+    let mut docs: IndexMap<String, GramDocument> = indexmap! {};
+
+    while let Some(msg) = read_message(&mut input)? {
+        let method = msg["method"].as_str().unwrap_or("");
+        let id = msg.get("id").cloned();
+        let params = &msg["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+        let line = params["position"]["line"].as_u64().unwrap_or(0) as usize;
+        let col = params["position"]["character"].as_u64().unwrap_or(0) as usize;
+
+        let result = match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "renameProvider": true,
+                },
+                "serverInfo": { "name": "ll1" },
+            })),
+            "shutdown" => Ok(Value::Null),
+            "exit" => return Ok(()),
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let text = if method == "textDocument/didOpen" {
+                    params["textDocument"]["text"].as_str()
+                } else {
+                    params["contentChanges"]
+                        .as_array()
+                        .and_then(|changes| changes.last())
+                        .and_then(|change| change["text"].as_str())
+                };
+
+                let doc = GramDocument::new(text.unwrap_or(""));
+                publish_diagnostics(&mut output, &uri, &doc)?;
+                docs.insert(uri, doc);
+                continue;
+            },
+            "textDocument/didClose" => {
+                docs.shift_remove(&uri);
+                write_message(&mut output, &json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": uri, "diagnostics": [] },
+                }))?;
+                continue;
+            },
+            "textDocument/definition" => Ok(docs
+                .get(&uri)
+                .and_then(|doc| doc.definition(line, col))
+                .map_or(Value::Null, |range| json!({ "uri": uri, "range": range.to_json() }))),
+            "textDocument/hover" => Ok(docs
+                .get(&uri)
+                .and_then(|doc| doc.hover(line, col))
+                .map_or(Value::Null, |(text, range)| json!({
+                    "contents": { "kind": "markdown", "value": text },
+                    "range": range.to_json(),
+                }))),
+            "textDocument/rename" => {
+                let new_name = params["newName"].as_str().unwrap_or("");
+
+                match docs.get(&uri).map(|doc| doc.rename(line, col, new_name)) {
+                    Some(Ok(ranges)) => {
+                        let edits = ranges
+                            .into_iter()
+                            .map(|range| json!({ "range": range.to_json(), "newText": new_name }))
+                            .collect_vec();
+
+                        Ok(json!({ "changes": { uri: edits } }))
+                    },
+                    Some(Err(msg)) => Err((-32602, msg)),
+                    None => Ok(Value::Null),
+                }
+            },
+            _ => Err((-32601, format!("unsupported method `{}`", method))),
+        };
+
+        // 通知不需要回复
+        let id = match id {
+            Some(id) => id,
+            None => continue,
+        };
+
+        let reply = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, msg)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": msg },
+            }),
+        };
+        write_message(&mut output, &reply)?;
+    }
+
+    Ok(())
+}
+
+fn publish_diagnostics<W: Write>(output: &mut W, uri: &str, doc: &GramDocument) -> Result<(), Box<dyn Error>> {
+    let diagnostics = doc
+        .diagnostics()
+        .into_iter()
+        .map(|(range, diag)| {
+            let severity = match diag.severity {
+                Severity::Error => 1,
+                Severity::Warning => 2,
+                Severity::Note => 3,
+            };
+            let message = [diag.msg.clone()].iter().chain(diag.notes.iter()).join("\n");
+
+            json!({
+                "range": range.to_json(),
+                "severity": severity,
+                "code": diag.code,
+                "source": "ll1",
+                "message": message,
+            })
+        })
+        .collect_vec();
+
+    write_message(output, &json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    }))
+}
+
+/// `Content-Length: N\r\n\r\n{...}`， 输入结束时为None
+fn read_message<R: BufRead>(input: &mut R) -> Result<Option<Value>, Box<dyn Error>> {
+    let mut len = None;
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            len = Some(value.trim().parse::<usize>()?);
+        }
+    }
+
+    let len = len.ok_or_else(|| Trap::new_box_err("missing Content-Length"))?;
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn write_message<W: Write>(output: &mut W, msg: &Value) -> Result<(), Box<dyn Error>> {
+    let body = msg.to_string();
+
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()?;
+
+    Ok(())
+}
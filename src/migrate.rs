@@ -0,0 +1,973 @@
+//! Grammar Migration: 把pest的`.pest`文件或者LALRPOP的语法声明翻译成`GramBuilder`，
+//! 只支持能用LL(1)表达的子集， 翻译不了或者近似翻译的构造记在报告里
+//!
+//! - 字符串字面量是加引号的终结符， pest的字符范围`'a'..'z'`是字符类`["a-z"]`
+//! - `?`、 `*`、 `+`和重复次数展开成辅助规则`rule_opt1`、 `rule_rep2`...，
+//!   嵌套的选择提取成`rule_alt3`
+//! - 直接左递归`A -> A α | β`改写成`A -> β A_tail1, A_tail1 -> α A_tail1 | ε`
+//! - pest的原子规则(`@`、 `$`)、 `WHITESPACE`/`COMMENT`和LALRPOP的正则终结符交给词法分析
+//! - LALRPOP的宏(`Comma<T>`)按用到的参数展开， 动作代码和类型被忽略
+//!
+//! ```ignore
+//! let migration = Migration::from_pest("calc", &fs::read_to_string("calc.pest")?)?;
+//! for diag in migration.report.iter() {
+//!     println!("{}", diag);
+//! }
+//! let gram = migration.build()?;
+//! ```
+
+use std::error::Error;
+
+use indexmap::{IndexMap, IndexSet};
+use itertools::Itertools;
+
+use crate::{
+    builder::GramBuilder,
+    diagnostic::{Diagnostic, Diagnostics},
+    error::Trap,
+    gram::{Gram, ERROR_SYM_NAME},
+    parser::SrcLoc,
+    scannerless::CharClass,
+};
+
+
+pub struct Migration {
+    pub builder: GramBuilder,
+    /// 没有翻译、 近似翻译或者改写了的构造
+    pub report: Diagnostics,
+}
+
+impl Migration {
+    /// 以`SOI`开头的规则是开始符号， 没有的话是第一个规则
+    pub fn from_pest(name: &str, src: &str) -> Result<Self, Box<dyn Error>> {
+        PestReader { toks: Toks::new(src)?, lowering: Lowering::default() }.read(name)
+    }
+
+    /// 第一个`pub`的非终结符是开始符号， 没有的话是第一个非终结符
+    pub fn from_lalrpop(name: &str, src: &str) -> Result<Self, Box<dyn Error>> {
+        LalrpopReader {
+            toks: Toks::new(src)?,
+            lowering: Lowering::default(),
+        }
+        .read(name)
+    }
+
+    /// LL(1)冲突等由`GramBuilder::build`报告
+    pub fn build(&self) -> Result<Gram, Diagnostics> {
+        self.builder.build()
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Ident(String),
+    Str(String),
+    Char(char),
+    /// LALRPOP的`r"..."`
+    Regex(String),
+    Num(usize),
+    Punct(String),
+}
+
+const PUNCTS: [&str; 37] = [
+    "=>@L", "=>@R", "=>?", "=>", "->", "..", "::", "@L", "@R",
+    "{", "}", "(", ")", "[", "]", "<", ">", "|", "~", "?", "*", "+", "&", "!",
+    "=", ":", ";", ",", "@", "$", "^", "#", "-", ".", "'", "/", "%",
+];
+
+struct Toks {
+    toks: Vec<(Tok, SrcLoc)>,
+    i: usize,
+}
+
+impl Toks {
+    fn new(src: &str) -> Result<Self, Box<dyn Error>> {
+        let chars = src.chars().collect_vec();
+        let mut toks = vec![];
+        let (mut ln, mut col) = (1, 1);
+        let mut i = 0;
+
+        while i < chars.len() {
+            let loc = SrcLoc::new((ln, col));
+            let start = i;
+            let c = chars[i];
+            let rest: String = chars[i..(i + 4).min(chars.len())].iter().collect();
+
+            if c.is_whitespace() {
+                i += 1;
+            }
+            else if rest.starts_with("//") {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            else if rest.starts_with("/*") {
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    i += 1;
+                }
+                i += 2;
+            }
+            else if c == '"' {
+                let (lit, end) = read_str(&chars, i + 1)?;
+                toks.push((Tok::Str(lit), loc));
+                i = end;
+            }
+            else if c == 'r' && matches!(chars.get(i + 1), Some('"') | Some('#')) {
+                let hashes = chars[i + 1..].iter().take_while(|c| **c == '#').count();
+                let open = i + 1 + hashes;
+                let close = format!("\"{}", "#".repeat(hashes));
+                let body: String = chars[open + 1..].iter().collect();
+                let len = body
+                    .find(&close)
+                    .ok_or_else(|| Trap::new_box_err(&format!("unterminated raw string at {}", loc)))?;
+
+                toks.push((Tok::Regex(body[..len].to_string()), loc));
+                i = open + 1 + body[..len].chars().count() + close.chars().count();
+            }
+            else if c == '\'' && chars.get(i + 2) == Some(&'\'') {
+                toks.push((Tok::Char(chars[i + 1]), loc));
+                i += 3;
+            }
+            else if c == '\'' && chars.get(i + 1) == Some(&'\\') && chars.get(i + 3) == Some(&'\'') {
+                toks.push((Tok::Char(unescape(chars[i + 2])), loc));
+                i += 4;
+            }
+            else if c.is_ascii_digit() {
+                let end = (i..chars.len()).find(|&j| !chars[j].is_ascii_digit()).unwrap_or(chars.len());
+                let num: String = chars[i..end].iter().collect();
+                toks.push((Tok::Num(num.parse()?), loc));
+                i = end;
+            }
+            else if c.is_alphanumeric() || c == '_' {
+                let end = (i..chars.len())
+                    .find(|&j| !(chars[j].is_alphanumeric() || chars[j] == '_'))
+                    .unwrap_or(chars.len());
+                toks.push((Tok::Ident(chars[i..end].iter().collect()), loc));
+                i = end;
+            }
+            else if let Some(punct) = PUNCTS.iter().find(|punct| rest.starts_with(*punct)) {
+                toks.push((Tok::Punct(punct.to_string()), loc));
+                i += punct.len();
+            }
+            else {
+                // 动作代码里别的字符
+                toks.push((Tok::Punct(c.to_string()), loc));
+                i += 1;
+            }
+
+            for c in chars[start..i.min(chars.len())].iter() {
+                if *c == '\n' {
+                    ln += 1;
+                    col = 1;
+                }
+                else {
+                    col += 1;
+                }
+            }
+        }
+
+        Ok(Self { toks, i: 0 })
+    }
+
+    fn peek(&self) -> Option<&Tok> {
+        self.toks.get(self.i).map(|(tok, _)| tok)
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<&Tok> {
+        self.toks.get(self.i + n).map(|(tok, _)| tok)
+    }
+
+    fn loc(&self) -> SrcLoc {
+        self.toks
+            .get(self.i)
+            .or_else(|| self.toks.last())
+            .map_or(SrcLoc::new((1, 1)), |(_, loc)| loc.clone())
+    }
+
+    fn next(&mut self) -> Option<Tok> {
+        let tok = self.peek().cloned();
+        self.i += 1;
+
+        tok
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), Some(Tok::Punct(p)) if p == punct)
+    }
+
+    fn is_ident(&self, ident: &str) -> bool {
+        matches!(self.peek(), Some(Tok::Ident(name)) if name == ident)
+    }
+
+    fn eat_punct(&mut self, punct: &str) -> bool {
+        let found = self.is_punct(punct);
+        if found {
+            self.i += 1;
+        }
+
+        found
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> Result<(), Box<dyn Error>> {
+        let loc = self.loc();
+
+        match self.next() {
+            Some(Tok::Punct(p)) if p == punct => Ok(()),
+            other => Err(Trap::new_box_err(&format!("expect `{}` at {}, found {:?}", punct, loc, other))),
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String, Box<dyn Error>> {
+        let loc = self.loc();
+
+        match self.next() {
+            Some(Tok::Ident(name)) => Ok(name),
+            other => Err(Trap::new_box_err(&format!("expect identifier at {}, found {:?}", loc, other))),
+        }
+    }
+
+    /// 跳过直到深度为0的`stops`之一(不吃掉它)， 只按`()[]{}`计算深度
+    fn skip_until(&mut self, stops: &[&str]) {
+        let mut depth = 0usize;
+
+        while let Some(tok) = self.peek() {
+            if let Tok::Punct(p) = tok {
+                if depth == 0 && stops.contains(&p.as_str()) {
+                    return;
+                }
+
+                match p.as_str() {
+                    "(" | "[" | "{" => depth += 1,
+                    ")" | "]" | "}" => {
+                        if depth == 0 {
+                            return;
+                        }
+                        depth -= 1;
+                    }
+                    _ => (),
+                }
+            }
+
+            self.i += 1;
+        }
+    }
+
+    /// 当前在`open`上， 跳过整个配对的块
+    fn skip_block(&mut self) {
+        self.i += 1;
+        self.skip_until(&[]);
+        self.i += 1;
+    }
+}
+
+fn read_str(chars: &[char], mut i: usize) -> Result<(String, usize), Box<dyn Error>> {
+    let mut lit = String::new();
+
+    loop {
+        match chars.get(i) {
+            Some('"') => return Ok((lit, i + 1)),
+            Some('\\') => {
+                match chars.get(i + 1) {
+                    Some(c) => lit.push(unescape(*c)),
+                    None => break,
+                }
+                i += 2;
+            }
+            Some(c) => {
+                lit.push(*c);
+                i += 1;
+            }
+            None => break,
+        }
+    }
+
+    Err(Trap::new_box_err("unterminated string literal"))
+}
+
+fn unescape(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        other => other,
+    }
+}
+
+
+#[derive(Debug, Clone)]
+enum Expr {
+    /// `GramBuilder`的符号， 规则名、 终结符或者加引号的终结符
+    Sym(String),
+    Seq(Vec<Expr>),
+    Choice(Vec<Expr>),
+    /// 最少、 最多的次数
+    Repeat(Box<Expr>, usize, Option<usize>),
+    /// LALRPOP的宏调用`Comma<Expr>`
+    Call(String, Vec<Expr>, SrcLoc),
+    Skip,
+}
+
+impl Expr {
+    fn literal(lit: &str) -> Self {
+        Self::Sym(format!("\"{}\"", lit))
+    }
+
+    fn class(ranges: &[(char, char)]) -> Self {
+        Self::Sym(CharClass { negated: false, ranges: ranges.to_vec() }.to_string())
+    }
+
+    fn subst(&self, args: &IndexMap<String, Expr>) -> Self {
+        match self {
+            Self::Sym(name) => args.get(name).cloned().unwrap_or_else(|| self.clone()),
+            Self::Seq(exprs) => Self::Seq(exprs.iter().map(|expr| expr.subst(args)).collect()),
+            Self::Choice(exprs) => Self::Choice(exprs.iter().map(|expr| expr.subst(args)).collect()),
+            Self::Repeat(expr, min, max) => Self::Repeat(Box::new(expr.subst(args)), *min, *max),
+            Self::Call(name, call_args, loc) => {
+                Self::Call(name.clone(), call_args.iter().map(|arg| arg.subst(args)).collect(), loc.clone())
+            }
+            Self::Skip => Self::Skip,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Lowering {
+    rules: IndexMap<String, Vec<Vec<String>>>,
+    /// 源语法里的规则名和生成的辅助规则名
+    names: IndexSet<String>,
+    /// 宏名 => (参数, 定义)
+    macros: IndexMap<String, (Vec<String>, Expr)>,
+    report: Diagnostics,
+}
+
+impl Lowering {
+    fn report(&mut self, diag: Diagnostic, loc: SrcLoc) {
+        self.report.push(diag.with_loc(loc));
+    }
+
+    fn untranslated(&mut self, what: &str, loc: SrcLoc) {
+        self.report(Diagnostic::warning("untranslated", &format!("{} is not translated", what)), loc);
+    }
+
+    fn fresh(&mut self, base: &str, kind: &str) -> String {
+        let name = (1..)
+            .map(|n| format!("{}_{}{}", base, kind, n))
+            .find(|name| !self.names.contains(name))
+            .unwrap();
+
+        self.names.insert(name.clone());
+        name
+    }
+
+    fn define(&mut self, rule: &str, expr: &Expr) {
+        // 先占位， 规则排在它的辅助规则前面
+        self.rules.insert(rule.to_string(), vec![]);
+
+        let alts = self.alts(rule, expr);
+        self.rules.insert(rule.to_string(), alts);
+    }
+
+    fn alts(&mut self, rule: &str, expr: &Expr) -> Vec<Vec<String>> {
+        match expr {
+            Expr::Choice(exprs) => exprs.iter().flat_map(|expr| self.alts(rule, expr)).collect(),
+            _ => vec![self.seq(rule, expr)],
+        }
+    }
+
+    fn seq(&mut self, rule: &str, expr: &Expr) -> Vec<String> {
+        match expr {
+            Expr::Sym(sym) => vec![sym.clone()],
+            Expr::Seq(exprs) => exprs.iter().flat_map(|expr| self.seq(rule, expr)).collect(),
+            Expr::Skip => vec![],
+            Expr::Call(name, args, loc) => self.instantiate(name, args, loc.clone()).into_iter().collect(),
+            Expr::Choice(_) => {
+                let name = self.fresh(rule, "alt");
+                self.define(&name, expr);
+
+                vec![name]
+            }
+            Expr::Repeat(expr, min, max) => {
+                let body = self.seq(rule, expr);
+                if body.is_empty() {
+                    return vec![];
+                }
+
+                let mut syms = (0..*min).flat_map(|_| body.clone()).collect_vec();
+
+                match max {
+                    None => {
+                        let name = self.fresh(rule, "rep");
+                        let alt = [body, vec![name.clone()]].concat();
+                        self.rules.insert(name.clone(), vec![alt, vec![]]);
+                        syms.push(name);
+                    }
+                    // 嵌套成`opt1 -> x opt2 | ε`， 而不是并排的`opt opt`， 后者不是LL(1)的
+                    Some(max) => {
+                        let mut tail: Option<String> = None;
+
+                        for _ in *min..*max {
+                            let name = self.fresh(rule, "opt");
+                            let alt = body.iter().cloned().chain(tail.take()).collect_vec();
+                            self.rules.insert(name.clone(), vec![alt, vec![]]);
+                            tail = Some(name);
+                        }
+
+                        syms.extend(tail);
+                    }
+                }
+
+                syms
+            }
+        }
+    }
+
+    /// 宏调用`Comma<Expr>`展开成规则`Comma_Expr`， 同样的参数只展开一次
+    fn instantiate(&mut self, name: &str, args: &[Expr], loc: SrcLoc) -> Option<String> {
+        let (params, body) = match self.macros.get(name) {
+            Some(def) => def.clone(),
+            None => {
+                self.untranslated(&format!("undefined macro `{}`", name), loc);
+                return None;
+            }
+        };
+
+        let arg_names = args
+            .iter()
+            .map(|arg| match arg {
+                Expr::Sym(sym) => Some(sym.chars().filter(|c| c.is_alphanumeric() || *c == '_').collect::<String>()),
+                _ => None,
+            })
+            .collect::<Option<Vec<String>>>();
+
+        let rule = match arg_names {
+            Some(arg_names) if params.len() == args.len() => format!("{}_{}", name, arg_names.join("_")),
+            _ => {
+                self.untranslated(&format!("macro call `{}` with complex arguments", name), loc);
+                return None;
+            }
+        };
+
+        if self.names.insert(rule.clone()) {
+            let subst = params.into_iter().zip(args.iter().cloned()).collect::<IndexMap<String, Expr>>();
+            self.define(&rule, &body.subst(&subst));
+        }
+
+        Some(rule)
+    }
+
+    /// 直接左递归`A -> A α | β`改写成`A -> β A_tail, A_tail -> α A_tail | ε`
+    fn remove_left_recursion(&mut self, locs: &IndexMap<String, SrcLoc>) {
+        for rule in self.rules.keys().cloned().collect_vec() {
+            let (recursive, others): (Vec<Vec<String>>, Vec<Vec<String>>) = self.rules[&rule]
+                .iter()
+                .cloned()
+                .partition(|alt| alt.first() == Some(&rule));
+
+            if recursive.is_empty() {
+                continue;
+            }
+
+            let tail = self.fresh(&rule, "tail");
+            let loc = locs.get(&rule).cloned().unwrap_or_else(|| SrcLoc::new((1, 1)));
+            self.report(
+                Diagnostic::note(
+                    "left-recursion-rewritten",
+                    &format!("left recursion of `{}` is rewritten through `{}`", rule, tail),
+                )
+                .with_note("the tree shape differs from the original grammar"),
+                loc,
+            );
+
+            let prods = others
+                .into_iter()
+                .map(|beta| [beta, vec![tail.clone()]].concat())
+                .collect();
+            let mut tail_prods = recursive
+                .into_iter()
+                .map(|alpha| [alpha[1..].to_vec(), vec![tail.clone()]].concat())
+                .collect_vec();
+            tail_prods.push(vec![]);
+
+            self.rules.insert(rule, prods);
+            self.rules.insert(tail, tail_prods);
+        }
+    }
+
+    fn finish(mut self, name: &str, start: Option<String>, locs: &IndexMap<String, SrcLoc>) -> Migration {
+        self.remove_left_recursion(locs);
+
+        let mut builder = GramBuilder::new(name);
+        let start = start.filter(|start| self.rules.contains_key(start));
+        let order = start
+            .iter()
+            .chain(self.rules.keys().filter(|rule| Some(*rule) != start.as_ref()))
+            .cloned()
+            .collect_vec();
+
+        for rule in order {
+            builder = builder.rule(&rule);
+
+            for alt in self.rules[&rule].iter() {
+                builder = if alt.is_empty() { builder.alt(["ε"]) } else { builder.alt(alt) };
+            }
+        }
+
+        Migration { builder, report: self.report }
+    }
+}
+
+
+struct PestReader {
+    toks: Toks,
+    lowering: Lowering,
+}
+
+impl PestReader {
+    fn read(mut self, name: &str) -> Result<Migration, Box<dyn Error>> {
+        let mut bodies = vec![];
+        let mut locs = IndexMap::new();
+        let mut start = None;
+
+        while self.toks.peek().is_some() {
+            let loc = self.toks.loc();
+            let rule = self.toks.expect_ident()?;
+            self.toks.expect_punct("=")?;
+
+            let atomic = if self.toks.eat_punct("@") || self.toks.eat_punct("$") {
+                true
+            }
+            else {
+                if self.toks.is_ident("_") {
+                    self.toks.i += 1;
+                }
+                self.toks.eat_punct("!");
+                false
+            };
+
+            self.toks.expect_punct("{")?;
+            let starts_with_soi = self.toks.is_ident("SOI");
+            let body = self.expr()?;
+            self.toks.expect_punct("}")?;
+
+            if rule == "WHITESPACE" || rule == "COMMENT" {
+                self.lowering.report(
+                    Diagnostic::note("lexer-rule", &format!("implicit `{}` is left to the lexer", rule)),
+                    loc,
+                );
+                continue;
+            }
+            if atomic {
+                self.lowering.report(
+                    Diagnostic::note(
+                        "lexer-rule",
+                        &format!("atomic rule `{}` becomes the token `{}`, define it in the lexer", rule, rule),
+                    ),
+                    loc,
+                );
+                continue;
+            }
+
+            if starts_with_soi && start.is_none() {
+                start = Some(rule.clone());
+            }
+
+            self.lowering.names.insert(rule.clone());
+            locs.insert(rule.clone(), loc);
+            bodies.push((rule, body));
+        }
+
+        for (rule, body) in bodies {
+            self.lowering.define(&rule, &body);
+        }
+
+        Ok(self.lowering.finish(name, start, &locs))
+    }
+
+    fn expr(&mut self) -> Result<Expr, Box<dyn Error>> {
+        self.toks.eat_punct("|");
+
+        let mut alts = vec![self.seq()?];
+        while self.toks.eat_punct("|") {
+            alts.push(self.seq()?);
+        }
+
+        Ok(if alts.len() == 1 { alts.pop().unwrap() } else { Expr::Choice(alts) })
+    }
+
+    fn seq(&mut self) -> Result<Expr, Box<dyn Error>> {
+        let mut items = vec![self.term()?];
+        while self.toks.eat_punct("~") {
+            items.push(self.term()?);
+        }
+
+        Ok(if items.len() == 1 { items.pop().unwrap() } else { Expr::Seq(items) })
+    }
+
+    fn term(&mut self) -> Result<Expr, Box<dyn Error>> {
+        let loc = self.toks.loc();
+
+        // 节点标签`#tag = e`
+        if self.toks.is_punct("#") {
+            self.toks.i += 1;
+            self.toks.expect_ident()?;
+            self.toks.expect_punct("=")?;
+        }
+
+        let mut predicate = false;
+        while self.toks.eat_punct("&") || self.toks.eat_punct("!") {
+            predicate = true;
+        }
+
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.toks.eat_punct("?") {
+                expr = Expr::Repeat(Box::new(expr), 0, Some(1));
+            }
+            else if self.toks.eat_punct("*") {
+                expr = Expr::Repeat(Box::new(expr), 0, None);
+            }
+            else if self.toks.eat_punct("+") {
+                expr = Expr::Repeat(Box::new(expr), 1, None);
+            }
+            else if self.toks.is_punct("{") {
+                self.toks.i += 1;
+                let (min, max) = self.bounds()?;
+                self.toks.expect_punct("}")?;
+                expr = Expr::Repeat(Box::new(expr), min, max);
+            }
+            else {
+                break;
+            }
+        }
+
+        if predicate {
+            self.lowering.untranslated("lookahead predicate", loc);
+            return Ok(Expr::Skip);
+        }
+
+        Ok(expr)
+    }
+
+    /// `{n}`、 `{n,}`、 `{,m}`、 `{n,m}`
+    fn bounds(&mut self) -> Result<(usize, Option<usize>), Box<dyn Error>> {
+        let min = match self.toks.peek() {
+            Some(Tok::Num(n)) => {
+                let n = *n;
+                self.toks.i += 1;
+                Some(n)
+            }
+            _ => None,
+        };
+
+        if !self.toks.eat_punct(",") {
+            return Ok((min.unwrap_or(0), min));
+        }
+
+        let max = match self.toks.peek() {
+            Some(Tok::Num(n)) => {
+                let n = *n;
+                self.toks.i += 1;
+                Some(n)
+            }
+            _ => None,
+        };
+
+        Ok((min.unwrap_or(0), max))
+    }
+
+    fn primary(&mut self) -> Result<Expr, Box<dyn Error>> {
+        let loc = self.toks.loc();
+
+        match self.toks.next() {
+            Some(Tok::Punct(p)) if p == "(" => {
+                let expr = self.expr()?;
+                self.toks.expect_punct(")")?;
+                Ok(expr)
+            }
+            Some(Tok::Str(lit)) => Ok(Expr::literal(&lit)),
+            Some(Tok::Punct(p)) if p == "^" => match self.toks.next() {
+                Some(Tok::Str(lit)) => {
+                    self.lowering.report(
+                        Diagnostic::warning(
+                            "approximated",
+                            &format!("case-insensitive \"{}\" is matched case-sensitively", lit),
+                        )
+                        .with_note("see `LL1Parser::with_case_insensitive`"),
+                        loc,
+                    );
+                    Ok(Expr::literal(&lit))
+                }
+                other => Err(Trap::new_box_err(&format!("expect string after `^` at {}, found {:?}", loc, other))),
+            },
+            Some(Tok::Char(lo)) => {
+                self.toks.expect_punct("..")?;
+                match self.toks.next() {
+                    Some(Tok::Char(hi)) => Ok(Expr::class(&[(lo, hi)])),
+                    other => Err(Trap::new_box_err(&format!("expect char at {}, found {:?}", loc, other))),
+                }
+            }
+            Some(Tok::Ident(name)) => Ok(self.builtin(&name, loc)),
+            other => Err(Trap::new_box_err(&format!("unexpected {:?} at {}", other, loc))),
+        }
+    }
+
+    fn builtin(&mut self, name: &str, loc: SrcLoc) -> Expr {
+        let ranges: &[(char, char)] = match name {
+            "SOI" => return Expr::Skip,
+            "EOI" => return Expr::Sym("$".to_string()),
+            "ANY" => return Expr::Sym("_".to_string()),
+            "ASCII_DIGIT" => &[('0', '9')],
+            "ASCII_NONZERO_DIGIT" => &[('1', '9')],
+            "ASCII_BIN_DIGIT" => &[('0', '1')],
+            "ASCII_OCT_DIGIT" => &[('0', '7')],
+            "ASCII_HEX_DIGIT" => &[('0', '9'), ('a', 'f'), ('A', 'F')],
+            "ASCII_ALPHA_LOWER" => &[('a', 'z')],
+            "ASCII_ALPHA_UPPER" => &[('A', 'Z')],
+            "ASCII_ALPHA" => &[('a', 'z'), ('A', 'Z')],
+            "ASCII_ALPHANUMERIC" => &[('a', 'z'), ('A', 'Z'), ('0', '9')],
+            "PUSH" | "POP" | "POP_ALL" | "PEEK" | "PEEK_ALL" | "DROP" => {
+                if self.toks.is_punct("(") || self.toks.is_punct("[") {
+                    self.toks.skip_block();
+                }
+                self.lowering.untranslated(&format!("stack operation `{}`", name), loc);
+                return Expr::Skip;
+            }
+            _ => return Expr::Sym(name.to_string()),
+        };
+
+        Expr::class(ranges)
+    }
+}
+
+
+struct LalrpopReader {
+    toks: Toks,
+    lowering: Lowering,
+}
+
+impl LalrpopReader {
+    fn read(mut self, name: &str) -> Result<Migration, Box<dyn Error>> {
+        let mut bodies = vec![];
+        let mut locs = IndexMap::new();
+        let mut start = None;
+
+        while self.toks.peek().is_some() {
+            let loc = self.toks.loc();
+            let precedence = self.annotations();
+
+            if self.toks.is_ident("use") || self.toks.is_ident("grammar") {
+                self.toks.skip_until(&[";"]);
+                self.toks.expect_punct(";")?;
+                continue;
+            }
+            if self.toks.is_ident("extern") || self.toks.is_ident("match") {
+                let what = self.toks.expect_ident()?;
+                self.toks.skip_until(&["{"]);
+                self.toks.skip_block();
+                while self.toks.is_ident("else") {
+                    self.toks.i += 1;
+                    self.toks.skip_block();
+                }
+                self.lowering.untranslated(&format!("`{}` block", what), loc);
+                continue;
+            }
+
+            let public = self.toks.is_ident("pub");
+            if public {
+                self.toks.i += 1;
+            }
+
+            let rule = self.toks.expect_ident()?;
+            let params = if self.toks.eat_punct("<") {
+                let mut params = vec![];
+                while !self.toks.eat_punct(">") {
+                    params.push(self.toks.expect_ident()?);
+                    self.toks.eat_punct(",");
+                }
+                params
+            }
+            else {
+                vec![]
+            };
+
+            if self.toks.eat_punct(":") {
+                self.toks.skip_until(&["="]);
+            }
+            self.toks.expect_punct("=")?;
+
+            let body = self.alternatives()?;
+            if precedence {
+                self.lowering.untranslated(&format!("precedence annotations of `{}`", rule), loc.clone());
+            }
+
+            if !params.is_empty() {
+                self.lowering.macros.insert(rule, (params, body));
+                continue;
+            }
+
+            if public && start.is_none() {
+                start = Some(rule.clone());
+            }
+
+            self.lowering.names.insert(rule.clone());
+            locs.insert(rule.clone(), loc);
+            bodies.push((rule, body));
+        }
+
+        for (rule, body) in bodies {
+            self.lowering.define(&rule, &body);
+        }
+
+        Ok(self.lowering.finish(name, start, &locs))
+    }
+
+    /// 跳过`#[...]`， 有优先级注解时返回true
+    fn annotations(&mut self) -> bool {
+        let mut precedence = false;
+
+        while self.toks.is_punct("#") && self.toks.peek_nth(1) == Some(&Tok::Punct("[".to_string())) {
+            self.toks.i += 1;
+            precedence |= matches!(
+                self.toks.peek_nth(1),
+                Some(Tok::Ident(name)) if name == "precedence" || name == "assoc"
+            );
+            self.toks.skip_block();
+        }
+
+        precedence
+    }
+
+    /// `{ alt, alt, }`或者单个`alt;`
+    fn alternatives(&mut self) -> Result<Expr, Box<dyn Error>> {
+        let mut alts = vec![];
+
+        if self.toks.eat_punct("{") {
+            while !self.toks.eat_punct("}") {
+                if self.annotations() {
+                    let loc = self.toks.loc();
+                    self.lowering.untranslated("precedence annotation", loc);
+                }
+                alts.push(self.alt(&["}"])?);
+
+                if !self.toks.eat_punct(",") && !self.toks.is_punct("}") {
+                    let loc = self.toks.loc();
+                    return Err(Trap::new_box_err(&format!("expect `,` or `}}` at {}", loc)));
+                }
+            }
+            self.toks.eat_punct(";");
+        }
+        else {
+            alts.push(self.alt(&[";"])?);
+            self.toks.expect_punct(";")?;
+        }
+
+        Ok(if alts.len() == 1 { alts.pop().unwrap() } else { Expr::Choice(alts) })
+    }
+
+    fn alt(&mut self, end: &[&str]) -> Result<Expr, Box<dyn Error>> {
+        let mut items = vec![];
+
+        loop {
+            if self.toks.peek().is_none() || self.toks.is_punct(",") || end.iter().any(|p| self.toks.is_punct(p)) {
+                break;
+            }
+            if self.toks.is_ident("if") {
+                let loc = self.toks.loc();
+                self.lowering.untranslated("macro condition", loc);
+                self.toks.skip_until(&["=>", "=>?", "=>@L", "=>@R", ","]);
+                continue;
+            }
+            if ["=>", "=>?", "=>@L", "=>@R"].iter().any(|p| self.toks.is_punct(p)) {
+                self.toks.i += 1;
+                self.toks.skip_until(&[",", ";"]);
+                break;
+            }
+
+            items.push(self.symbol()?);
+        }
+
+        Ok(Expr::Seq(items))
+    }
+
+    fn symbol(&mut self) -> Result<Expr, Box<dyn Error>> {
+        let mut expr = if self.toks.eat_punct("<") {
+            // `<name:Sym ...>`、 `<mut name:Sym>`或者`<Sym ...>`
+            if self.toks.is_ident("mut") {
+                self.toks.i += 1;
+            }
+            if matches!(self.toks.peek(), Some(Tok::Ident(_))) && self.toks.peek_nth(1) == Some(&Tok::Punct(":".to_string())) {
+                self.toks.i += 2;
+            }
+
+            let mut items = vec![];
+            while !self.toks.eat_punct(">") {
+                items.push(self.symbol()?);
+            }
+            Expr::Seq(items)
+        }
+        else {
+            self.atom()?
+        };
+
+        loop {
+            if self.toks.eat_punct("?") {
+                expr = Expr::Repeat(Box::new(expr), 0, Some(1));
+            }
+            else if self.toks.eat_punct("*") {
+                expr = Expr::Repeat(Box::new(expr), 0, None);
+            }
+            else if self.toks.eat_punct("+") {
+                expr = Expr::Repeat(Box::new(expr), 1, None);
+            }
+            else {
+                break;
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn atom(&mut self) -> Result<Expr, Box<dyn Error>> {
+        let loc = self.toks.loc();
+
+        match self.toks.next() {
+            Some(Tok::Str(lit)) => Ok(Expr::literal(&lit)),
+            Some(Tok::Regex(regex)) => {
+                self.lowering.report(
+                    Diagnostic::note(
+                        "lexer-rule",
+                        &format!("regex terminal r\"{}\" becomes the token `{}`, define it in the lexer", regex, regex),
+                    ),
+                    loc,
+                );
+                Ok(Expr::literal(&regex))
+            }
+            Some(Tok::Punct(p)) if p == "(" => {
+                let mut items = vec![];
+                while !self.toks.eat_punct(")") {
+                    items.push(self.symbol()?);
+                }
+                Ok(Expr::Seq(items))
+            }
+            Some(Tok::Punct(p)) if p == "!" => Ok(Expr::Sym(ERROR_SYM_NAME.to_string())),
+            Some(Tok::Punct(p)) if p == "@L" || p == "@R" => Ok(Expr::Skip),
+            Some(Tok::Ident(name)) if self.toks.is_punct("<") => {
+                self.toks.expect_punct("<")?;
+
+                let mut args = vec![];
+                while !self.toks.eat_punct(">") {
+                    args.push(self.symbol()?);
+                    self.toks.eat_punct(",");
+                }
+
+                Ok(Expr::Call(name, args, loc))
+            }
+            Some(Tok::Ident(name)) => Ok(Expr::Sym(name)),
+            other => Err(Trap::new_box_err(&format!("unexpected {:?} at {}", other, loc))),
+        }
+    }
+}
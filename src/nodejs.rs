@@ -0,0 +1,127 @@
+//! Node.js Bindings: 用napi-rs导出解析到JSON和诊断， JS写的编辑器扩展和构建工具直接调用，
+//! 不用另起进程， 需要开启`nodejs`特性， 构建出的动态库改名为`ll1engine.node`
+//!
+//! ```none
+//! cargo rustc --lib --release --features nodejs --crate-type cdylib
+//! cp target/release/libll1engine.so ll1engine.node
+//! ```
+//!
+//! ```js
+//! const { Parser, checkGrammar } = require("./ll1engine.node");
+//!
+//! checkGrammar(dsl);  // { format: "ll1engine-diagnostics", version: 1, diagnostics: [{ severity, code, message, loc, notes }] }
+//!
+//! const parser = new Parser(dsl, [{ name: "ws", pattern: "\\s+", skip: true }, { name: "id", pattern: "[a-z]+" }]);
+//! const { ast, diagnostics } = parser.parse(src);
+//! // ast: { format: "ll1engine-ast", version: 1, root: { type: "tree", kind: "Prog", span, children: [...] } }
+//! ```
+//!
+//! AST和诊断的形状见`schema`模块， 不能识别的字符使`ast`为`null`
+//!
+//! Node-API的符号在运行时从宿主装载， 所以开启特性后可执行文件照常链接，
+//! 不在node里运行时(比如`cargo test`)napi会打印装载失败的提示， 不影响其余功能
+
+use napi::{Error as NapiError, Result, Status};
+use napi_derive::napi;
+use serde_json::Value;
+
+use crate::{
+    builder::GramBuilder,
+    diagnostic::{Diagnostic, Diagnostics},
+    lexer::{Lexer, RegexTokenMatcher},
+    parser::{LL1Parser, ParseOptions},
+};
+
+
+#[napi(object)]
+pub struct TokenRuleSpec {
+    pub name: String,
+    pub pattern: String,
+    /// 匹配的token被丢弃(空白、 注释)
+    pub skip: Option<bool>,
+}
+
+
+#[napi]
+pub struct Parser {
+    parser: LL1Parser,
+    lexer: Lexer,
+}
+
+#[napi]
+impl Parser {
+    /// token规则的声明顺序就是优先级， 语法或者正则有问题时抛出异常
+    #[napi(constructor)]
+    pub fn new(dsl: String, tokens: Vec<TokenRuleSpec>) -> Result<Self> {
+        let gram = GramBuilder::from_dsl(&dsl)
+            .map_err(|err| invalid_arg(err.to_string()))?
+            .build()
+            .map_err(|diags| invalid_arg(diags.to_string()))?;
+        let parser = LL1Parser::try_new(gram).map_err(|err| invalid_arg(err.to_string()))?;
+
+        let mut rules = vec![];
+        for spec in tokens.iter() {
+            let matcher = RegexTokenMatcher::try_new(&spec.pattern).map_err(|err| invalid_arg(err.to_string()))?;
+            rules.push((matcher, spec.name.clone()));
+        }
+
+        let mut lexer = Lexer::new(rules);
+        for spec in tokens.iter().filter(|spec| spec.skip.unwrap_or(false)) {
+            lexer = lexer.with_skip(&spec.name);
+        }
+
+        let lexer = lexer.compile().map_err(|err| invalid_arg(err.to_string()))?;
+
+        Ok(Self { parser, lexer })
+    }
+
+    /// `{ ast, diagnostics }`， 语法错误会被恢复， 作为诊断返回
+    #[napi]
+    pub fn parse(&self, src: String) -> Result<Value> {
+        serde_json::from_str(&self.parse_to_json(src)).map_err(|err| NapiError::from_reason(err.to_string()))
+    }
+
+    /// 同`parse`， 直接返回JSON文本， 结果要转发给别的进程时省去一次转换
+    #[napi(js_name = "parseToJson")]
+    pub fn parse_to_json(&self, src: String) -> String {
+        let tokens = match self.lexer.tokenize_str(&src) {
+            Ok(tokens) => tokens,
+            Err(err) => {
+                let mut diags = Diagnostics::new();
+                diags.push(Diagnostic::error("unrecognized-token", &err.to_string()));
+
+                return format!(r#"{{"ast":null,"diagnostics":{}}}"#, diags.to_json());
+            }
+        };
+
+        let (root, diags) = self.parser.parse_diag(tokens, &ParseOptions::default());
+
+        format!(r#"{{"ast":{},"diagnostics":{}}}"#, root.as_ref().borrow().to_json(), diags.to_json())
+    }
+}
+
+/// DSL的语法错误、 语法的错误和lint警告
+#[napi(js_name = "checkGrammar")]
+pub fn check_grammar(dsl: String) -> Result<Value> {
+    let diags = match GramBuilder::from_dsl(&dsl) {
+        Ok(builder) => {
+            let (gram, mut diags) = builder.assemble();
+            diags.extend(gram.validate());
+
+            diags
+        }
+        Err(err) => {
+            let mut diags = Diagnostics::new();
+            diags.push(Diagnostic::error("dsl-syntax", &err.to_string()));
+
+            diags
+        }
+    };
+
+    serde_json::from_str(&diags.to_json()).map_err(|err| NapiError::from_reason(err.to_string()))
+}
+
+
+fn invalid_arg(msg: String) -> NapiError {
+    NapiError::new(Status::InvalidArg, msg)
+}
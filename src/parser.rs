@@ -1,32 +1,85 @@
 //! Synax Parser: translates directly into synax tree based on rule.rs.
 
+use indexmap::{indexmap, indexset, IndexMap, IndexSet};
 use itertools::Itertools;
-use m6stack::Stack;
 
+use std::any::Any;
 use std::cell::RefCell;
+use std::hash::{Hash, Hasher};
 use std::fmt;
 use std::rc::Rc;
+use std::sync::Arc;
 use std::path::PathBuf;
-use std::error::Error;
 use std::fs;
+use std::io;
 
 use crate::gram::*;
-use crate::{
-    VERBOSE, VerboseLv
-};
+use crate::error::{LlResult, ParseError, ParseErrorKind};
+use crate::repair::hint_similar;
+use crate::lexer::Lexer;
+use crate::redact::Redaction;
+use crate::VerboseLv;
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use crate::stats::ParseStats;
+use crate::analysis::GramAnalysis;
+use crate::lookahead::follow_entries;
+use crate::resolve::Resolution;
+use crate::trace::TraceStep;
 
 
 ////////////////////////////////////////////////////////////////////////////////
-//// Token
+////// Token
+
+/// 词法分析时附加在token上的值， 比如已经解析好的整数字面量
+pub type Payload = Arc<dyn Any + Send + Sync>;
 
 #[derive(Debug, Clone)]
 pub struct Token {
     name: String,
     value: String,
-    loc: SrcLoc
+    loc: SrcLoc,
+    /// 不是来自源码， 而是编辑语法树时合成的
+    synthesized: bool,
+    payload: Option<Payload>,
 }
 
 impl Token {
+    pub fn new(name: &str, value: &str, loc: SrcLoc) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            loc,
+            synthesized: false,
+            payload: None,
+        }
+    }
+
+    /// 能匹配终结符`sym`的token， 值取约束的值， 没有约束就用token名
+    pub fn from_sym(sym: &GramSym, loc: SrcLoc) -> Self {
+        let name = sym.name();
+        let (name, value) = sym.guard().unwrap_or((name.as_ref(), name.as_ref()));
+
+        Self::new(name, value, loc)
+    }
+
+    pub fn with_payload<T: Any + Send + Sync>(mut self, value: T) -> Self {
+        self.payload = Some(Arc::new(value));
+        self
+    }
+
+    pub(crate) fn set_payload(&mut self, payload: Option<Payload>) {
+        self.payload = payload;
+    }
+
+    /// 类型不符时为None
+    pub fn payload<T: Any>(&self) -> Option<&T> {
+        self.payload.as_ref()?.downcast_ref::<T>()
+    }
+
+    pub fn has_payload(&self) -> bool {
+        self.payload.is_some()
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -39,8 +92,16 @@ impl Token {
         self.loc.clone()
     }
 
+    pub fn is_synthesized(&self) -> bool {
+        self.synthesized
+    }
+
+    pub(crate) fn set_synthesized(&mut self) {
+        self.synthesized = true;
+    }
+
     pub fn to_fst_set_sym(&self) -> FstSetSym {
-        FstSetSym::Sym(self.name.clone())
+        FstSetSym::Sym(self.to_gram_sym())
     }
 
     /// To GramSym::Terminal
@@ -49,11 +110,24 @@ impl Token {
     }
 
     pub fn to_pred_set_sym(&self) -> PredSetSym {
-        PredSetSym::Sym(self.name.clone())
+        PredSetSym::Sym(self.to_gram_sym())
+    }
+
+    /// 带值约束的终结符还要求值相等， `_`匹配任意token， `~{...}`匹配不在集合里的token，
+    /// 字符类匹配值是类里一个字符的token， 输入结束`$`不匹配任何token
+    pub fn matches(&self, sym: &GramSym) -> bool {
+        match sym {
+            GramSym::Any => true,
+            GramSym::NotSet(excluded) => !excluded.contains(&self.name),
+            GramSym::Guarded(name, value) => self.name == *name && self.value == *value,
+            GramSym::CharClass(class) => class.matches(&self.value),
+            GramSym::Terminal(name) => *name == self.name,
+            GramSym::Eof | GramSym::NonTerminal(_) => false,
+        }
     }
 
     pub fn to_foll_set_sym(&self) -> FollSetSym {
-        FollSetSym::Sym(self.name.clone())
+        FollSetSym::Sym(self.to_gram_sym())
     }
 }
 
@@ -66,7 +140,59 @@ impl fmt::Display for Token {
 
 
 ////////////////////////////////////////////////////////////////////////////////
-//// Source File Structure
+////// Source File Structure
+
+
+/// 行列号的约定， 默认与编辑器一致： 行列都从1开始
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocConfig {
+    pub one_based_line: bool,
+    pub one_based_col: bool,
+    /// tab推进到下一个制表位， 宽度为1时tab只算一列
+    pub tab_width: usize,
+    /// 单独的`\r`(老式Mac)也算换行， `\r\n`总是一个换行
+    pub cr_newline: bool,
+}
+
+impl Default for LocConfig {
+    fn default() -> Self {
+        Self {
+            one_based_line: true,
+            one_based_col: true,
+            tab_width: 1,
+            cr_newline: false,
+        }
+    }
+}
+
+impl LocConfig {
+    pub fn first_line(&self) -> usize {
+        if self.one_based_line { 1 } else { 0 }
+    }
+
+    pub fn first_col(&self) -> usize {
+        if self.one_based_col { 1 } else { 0 }
+    }
+
+    /// 在`col`处遇到字符`c`之后的列
+    pub fn next_col(&self, col: usize, c: char) -> usize {
+        if c == '\t' && self.tab_width > 1 {
+            let base = self.first_col();
+
+            ((col - base) / self.tab_width + 1) * self.tab_width + base
+        } else {
+            col + 1
+        }
+    }
+
+    /// `c`后面跟着`next`时， `c`之后是否换行
+    pub fn is_line_break(&self, c: char, next: Option<char>) -> bool {
+        c == '\n' || (self.cr_newline && c == '\r' && next != Some('\n'))
+    }
+}
+
+
+pub(crate) const BOM: char = '\u{feff}';
 
 
 /// SrcFileInfo
@@ -79,36 +205,74 @@ pub struct SrcFileInfo {
     /// lines[x]: number of total chars until lines x [x]
     /// inspired by `proc_macro2`: `FileInfo`
     lines: Vec<usize>,
-
-    srcstr: String
+    /// 每行开头的字节偏移
+    line_bytes: Vec<usize>,
+
+    srcstr: String,
+    /// 源码的char数
+    chars: usize,
+    /// 源码里有tab； 没有时(或者tab只算一列时)列号由偏移直接算出
+    tabs: bool,
+    config: LocConfig,
+    /// 源码开头有UTF-8 BOM(已去掉)
+    bom: bool,
 }
 
 impl SrcFileInfo {
-    pub fn new(path: PathBuf) -> Result<Self, Box<dyn Error>> {
+    pub fn new(path: PathBuf) -> LlResult<Self> {
         let srcstr = fs::read_to_string(&path)?;
 
-        let lines = Self::build_lines(&srcstr);
+        Ok(Self::from_srcstr(path, srcstr))
+    }
+
+    /// 源代码不在文件里时， path只用于显示。
+    /// 开头的BOM会被去掉， 不占位置
+    pub fn from_srcstr(path: PathBuf, srcstr: String) -> Self {
+        let (srcstr, bom) = match srcstr.strip_prefix(BOM) {
+            Some(rest) => (rest.to_string(), true),
+            None => (srcstr, false),
+        };
+
+        let config = LocConfig::default();
+        let (lines, line_bytes) = Self::build_lines(&srcstr, &config);
 
-        Ok(Self {
+        Self {
             path,
             lines,
-            srcstr
-        })
+            line_bytes,
+            chars: srcstr.chars().count(),
+            tabs: srcstr.contains('\t'),
+            srcstr,
+            config,
+            bom,
+        }
+    }
+
+    pub fn with_config(mut self, config: LocConfig) -> Self {
+        let (lines, line_bytes) = Self::build_lines(&self.srcstr, &config);
+
+        self.lines = lines;
+        self.line_bytes = line_bytes;
+        self.config = config;
+        self
     }
 
-    fn build_lines(srcstr: &str) -> Vec<usize> {
+    fn build_lines(srcstr: &str, config: &LocConfig) -> (Vec<usize>, Vec<usize>) {
         let mut lines = vec![0];
+        let mut line_bytes = vec![0];
         let mut total = 0usize;
+        let mut chars = srcstr.char_indices().peekable();
 
-        for c in srcstr.chars() {
+        while let Some((pos, c)) = chars.next() {
             total += 1;
 
-            if c == '\n' {
+            if config.is_line_break(c, chars.peek().map(|(_, next)| *next)) {
                 lines.push(total);
+                line_bytes.push(pos + c.len_utf8());
             }
         }
 
-        lines
+        (lines, line_bytes)
     }
 
     pub fn get_srcstr(&self) -> &str {
@@ -119,21 +283,81 @@ impl SrcFileInfo {
         &self.path
     }
 
+    pub fn config(&self) -> &LocConfig {
+        &self.config
+    }
+
+    pub fn has_bom(&self) -> bool {
+        self.bom
+    }
+
+    /// 第`ln`行的内容(按`LocConfig`的行号)， 不含换行符
+    pub fn line_text(&self, ln: usize) -> Option<&str> {
+        let idx = ln.checked_sub(self.config.first_line())?;
+        let start = *self.line_bytes.get(idx)?;
+        let end = self.line_bytes.get(idx + 1).copied().unwrap_or(self.srcstr.len());
+        let text = &self.srcstr[start..end];
+
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        Some(text.strip_suffix('\r').unwrap_or(text))
+    }
+
+    /// `offset`按char计算， 行列号按`LocConfig`的约定
     pub fn offset2srcloc(&self, offset: usize) -> SrcLoc {
-        match self.lines.binary_search(&offset) {
-            Ok(found) => {
-                SrcLoc {
-                    ln: found,
-                    col: 0  // 换行处
-                }
-            },
-            Err(idx) => {
-                SrcLoc {
-                    ln: idx,
-                    col: offset - self.lines[idx - 1]  // 显然idx >= 0
-                }
+        // 最后一个不超过offset的行首
+        let idx = match self.lines.binary_search(&offset) {
+            Ok(found) => found,
+            Err(idx) => idx - 1,  // lines[0] == 0， 显然idx >= 1
+        };
+
+        let col = if self.is_col_linear() {
+            self.config.first_col() + offset - self.lines[idx]
+        }
+        else {
+            self.srcstr[self.line_bytes[idx]..]
+                .chars()
+                .take(offset - self.lines[idx])
+                .fold(self.config.first_col(), |col, c| self.config.next_col(col, c))
+        };
+
+        SrcLoc {
+            ln: idx + self.config.first_line(),
+            col,
+        }
+    }
+
+    /// `offset2srcloc`的逆运算， 位置不在源码里(或者落在tab展开的中间)时为None
+    pub fn srcloc2offset(&self, loc: &SrcLoc) -> Option<usize> {
+        let idx = loc.ln.checked_sub(self.config.first_line())?;
+        let line_start = *self.lines.get(idx)?;
+        let line_len = self.lines.get(idx + 1).map_or(self.chars, |next| *next) - line_start;
+
+        if self.is_col_linear() {
+            let i = loc.col.checked_sub(self.config.first_col())?;
+
+            return if i <= line_len { Some(line_start + i) } else { None };
+        }
+
+        let mut col = self.config.first_col();
+        let mut chars = self.srcstr[self.line_bytes[idx]..].chars();
+
+        for i in 0..=line_len {
+            if col == loc.col {
+                return Some(line_start + i);
+            }
+            if col > loc.col {
+                return None;
             }
+
+            col = self.config.next_col(col, chars.next()?);
         }
+
+        None
+    }
+
+    /// 每个char都只占一列
+    fn is_col_linear(&self) -> bool {
+        self.config.tab_width <= 1 || !self.tabs
     }
 }
 
@@ -144,7 +368,7 @@ impl fmt::Debug for SrcFileInfo {
 }
 
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct SrcLoc {
     pub ln: usize,
     pub col: usize
@@ -177,7 +401,7 @@ impl fmt::Display for SrcLoc {
 /////// AST
 
 /// AST Node
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ASTNode {
     Tree(Rc<RefCell<AST>>),
     Leaf(Rc<Token>),
@@ -185,14 +409,9 @@ pub enum ASTNode {
 
 impl ASTNode {
     pub fn dump(&self, f: &mut fmt::Formatter, padlevel: usize) -> fmt::Result {
-        let padding = "  ".repeat(padlevel);
-
         match self {
-            Self::Leaf(token) => writeln!(f, "{}({}){}", padding, padlevel, *token),
-            Self::Tree(ast) => {
-                let ast_ref = ast.as_ref().borrow();
-                ast_ref.dump(f, padlevel)
-            }
+            Self::Leaf(token) => writeln!(f, "{}({}){}", "  ".repeat(padlevel), padlevel, *token),
+            Self::Tree(ast) => ast.as_ref().borrow().dump_with(f, padlevel, &DumpOptions::default()),
         }
     }
 
@@ -238,7 +457,14 @@ impl fmt::Display for ASTNode {
 pub struct AST {
     /// AST's grammar type
     sym: GramSym,
+    /// 测过`SmallVec`之类的内联存储， 节点变大反而让解析慢了两成以上， 所以还是`Vec`
     elems: Vec<(GramSym, ASTNode)>,
+    /// 推导出这个节点的产生式上的注解
+    attrs: ProdAttrs,
+    /// 推导出这个节点的产生式
+    prod: Option<GramProd>,
+    /// 编辑语法树时拼接进来的
+    synthesized: bool,
 }
 
 impl AST {
@@ -246,6 +472,9 @@ impl AST {
         Self {
             sym: sym.clone(),
             elems: vec![],
+            attrs: ProdAttrs::new(),
+            prod: None,
+            synthesized: false,
         }
     }
 
@@ -253,6 +482,24 @@ impl AST {
         &self.sym
     }
 
+    pub fn attrs(&self) -> &ProdAttrs {
+        &self.attrs
+    }
+
+    pub fn attr(&self, key: &str) -> Option<&str> {
+        self.attrs.get(key).map(|value| value.as_str())
+    }
+
+    /// 推导出这个节点的产生式， 语义分析可以按分支分派， 不用检查子节点去猜；
+    /// 它的编号见`Gram::prod_index`， 不是解析出来的节点(手工构建、 反序列化的)为None
+    pub fn prod(&self) -> Option<&GramProd> {
+        self.prod.as_ref()
+    }
+
+    pub(crate) fn set_attrs(&mut self, attrs: ProdAttrs) {
+        self.attrs = attrs;
+    }
+
     pub fn elem_syms(&self) -> Vec<GramSym> {
         self.elems.iter().map(|x| x.0.clone()).collect_vec()
     }
@@ -270,10 +517,12 @@ impl AST {
     }
 
     pub fn insert_leaf(&mut self, token: Token) {
-        let leaf_name = token.to_gram_sym();
-        let leaf = ASTNode::Leaf(Rc::new(token));
+        self.insert_leaf_as(token.to_gram_sym(), token);
+    }
 
-        self.elems.push((leaf_name, leaf));
+    /// 叶子的符号不是token名， 比如规则里写明的输入结束`$`
+    pub(crate) fn insert_leaf_as(&mut self, sym: GramSym, token: Token) {
+        self.elems.push((sym, ASTNode::Leaf(Rc::new(token))));
     }
 
     pub fn insert_tree(&mut self, tree: Rc<RefCell<AST>>) {
@@ -287,13 +536,40 @@ impl AST {
         self.elems.push((node.to_gram_sym().to_owned(), node));
     }
 
-    fn dump(&self, f: &mut fmt::Formatter, padlevel: usize) -> fmt::Result {
-        let padding = "  ".repeat(padlevel);
+    /// 流式写出， 大树输出到文件或者`BufWriter`时不必先拼成一个字符串
+    pub fn write_to<W: io::Write>(&self, w: &mut W, options: &DumpOptions) -> io::Result<()> {
+        let mut adapter = IoAdapter { inner: w, error: None };
+
+        self.dump_with(&mut adapter, 0, options).map_err(|_| {
+            adapter
+                .error
+                .take()
+                .unwrap_or_else(|| io::Error::other("formatter error"))
+        })
+    }
+
+    /// 用显式的栈遍历， 很深的树也不会栈溢出
+    fn dump_with<W: fmt::Write>(&self, w: &mut W, padlevel: usize, options: &DumpOptions) -> fmt::Result {
+        writeln!(w, "{}", options.tree_line(self, padlevel))?;
 
-        writeln!(f, "{}({}){}: ", padding, padlevel, self.sym())?;
+        let mut stack = vec![];
+        push_dump_elems(&mut stack, &self.elems, padlevel + 1, options);
 
-        for (_elem_sym, elem_node) in self.elems.iter() {
-            elem_node.dump(f, padlevel + 1)?;
+        while let Some((item, padlevel)) = stack.pop() {
+            match item {
+                DumpItem::Node(ASTNode::Leaf(token)) => {
+                    writeln!(w, "{}", options.leaf_line(&token, padlevel))?
+                }
+                DumpItem::Node(ASTNode::Tree(ast)) => {
+                    let ast_ref = ast.as_ref().borrow();
+                    writeln!(w, "{}", options.tree_line(&ast_ref, padlevel))?;
+
+                    push_dump_elems(&mut stack, &ast_ref.elems, padlevel + 1, options);
+                }
+                DumpItem::Elided(n) => {
+                    writeln!(w, "{}", options.elided_line(n, padlevel))?
+                }
+            }
         }
 
         Ok(())
@@ -303,6 +579,8 @@ impl AST {
     #[allow(unused)]
     fn copy_tree(&self) -> Rc<RefCell<Self>> {
         let mut new_tree = Self::new(self.sym());
+        new_tree.attrs = self.attrs.clone();
+        new_tree.prod = self.prod.clone();
 
         for (sym, node) in self.elems.iter() {
             match node {
@@ -323,220 +601,2971 @@ impl AST {
 
 impl fmt::Display for AST {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.dump(f, 0)
+        self.dump_with(f, 0, &DumpOptions::default())
     }
 }
 
 
-////////////////////////////////////////////////////////////////////////////////
-/////// LL(1) Parser
-
-pub struct LL1Parser {
-    name: String,
-    gram: Gram,
-    prediction_sets: PredSet,
+/// `AST::write_to`的选项， 默认和`Display`一样输出整棵树
+#[derive(Debug, Clone, Default)]
+pub struct DumpOptions {
+    /// 更深的节点折叠成一行`... n elided`
+    pub max_depth: Option<usize>,
+    /// 每个节点最多输出的子节点数， 其余的折叠成一行
+    pub max_children: Option<usize>,
+    /// 不缩进， 只保留层级标记， 输出不再随深度平方增长
+    pub compact: bool,
+    /// 用ANSI颜色区分非终结符、 终结符和token的值
+    pub color: bool,
+    /// token的值超过这么多字符时截断
+    pub max_value_len: Option<usize>,
+    /// 在子树后面打印它从第一个到最后一个token的位置
+    pub spans: bool,
 }
 
-type LL1ParseStatesStack = Vec<(Rc<RefCell<AST>>, Stack<GramSym>)>;
+impl DumpOptions {
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
 
-impl LL1Parser {
-    pub fn new(gram: Gram) -> Self {
-        let first_sets = gram.first_sets();
-        let follow_sets = gram.follow_sets(&first_sets);
-        let prediction_sets = gram.prediction_sets(&first_sets, &follow_sets);
+    pub fn with_max_children(mut self, max_children: usize) -> Self {
+        self.max_children = Some(max_children);
+        self
+    }
 
-        Self {
-            name: gram.name().to_string(),
-            gram,
-            prediction_sets,
-        }
+    pub fn with_compact(mut self, compact: bool) -> Self {
+        self.compact = compact;
+        self
     }
 
-    pub fn name(&self) -> &str {
-        &self.name
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
     }
 
-    pub fn predict_prod(
-        &self,
-        lfsym: &GramSym,
-        la: PredSetSym,
-    ) -> Option<&GramProd>
-    {
-        self.prediction_sets.predict(lfsym, la)
+    pub fn with_max_value_len(mut self, max_value_len: usize) -> Self {
+        self.max_value_len = Some(max_value_len);
+        self
     }
 
-    pub fn parse(&self, tokens: Vec<Token>) -> Result<Rc<RefCell<AST>>, String> {
-        if tokens.is_empty() {
-            return Err("empty tokens".to_string());
-        }
+    pub fn with_spans(mut self, spans: bool) -> Self {
+        self.spans = spans;
+        self
+    }
 
-        if VERBOSE.with(|verbose| verbose.clone()) == VerboseLv::V2 {
-            println!("tokens: {:#?}\n", tokens);
-            println!("LL(1): ");
+    fn paint(&self, color: &str, text: &dyn fmt::Display) -> String {
+        if self.color {
+            format!("{}{}{}", color, text, ANSI_RESET)
+        }
+        else {
+            text.to_string()
         }
+    }
 
-        let start_sym = self.gram.start_sym().unwrap();
-        let root = Rc::new(RefCell::new(AST::new(&start_sym)));
+    fn prefix(&self, padlevel: usize) -> String {
+        let padding = if self.compact { String::new() } else { "  ".repeat(padlevel) };
 
-        let mut res = Err(format!(
-            "Unexpected token: `{}` for root grammar",
-            tokens[0]
-        ));
+        format!("{}{}", padding, self.paint(ANSI_DIM, &format!("({})", padlevel)))
+    }
 
-        // Check root， 分支预测
-        if let Some(prod)
-        = self.predict_prod(start_sym, tokens[0].to_pred_set_sym()) {
-            if let GramSymStr::Str(gramsym_vec) = &prod.rhstr {
-                // gramsym_vec rev for stack
-                let states_stack = vec![(root.clone(), Stack::from(gramsym_vec.clone()))];
-
-                match ll1_parse(&self, &tokens[..], states_stack) {
-                    Ok(_res) => {
-                        res = Ok(_res);
-                    }
-                    Err(msg) => {
-                        res = Err(msg);
-                    }
-                }
+    fn tree_line(&self, tree: &AST, padlevel: usize) -> String {
+        let mut line = format!("{}{}: ", self.prefix(padlevel), self.paint(ANSI_NONTERMINAL, tree.sym()));
 
-            }
-            else {
-                unreachable!()
+        if self.spans {
+            if let (Some(first), Some(last)) = (tree.first_token(), tree.last_token()) {
+                line.push_str(&self.paint(ANSI_DIM, &format!("{}..{}", first.loc(), last.loc())));
             }
         }
 
-        res
+        line
+    }
+
+    fn leaf_line(&self, token: &Token, padlevel: usize) -> String {
+        let value = match self.max_value_len {
+            Some(max_len) if token.value().chars().count() > max_len => {
+                format!("{}...", token.value().chars().take(max_len).collect::<String>())
+            }
+            _ => token.value().to_string(),
+        };
+
+        format!(
+            "{}{}: {} {}",
+            self.prefix(padlevel),
+            self.paint(ANSI_TERMINAL, &token.to_gram_sym()),
+            self.paint(ANSI_VALUE, &value),
+            self.paint(ANSI_DIM, &token.loc())
+        )
+    }
+
+    fn elided_line(&self, n: usize, padlevel: usize) -> String {
+        format!("{}{}", self.prefix(padlevel), self.paint(ANSI_DIM, &format!("... {} elided", n)))
     }
 }
 
+const ANSI_RESET: &str = "\x1b[0m";
+const ANSI_DIM: &str = "\x1b[2m";
+const ANSI_NONTERMINAL: &str = "\x1b[1;34m";
+const ANSI_TERMINAL: &str = "\x1b[32m";
+const ANSI_VALUE: &str = "\x1b[33m";
 
+enum DumpItem {
+    Node(ASTNode),
+    /// 被折叠的节点数
+    Elided(usize),
+}
 
-/// Result: <ASTRoot, UnsupportedTokenType>
-fn ll1_parse(
-    parser: &LL1Parser,
-    tokens: &[Token],
-    mut states_stack: LL1ParseStatesStack,
-) -> Result<Rc<RefCell<AST>>, String> {
-    if tokens.is_empty() {
-        return Err("empty tokens".to_string());
+/// 按逆序压栈， 弹出时就是原来的顺序
+fn push_dump_elems(
+    stack: &mut Vec<(DumpItem, usize)>,
+    elems: &[(GramSym, ASTNode)],
+    padlevel: usize,
+    options: &DumpOptions,
+) {
+    if elems.is_empty() {
+        return;
     }
 
-    let root = states_stack[0].0.clone();
-    let tokenslen = tokens.len();
-    let tokenslastpos = tokenslen - 1;
-    let mut i = 0;
+    if options.max_depth.is_some_and(|max_depth| padlevel > max_depth) {
+        stack.push((DumpItem::Elided(elems.len()), padlevel));
+        return;
+    }
 
-    while let Some((cur_ast, mut symstr_stack)) = states_stack.pop() {
-        if VERBOSE.with(|verbose| verbose.clone()) == VerboseLv::V2 {
-            println!(
-                ">>> `{} => ...{}`",
-                cur_ast.as_ref().borrow().sym(),
-                symstr_stack
-            );
+    let shown = options.max_children.map_or(elems.len(), |max| max.min(elems.len()));
+    if shown < elems.len() {
+        stack.push((DumpItem::Elided(elems.len() - shown), padlevel));
+    }
+
+    for (_elem_sym, elem_node) in elems[..shown].iter().rev() {
+        stack.push((DumpItem::Node(elem_node.clone()), padlevel));
+    }
+}
+
+/// 把`io::Write`接到`fmt::Write`上， 记下真正的io错误
+struct IoAdapter<'a, W> {
+    inner: &'a mut W,
+    error: Option<io::Error>,
+}
+
+impl<'a, W: io::Write> fmt::Write for IoAdapter<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|err| {
+            self.error = Some(err);
+            fmt::Error
+        })
+    }
+}
+
+/// 默认的析构沿子树递归， 很深的树会栈溢出， 这里把独占的子树搬到堆上的栈里逐个释放
+impl Drop for AST {
+    fn drop(&mut self) {
+        let mut stack = subtrees(&mut self.elems);
+
+        while let Some(tree) = stack.pop() {
+            if let Ok(cell) = Rc::try_unwrap(tree) {
+                stack.extend(subtrees(&mut cell.borrow_mut().elems));
+            }
+        }
+    }
+}
+
+fn subtrees(elems: &mut Vec<(GramSym, ASTNode)>) -> Vec<Rc<RefCell<AST>>> {
+    std::mem::take(elems)
+        .into_iter()
+        .filter_map(|(_sym, node)| match node {
+            ASTNode::Tree(tree) => Some(tree),
+            ASTNode::Leaf(_) => None,
+        })
+        .collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+////// Subtree Extraction
+
+impl AST {
+    /// 沿着非终结符名字的路径往下走(每一步取第一个匹配的子树)， 把到达的节点复制成独立的树，
+    /// 空路径就是自己
+    pub fn extract(&self, sym_path: &[&str]) -> Option<FrozenAst> {
+        let child_tree = |tree: &AST, name: &str| {
+            tree.elems.iter().find_map(|(sym, node)| match node {
+                ASTNode::Tree(subtree) if sym.name() == name => Some(subtree.clone()),
+                _ => None,
+            })
+        };
+
+        let (name, rest) = match sym_path.split_first() {
+            None => return Some(self.freeze()),
+            Some(step) => step,
+        };
+
+        let mut cur = child_tree(self, name)?;
+        for name in rest {
+            let next = child_tree(&cur.as_ref().borrow(), name)?;
+            cur = next;
         }
 
-        // 分支匹配，遇到终结符直接匹配，遇到非终结符就入栈回到起点
-        while let Some(right_sym) = symstr_stack.pop() {
-            if i > tokenslastpos {
-                if let Some(_)  // 检查当前产生式是否允许结束
-                = parser.predict_prod(&right_sym, PredSetSym::EndMarker)
-                {
-                    return Ok(root);
-                } else {
-                    return Err(format!(
-                        "Unfinished production: {:?}",
-                        (
-                            cur_ast.as_ref().borrow().sym(),
-                            symstr_stack
-                        )
-                    ));
-                }
+        let extracted = cur.as_ref().borrow().freeze();
+
+        Some(extracted)
+    }
+
+    /// 把这个节点当作独立的树， 位置以第一个token为原点重新计算
+    pub fn freeze(&self) -> FrozenAst {
+        let origin = self.first_token().map(|token| token.loc());
+        let base = origin.clone().unwrap_or(SrcLoc::new((1, 1)));
+
+        FrozenAst {
+            root: self.rebase(&base),
+            origin,
+        }
+    }
+
+    pub fn first_token(&self) -> Option<Rc<Token>> {
+        self.edge_token(false)
+    }
+
+    /// 深度优先找第一个(`last`时最后一个)token， 用显式的栈代替递归
+    fn edge_token(&self, last: bool) -> Option<Rc<Token>> {
+        let children = |tree: &AST| {
+            let mut nodes = tree.elems.iter().map(|(_, node)| node.clone()).collect_vec();
+            if !last {
+                nodes.reverse();
             }
 
-            if right_sym.is_terminal() {
-                if VERBOSE.with(|verbose| verbose.clone()) == VerboseLv::V2 {
-                    println!("? eat terminal: `{}`", right_sym);
-                }
+            nodes
+        };
+
+        let mut stack = children(self);
+        while let Some(node) = stack.pop() {
+            match node {
+                ASTNode::Leaf(token) => return Some(token),
+                ASTNode::Tree(subtree) => stack.extend(children(&subtree.as_ref().borrow())),
+            }
+        }
 
-                if right_sym == tokens[i].to_gram_sym() {
-                    cur_ast.as_ref().borrow_mut().insert_leaf(tokens[i].clone());
+        None
+    }
 
-                    // cosume a token
-                    if VERBOSE.with(|verbose| verbose.clone()) == VerboseLv::V2 {
-                        println!("! eaten token: {:?}", tokens[i]);
-                    }
+    fn rebase(&self, base: &SrcLoc) -> AST {
+        self.copy_with(false, |token| {
+            let mut new_token = token.clone();
+            new_token.loc = rebase_loc(&token.loc, base);
 
-                    i += 1;
+            new_token
+        })
+    }
 
-                    if i == tokenslen {
-                        break;
+    /// 复制整棵树， token换成`map_token`的结果， `synthesized`时每个节点都标记为合成的，
+    /// 先建好子树的空壳挂到父节点上， 再用栈逐个填充
+    fn copy_with<F>(&self, synthesized: bool, map_token: F) -> AST
+    where
+        F: Fn(&Token) -> Token
+    {
+        let shell = |tree: &AST| {
+            let mut new_tree = AST::new(tree.sym());
+            new_tree.attrs = tree.attrs.clone();
+            new_tree.prod = tree.prod.clone();
+            new_tree.synthesized = synthesized || tree.synthesized;
+
+            new_tree
+        };
+
+        type Pending = Vec<(Rc<RefCell<AST>>, Rc<RefCell<AST>>)>;
+        let copy_elems = |tree: &AST, new_tree: &mut AST, stack: &mut Pending| {
+            for (sym, node) in tree.elems.iter() {
+                let new_node = match node {
+                    ASTNode::Leaf(token) => ASTNode::Leaf(Rc::new(map_token(token))),
+                    ASTNode::Tree(subtree) => {
+                        let new_subtree = Rc::new(RefCell::new(shell(&subtree.as_ref().borrow())));
+                        stack.push((subtree.clone(), new_subtree.clone()));
+
+                        ASTNode::Tree(new_subtree)
                     }
-                }
-                else {
-                    return Err(format!(
-                        "Unmatched token{}, a {} expected",
-                        tokens[i], right_sym
-                    ));
-                }
+                };
+
+                new_tree.elems.push((sym.clone(), new_node));
             }
-            else { // handle nonterminal
-
-                if let Some(prod)
-                = parser.predict_prod(&right_sym, tokens[i].to_pred_set_sym()) {
-
-                    match &prod.rhstr {
-                        GramSymStr::Str(symstr_vec) => {
-                            // 保存环境， 入栈
-                            let sub_sym_tree = Rc::new(RefCell::new(AST::new(&right_sym)));
-                            cur_ast
-                            .as_ref()
-                            .borrow_mut()
-                            .insert_tree(sub_sym_tree.clone());
-                            states_stack.push((cur_ast.clone(), symstr_stack.clone()));
-
-                            // 在计算predsets时已经把epsilon str的情况单独提出来了
-                            states_stack.push((sub_sym_tree, Stack::from(symstr_vec.clone())));
-
-                            if VERBOSE.with(|verbose| verbose.clone()) == VerboseLv::V2 {
-                                println!(
-                                    "  -> `{}`: `{}`",
-                                    right_sym,
-                                    GramSymStr::Str(symstr_vec.clone())
-                                );
-                            }
-
-                            break;
-                        },
-                        GramSymStr::Epsilon => {
-                            continue;
-                        }
-                    }
+        };
+
+        let mut stack = vec![];
+        let mut new_root = shell(self);
+        copy_elems(self, &mut new_root, &mut stack);
+
+        while let Some((tree, new_tree)) = stack.pop() {
+            copy_elems(&tree.as_ref().borrow(), &mut new_tree.as_ref().borrow_mut(), &mut stack);
+        }
+
+        new_root
+    }
+}
+
+/// `base`处变成(1, 1)， 同一行的列相应左移
+fn rebase_loc(loc: &SrcLoc, base: &SrcLoc) -> SrcLoc {
+    if loc.ln == base.ln {
+        SrcLoc::new((1, loc.col.saturating_sub(base.col) + 1))
+    } else {
+        SrcLoc::new((loc.ln.saturating_sub(base.ln) + 1, loc.col))
+    }
+}
+
+/// 从原树上复制出来的独立子树， 与原树不再共享节点， 只读
+#[derive(Debug)]
+pub struct FrozenAst {
+    root: AST,
+    /// 第一个token在原来源码中的位置
+    origin: Option<SrcLoc>,
+}
+
+impl FrozenAst {
+    pub fn origin(&self) -> Option<&SrcLoc> {
+        self.origin.as_ref()
+    }
+
+    /// 把子树中的位置换算回原来的源码位置
+    pub fn to_source_loc(&self, loc: &SrcLoc) -> SrcLoc {
+        match &self.origin {
+            None => loc.clone(),
+            Some(origin) if loc.ln == 1 => SrcLoc::new((origin.ln, (loc.col + origin.col).saturating_sub(1))),
+            Some(origin) => SrcLoc::new((loc.ln + origin.ln - 1, loc.col)),
+        }
+    }
+
+    pub fn into_tree(self) -> Rc<RefCell<AST>> {
+        Rc::new(RefCell::new(self.root))
+    }
+}
+
+impl std::ops::Deref for FrozenAst {
+    type Target = AST;
+
+    fn deref(&self) -> &Self::Target {
+        &self.root
+    }
+}
+
+impl fmt::Display for FrozenAst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.root.dump_with(f, 0, &DumpOptions::default())
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+////// Splicing
+
+/// 从某个节点出发， 逐层的子节点下标
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NodeId(pub Vec<usize>);
+
+impl NodeId {
+    pub fn child(&self, idx: usize) -> Self {
+        let mut path = self.0.clone();
+        path.push(idx);
+
+        Self(path)
+    }
+
+    fn split_last(&self) -> Option<(&usize, &[usize])> {
+        self.0.split_last()
+    }
+}
+
+impl ASTNode {
+    pub fn is_synthesized(&self) -> bool {
+        match self {
+            Self::Leaf(token) => token.is_synthesized(),
+            Self::Tree(tree) => tree.as_ref().borrow().is_synthesized(),
+        }
+    }
+}
+
+impl AST {
+    pub fn is_synthesized(&self) -> bool {
+        self.synthesized
+    }
+
+    pub(crate) fn set_synthesized(&mut self) {
+        self.synthesized = true;
+    }
+
+    /// 在以自己为根的树里找`target`节点
+    pub fn node_id(&self, target: &Rc<RefCell<AST>>) -> Option<NodeId> {
+        // 先序遍历， 记下访问过的子树的父节点(在`visited`中的位置)和下标， 找到后顺着父节点拼出路径
+        let mut visited: Vec<(Option<usize>, usize)> = vec![];
+        let push_subtrees = |tree: &AST, parent: Option<usize>, stack: &mut Vec<_>| {
+            for (idx, (_, node)) in tree.elems.iter().enumerate().rev() {
+                if let ASTNode::Tree(subtree) = node {
+                    stack.push((parent, idx, subtree.clone()));
                 }
-                else {
-                    return Err(format!(
-                        "Unexpected token {} for derive {}",
-                        tokens[i], right_sym
-                    ));
+            }
+        };
+
+        let mut stack = vec![];
+        push_subtrees(self, None, &mut stack);
+
+        while let Some((parent, idx, subtree)) = stack.pop() {
+            let i = visited.len();
+            visited.push((parent, idx));
+
+            if Rc::ptr_eq(&subtree, target) {
+                let mut path = vec![];
+                let mut cur = Some(i);
+                while let Some(j) = cur {
+                    let (parent, idx) = visited[j];
+                    path.push(idx);
+                    cur = parent;
                 }
+                path.reverse();
+
+                return Some(NodeId(path));
             }
-        } // end while rhsymstr
 
-        if VERBOSE.with(|verbose| verbose.clone()) == VerboseLv::V2 {
-            println!();
+            push_subtrees(&subtree.as_ref().borrow(), Some(i), &mut stack);
         }
-    } // end while lfsym
 
-    if i < tokenslastpos {
-        return Err(format!(
-            "Tokens remains: `{:?}`",
-            tokens.get(i..tokenslen).unwrap()
-        ));
+        None
     }
 
-    Ok(root)
-}
+    pub fn get_node(&self, id: &NodeId) -> Option<ASTNode> {
+        let (idx, parent_path) = id.split_last()?;
+
+        self.with_parent(parent_path, |parent| {
+            parent.elems.get(*idx).map(|(_, node)| node.clone())
+        })?
+    }
+
+    /// 用`new_node`替换`id`处的节点， 返回原来的节点。
+    /// 新节点标记为合成的， 其中token的位置锚定在原节点的第一个token处
+    pub fn replace_child(&mut self, id: &NodeId, new_node: ASTNode) -> Option<ASTNode> {
+        let (idx, parent_path) = id.split_last()?;
+        let idx = *idx;
+
+        self.with_parent_mut(parent_path, |parent| {
+            if idx >= parent.elems.len() {
+                return None;
+            }
+
+            let anchor = parent.anchor_at(idx);
+            let new_node = synthesize(&new_node, &anchor);
+            let new_sym = new_node.to_gram_sym();
+
+            Some(std::mem::replace(&mut parent.elems[idx], (new_sym, new_node)).1)
+        })?
+    }
+
+    /// 把`new_node`插入到`id`处(下标可以等于子节点数， 即追加)， 原来的节点依次后移。
+    /// 新节点标记为合成的， 其中token的位置锚定在插入点后面的第一个token处
+    pub fn insert_child_at(&mut self, id: &NodeId, new_node: ASTNode) -> bool {
+        let (idx, parent_path) = match id.split_last() {
+            Some(split) => split,
+            None => return false,
+        };
+        let idx = *idx;
+
+        self.with_parent_mut(parent_path, |parent| {
+            if idx > parent.elems.len() {
+                return false;
+            }
+
+            let anchor = parent.anchor_at(idx);
+            let new_node = synthesize(&new_node, &anchor);
+            let new_sym = new_node.to_gram_sym();
+
+            parent.elems.insert(idx, (new_sym, new_node));
+            true
+        })
+        .unwrap_or(false)
+    }
+
+    fn with_parent<R>(&self, parent_path: &[usize], f: impl FnOnce(&AST) -> R) -> Option<R> {
+        match parent_path.split_first() {
+            None => Some(f(self)),
+            Some((idx, rest)) => match self.elems.get(*idx) {
+                Some((_, ASTNode::Tree(subtree))) => subtree.as_ref().borrow().with_parent(rest, f),
+                _ => None,
+            },
+        }
+    }
+
+    fn with_parent_mut<R>(&mut self, parent_path: &[usize], f: impl FnOnce(&mut AST) -> R) -> Option<R> {
+        match parent_path.split_first() {
+            None => Some(f(self)),
+            Some((idx, rest)) => match self.elems.get(*idx) {
+                Some((_, ASTNode::Tree(subtree))) => {
+                    subtree.as_ref().borrow_mut().with_parent_mut(rest, f)
+                },
+                _ => None,
+            },
+        }
+    }
+
+    /// 下标`idx`处(含)之后的第一个token的位置， 没有就用之前的最后一个token
+    fn anchor_at(&self, idx: usize) -> SrcLoc {
+        let after = self.elems[idx.min(self.elems.len())..]
+            .iter()
+            .find_map(|(_, node)| node_first_token(node));
+
+        let before = || {
+            self.elems[..idx.min(self.elems.len())]
+                .iter()
+                .rev()
+                .find_map(|(_, node)| node_last_token(node))
+        };
+
+        after
+            .or_else(before)
+            .map(|token| token.loc())
+            .unwrap_or(SrcLoc::new((0, 0)))
+    }
+
+    pub fn last_token(&self) -> Option<Rc<Token>> {
+        self.edge_token(true)
+    }
+
+    /// 按先序展平成节点表， 0是根， 父节点的下标总是小于子节点，
+    /// 给不方便持有`Rc`的宿主(C、 Python)遍历
+    pub fn flatten(&self) -> Vec<FlatNode> {
+        let mut nodes = vec![FlatNode {
+            name: self.sym.name().to_string(),
+            value: None,
+            loc: None,
+            parent: None,
+            children: vec![],
+        }];
+        let mut stack = self.elems.iter().rev().map(|(_, node)| (node.clone(), 0)).collect_vec();
+
+        while let Some((node, parent)) = stack.pop() {
+            let id = nodes.len();
+            nodes[parent].children.push(id);
+
+            match node {
+                ASTNode::Leaf(token) => nodes.push(FlatNode {
+                    name: token.name().to_string(),
+                    value: Some(token.value().to_string()),
+                    loc: Some(token.loc()),
+                    parent: Some(parent),
+                    children: vec![],
+                }),
+                ASTNode::Tree(tree) => {
+                    let tree = tree.as_ref().borrow();
+                    nodes.push(FlatNode {
+                        name: tree.sym.name().to_string(),
+                        value: None,
+                        loc: None,
+                        parent: Some(parent),
+                        children: vec![],
+                    });
+                    stack.extend(tree.elems.iter().rev().map(|(_, node)| (node.clone(), id)));
+                }
+            }
+        }
+
+        // 子节点都在后面， 倒着填子树的位置
+        for id in (0..nodes.len()).rev() {
+            if nodes[id].loc.is_none() {
+                nodes[id].loc = nodes[id].children.iter().find_map(|&child| nodes[child].loc.clone());
+            }
+        }
+
+        nodes
+    }
+}
+
+/// `AST::flatten`的节点
+#[derive(Debug, Clone)]
+pub struct FlatNode {
+    /// 非终结符名或者token名
+    pub name: String,
+    /// 只有叶子有值
+    pub value: Option<String>,
+    /// 子树取第一个token的位置， 没有token的子树为None
+    pub loc: Option<SrcLoc>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+}
+
+fn node_first_token(node: &ASTNode) -> Option<Rc<Token>> {
+    match node {
+        ASTNode::Leaf(token) => Some(token.clone()),
+        ASTNode::Tree(subtree) => subtree.as_ref().borrow().first_token(),
+    }
+}
+
+fn node_last_token(node: &ASTNode) -> Option<Rc<Token>> {
+    match node {
+        ASTNode::Leaf(token) => Some(token.clone()),
+        ASTNode::Tree(subtree) => subtree.as_ref().borrow().last_token(),
+    }
+}
+
+/// 复制一份标记为合成的节点， token的位置都放到`anchor`
+fn synthesize(node: &ASTNode, anchor: &SrcLoc) -> ASTNode {
+    match node {
+        ASTNode::Leaf(token) => {
+            let mut new_token = token.as_ref().clone();
+            new_token.loc = anchor.clone();
+            new_token.synthesized = true;
+
+            ASTNode::Leaf(Rc::new(new_token))
+        },
+        ASTNode::Tree(subtree) => {
+            let new_tree = subtree.as_ref().borrow().copy_with(true, |token| {
+                let mut new_token = token.clone();
+                new_token.loc = anchor.clone();
+                new_token.synthesized = true;
+
+                new_token
+            });
+
+            ASTNode::Tree(Rc::new(RefCell::new(new_tree)))
+        },
+    }
+}
+
+
+/// 结构比较和哈希时， 是否把token的位置也算进去
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocMode {
+    Ignore,
+    Include,
+}
+
+impl AST {
+    /// 符号和子节点(token的名字和值)都相同， 产生式注解不参与比较
+    /// 成对的子节点放到栈里逐个比较， 不递归
+    pub fn structural_eq(&self, other: &Self, mode: LocMode) -> bool {
+        let shallow_eq = |tree: &AST, other_tree: &AST, stack: &mut Vec<(ASTNode, ASTNode)>| {
+            if tree.sym != other_tree.sym || tree.elems.len() != other_tree.elems.len() {
+                return false;
+            }
+
+            stack.extend(
+                tree.elems
+                    .iter()
+                    .zip(other_tree.elems.iter())
+                    .map(|((_, node), (_, other_node))| (node.clone(), other_node.clone()))
+            );
+
+            true
+        };
+
+        let mut stack = vec![];
+        if !shallow_eq(self, other, &mut stack) {
+            return false;
+        }
+
+        while let Some((node, other_node)) = stack.pop() {
+            let eq = match (&node, &other_node) {
+                (ASTNode::Leaf(token), ASTNode::Leaf(other_token)) => token_eq(token, other_token, mode),
+                (ASTNode::Tree(tree), ASTNode::Tree(other_tree)) => {
+                    Rc::ptr_eq(tree, other_tree)
+                    || shallow_eq(&tree.as_ref().borrow(), &other_tree.as_ref().borrow(), &mut stack)
+                },
+                _ => false,
+            };
+
+            if !eq {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// 按先序哈希， 同`structural_eq`不递归
+    pub fn structural_hash<H: Hasher>(&self, state: &mut H, mode: LocMode) {
+        self.sym.hash(state);
+        self.elems.len().hash(state);
+
+        let mut stack = self.elems.iter().rev().map(|(_, node)| node.clone()).collect_vec();
+        while let Some(node) = stack.pop() {
+            match node {
+                ASTNode::Leaf(token) => hash_token(&token, state, mode),
+                ASTNode::Tree(tree) => {
+                    let tree = tree.as_ref().borrow();
+                    1u8.hash(state);
+                    tree.sym.hash(state);
+                    tree.elems.len().hash(state);
+
+                    stack.extend(tree.elems.iter().rev().map(|(_, node)| node.clone()));
+                }
+            }
+        }
+    }
+}
+
+fn token_eq(token: &Token, other_token: &Token, mode: LocMode) -> bool {
+    token.name() == other_token.name()
+    && token.value() == other_token.value()
+    && (mode == LocMode::Ignore || token.loc() == other_token.loc())
+}
+
+fn hash_token<H: Hasher>(token: &Token, state: &mut H, mode: LocMode) {
+    0u8.hash(state);
+    token.name().hash(state);
+    token.value().hash(state);
+
+    if mode == LocMode::Include {
+        token.loc().hash(state);
+    }
+}
+
+impl ASTNode {
+    pub fn structural_eq(&self, other: &Self, mode: LocMode) -> bool {
+        match (self, other) {
+            (Self::Leaf(token), Self::Leaf(other_token)) => token_eq(token, other_token, mode),
+            (Self::Tree(tree), Self::Tree(other_tree)) => {
+                Rc::ptr_eq(tree, other_tree)
+                || tree.as_ref().borrow().structural_eq(&other_tree.as_ref().borrow(), mode)
+            },
+            _ => false,
+        }
+    }
+
+    pub fn structural_hash<H: Hasher>(&self, state: &mut H, mode: LocMode) {
+        match self {
+            Self::Leaf(token) => hash_token(token, state, mode),
+            Self::Tree(tree) => {
+                1u8.hash(state);
+                tree.as_ref().borrow().structural_hash(state, mode);
+            }
+        }
+    }
+}
+
+/// 默认的比较和哈希忽略位置， 方便测试里直接比较语法树
+impl PartialEq for AST {
+    fn eq(&self, other: &Self) -> bool {
+        self.structural_eq(other, LocMode::Ignore)
+    }
+}
+
+impl Eq for AST {}
+
+impl Hash for AST {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.structural_hash(state, LocMode::Ignore)
+    }
+}
+
+impl PartialEq for ASTNode {
+    fn eq(&self, other: &Self) -> bool {
+        self.structural_eq(other, LocMode::Ignore)
+    }
+}
+
+impl Eq for ASTNode {}
+
+/// 以子树为键的缓存可以直接用`ASTNode::Tree`
+impl Hash for ASTNode {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.structural_hash(state, LocMode::Ignore)
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+/////// LL(1) Parser
+
+pub struct LL1Parser {
+    name: String,
+    gram: Gram,
+    pub(crate) prediction_sets: PredSet,
+    /// 错误产生式和它的同步符号集
+    error_prods: IndexMap<GramSym, (GramProd, IndexSet<PredSetSym>)>,
+    /// token名 => 语法里带值约束的终结符
+    guards: IndexMap<String, Vec<GramSym>>,
+    /// 语法里的字符类、 `~{...}`和`_`， 按这个顺序
+    wildcards: Vec<GramSym>,
+    /// 值约束不区分大小写的token名
+    case_insensitive: IndexSet<String>,
+    all_case_insensitive: bool,
+    /// 预测表中来自FOLLOW集的表项
+    pub(crate) follow_entries: IndexSet<(GramSym, PredSetSym)>,
+    /// 用回调裁决过的冲突
+    pub(crate) resolutions: Vec<Resolution>,
+    /// 构建时的警告
+    pub(crate) warnings: Diagnostics,
+    /// token名 => 关键字的字面串， 拼写提示用
+    keywords: IndexMap<String, String>,
+}
+
+/// 正在匹配的产生式右部和下一个符号的位置， 直接引用预测表里的产生式，
+/// 展开非终结符时只压入一帧， 不复制符号
+#[derive(Clone, Copy, Default)]
+struct Frame<'a> {
+    rhs: &'a [GramSym],
+    pos: usize,
+}
+
+impl<'a> Frame<'a> {
+    fn new(rhs: &'a [GramSym]) -> Self {
+        Self { rhs, pos: 0 }
+    }
+
+    fn next(&mut self) -> Option<&'a GramSym> {
+        let sym = self.rhs.get(self.pos)?;
+        self.pos += 1;
+
+        Some(sym)
+    }
+
+    /// 退回上一个取出的符号
+    fn back(&mut self) {
+        self.pos -= 1;
+    }
+
+    /// 还没匹配的符号
+    fn rest(&self) -> &'a [GramSym] {
+        &self.rhs[self.pos..]
+    }
+}
+
+impl fmt::Display for Frame<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.rest().iter().join(" "))
+    }
+}
+
+impl fmt::Debug for Frame<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.rest()).finish()
+    }
+}
+
+type LL1ParseStatesStack<'a> = Vec<(Rc<RefCell<AST>>, Frame<'a>)>;
+/// 节点和它当时的(子节点, 注解, 产生式)
+type ASTSnapshot = Vec<(Rc<RefCell<AST>>, Vec<(GramSym, ASTNode)>, ProdAttrs, Option<GramProd>)>;
+
+impl LL1Parser {
+    /// 不检查语法， LL(1)冲突按后定义者优先裁决， 裁决记在`warnings`里；
+    /// 语法需要是良构的(见`Gram::check_well_formed`)， 否则解析时才会报错
+    #[deprecated(note = "LL(1)冲突不会报错， 用`LL1Parser::builder`")]
+    pub fn new(gram: Gram) -> Self {
+        Self::from_analysis(GramAnalysis::new(gram)).resolve_by_default()
+    }
+
+    /// 语法为空或者引用了未定义的非终结符时报错； LL(1)冲突不算错， 按后定义者优先裁决，
+    /// 裁决记在`warnings`里
+    pub fn try_new(gram: Gram) -> LlResult<Self> {
+        gram.check_well_formed()?;
+
+        Ok(Self::from_analysis(GramAnalysis::new(gram)).resolve_by_default())
+    }
+
+    /// 直接用(可能增量更新过的)分析结果， 不再重算
+    pub fn from_analysis(analysis: GramAnalysis) -> Self {
+        let GramAnalysis {
+            gram,
+            fstsets: first_sets,
+            follsets: follow_sets,
+            predsets: prediction_sets,
+            ..
+        } = analysis;
+
+        let error_prods = gram
+            .error_prods()
+            .into_iter()
+            .map(|(sym, prod)| {
+                // 跳过`error`之后， 剩余部分的向前看符号就是同步符号
+                let rest = prod.rhstr.get_normal().unwrap()[1..].to_vec();
+                let rest_prod = GramProd::new(
+                    sym.clone(),
+                    if rest.is_empty() {
+                        GramSymStr::Epsilon
+                    } else {
+                        GramSymStr::Str(rest)
+                    },
+                );
+                let syncset = rest_prod.lookahead(&first_sets, &follow_sets);
+
+                (sym, (prod, syncset))
+            })
+            .collect();
+
+        let follow_entries = follow_entries(&gram, &first_sets, &follow_sets);
+
+        let mut guards: IndexMap<String, Vec<GramSym>> = indexmap! {};
+        for sym in gram.term_syms() {
+            if let Some((name, _value)) = sym.guard() {
+                guards.entry(name.to_string()).or_default().push(sym.clone());
+            }
+        }
+
+        let wildcards = gram
+            .term_syms()
+            .into_iter()
+            .filter(|sym| sym.is_wildcard())
+            .sorted_by_key(|sym| match sym {
+                GramSym::Any => 2,
+                GramSym::NotSet(_) => 1,
+                _ => 0,
+            })
+            .collect_vec();
+
+        Self {
+            name: gram.name().to_string(),
+            gram,
+            prediction_sets,
+            error_prods,
+            guards,
+            wildcards,
+            case_insensitive: indexset! {},
+            all_case_insensitive: false,
+            follow_entries,
+            resolutions: vec![],
+            warnings: Diagnostics::new(),
+            keywords: indexmap! {},
+        }
+    }
+
+    /// 从词法规则里取关键字的字面串， 出错的标识符和期待的关键字拼写接近时提示"did you mean"
+    pub fn with_keywords(mut self, lexer: &Lexer) -> Self {
+        self.keywords = lexer.keywords();
+        self
+    }
+
+    /// token `name`的值约束不区分大小写(token的值保持原样)，
+    /// 词法上对应`Lexer::with_case_insensitive`
+    pub fn with_case_insensitive(mut self, name: &str) -> Self {
+        self.case_insensitive.insert(name.to_string());
+        self
+    }
+
+    /// 所有的值约束都不区分大小写
+    pub fn with_all_case_insensitive(mut self) -> Self {
+        self.all_case_insensitive = true;
+        self
+    }
+
+    pub fn is_case_insensitive(&self, name: &str) -> bool {
+        self.all_case_insensitive || self.case_insensitive.contains(name)
+    }
+
+    /// 同`Token::matches`， 但考虑大小写设置
+    pub fn token_matches(&self, token: &Token, sym: &GramSym) -> bool {
+        match sym.guard() {
+            Some((name, value)) if self.is_case_insensitive(name) => {
+                token.name() == name && token.value().to_lowercase() == value.to_lowercase()
+            },
+            _ => token.matches(sym),
+        }
+    }
+
+    /// token的向前看符号， 能匹配的带值约束的终结符在前， 能匹配的通配符在后
+    pub fn lookaheads(&self, token: &Token) -> Vec<PredSetSym> {
+        let mut las = self.guards
+            .get(token.name())
+            .into_iter()
+            .flatten()
+            .filter(|sym| self.token_matches(token, sym))
+            .map(|sym| sym.to_pred_set_sym())
+            .collect_vec();
+
+        las.push(token.to_pred_set_sym());
+        las.extend(
+            self.wildcards
+                .iter()
+                .filter(|sym| token.matches(sym))
+                .map(|sym| sym.to_pred_set_sym())
+        );
+
+        las
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn gram(&self) -> &Gram {
+        &self.gram
+    }
+
+    pub fn prediction_sets(&self) -> &PredSet {
+        &self.prediction_sets
+    }
+
+    pub fn predict_prod(
+        &self,
+        lfsym: &GramSym,
+        la: PredSetSym,
+    ) -> Option<&GramProd>
+    {
+        self.prediction_sets.predict(lfsym, la)
+    }
+
+    /// 同`predict_prod`， 但值约束的终结符优先
+    pub fn predict_token(&self, lfsym: &GramSym, token: &Token) -> Option<&GramProd> {
+        self.lookaheads(token)
+            .into_iter()
+            .find_map(|la| self.predict_prod(lfsym, la))
+    }
+
+    pub fn error_prod(&self, lfsym: &GramSym) -> Option<&GramProd> {
+        self.error_prods.get(lfsym).map(|(prod, _)| prod)
+    }
+
+    pub fn parse(&self, tokens: Vec<Token>) -> Result<Rc<RefCell<AST>>, ParseError> {
+        let (root, mut errors) = self.parse_with(tokens, &ParseOptions::default());
+
+        if errors.is_empty() {
+            Ok(root)
+        }
+        else {
+            Err(errors.remove(0))
+        }
+    }
+
+    /// 不做恢复地解析， 失败时返回出错的token位置和错误
+    pub(crate) fn try_reach(&self, tokens: &[Token]) -> Result<(), (usize, ParseError)> {
+        let mut machine = LL1ParseMachine::new(self, &ParseOptions::default());
+        machine.tokens = tokens.to_vec();
+
+        machine.finish_input().map_err(|err| (machine.i, err))
+    }
+
+    /// 不做恢复地解析， 出错时停在出错处， 连同检查点一起返回，
+    /// 调用者修补tokens之后可以用`resume`从原处继续
+    pub fn parse_resumable(
+        &self,
+        tokens: Vec<Token>
+    ) -> Result<Rc<RefCell<AST>>, (ParseError, ParseCheckpoint<'_>)>
+    {
+        let mut machine = LL1ParseMachine::new(self, &ParseOptions::default());
+        machine.tokens = tokens;
+
+        self.resume(ParseCheckpoint { machine: Box::new(machine) })
+    }
+
+    /// 从检查点继续解析， 检查点必须来自同一个解析器
+    pub fn resume<'a>(
+        &'a self,
+        checkpoint: ParseCheckpoint<'a>
+    ) -> Result<Rc<RefCell<AST>>, (ParseError, ParseCheckpoint<'a>)>
+    {
+        debug_assert!(std::ptr::eq(self, checkpoint.machine.parser));
+        let mut machine = checkpoint.machine;
+
+        // 还没吃掉任何token， 从头开始与原处继续是一样的
+        if machine.i == 0 {
+            let tokens = std::mem::take(&mut machine.tokens);
+            machine = Box::new(LL1ParseMachine::new(self, &ParseOptions::default()));
+            machine.tokens = tokens;
+        }
+        else if let Some(sym) = machine.retry.take() {
+            let frame = &mut machine.states_stack.last_mut().unwrap().1;
+            frame.back();
+            debug_assert_eq!(frame.rest().first(), Some(&sym));
+        }
+
+        match machine.finish_input() {
+            Ok(()) => Ok(machine.root),
+            Err(err) => Err((err, ParseCheckpoint { machine })),
+        }
+    }
+
+    /// 增量解析， token可以边产生边喂给返回的状态机
+    pub fn machine(&self, options: &ParseOptions) -> LL1ParseMachine<'_> {
+        LL1ParseMachine::new(self, options)
+    }
+
+    /// 借助错误产生式(`A -> error α`)进行恢复， 尽量构建完整的AST，
+    /// 出错的部分用`error`叶子节点占位
+    pub fn parse_recover(&self, tokens: Vec<Token>) -> (Rc<RefCell<AST>>, Vec<ParseError>) {
+        let options = ParseOptions {
+            error_prods: true,
+            ..ParseOptions::default()
+        };
+
+        self.parse_with(tokens, &options)
+    }
+
+    /// Result: <ASTRoot, Errors>， 开启恢复时会尽量收集所有的错误
+    pub fn parse_with(
+        &self,
+        tokens: Vec<Token>,
+        options: &ParseOptions
+    ) -> (Rc<RefCell<AST>>, Vec<ParseError>)
+    {
+        let (root, errors, _warnings) = LL1ParseMachine::new(self, options).run(tokens);
+
+        (root, errors)
+    }
+
+    /// 同`parse_with`， 但把错误和警告(比如匹配到废弃的产生式)一起作为诊断信息返回
+    pub fn parse_diag(
+        &self,
+        tokens: Vec<Token>,
+        options: &ParseOptions
+    ) -> (Rc<RefCell<AST>>, Diagnostics)
+    {
+        let (root, errors, warnings) = LL1ParseMachine::new(self, options).run(tokens);
+
+        let mut diags = Diagnostics::new();
+        diags.extend(errors.iter().map(Diagnostic::from));
+        diags.extend(warnings);
+
+        (root, diags)
+    }
+
+    /// 开启profile解析， 多返回每个规则的统计， 见`ParseStats::by_rule`
+    pub fn parse_profile(
+        &self,
+        tokens: Vec<Token>,
+        options: &ParseOptions
+    ) -> (Rc<RefCell<AST>>, Vec<ParseError>, ParseStats)
+    {
+        let options = ParseOptions { profile: true, ..options.clone() };
+        let mut machine = LL1ParseMachine::new(self, &options);
+        machine.run_all(tokens);
+
+        let stats = machine.stats.take().unwrap();
+
+        (machine.root, machine.errors, stats)
+    }
+
+    /// 同`parse_with`， 但重用`arena`里的节点和栈， 适合批量解析大量的小文件，
+    /// 选项见`ParseArena::with_options`
+    pub fn parse_in(&self, tokens: Vec<Token>, arena: &mut ParseArena) -> (Rc<RefCell<AST>>, Vec<ParseError>) {
+        let mut machine = LL1ParseMachine::new(self, &arena.options);
+        machine.pool = std::mem::take(&mut arena.pool);
+
+        let start_sym = machine.root.as_ref().borrow().sym().clone();
+        machine.root = machine.new_node(&start_sym);
+
+        machine.run_all(tokens);
+
+        arena.pool = std::mem::take(&mut machine.pool);
+        arena.roots.push(machine.root.clone());
+
+        (machine.root, machine.errors)
+    }
+}
+
+
+/// 多次解析之间重用的节点， 见`LL1Parser::parse_in`
+#[derive(Default)]
+pub struct ParseArena {
+    options: ParseOptions,
+    pool: Vec<Rc<RefCell<AST>>>,
+    /// 解析出来的树， `reset`时回收
+    roots: Vec<Rc<RefCell<AST>>>,
+}
+
+impl ParseArena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_options(mut self, options: &ParseOptions) -> Self {
+        self.options = options.clone();
+        self
+    }
+
+    /// 回收之前解析出来的树里调用者已经不再持有的节点，
+    /// 还被持有的节点(以及它的子树)原样留给调用者
+    pub fn reset(&mut self) {
+        let mut pending = std::mem::take(&mut self.roots);
+
+        while let Some(node) = pending.pop() {
+            if Rc::strong_count(&node) > 1 {
+                continue;
+            }
+
+            {
+                let mut ast_mut = node.as_ref().borrow_mut();
+                pending.extend(ast_mut.elems.drain(..).filter_map(|(_, child)| match child {
+                    ASTNode::Tree(subtree) => Some(subtree),
+                    ASTNode::Leaf(_) => None,
+                }));
+            }
+
+            self.pool.push(node);
+        }
+    }
+
+    /// 池里可以重用的节点数
+    pub fn pooled(&self) -> usize {
+        self.pool.len()
+    }
+}
+
+
+/// 解析行为的开关， 默认遇到第一个错误就停止。
+///
+/// 新加的开关都有默认值， 用`..Default::default()`或者`with_*`构建时不受影响
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// 调试输出的详细程度， 没有开启`tracing`特性时打印到标准输出
+    pub verbose: VerboseLv,
+
+    /// 使用语法里的错误产生式恢复
+    pub error_prods: bool,
+
+    /// 出错后跳过到这些同步终结符(比如`semi`)之后继续解析，
+    /// 在一次解析中收集尽可能多的错误
+    pub sync_terminals: Vec<String>,
+
+    /// 保留同一位置上的重复错误和连锁错误， 默认只报告第一个
+    pub keep_follow_on_errors: bool,
+
+    /// 恢复模式下最多恢复的错误数， 到达后停止解析； 终止解析的错误不计入， 总是报告
+    pub max_errors: Option<usize>,
+
+    /// 解析时忽略的token名(注释、 空白)， 词法分析器保留它们给别的工具用时在这里跳过
+    pub trivia: Vec<String>,
+
+    /// 输入结束时追加的虚拟token(比如补上最后一行的换行)， 会被标记为合成的
+    pub trailing_tokens: Vec<Token>,
+
+    /// 输入结束时栈上剩下的非终结符也照常展开， 推导出ε的留下空的子树， 默认省略它们
+    pub strict_eof: bool,
+
+    /// 推导出ε的非终结符也留下空的子树(包括输入结束时)， 默认省略它们
+    pub materialize_epsilon: bool,
+
+    /// 忠实的推导树： 同`materialize_epsilon`， 并且右部的`$`也留下一个值为空的合成token，
+    /// 没有错误时每个节点的子节点和推导它的产生式(见`AST::prod`)的右部一一对应
+    pub faithful: bool,
+
+    /// 错误信息和跟踪记录里token的值怎么显示， 见`Redaction`
+    pub redact: Redaction,
+
+    /// 记录每个规则的展开次数和耗时， 见`ParseStats`
+    pub profile: bool,
+}
+
+impl ParseOptions {
+    pub fn with_verbose(mut self, verbose: VerboseLv) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    pub fn with_error_prods(mut self, error_prods: bool) -> Self {
+        self.error_prods = error_prods;
+        self
+    }
+
+    pub fn with_sync_terminals(mut self, names: &[&str]) -> Self {
+        self.sync_terminals = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    pub fn with_keep_follow_on_errors(mut self, keep: bool) -> Self {
+        self.keep_follow_on_errors = keep;
+        self
+    }
+
+    pub fn with_max_errors(mut self, max_errors: usize) -> Self {
+        self.max_errors = Some(max_errors);
+        self
+    }
+
+    pub fn with_trivia(mut self, names: &[&str]) -> Self {
+        self.trivia = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    pub fn with_trailing_tokens(mut self, tokens: Vec<Token>) -> Self {
+        self.trailing_tokens = tokens;
+        self
+    }
+
+    pub fn with_strict_eof(mut self, strict_eof: bool) -> Self {
+        self.strict_eof = strict_eof;
+        self
+    }
+
+    pub fn with_materialize_epsilon(mut self, materialize_epsilon: bool) -> Self {
+        self.materialize_epsilon = materialize_epsilon;
+        self
+    }
+
+    pub fn with_faithful(mut self, faithful: bool) -> Self {
+        self.faithful = faithful;
+        self
+    }
+
+    pub fn with_redact(mut self, redact: Redaction) -> Self {
+        self.redact = redact;
+        self
+    }
+
+    pub fn with_profile(mut self, profile: bool) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    fn is_trivia(&self, token: &Token) -> bool {
+        self.trivia.iter().any(|name| name == token.name())
+    }
+
+    fn keeps_epsilon(&self) -> bool {
+        self.materialize_epsilon || self.faithful
+    }
+}
+
+
+/// 事件模式(SAX风格)下的解析输出， 代替构建语法树。
+///
+/// `EnterRule`和`ExitRule`总是成对出现， 只有推导出非空串的规则才会有事件
+#[derive(Debug, Clone)]
+pub enum ParseEvent {
+    EnterRule(GramSym),
+    Token(Token),
+    ExitRule(GramSym),
+    Error(ParseError),
+}
+
+
+/// 丢弃模式下接收完成的子树
+type SubtreeSink<'a> = Box<dyn FnMut(Rc<RefCell<AST>>) + 'a>;
+
+/// 接收解析的每一步， 见`trace`
+type StepSink<'a> = Box<dyn FnMut(TraceStep) + 'a>;
+
+/// LL(1)解析的状态机， token可以一个一个地喂进来(`feed`)，
+/// 不够时就暂停， 直到`finish`表示输入结束
+pub struct LL1ParseMachine<'a> {
+    parser: &'a LL1Parser,
+    options: ParseOptions,
+    tokens: Vec<Token>,
+    /// 输入已经完整
+    finished: bool,
+    started: bool,
+    /// 解析已经结束(成功或者失败)
+    done: bool,
+    failed: bool,
+    /// 恢复需要看到后面的token， 输入完整之前先挂起
+    pending: Option<ParseError>,
+    root: Rc<RefCell<AST>>,
+    states_stack: LL1ParseStatesStack<'a>,
+    /// 当前token的位置
+    i: usize,
+    last_recover_pos: Option<usize>,
+    last_error_pos: Option<usize>,
+    errors: Vec<ParseError>,
+    warnings: Vec<Diagnostic>,
+    /// 事件模式： 不建树， 只把解析过程交给sink
+    sink: Option<Box<dyn FnMut(ParseEvent) + 'a>>,
+    /// 丢弃模式： `discard_names`里的非终结符的子树完成后交给回调， 然后从父节点上摘掉
+    discard: Option<SubtreeSink<'a>>,
+    discard_names: IndexSet<String>,
+    /// 跟踪模式： 每次预测、 匹配和出错时调用
+    trace: Option<StepSink<'a>>,
+    /// 从`ParseArena`借来的可以重用的节点
+    pool: Vec<Rc<RefCell<AST>>>,
+    /// 每个展开中的产生式一个span， 以节点地址为键
+    #[cfg(feature = "tracing")]
+    spans: IndexMap<usize, tracing::Span>,
+    stats: Option<ParseStats>,
+    /// 每个产生式被用到的次数
+    coverage: Option<IndexMap<GramProd, usize>>,
+    /// 每个预测表项被用到的次数
+    lookahead_hits: Option<IndexMap<(GramSym, PredSetSym), usize>>,
+    /// 出错时弹出的符号， 从检查点继续时要重新匹配
+    retry: Option<GramSym>,
+}
+
+impl<'a> LL1ParseMachine<'a> {
+    fn new(parser: &'a LL1Parser, options: &ParseOptions) -> Self {
+        // 空语法在开始解析时报错
+        let start_sym = parser
+            .gram
+            .start_sym()
+            .cloned()
+            .unwrap_or_else(|| GramSym::NonTerminal(parser.name.clone()));
+
+        Self {
+            parser,
+            options: options.clone(),
+            tokens: vec![],
+            finished: false,
+            started: false,
+            done: false,
+            failed: false,
+            pending: None,
+            root: Rc::new(RefCell::new(AST::new(&start_sym))),
+            states_stack: vec![],
+            i: 0,
+            last_recover_pos: None,
+            last_error_pos: None,
+            errors: vec![],
+            warnings: vec![],
+            sink: None,
+            discard: None,
+            discard_names: IndexSet::new(),
+            trace: None,
+            pool: vec![],
+            #[cfg(feature = "tracing")]
+            spans: IndexMap::new(),
+            stats: if options.profile { Some(ParseStats::default()) } else { None },
+            coverage: None,
+            lookahead_hits: None,
+            retry: None,
+        }
+    }
+
+    pub(crate) fn with_coverage(mut self) -> Self {
+        self.coverage = Some(IndexMap::new());
+        self
+    }
+
+    pub(crate) fn take_coverage(&mut self) -> Option<IndexMap<GramProd, usize>> {
+        self.coverage.take()
+    }
+
+    pub(crate) fn with_lookahead_hits(mut self) -> Self {
+        self.lookahead_hits = Some(IndexMap::new());
+        self
+    }
+
+    pub(crate) fn take_lookahead_hits(&mut self) -> Option<IndexMap<(GramSym, PredSetSym), usize>> {
+        self.lookahead_hits.take()
+    }
+
+    /// 一次性解析全部tokens， 留着状态机以便取出附加的统计
+    pub(crate) fn run_all(&mut self, tokens: Vec<Token>) {
+        self.set_tokens(tokens);
+
+        let res = self.finish_input();
+        let _ = self.fail_on(res);
+    }
+
+    pub(crate) fn with_sink<F: FnMut(ParseEvent) + 'a>(mut self, sink: F) -> Self {
+        self.sink = Some(Box::new(sink));
+        self
+    }
+
+    pub(crate) fn with_discard<F>(mut self, names: &[&str], callback: F) -> Self
+    where F: FnMut(Rc<RefCell<AST>>) + 'a
+    {
+        self.discard = Some(Box::new(callback));
+        self.discard_names = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    pub(crate) fn with_trace<F: FnMut(TraceStep) + 'a>(mut self, callback: F) -> Self {
+        self.trace = Some(Box::new(callback));
+        self
+    }
+
+    pub(crate) fn is_failed(&self) -> bool {
+        self.failed
+    }
+
+    pub(crate) fn root(&self) -> &Rc<RefCell<AST>> {
+        &self.root
+    }
+
+    /// 节点还在栈上， 说明它的产生式还没匹配完
+    pub(crate) fn is_open(&self, ast: &Rc<RefCell<AST>>) -> bool {
+        self.states_stack.iter().any(|(each_ast, _)| Rc::ptr_eq(each_ast, ast))
+    }
+
+    /// 喂入下一个token并尽量往前解析， 遇到无法恢复的错误时返回它，
+    /// 之后的token会被忽略
+    pub fn feed(&mut self, token: Token) -> Result<(), ParseError> {
+        if self.failed || self.options.is_trivia(&token) {
+            return Ok(());
+        }
+
+        self.tokens.push(token);
+
+        let res = self.drive().and_then(|_| {
+            if self.done { self.check_remains() } else { Ok(()) }
+        });
+
+        self.fail_on(res)
+    }
+
+    /// 输入结束， Result: <ASTRoot, Errors>
+    pub fn finish(self) -> (Rc<RefCell<AST>>, Vec<ParseError>) {
+        let (root, errors, _warnings) = self.finish_();
+
+        (root, errors)
+    }
+
+    /// 已经喂入的token数
+    pub fn fed(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    /// 记下当前的状态(栈、位置和正在构建的子树)， 之后可以用`reset`回到这里，
+    /// 可以在此之上实现推测解析、试探选择分支等策略
+    pub fn mark(&self) -> ParseMark<'a> {
+        let mut nodes: ASTSnapshot = vec![];
+
+        let open_asts = std::iter::once(&self.root)
+            .chain(self.states_stack.iter().map(|(ast, _)| ast));
+
+        for ast in open_asts {
+            if nodes.iter().all(|(each_ast, ..)| !Rc::ptr_eq(each_ast, ast)) {
+                let ast_ref = ast.as_ref().borrow();
+                nodes.push((ast.clone(), ast_ref.elems.clone(), ast_ref.attrs.clone(), ast_ref.prod.clone()));
+            }
+        }
+
+        ParseMark {
+            states_stack: self.states_stack.clone(),
+            nodes,
+            fed: self.tokens.len(),
+            i: self.i,
+            started: self.started,
+            finished: self.finished,
+            done: self.done,
+            failed: self.failed,
+            pending: self.pending.clone(),
+            retry: self.retry.clone(),
+            last_recover_pos: self.last_recover_pos,
+            last_error_pos: self.last_error_pos,
+            errors: self.errors.len(),
+            warnings: self.warnings.len(),
+        }
+    }
+
+    /// 回到`mark`时的状态， 之后喂入的token会被丢弃；
+    /// 已经发出的事件、统计、覆盖率和丢弃的子树不会回滚
+    pub fn reset(&mut self, mark: &ParseMark<'a>) {
+        for (ast, elems, attrs, prod) in mark.nodes.iter() {
+            let mut ast_mut = ast.as_ref().borrow_mut();
+            ast_mut.elems = elems.clone();
+            ast_mut.attrs = attrs.clone();
+            ast_mut.prod = prod.clone();
+        }
+
+        self.states_stack = mark.states_stack.clone();
+        self.tokens.truncate(mark.fed);
+        self.i = mark.i;
+        self.started = mark.started;
+        self.finished = mark.finished;
+        self.done = mark.done;
+        self.failed = mark.failed;
+        self.pending = mark.pending.clone();
+        self.retry = mark.retry.clone();
+        self.last_recover_pos = mark.last_recover_pos;
+        self.last_error_pos = mark.last_error_pos;
+        self.errors.truncate(mark.errors);
+        self.warnings.truncate(mark.warnings);
+    }
+
+    /// 开启了`ParseOptions::profile`时才有
+    pub fn stats(&self) -> Option<&ParseStats> {
+        self.stats.as_ref()
+    }
+
+    /// Result: <ASTRoot, Errors, Warnings>
+    fn finish_(mut self) -> (Rc<RefCell<AST>>, Vec<ParseError>, Vec<Diagnostic>) {
+        if !self.failed {
+            let res = self.finish_input();
+            let _ = self.fail_on(res);
+        }
+
+        (self.root, self.errors, self.warnings)
+    }
+
+    fn run(mut self, tokens: Vec<Token>) -> (Rc<RefCell<AST>>, Vec<ParseError>, Vec<Diagnostic>) {
+        verbose!(
+            self.options.verbose => V2,
+            "tokens: {:#?}\n",
+            tokens.iter().map(|token| self.redacted(token)).collect_vec()
+        );
+
+        self.set_tokens(tokens);
+        self.finish_()
+    }
+
+    fn set_tokens(&mut self, mut tokens: Vec<Token>) {
+        if !self.options.trivia.is_empty() {
+            tokens.retain(|token| !self.options.is_trivia(token));
+        }
+
+        self.tokens = tokens;
+    }
+
+    fn fail_on(&mut self, mut res: Result<(), ParseError>) -> Result<(), ParseError> {
+        if let Err(err) = res.as_mut() {
+            if err.backtrace().is_empty() {
+                err.set_backtrace(self.backtrace());
+            }
+
+            self.failed = true;
+            self.done = true;
+
+            // 终止解析的错误总是报告， 上限只限制恢复过的错误
+            self.errors.push(err.clone());
+            self.emit(ParseEvent::Error(err.clone()));
+            self.trace_step(|machine| TraceStep::Error { pos: machine.i, err: err.clone() });
+
+            self.close_frames(0);
+        }
+
+        res
+    }
+
+    fn finish_input(&mut self) -> Result<(), ParseError> {
+        for token in self.options.trailing_tokens.iter() {
+            let mut token = token.clone();
+            token.set_synthesized();
+
+            self.tokens.push(token);
+        }
+        self.finished = true;
+
+        if let Some(err) = self.pending.take() {
+            self.recover_from(err)?;
+        }
+
+        self.drive()?;
+        self.check_remains()
+    }
+
+    fn check_remains(&self) -> Result<(), ParseError> {
+        let tokenslen = self.tokens.len();
+
+        if self.i < tokenslen {
+            return Err(ParseError::new(
+                ParseErrorKind::TokensRemain,
+                &format!("Tokens remains: `{:?}`", self.tokens[self.i..tokenslen].iter().map(|token| self.redacted(token)).collect_vec()),
+                Some(self.redacted(&self.tokens[self.i])),
+                vec![PredSetSym::EndMarker],
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Check root， 分支预测
+    fn start(&mut self) -> Result<(), ParseError> {
+        let start_sym = self.root.as_ref().borrow().sym().clone();
+        self.emit(ParseEvent::EnterRule(start_sym.clone()));
+
+        if let Some(prod)
+        = self.parser.predict_token(&start_sym, &self.tokens[0]) {
+            self.on_predict(prod);
+            self.apply_prod(&self.root.clone(), prod);
+            self.open_rule(&self.root.clone(), prod);
+
+            // 只有token本身是结束符(`$`)时才会预测出ε
+            let frame = match &prod.rhstr {
+                GramSymStr::Str(gramsym_vec) => Frame::new(gramsym_vec),
+                GramSymStr::Epsilon => Frame::default(),
+            };
+
+            self.states_stack.push((self.root.clone(), frame));
+            Ok(())
+        }
+        else {
+            let mut err = ParseError::new(
+                ParseErrorKind::UnexpectedToken,
+                &format!("Unexpected token: `{}` for root grammar", self.redacted(&self.tokens[0])),
+                Some(self.redacted(&self.tokens[0])),
+                self.parser.prediction_sets.expected(&start_sym),
+            );
+            hint_similar(&mut err, &self.parser.keywords);
+
+            self.states_stack.push((self.root.clone(), Frame::default()));
+            self.recover_from(err)
+        }
+    }
+
+    /// 一直解析到结束， 或者token不够时暂停
+    fn drive(&mut self) -> Result<(), ParseError> {
+        if self.done || self.pending.is_some() {
+            return Ok(());
+        }
+
+        if !self.started {
+            if self.parser.gram.start_sym().is_none() {
+                return Err(ParseError::new(
+                    ParseErrorKind::EmptyGram,
+                    &format!("grammar {} has no production", self.parser.name),
+                    None,
+                    vec![]
+                ));
+            }
+
+            if self.tokens.is_empty() {
+                if self.finished {
+                    return Err(ParseError::new(
+                        ParseErrorKind::EmptyTokens, "empty tokens", None, vec![]
+                    ));
+                }
+
+                return Ok(());
+            }
+
+            verbose!(self.options.verbose => V2, "LL(1): ");
+
+            self.started = true;
+            self.start()?;
+
+            if self.pending.is_some() {
+                return Ok(());
+            }
+        }
+
+        while let Some((cur_ast, mut frame)) = self.states_stack.pop() {
+            #[cfg(feature = "tracing")]
+            let _span = self.spans
+                .get(&(Rc::as_ptr(&cur_ast) as usize))
+                .map(|span| span.clone().entered());
+
+            verbose!(
+                self.options.verbose => V2,
+                ">>> `{} => ...{}`",
+                cur_ast.as_ref().borrow().sym(),
+                frame
+            );
+
+            // 分支匹配，遇到终结符直接匹配，遇到非终结符就入栈回到起点
+            let mut frame_done = true;
+
+            while let Some(right_sym) = frame.next() {
+                let i = self.i;
+
+                if i >= self.tokens.len() {
+                    // 等待更多的token
+                    if !self.finished {
+                        frame.back();
+                        self.states_stack.push((cur_ast, frame));
+
+                        return Ok(());
+                    }
+
+                    // 规则里写明的输入结束
+                    if right_sym.is_eof() {
+                        if self.options.faithful {
+                            let loc = self.tokens.last().map_or(SrcLoc::new((1, 1)), |token| token.loc());
+                            let mut token = Token::from_sym(right_sym, loc);
+                            token.value.clear();
+                            token.synthesized = true;
+
+                            self.eat_as(&cur_ast, GramSym::Eof, token);
+                        }
+
+                        continue;
+                    }
+
+                    // 推导出ε的非终结符， 继续检查后面的符号和外层的规则， 整个栈都能推导出ε才算结束
+                    if let Some(prod) = self.parser.predict_prod(right_sym, PredSetSym::EndMarker) {
+                        self.on_predict(prod);
+
+                        if !self.options.strict_eof && !self.options.keeps_epsilon() {
+                            continue;
+                        }
+
+                        self.expand(&cur_ast, frame, right_sym, prod);
+                        frame_done = false;
+                        break;
+                    }
+
+                    frame.back();
+                    self.states_stack.push((cur_ast.clone(), frame));
+                    frame.next();
+
+                    return Err(ParseError::new(
+                        ParseErrorKind::UnfinishedProd,
+                        &format!(
+                            "Unfinished production: {:?}",
+                            (
+                                cur_ast.as_ref().borrow().sym(),
+                                frame
+                            )
+                        ),
+                        None,
+                        if right_sym.is_terminal() {
+                            vec![right_sym.to_pred_set_sym()]
+                        } else {
+                            self.parser.prediction_sets.expected(right_sym)
+                        },
+                    ));
+                }
+
+                if right_sym.is_terminal() {
+                    verbose!(self.options.verbose => V2, "? eat terminal: `{}`", right_sym);
+
+                    if self.parser.token_matches(&self.tokens[i], right_sym) {
+                        let token = self.tokens[i].clone();
+                        self.eat(&cur_ast, token);
+
+                        // cosume a token
+                        #[cfg(feature = "tracing")]
+                        tracing::trace!(token = %self.redacted(&self.tokens[i]), rule = %right_sym, "match");
+                        #[cfg(not(feature = "tracing"))]
+                        verbose!(self.options.verbose => V2, "! eaten token: {:?}", self.redacted(&self.tokens[i]));
+
+                        self.i += 1;
+                    }
+                    else {
+                        let mut err = ParseError::new(
+                            ParseErrorKind::UnmatchedToken,
+                            &format!(
+                                "Unmatched token{}, a {} expected",
+                                self.redacted(&self.tokens[i]), right_sym
+                            ),
+                            Some(self.redacted(&self.tokens[i])),
+                            vec![right_sym.to_pred_set_sym()],
+                        );
+
+                        hint_similar(&mut err, &self.parser.keywords);
+
+                        self.retry = Some(right_sym.clone());
+                        self.states_stack.push((cur_ast.clone(), frame));
+                        self.recover_from(err)?;
+                        frame_done = false;
+                        break;
+                    }
+                }
+                else { // handle nonterminal
+
+                    if let Some(prod)
+                    = self.parser.predict_token(right_sym, &self.tokens[i]) {
+                        self.on_predict(prod);
+
+                        if prod.rhstr.is_epsilon() && !self.options.keeps_epsilon() {
+                            continue;
+                        }
+
+                        // 保存环境， 入栈
+                        self.expand(&cur_ast, frame, right_sym, prod);
+
+                        verbose!(self.options.verbose => V2, "  -> `{}`: `{}`", right_sym, prod.rhstr);
+
+                        frame_done = false;
+                        break;
+                    }
+                    else {
+                        let mut err = ParseError::new(
+                            ParseErrorKind::UnexpectedToken,
+                            &format!(
+                                "Unexpected token {} for derive {}",
+                                self.redacted(&self.tokens[i]), right_sym
+                            ),
+                            Some(self.redacted(&self.tokens[i])),
+                            self.parser.prediction_sets.expected(right_sym),
+                        );
+
+                        hint_similar(&mut err, &self.parser.keywords);
+
+                        self.retry = Some(right_sym.clone());
+                        self.states_stack.push((cur_ast.clone(), frame));
+                        self.recover_from(err)?;
+                        frame_done = false;
+                        break;
+                    }
+                }
+            } // end while rhsymstr
+
+            if frame_done {
+                let sym = cur_ast.as_ref().borrow().sym().clone();
+                self.emit(ParseEvent::ExitRule(sym));
+                self.close_rule(&cur_ast);
+            }
+
+            verbose!(self.options.verbose => V2);
+
+            if self.pending.is_some() {
+                return Ok(());
+            }
+        } // end while lfsym
+
+        self.done = true;
+
+        Ok(())
+    }
+
+    /// 按`prod`为`sym`新建子树， 当前的状态和子树的状态依次入栈， ε的分支留下空的子树
+    fn expand(&mut self, cur_ast: &Rc<RefCell<AST>>, frame: Frame<'a>, sym: &GramSym, prod: &'a GramProd) {
+        let sub_sym_tree = self.new_node(sym);
+        self.apply_prod(&sub_sym_tree, prod);
+        self.enter(cur_ast, sub_sym_tree.clone());
+        self.open_rule(&sub_sym_tree, prod);
+        self.states_stack.push((cur_ast.clone(), frame));
+
+        let sub_frame = match &prod.rhstr {
+            GramSymStr::Str(symstr_vec) => Frame::new(symstr_vec),
+            GramSymStr::Epsilon => Frame::default(),
+        };
+        self.states_stack.push((sub_sym_tree, sub_frame));
+    }
+
+    fn apply_prod(&self, ast: &Rc<RefCell<AST>>, prod: &GramProd) {
+        let mut ast_mut = ast.as_ref().borrow_mut();
+        ast_mut.attrs = prod.attrs.clone();
+        ast_mut.prod = Some(prod.clone());
+    }
+
+    /// 优先重用池里的节点， 保留它原来的容量
+    fn new_node(&mut self, sym: &GramSym) -> Rc<RefCell<AST>> {
+        match self.pool.pop() {
+            Some(node) => {
+                {
+                    let mut ast_mut = node.as_ref().borrow_mut();
+                    ast_mut.sym = sym.clone();
+                    ast_mut.elems.clear();
+                    ast_mut.attrs.clear();
+                    ast_mut.prod = None;
+                    ast_mut.synthesized = false;
+                }
+
+                node
+            }
+            None => Rc::new(RefCell::new(AST::new(sym))),
+        }
+    }
+
+    fn emit(&mut self, event: ParseEvent) {
+        if let Some(sink) = self.sink.as_mut() {
+            sink(event);
+        }
+    }
+
+    /// 按`ParseOptions::redact`处理过的token， 用在错误和跟踪记录里
+    fn redacted(&self, token: &Token) -> Token {
+        self.options.redact.token(token)
+    }
+
+    /// 只在跟踪模式下才构造`TraceStep`
+    fn trace_step<F: FnOnce(&Self) -> TraceStep>(&mut self, step: F) {
+        if let Some(mut trace) = self.trace.take() {
+            trace(step(self));
+            self.trace = Some(trace);
+        }
+    }
+
+    fn eat(&mut self, cur_ast: &Rc<RefCell<AST>>, token: Token) {
+        self.eat_as(cur_ast, token.to_gram_sym(), token);
+    }
+
+    fn eat_as(&mut self, cur_ast: &Rc<RefCell<AST>>, sym: GramSym, token: Token) {
+        if let Some(stats) = self.stats.as_mut() {
+            stats.eat(cur_ast.as_ref().borrow().sym());
+        }
+
+        self.trace_step(|machine| TraceStep::Match {
+            pos: machine.i,
+            rule: cur_ast.as_ref().borrow().sym().clone(),
+            token: machine.redacted(&token),
+        });
+
+        if self.sink.is_some() {
+            self.emit(ParseEvent::Token(token));
+        }
+        else {
+            cur_ast.as_ref().borrow_mut().insert_leaf_as(sym, token);
+        }
+    }
+
+    fn enter(&mut self, cur_ast: &Rc<RefCell<AST>>, sub_ast: Rc<RefCell<AST>>) {
+        if self.sink.is_some() {
+            let sym = sub_ast.as_ref().borrow().sym().clone();
+            self.emit(ParseEvent::EnterRule(sym));
+        }
+        else {
+            cur_ast.as_ref().borrow_mut().insert_tree(sub_ast);
+        }
+    }
+
+    /// 丢弃栈上`len`以上的状态， 事件模式下依次结束对应的规则
+    fn close_frames(&mut self, len: usize) {
+        while self.states_stack.len() > len {
+            let (ast, _) = self.states_stack.pop().unwrap();
+
+            let sym = ast.as_ref().borrow().sym().clone();
+            self.emit(ParseEvent::ExitRule(sym));
+            self.close_rule(&ast);
+        }
+    }
+
+    fn open_rule(&mut self, ast: &Rc<RefCell<AST>>, prod: &GramProd) {
+        if let Some(stats) = self.stats.as_mut() {
+            stats.open(Rc::as_ptr(ast) as usize, prod);
+        }
+
+        #[cfg(feature = "tracing")]
+        {
+            let span = tracing::debug_span!(
+                "expand",
+                rule = %prod.lfsym,
+                prod = %prod.rhstr
+            );
+
+            self.spans.insert(Rc::as_ptr(ast) as usize, span);
+        }
+    }
+
+    fn close_rule(&mut self, ast: &Rc<RefCell<AST>>) {
+        if let Some(stats) = self.stats.as_mut() {
+            stats.close(Rc::as_ptr(ast) as usize);
+        }
+
+        // 父节点的状态在栈顶， 根节点不会被丢弃
+        if let (Some(callback), Some((parent, _))) = (self.discard.as_mut(), self.states_stack.last()) {
+            if self.discard_names.contains(ast.as_ref().borrow().sym().name().as_ref()) {
+                parent.as_ref().borrow_mut().elems.retain(|(_, node)| match node {
+                    ASTNode::Tree(subtree) => !Rc::ptr_eq(subtree, ast),
+                    ASTNode::Leaf(_) => true,
+                });
+                callback(ast.clone());
+            }
+        }
+
+        #[cfg(feature = "tracing")]
+        self.spans.remove(&(Rc::as_ptr(ast) as usize));
+    }
+
+    fn on_predict(&mut self, prod: &GramProd) {
+        self.check_deprecated(prod);
+
+        self.trace_step(|machine| TraceStep::Predict {
+            pos: machine.i,
+            prod: prod.clone(),
+            lookahead: machine.tokens.get(machine.i).map(|token| machine.redacted(token)),
+        });
+
+        if let Some(hits) = self.coverage.as_mut() {
+            *hits.entry(prod.clone()).or_default() += 1;
+        }
+
+        if let Some(hits) = self.lookahead_hits.as_mut() {
+            let parser = self.parser;
+
+            // 没有token了就是按$预测的
+            let la = match self.tokens.get(self.i) {
+                Some(token) => parser
+                    .lookaheads(token)
+                    .into_iter()
+                    .find(|la| parser.predict_prod(&prod.lfsym, la.clone()).is_some())
+                    .unwrap_or(PredSetSym::EndMarker),
+                None => PredSetSym::EndMarker,
+            };
+
+            *hits.entry((prod.lfsym.clone(), la)).or_default() += 1;
+        }
+    }
+
+    fn check_deprecated(&mut self, prod: &GramProd) {
+        if let Some(note) = prod.attr("deprecated") {
+            let mut diag = Diagnostic::warning(
+                "deprecated",
+                &format!("use of deprecated production `{}`", prod)
+            )
+            .with_loc(self.tokens[self.i.min(self.tokens.len() - 1)].loc());
+
+            if !note.is_empty() {
+                diag = diag.with_note(note);
+            }
+
+            self.warnings.push(diag);
+        }
+    }
+
+    /// 栈上正在展开的非终结符， 从外到内
+    fn backtrace(&self) -> Vec<GramSym> {
+        self.states_stack
+            .iter()
+            .map(|(ast, _)| ast.as_ref().borrow().sym().clone())
+            .collect()
+    }
+
+    fn recover_from(&mut self, mut err: ParseError) -> Result<(), ParseError> {
+        err.set_backtrace(self.backtrace());
+
+        let recovery_on = self.options.error_prods || !self.options.sync_terminals.is_empty();
+        if recovery_on && !self.finished {
+            self.pending = Some(err);
+            return Ok(());
+        }
+
+        // 恢复过的错误已经到达上限， 这个错误终止解析
+        if self.reach_max_errors() {
+            return Err(err);
+        }
+
+        let errpos = self.i;
+        let recovered = (self.options.error_prods && self.recover_by_error_prod())
+        || (!self.options.sync_terminals.is_empty() && self.recover_by_sync());
+
+        if !recovered {
+            return Err(err);
+        }
+        self.retry = None;
+
+        self.report(errpos, err);
+
+        if self.reach_max_errors() {
+            verbose!(self.options.verbose => V2, "!! too many errors, stop\n");
+
+            self.close_frames(0);
+            self.i = self.tokens.len();
+        }
+
+        Ok(())
+    }
+
+    /// 同一位置上的后续错误多半是恢复引起的连锁错误， 只保留第一个
+    fn report(&mut self, errpos: usize, err: ParseError) {
+        let follow_on = self.last_error_pos == Some(errpos);
+        let duplicated = self.errors.iter().any(|reported| {
+            reported.kind() == err.kind()
+            && reported.msg() == err.msg()
+        });
+
+        if self.options.keep_follow_on_errors || !(follow_on || duplicated) {
+            self.emit(ParseEvent::Error(err.clone()));
+            self.trace_step(|_| TraceStep::Error { pos: errpos, err: err.clone() });
+            self.errors.push(err);
+        }
+
+        self.last_error_pos = Some(errpos);
+    }
+
+    fn reach_max_errors(&self) -> bool {
+        match self.options.max_errors {
+            Some(max_errors) => self.errors.len() >= max_errors,
+            None => false,
+        }
+    }
+
+    /// 回退到最近的带有错误产生式`A -> error α`的非终结符A,
+    /// 丢弃到同步符号为止的token， 用`error`叶子节点替换A已经构建的部分， 然后继续匹配α
+    fn recover_by_error_prod(&mut self) -> bool {
+        let parser = self.parser;
+        let found = self.states_stack.iter().rposition(|(ast, _)| {
+            parser.error_prods.contains_key(ast.as_ref().borrow().sym())
+        });
+
+        let idx = match found {
+            Some(idx) => idx,
+            None => return false,
+        };
+
+        let ast = self.states_stack[idx].0.clone();
+        let (prod, syncset)
+        = parser.error_prods.get(ast.as_ref().borrow().sym()).unwrap();
+
+        // 同一位置上再次出错至少要跳过一个token， 否则会原地反复恢复
+        let from = self.i;
+        let mut to = self.i;
+        if self.last_recover_pos == Some(self.i) {
+            to += 1;
+        }
+
+        while to < self.tokens.len()
+        && !parser.lookaheads(&self.tokens[to]).iter().any(|la| syncset.contains(la)) {
+            to += 1;
+        }
+
+        if to >= self.tokens.len() && !syncset.contains(&PredSetSym::EndMarker) {
+            return false;
+        }
+        self.i = to.min(self.tokens.len());
+        self.last_recover_pos = Some(self.i);
+
+        let skipped = &self.tokens[from.min(self.i)..self.i];
+        let loc = match skipped.first() {
+            Some(token) => token.loc(),
+            None => self.tokens[from.min(self.tokens.len() - 1)].loc(),
+        };
+        let error_token = Token::new(
+            ERROR_SYM_NAME,
+            &skipped.iter().map(|token| token.value()).join(" "),
+            loc
+        );
+
+        verbose!(
+            self.options.verbose => V2,
+            "!! recover by `{}`, skip: {:?}\n",
+            prod,
+            skipped.iter().map(|token| self.redacted(token)).collect_vec()
+        );
+
+        self.on_predict(prod);
+        self.close_frames(idx + 1);
+        ast.as_ref().borrow_mut().elems.clear();
+        self.apply_prod(&ast, prod);
+        self.eat(&ast, error_token);
+
+        // 跳过开头的`error`
+        let mut frame = Frame::new(prod.rhstr.get_normal().unwrap());
+        frame.next();
+        self.states_stack[idx].1 = frame;
+
+        true
+    }
+
+    /// Panic Mode: 跳过到下一个同步终结符(并吃掉它)， 然后从栈顶往下找第一个
+    /// 能接受后续token的位置继续， 找不到就跳到再下一个同步终结符。
+    /// 出错的那一层只有排在同步终结符之后的符号可以作为继续点
+    fn recover_by_sync(&mut self) -> bool {
+        let tokenslen = self.tokens.len();
+        let mut to = self.i;
+
+        loop {
+            while to < tokenslen
+            && !self.options.sync_terminals.iter().any(|name| name == self.tokens[to].name()) {
+                to += 1;
+            }
+
+            if to >= tokenslen {
+                return false;
+            }
+            to += 1;
+
+            // 输入恰好在同步终结符处结束， 不再继续解析
+            if to == tokenslen {
+                verbose!(self.options.verbose => V2, "!! sync to end\n");
+
+                self.i = to;
+                self.close_frames(0);
+                return true;
+            }
+
+            let sync_token = self.tokens[to - 1].clone();
+            let la_token = self.tokens[to].clone();
+            let toppos = self.states_stack.len().saturating_sub(1);
+
+            for idx in (0..self.states_stack.len()).rev() {
+                let mut frame = self.states_stack[idx].1;
+                let mut closed = idx < toppos;
+
+                while let Some(sym) = frame.next() {
+                    if !closed {
+                        closed = self.parser.token_matches(&sync_token, sym);
+                        continue;
+                    }
+
+                    let accept = if sym.is_terminal() {
+                        self.parser.token_matches(&la_token, sym)
+                    } else {
+                        self.parser.predict_token(sym, &la_token).is_some()
+                    };
+
+                    if accept {
+                        verbose!(
+                            self.options.verbose => V2,
+                            "!! sync to {}, resume at `{}`\n",
+                            self.redacted(&self.tokens[to]), sym
+                        );
+
+                        frame.back();
+                        self.close_frames(idx + 1);
+                        self.states_stack[idx].1 = frame;
+                        self.i = to;
+
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+/// `LL1ParseMachine::mark`记下的状态
+#[derive(Clone)]
+pub struct ParseMark<'a> {
+    states_stack: LL1ParseStatesStack<'a>,
+    /// 当时还没构建完的节点和它们的内容
+    nodes: ASTSnapshot,
+    /// 当时已经喂入的token数
+    fed: usize,
+    i: usize,
+    started: bool,
+    finished: bool,
+    done: bool,
+    failed: bool,
+    pending: Option<ParseError>,
+    retry: Option<GramSym>,
+    last_recover_pos: Option<usize>,
+    last_error_pos: Option<usize>,
+    errors: usize,
+    warnings: usize,
+}
+
+
+/// `parse_resumable`出错时的现场， 修补好出错处的tokens之后交给`resume`
+pub struct ParseCheckpoint<'a> {
+    machine: Box<LL1ParseMachine<'a>>,
+}
+
+impl<'a> ParseCheckpoint<'a> {
+    /// 出错处的token位置
+    pub fn pos(&self) -> usize {
+        self.machine.i
+    }
+
+    pub fn tokens(&self) -> &[Token] {
+        &self.machine.tokens
+    }
+
+    /// 丢弃出错处开始的`n`个token
+    pub fn skip(&mut self, n: usize) {
+        let i = self.machine.i;
+        let to = (i + n).min(self.machine.tokens.len());
+
+        self.machine.tokens.drain(i..to);
+    }
+
+    /// 在出错处插入一个token
+    pub fn insert(&mut self, token: Token) {
+        self.machine.tokens.insert(self.machine.i, token);
+    }
+}
 
 
 #[cfg(test)]
 mod test {
+    use std::thread;
+
+    use super::*;
+    use crate::{builder::GramBuilder, lexer::RegexTokenMatcher, session::ParseSession, tokens::Span};
+
+    fn nested_parser() -> LL1Parser {
+        let gram = GramBuilder::from_dsl("grammar![nested| L: | lp L rp; | x; |]")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        LL1Parser::builder(gram).build().unwrap()
+    }
+
+    fn nested_tokens(depth: usize) -> Vec<Token> {
+        let mut tokens = vec![];
+        for i in 0..depth {
+            tokens.push(Token::new("lp", "(", SrcLoc::new((1, i))));
+        }
+        tokens.push(Token::new("x", "x", SrcLoc::new((1, depth))));
+        for i in 0..depth {
+            tokens.push(Token::new("rp", ")", SrcLoc::new((1, depth + 1 + i))));
+        }
+
+        tokens
+    }
+
+    #[test]
+    fn test_deep_nesting_parse_and_drop() {
+        let depth = 100_000;
+        let parser = nested_parser();
+        let root = parser.parse(nested_tokens(depth)).unwrap();
+
+        let mut level = 0;
+        let mut cur = root.clone();
+        loop {
+            let next = match cur.as_ref().borrow().get_elem(&GramSym::NonTerminal("L".to_string())) {
+                Some(ASTNode::Tree(subtree)) => subtree.clone(),
+                _ => break,
+            };
+            cur = next;
+            level += 1;
+        }
+        assert_eq!(level, depth);
+
+        // 各种遍历都不能递归
+        {
+            let tree = root.as_ref().borrow();
+            assert_eq!(tree.first_token().unwrap().value(), "(");
+            assert_eq!(tree.last_token().unwrap().value(), ")");
+            assert_eq!(tree.node_id(&cur), Some(NodeId(vec![1; depth])));
+
+            let frozen = tree.extract(&["L"]).unwrap();
+            assert_eq!(frozen.first_token().unwrap().loc(), SrcLoc::new((1, 1)));
+            let innermost = tree.extract(&vec!["L"; depth]).unwrap();
+            assert_eq!(innermost.first_token().unwrap().value(), "x");
+
+            let other = parser.parse(nested_tokens(depth)).unwrap();
+            let other = other.as_ref().borrow();
+            assert!(tree.structural_eq(&other, LocMode::Include));
+            assert!(!tree.structural_eq(&frozen, LocMode::Ignore));
+
+            let hash = |tree: &AST| {
+                let mut state = std::collections::hash_map::DefaultHasher::new();
+                tree.structural_hash(&mut state, LocMode::Include);
+                state.finish()
+            };
+            assert_eq!(hash(&tree), hash(&other));
+        }
+
+        let lexer = Lexer::new(vec![
+            (RegexTokenMatcher::new(r"\("), "lp".to_string()),
+            (RegexTokenMatcher::new(r"\)"), "rp".to_string()),
+            (RegexTokenMatcher::new("x"), "x".to_string()),
+        ]);
+        let srcstr = format!("{}x{}", "(".repeat(depth), ")".repeat(depth));
+        let srcfile = SrcFileInfo::from_srcstr(PathBuf::from("deep"), srcstr);
+        let session = ParseSession::parse(&parser, &lexer, srcfile, &ParseOptions::default())
+            .unwrap()
+            .with_symbol("L", "expr", &["x"]);
+        assert_eq!(session.span_of(session.root()), Some(Span::new(0, 2 * depth + 1)));
+        assert_eq!(session.document_symbols().len(), 1);
+
+        drop(session);
+        drop(cur);
+        drop(root);
+    }
+
+    #[test]
+    fn test_deep_nesting_display() {
+        // 输出的缩进和深度的平方成正比， 用较浅的树和很小的栈检查没有递归
+        let depth = 3_000;
+        let parser = nested_parser();
+
+        let lines = thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || {
+                let root = parser.parse(nested_tokens(depth)).unwrap();
+                let text = format!("{}", root.as_ref().borrow());
+                text.lines().count()
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        assert_eq!(lines, 3 * depth + 2);
+    }
+
+    #[test]
+    fn test_max_errors_keeps_fatal_error() {
+        let gram = GramBuilder::from_dsl("grammar![s| S: | Stmt S; | ε; Stmt: | id eq id semi; |]")
+            .unwrap()
+            .build()
+            .unwrap();
+        let parser = LL1Parser::builder(gram).build().unwrap();
+        let tokens = stmt_tokens("id id semi id eq id semi id id semi id id semi");
+
+        let positions = |errors: &[ParseError]| errors.iter().map(|err| err.token().unwrap().loc()).collect_vec();
+        let all = [SrcLoc::new((1, 1)), SrcLoc::new((1, 8)), SrcLoc::new((1, 11))];
+
+        // 为0时第一个错误就终止解析， 否则恢复max_errors个之后停止
+        for (max_errors, expected) in [(0, 1), (1, 1), (2, 2), (3, 3), (4, 3)] {
+            let options = ParseOptions::default().with_sync_terminals(&["semi"]).with_max_errors(max_errors);
+            let (_, errors) = parser.parse_with(tokens.clone(), &options);
+
+            assert_eq!(positions(&errors), all[..expected], "max_errors {}", max_errors);
+        }
+
+        let options = ParseOptions::default().with_sync_terminals(&["semi"]);
+        let (_, errors) = parser.parse_with(tokens.clone(), &options);
+        assert_eq!(positions(&errors), all);
+
+        let options = ParseOptions::default().with_max_errors(0);
+        let (_, errors) = parser.parse_with(tokens, &options);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_unfinished_nullable_tail() {
+        // `A`在结尾可以推导出ε， 但后面的`semi`不行
+        let gram = GramBuilder::from_dsl("grammar![tail| S: | P A semi; | x A; P: | p; A: | y; | ε; |]")
+            .unwrap()
+            .build()
+            .unwrap();
+        let parser = LL1Parser::builder(gram).build().unwrap();
+
+        let tokens = |names: &[&str]| {
+            names.iter().enumerate().map(|(i, name)| Token::new(name, name, SrcLoc::new((1, i)))).collect_vec()
+        };
+
+        for names in [&["p"][..], &["p", "y"]].iter() {
+            let err = parser.parse(tokens(names)).unwrap_err();
+            assert_eq!(err.kind(), &ParseErrorKind::UnfinishedProd);
+            assert_eq!(err.expected(), &[PredSetSym::Sym(GramSym::Terminal("semi".to_string()))]);
+        }
+
+        assert!(parser.parse(tokens(&["x"])).is_ok());
+        assert!(parser.parse(tokens(&["p", "semi"])).is_ok());
+
+        let options = ParseOptions {
+            trailing_tokens: tokens(&["semi"]),
+            ..Default::default()
+        };
+        let (_, errors) = parser.parse_with(tokens(&["p", "y"]), &options);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_strict_eof() {
+        let gram = GramBuilder::from_dsl("grammar![tail| S: | x A B; A: | C; B: | y; | ε; C: | ε; |]")
+            .unwrap()
+            .build()
+            .unwrap();
+        let parser = LL1Parser::builder(gram).build().unwrap();
+        let tokens = vec![Token::new("x", "x", SrcLoc::new((1, 1)))];
+
+        let (root, errors) = parser.parse_with(tokens.clone(), &ParseOptions::default());
+        assert!(errors.is_empty());
+        assert_eq!(root.as_ref().borrow().elems_vec().len(), 1);
+
+        let options = ParseOptions { strict_eof: true, ..Default::default() };
+        let (root, errors) = parser.parse_with(tokens, &options);
+        assert!(errors.is_empty());
+
+        let root = root.as_ref().borrow();
+        let names = root.elems_vec().iter().map(|(sym, _)| sym.name().to_string()).collect_vec();
+        assert_eq!(names, ["x", "A", "B"]);
+
+        match root.get_elem(&GramSym::NonTerminal("A".to_string())) {
+            Some(ASTNode::Tree(subtree)) => {
+                let subtree = subtree.as_ref().borrow();
+                assert_eq!(subtree.elems_vec().len(), 1);
+                assert!(subtree.get_elem(&GramSym::NonTerminal("C".to_string())).is_some());
+            }
+            _ => panic!("missing empty subtree A"),
+        }
+    }
+
+    #[test]
+    fn test_materialize_epsilon() {
+        let gram = GramBuilder::from_dsl("grammar![opt| S: | x Opt y Opt; Opt: | z; | ε; |]")
+            .unwrap()
+            .build()
+            .unwrap();
+        let parser = LL1Parser::builder(gram).build().unwrap();
+        let tokens = vec![
+            Token::new("x", "x", SrcLoc::new((1, 1))),
+            Token::new("y", "y", SrcLoc::new((1, 3))),
+        ];
+
+        let options = ParseOptions { materialize_epsilon: true, ..Default::default() };
+        let (root, errors) = parser.parse_with(tokens, &options);
+        assert!(errors.is_empty());
+
+        let root = root.as_ref().borrow();
+        let names = root.elems_vec().iter().map(|(sym, _)| sym.name().to_string()).collect_vec();
+        assert_eq!(names, ["x", "Opt", "y", "Opt"]);
+
+        for (_, node) in root.elems_vec() {
+            if let ASTNode::Tree(subtree) = node {
+                assert!(subtree.as_ref().borrow().elems_vec().is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_prod_recorded() {
+        let parser = nested_parser();
+        let root = parser.parse(nested_tokens(1)).unwrap();
+        let root = root.as_ref().borrow();
+
+        assert_eq!(root.prod().unwrap().to_string(), "[L] -> <lp> [L] <rp>");
+
+        match root.get_elem(&GramSym::NonTerminal("L".to_string())) {
+            Some(ASTNode::Tree(subtree)) => {
+                assert_eq!(subtree.as_ref().borrow().prod().unwrap().to_string(), "[L] -> <x>");
+            }
+            _ => panic!("missing subtree L"),
+        }
+    }
+
+    #[test]
+    fn test_faithful_tree() {
+        let gram = GramBuilder::from_dsl("grammar![file| File: | Items $; Items: | item Items; | ε; |]")
+            .unwrap()
+            .build()
+            .unwrap();
+        let parser = LL1Parser::builder(gram.clone()).build().unwrap();
+        let tokens = vec![Token::new("item", "a", SrcLoc::new((1, 1)))];
+
+        let options = ParseOptions { faithful: true, ..Default::default() };
+        let (root, errors) = parser.parse_with(tokens, &options);
+        assert!(errors.is_empty());
+
+        let mut stack = vec![root];
+        while let Some(tree) = stack.pop() {
+            let tree = tree.as_ref().borrow();
+            let prod = tree.prod().unwrap();
+            let rhs = prod.rhstr.get_normal().map_or(vec![], |rhs| rhs.clone());
+
+            assert!(gram.prod_index(prod).is_some());
+            assert_eq!(tree.elems_vec().iter().map(|(sym, _)| sym.clone()).collect_vec(), rhs);
+
+            for (_, node) in tree.elems_vec() {
+                if let ASTNode::Tree(subtree) = node {
+                    stack.push(subtree.clone());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_quoted_special_names() {
+        let gram = GramBuilder::from_dsl(r#"grammar![names| S: | "_" "$" T; T: | _ ~{x}; |]"#)
+            .unwrap()
+            .build()
+            .unwrap();
+        let tail = gram.productions().find(|prod| prod.lfsym.name() == "T").unwrap();
+        assert_eq!(tail.rhstr.get_normal().unwrap(), &[GramSym::Any, GramSym::not_set(["x"])]);
+
+        let rebuilt = GramBuilder::from_dsl(&gram.to_dsl()).unwrap().build().unwrap();
+        assert_eq!(rebuilt.productions().collect_vec(), gram.productions().collect_vec());
+
+        let parser = LL1Parser::builder(gram).build().unwrap();
+        let tokens = |names: &[&str]| {
+            names.iter().map(|name| Token::new(name, name, SrcLoc::new((1, 1)))).collect_vec()
+        };
+
+        let root = parser.parse(tokens(&["_", "$", "q", "y"])).unwrap();
+        let names = root.as_ref().borrow().elem_syms();
+        assert_eq!(names[..2], [GramSym::Terminal("_".to_string()), GramSym::Terminal("$".to_string())]);
+
+        assert!(parser.parse(tokens(&["q", "$", "q", "y"])).is_err());
+        assert!(parser.parse(tokens(&["_", "q", "y"])).is_err());
+        assert!(parser.parse(tokens(&["_", "$", "q", "x"])).is_err());
+    }
+
+    #[test]
+    fn test_guarded_terminal() {
+        let gram = GramBuilder::from_dsl(r#"grammar![guard| S: | id("self") dot id; | id eq id; | "kw(\"x\")"; |]"#)
+            .unwrap()
+            .build()
+            .unwrap();
+        let syms = gram.productions().map(|prod| prod.rhstr.get_normal().unwrap()[0].clone()).collect_vec();
+        assert_eq!(syms, [
+            GramSym::Guarded("id".to_string(), "self".to_string()),
+            GramSym::Terminal("id".to_string()),
+            GramSym::Terminal("kw(\"x\")".to_string()),
+        ]);
+
+        let rebuilt = GramBuilder::from_dsl(&gram.to_dsl()).unwrap().build().unwrap();
+        assert_eq!(rebuilt.productions().collect_vec(), gram.productions().collect_vec());
+
+        let parser = LL1Parser::builder(gram).build().unwrap();
+        let tokens = |pairs: &[(&str, &str)]| {
+            pairs.iter().map(|(name, value)| Token::new(name, value, SrcLoc::new((1, 1)))).collect_vec()
+        };
+
+        assert!(parser.parse(tokens(&[("id", "self"), ("dot", "."), ("id", "x")])).is_ok());
+        assert!(parser.parse(tokens(&[("id", "a"), ("eq", "="), ("id", "b")])).is_ok());
+        assert!(parser.parse(tokens(&[("id", "a"), ("dot", "."), ("id", "b")])).is_err());
+        assert!(parser.parse(tokens(&[("kw(\"x\")", "x")])).is_ok());
+        assert!(parser.parse(tokens(&[("kw", "x")])).is_err());
+    }
+
+    #[test]
+    fn test_char_class_terminal() {
+        use crate::scannerless::{CharClass, Scanner};
+
+        let src = r#"grammar![class| S: | ["a-z"] Tail; Tail: | "+" ["a-z"] Tail; | "[a-z]"; | ε; |]"#;
+        let gram = GramBuilder::from_dsl(src).unwrap().build().unwrap();
+        let class = CharClass::parse("[a-z]").unwrap();
+        assert_eq!(gram.productions().next().unwrap().rhstr.get_normal().unwrap()[0], GramSym::CharClass(class));
+
+        let rebuilt = GramBuilder::from_dsl(&gram.to_dsl()).unwrap().build().unwrap();
+        assert_eq!(rebuilt.productions().collect_vec(), gram.productions().collect_vec());
+
+        let escaped = CharClass::parse(r"[^\]\-\\\n a-c]").unwrap();
+        assert_eq!(CharClass::parse(&escaped.to_string()), Some(escaped));
+
+        let scanner = Scanner::new(&gram);
+        let parser = LL1Parser::builder(gram).build().unwrap();
+        assert_eq!(scanner.literals(), ["[a-z]", "+"]);
+
+        let (_, errors) = scanner.parse(&parser, "a+b[a-z]", &ParseOptions::default());
+        assert!(errors.is_empty());
+        let (_, errors) = scanner.parse(&parser, "a+B", &ParseOptions::default());
+        assert!(!errors.is_empty());
+        let (_, errors) = scanner.parse(&parser, "[a-z]", &ParseOptions::default());
+        assert!(!errors.is_empty());
+    }
+
+    /// 在子进程里跑， 检查打印出来的V2跟踪
+    #[cfg(not(feature = "tracing"))]
+    #[test]
+    fn test_redacted_verbose_trace() {
+        if let Some(mode) = std::env::var_os("LL1_TRACE_REDACT") {
+            let redact = if mode == "elide" { Redaction::Elide } else { Redaction::Keep };
+            let tokens = ["id", "eq", "id", "semi", "id", "id", "semi", "id", "eq", "id", "semi"]
+                .iter()
+                .map(|name| Token::new(name, if *name == "id" { "hunter2" } else { name }, SrcLoc::new((1, 1))))
+                .collect_vec();
+
+            let recovering = [
+                ("grammar![s| S: | Stmt S; | ε; Stmt: | id eq id semi; | error semi; |]", true, vec![]),
+                ("grammar![s| S: | Stmt S; | ε; Stmt: | id eq id semi; |]", false, vec!["semi".to_string()]),
+            ];
+            for (src, error_prods, sync_terminals) in recovering {
+                let gram = GramBuilder::from_dsl(src).unwrap().build().unwrap();
+                let parser = LL1Parser::builder(gram).build().unwrap();
+                let options = ParseOptions {
+                    verbose: VerboseLv::V2,
+                    error_prods,
+                    sync_terminals,
+                    redact,
+                    ..Default::default()
+                };
+                let (_, errors) = parser.parse_with(tokens.clone(), &options);
+                assert_eq!(errors.len(), 1);
+            }
+            return;
+        }
+
+        let trace = |mode: &str| {
+            let output = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "parser::test::test_redacted_verbose_trace", "--nocapture"])
+                .env("LL1_TRACE_REDACT", mode)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+            String::from_utf8(output.stdout).unwrap()
+        };
+
+        // 不打码时每一处都能看到token的值
+        let kept = trace("keep");
+        let shows_value = |marker: &str, end: &str| {
+            kept.split(marker).skip(1).any(|rest| rest.split(end).next().unwrap().contains("hunter2"))
+        };
+        assert!(shows_value("tokens: ", "\n]"));
+        for marker in ["! eaten token: ", "!! recover by ", "!! sync to "] {
+            assert!(shows_value(marker, "\n"), "{}", marker);
+        }
+
+        let elided = trace("elide");
+        assert!(elided.contains("!! recover by ") && elided.contains("!! sync to "));
+        assert!(!elided.contains("hunter2"));
+    }
+
+    #[test]
+    fn test_resume_at_every_split() {
+        let gram = GramBuilder::from_dsl("grammar![list| S: | lp Xs rp semi; Xs: | x Xs; | ε; |]")
+            .unwrap()
+            .build()
+            .unwrap();
+        let parser = LL1Parser::builder(gram).build().unwrap();
+        let tokens = ["lp", "x", "x", "x", "rp", "semi"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| Token::new(name, name, SrcLoc::new((1, i))))
+            .collect_vec();
+
+        let (expected, errors) = parser.parse_with(tokens.clone(), &ParseOptions::default());
+        assert!(errors.is_empty());
+
+        // 前缀都不是完整的句子， 在结尾出错， 补上剩下的token继续
+        for split in 0..tokens.len() {
+            let (_err, mut checkpoint) = match parser.parse_resumable(tokens[..split].to_vec()) {
+                Ok(_) => panic!("prefix of {} tokens accepted", split),
+                Err(res) => res,
+            };
+            assert_eq!(checkpoint.pos(), split);
+
+            for token in tokens[split..].iter().rev() {
+                checkpoint.insert(token.clone());
+            }
+            let root = match parser.resume(checkpoint) {
+                Ok(root) => root,
+                Err((err, _)) => panic!("split at {}: {}", split, err),
+            };
+
+            assert!(
+                root.as_ref().borrow().structural_eq(&expected.as_ref().borrow(), LocMode::Include),
+                "split at {}",
+                split
+            );
+        }
+    }
+
+    #[test]
+    fn test_reset_to_mark() {
+        let gram = GramBuilder::from_dsl("grammar![s| S: | Stmt S; | ε; Stmt: | id eq id semi; | error semi; |]")
+            .unwrap()
+            .build()
+            .unwrap();
+        let parser = LL1Parser::builder(gram).build().unwrap();
+        let tokens = |names: &[&str]| {
+            names.iter().enumerate().map(|(i, name)| Token::new(name, name, SrcLoc::new((1, i)))).collect_vec()
+        };
+        let input = tokens(&["id", "eq", "id", "semi", "id", "id", "semi", "id", "eq", "id", "semi"]);
+        let garbage = tokens(&["eq", "eq", "semi", "id", "eq", "id", "semi", "id"]);
+
+        // 不恢复时第一个错误终止解析； 恢复时错误挂起到输入结束
+        for error_prods in [false, true] {
+            let options = ParseOptions { error_prods, ..Default::default() };
+            let (expected, expected_errors) = parser.parse_with(input.clone(), &options);
+            assert_eq!(expected_errors.len(), 1);
+
+            // 在每个位置试探着喂入一段错误的输入， 回退之后结果和没有试探过一样
+            for split in 0..=input.len() {
+                let mut machine = parser.machine(&options);
+                for token in input[..split].iter() {
+                    let _ = machine.feed(token.clone());
+                }
+
+                let mark = machine.mark();
+                for token in garbage.iter() {
+                    let _ = machine.feed(token.clone());
+                }
+                if !error_prods {
+                    assert!(machine.is_failed(), "split at {}", split);
+                }
+                machine.reset(&mark);
+
+                for token in input[split..].iter() {
+                    let _ = machine.feed(token.clone());
+                }
+                let (root, errors) = machine.finish();
+
+                assert!(
+                    root.as_ref().borrow().structural_eq(&expected.as_ref().borrow(), LocMode::Include),
+                    "error_prods: {}, split at {}",
+                    error_prods,
+                    split
+                );
+                assert_eq!(
+                    errors.iter().map(|err| err.to_string()).collect_vec(),
+                    expected_errors.iter().map(|err| err.to_string()).collect_vec(),
+                    "error_prods: {}, split at {}",
+                    error_prods,
+                    split
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_arena() {
+        let parser = nested_parser();
+        let mut arena = ParseArena::new();
+
+        // 所有子树节点的地址
+        fn nodes(root: &Rc<RefCell<AST>>) -> Vec<usize> {
+            let mut addrs = vec![Rc::as_ptr(root) as usize];
+            for (_, child) in root.as_ref().borrow().elems.iter() {
+                if let ASTNode::Tree(subtree) = child {
+                    addrs.extend(nodes(subtree));
+                }
+            }
+
+            addrs
+        }
+
+        let (held, errors) = parser.parse_in(nested_tokens(3), &mut arena);
+        assert!(errors.is_empty());
+        let (held_expected, _) = parser.parse_with(nested_tokens(3), &ParseOptions::default());
+        let held_nodes = nodes(&held);
+
+        let (dropped, errors) = parser.parse_in(nested_tokens(5), &mut arena);
+        assert!(errors.is_empty());
+        let dropped_nodes = nodes(&dropped);
+        drop(dropped);
+
+        // 只回收不再被持有的树
+        arena.reset();
+        assert_eq!(arena.pooled(), dropped_nodes.len());
+
+        let (root, errors) = parser.parse_in(nested_tokens(2), &mut arena);
+        assert!(errors.is_empty());
+        assert_eq!(arena.pooled(), dropped_nodes.len() - nodes(&root).len());
+        assert!(nodes(&root).iter().all(|addr| dropped_nodes.contains(addr)));
+        assert!(nodes(&root).iter().all(|addr| !held_nodes.contains(addr)));
+
+        let (expected, _) = parser.parse_with(nested_tokens(2), &ParseOptions::default());
+        assert!(root.as_ref().borrow().structural_eq(&expected.as_ref().borrow(), LocMode::Include));
+
+        // 被持有的树原样保留
+        arena.reset();
+        assert!(held.as_ref().borrow().structural_eq(&held_expected.as_ref().borrow(), LocMode::Include));
+        assert_eq!(nodes(&held), held_nodes);
+    }
+
+    /// 紧凑地写出树的形状， 叶子节点写成`name:value`
+    fn sexp(tree: &Rc<RefCell<AST>>) -> String {
+        let tree = tree.as_ref().borrow();
+        let mut elems = tree.elems_vec().into_iter().map(|(_, node)| match node {
+            ASTNode::Tree(subtree) => sexp(subtree),
+            ASTNode::Leaf(token) => format!("{}:{}", token.name(), token.value()),
+        });
+
+        format!("({} {})", tree.sym().name(), elems.join(" "))
+    }
+
+    fn stmt_tokens(src: &str) -> Vec<Token> {
+        src.split_whitespace()
+            .enumerate()
+            .map(|(i, name)| Token::new(name, &format!("{}{}", name, i), SrcLoc::new((1, i))))
+            .collect_vec()
+    }
+
+    #[test]
+    fn test_recovered_tree_by_error_prod() {
+        let gram = GramBuilder::from_dsl("grammar![s| S: | Stmt S; | ε; Stmt: | id eq id semi; | error semi; |]")
+            .unwrap()
+            .build()
+            .unwrap();
+        let parser = LL1Parser::builder(gram).build().unwrap();
+        let options = ParseOptions { error_prods: true, ..Default::default() };
+
+        let (root, errors) = parser.parse_with(stmt_tokens("id eq id semi id id eq semi id eq id semi"), &options);
+        assert_eq!(errors.len(), 1);
+        // `error`叶子挂在出错的Stmt下， 替换掉已经吃掉的`id4`， 值是跳过的tokens
+        assert_eq!(
+            sexp(&root),
+            "(S (Stmt id:id0 eq:eq1 id:id2 semi:semi3) \
+            (S (Stmt error:id5 eq6 semi:semi7) \
+            (S (Stmt id:id8 eq:eq9 id:id10 semi:semi11))))"
+        );
+        let error = root.as_ref().borrow().get_node(&NodeId(vec![1, 0, 0])).unwrap();
+        assert_eq!(error.get_token().unwrap().loc(), SrcLoc::new((1, 5)));
+    }
+
+    #[test]
+    fn test_follow_on_errors_suppressed() {
+        let gram = GramBuilder::from_dsl(
+            "grammar![s| S: | Stmt S; | ε; Stmt: | id eq E semi; | error semi; E: | id; | lp E rp; | error; |]"
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+        let parser = LL1Parser::builder(gram).build().unwrap();
+        let kinds = |errors: &[ParseError]| errors.iter().map(|err| (err.kind().clone(), err.token().unwrap().loc())).collect_vec();
+
+        // `E`在`rp`处恢复之后， `Stmt`又在同一个`rp`处出错
+        let tokens = stmt_tokens("id eq lp rp semi id eq id semi");
+        let options = ParseOptions { error_prods: true, ..Default::default() };
+        let (_, errors) = parser.parse_with(tokens.clone(), &options);
+        assert_eq!(kinds(&errors), [(ParseErrorKind::UnexpectedToken, SrcLoc::new((1, 3)))]);
+
+        let (_, errors) = parser.parse_with(tokens, &options.clone().with_keep_follow_on_errors(true));
+        assert_eq!(kinds(&errors), [
+            (ParseErrorKind::UnexpectedToken, SrcLoc::new((1, 3))),
+            (ParseErrorKind::UnmatchedToken, SrcLoc::new((1, 3))),
+        ]);
+
+        // 展开自同一处的tokens位置相同， 报出来的错误也一模一样
+        let tokens = ["id", "id", "semi", "id", "id", "semi"]
+            .iter()
+            .map(|name| Token::new(name, name, SrcLoc::new((1, 1))))
+            .collect_vec();
+        let (_, errors) = parser.parse_with(tokens.clone(), &options);
+        assert_eq!(kinds(&errors), [(ParseErrorKind::UnmatchedToken, SrcLoc::new((1, 1)))]);
+
+        let (_, errors) = parser.parse_with(tokens, &options.clone().with_keep_follow_on_errors(true));
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].msg(), errors[1].msg());
+    }
+
+    #[test]
+    fn test_recovered_tree_by_sync() {
+        let gram = GramBuilder::from_dsl("grammar![s| S: | Stmt S; | ε; Stmt: | id eq id semi; |]")
+            .unwrap()
+            .build()
+            .unwrap();
+        let parser = LL1Parser::builder(gram).build().unwrap();
+        let options = ParseOptions { sync_terminals: vec!["semi".to_string()], ..Default::default() };
+
+        let (root, errors) = parser.parse_with(stmt_tokens("id eq id semi id id eq semi id eq id semi"), &options);
+        assert_eq!(errors.len(), 1);
+        // 出错的Stmt只留下已经吃掉的部分， 跳过的tokens连同同步终结符都不在树上， 也没有`error`节点
+        assert_eq!(
+            sexp(&root),
+            "(S (Stmt id:id0 eq:eq1 id:id2 semi:semi3) \
+            (S (Stmt id:id4) \
+            (S (Stmt id:id8 eq:eq9 id:id10 semi:semi11))))"
+        );
+    }
 }
@@ -0,0 +1,259 @@
+//! Provenance: 变换生成的符号和产生式对应回原语法， 并把变换后语法解析出的AST还原成原语法的形状
+//!
+//! 产生式上的`origin`注解记录它改写自原语法的哪个产生式， `synthetic`注解记录生成的符号是什么:
+//!
+//! - `alias:S`  代表原来的整个符号S(新的开始符号、 提升出来的终结符)
+//! - `part:A`   A的某个产生式右边的一段(拆分长产生式)， 还原时展开到父节点里
+//! - `tail:A`   消除A的左递归引入的尾部， 还原成左结合的嵌套
+
+use std::{cell::RefCell, collections::VecDeque, fmt, rc::Rc};
+
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{
+    error::ParseError,
+    gram::{Gram, GramProd, GramSym, GramSymStr, ProdAttrs},
+    parser::{ASTNode, LL1Parser, ParseOptions, Token, AST},
+    transform::ORIGIN_ATTR,
+};
+
+
+/// 记录生成的符号的产生式注解
+pub const SYNTHETIC_ATTR: &str = "synthetic";
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SymOrigin {
+    Alias(GramSym),
+    Part(GramSym),
+    Tail(GramSym),
+}
+
+impl SymOrigin {
+    pub fn sym(&self) -> &GramSym {
+        match self {
+            Self::Alias(sym) | Self::Part(sym) | Self::Tail(sym) => sym,
+        }
+    }
+
+    /// 符号的种类由原语法决定
+    fn parse(value: &str, original: &Gram) -> Option<Self> {
+        let (kind, name) = value.split_once(':')?;
+        let sym = original
+            .symbols()
+            .find(|sym| sym.name() == name)
+            .cloned()
+            .unwrap_or_else(|| GramSym::NonTerminal(name.to_string()));
+
+        match kind {
+            "alias" => Some(Self::Alias(sym)),
+            "part" => Some(Self::Part(sym)),
+            "tail" => Some(Self::Tail(sym)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SymOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Alias(sym) => write!(f, "alias:{}", sym.name()),
+            Self::Part(sym) => write!(f, "part:{}", sym.name()),
+            Self::Tail(sym) => write!(f, "tail:{}", sym.name()),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    original: Gram,
+    /// 生成的符号 => 它在原语法里代表什么
+    pub syms: IndexMap<GramSym, SymOrigin>,
+    /// `origin`注解的文本 => 原语法的产生式
+    prods: IndexMap<String, GramProd>,
+}
+
+impl Provenance {
+    /// 从`transformed`的注解里收集到`original`的映射
+    pub fn new(original: &Gram, transformed: &Gram) -> Self {
+        let mut syms = IndexMap::new();
+
+        for prod in transformed.productions() {
+            if let Some(origin) = prod.attr(SYNTHETIC_ATTR).and_then(|value| SymOrigin::parse(value, original)) {
+                syms.entry(prod.lfsym.clone()).or_insert(origin);
+            }
+        }
+
+        let prods = original
+            .productions()
+            .map(|prod| (prod.to_string(), prod.clone()))
+            .collect();
+
+        Self {
+            original: original.clone(),
+            syms,
+            prods,
+        }
+    }
+
+    pub fn original(&self) -> &Gram {
+        &self.original
+    }
+
+    /// 变换后的产生式改写自原语法的哪个产生式， 没有改写过的就是它自己
+    pub fn origin_of(&self, prod: &GramProd) -> Option<&GramProd> {
+        let key = prod.attr(ORIGIN_ATTR).map_or_else(|| prod.to_string(), |origin| origin.to_string());
+
+        self.prods.get(&key)
+    }
+
+    /// 还原成原语法的AST:
+    /// 展开`part`节点， 把`alias`节点换成原来的符号， 把`tail`链还原成左结合的嵌套，
+    /// 消除单产生式时跳过的中间节点按原语法补回来。
+    ///
+    /// 每一步变换都只是局部的改写时结果和直接用原语法解析一致， 像格雷巴赫范式这样反复代换过的，
+    /// 只能保证符号都属于原语法
+    pub fn restore(&self, root: &Rc<RefCell<AST>>) -> Rc<RefCell<AST>> {
+        let ast = root.as_ref().borrow();
+        let sym = match self.syms.get(ast.sym()) {
+            Some(SymOrigin::Alias(sym)) if sym.is_nonterminal() => sym.clone(),
+            _ => ast.sym().clone(),
+        };
+
+        let (children, tails) = self.restore_children(&ast);
+        let mut tree = self.build(&sym, ast.attr(ORIGIN_ATTR), ast.attrs().clone(), children);
+
+        // A -> β Z, Z -> α Z  =>  A(A(A(β) α1) α2)
+        for (attrs, segment) in tails {
+            let head = ASTNode::Tree(tree);
+            let origin = attrs.get(ORIGIN_ATTR).map(|origin| origin.as_str());
+            tree = self.build(&sym, origin, attrs.clone(), [vec![head], segment].concat());
+        }
+
+        tree
+    }
+
+    /// 还原后的子节点， 以及tail链上每一段的(注解, 子节点)
+    fn restore_children(&self, ast: &AST) -> (Vec<ASTNode>, Vec<(ProdAttrs, Vec<ASTNode>)>) {
+        let mut children = vec![];
+        let mut tails = vec![];
+
+        for (_sym, node) in ast.elems_vec() {
+            let subtree = match node {
+                ASTNode::Leaf(_) => {
+                    children.push(node.clone());
+                    continue;
+                }
+                ASTNode::Tree(subtree) => subtree,
+            };
+
+            let sub = subtree.as_ref().borrow();
+            match self.syms.get(sub.sym()) {
+                Some(SymOrigin::Part(_)) => {
+                    let (sub_children, sub_tails) = self.restore_children(&sub);
+                    children.extend(sub_children);
+                    tails.extend(sub_tails);
+                }
+                Some(SymOrigin::Alias(term)) if term.is_terminal() => {
+                    children.extend(self.restore_children(&sub).0);
+                }
+                Some(SymOrigin::Tail(_)) => {
+                    let (segment, sub_tails) = self.restore_children(&sub);
+                    tails.push((sub.attrs().clone(), segment));
+                    tails.extend(sub_tails);
+                }
+                _ => children.push(ASTNode::Tree(self.restore(subtree))),
+            }
+        }
+
+        (children, tails)
+    }
+
+    /// 产生式改写自别的规则(单产生式被消除了)时， 按原语法的单产生式链补上中间节点
+    fn build(
+        &self,
+        sym: &GramSym,
+        origin: Option<&str>,
+        attrs: ProdAttrs,
+        children: Vec<ASTNode>
+    ) -> Rc<RefCell<AST>>
+    {
+        let origin_prod = origin.and_then(|origin| self.prods.get(origin));
+        let (chain, attrs) = match origin_prod {
+            Some(prod) => (self.unit_chain(sym, &prod.lfsym), prod.attrs.clone()),
+            None => (vec![sym.clone()], attrs),
+        };
+
+        let mut inner = AST::new(chain.last().unwrap());
+        inner.set_attrs(attrs);
+        for child in children {
+            inner.insert_node(child);
+        }
+
+        let mut tree = Rc::new(RefCell::new(inner));
+        for outer_sym in chain.iter().rev().skip(1) {
+            let mut outer = AST::new(outer_sym);
+            let unit = GramProd::new(
+                outer_sym.clone(),
+                GramSymStr::Str(vec![tree.as_ref().borrow().sym().clone()])
+            );
+            if let Some(prod) = self.original.productions().find(|prod| **prod == unit) {
+                outer.set_attrs(prod.attrs.clone());
+            }
+            outer.insert_tree(tree);
+            tree = Rc::new(RefCell::new(outer));
+        }
+
+        tree
+    }
+
+    /// 原语法里从`from`经单产生式到`to`的符号链(包括两端)， 找不到时只有`from`
+    fn unit_chain(&self, from: &GramSym, to: &GramSym) -> Vec<GramSym> {
+        let mut prev: IndexMap<GramSym, GramSym> = IndexMap::new();
+        let mut visited = IndexSet::new();
+        let mut queue = VecDeque::new();
+
+        visited.insert(from.clone());
+        queue.push_back(from.clone());
+
+        while let Some(cur) = queue.pop_front() {
+            if &cur == to {
+                let mut chain = vec![cur];
+                while let Some(sym) = prev.get(chain.last().unwrap()) {
+                    chain.push(sym.clone());
+                }
+                chain.reverse();
+
+                return chain;
+            }
+
+            for prod in self.original.alts_of(&cur) {
+                if let Some([next]) = prod.rhstr.get_normal().map(|normal_str| &normal_str[..]) {
+                    if next.is_nonterminal() && visited.insert(next.clone()) {
+                        prev.insert(next.clone(), cur.clone());
+                        queue.push_back(next.clone());
+                    }
+                }
+            }
+        }
+
+        vec![from.clone()]
+    }
+}
+
+
+impl LL1Parser {
+    /// 同`parse_with`， 解析器的语法由`provenance`的原语法变换而来， 结果还原成原语法的AST
+    pub fn parse_restored(
+        &self,
+        tokens: Vec<Token>,
+        options: &ParseOptions,
+        provenance: &Provenance
+    ) -> (Rc<RefCell<AST>>, Vec<ParseError>)
+    {
+        let (root, errors) = self.parse_with(tokens, options);
+
+        (provenance.restore(&root), errors)
+    }
+}
@@ -0,0 +1,289 @@
+//! Python Bindings: 用PyO3导出`Grammar`、 `Parser`和`Ast`， 在Python里定义语法、 解析文件，
+//! 需要开启`python`特性， 用maturin构建扩展模块
+//!
+//! ```none
+//! maturin build --release --features python,pyo3/extension-module
+//! ```
+//!
+//! ```python
+//! import ll1engine
+//!
+//! gram = ll1engine.Grammar(open("lang.gram").read())
+//! parser = ll1engine.Parser(gram, [("ws", r"\s+"), ("id", "[a-z]+"), ("eq", "="), ...], skip=["ws"])
+//!
+//! try:
+//!     ast = parser.parse_file("main.lang")
+//! except ll1engine.ParseError as err:
+//!     print(err.line, err.col, err.expected)
+//!
+//! for node in ast.walk():
+//!     print(node.name, node.value, node.loc)
+//! ```
+//!
+//! 异常都继承`ll1engine.Error`： 语法有问题是`GrammarError`， 不能识别的字符是`LexError`，
+//! 语法错误是`ParseError`(带`kind`、 `line`、 `col`、 `expected`属性)
+
+use std::{convert::TryFrom, path::PathBuf, sync::Arc};
+
+use pyo3::{
+    create_exception,
+    exceptions::{PyException, PyIndexError, PyOSError, PyValueError},
+    prelude::*,
+};
+
+use crate::{
+    builder::GramBuilder,
+    error::ParseError as LlParseError,
+    gram::Gram,
+    lexer::{Lexer, RegexTokenMatcher},
+    parser::{FlatNode, LL1Parser, ParseOptions, SrcFileInfo, Token},
+};
+
+
+create_exception!(ll1engine, Error, PyException);
+create_exception!(ll1engine, GrammarError, Error);
+create_exception!(ll1engine, LexError, Error);
+create_exception!(ll1engine, ParseError, Error);
+
+
+fn parse_error(py: Python<'_>, err: &LlParseError) -> PyErr {
+    let pyerr = ParseError::new_err(err.to_string());
+    let value = pyerr.value(py);
+    let (line, col) = err.loc().map_or((None, None), |loc| (Some(loc.ln), Some(loc.col)));
+    let expected = err.expected().iter().map(|la| la.to_string()).collect::<Vec<_>>();
+
+    // 设置新建的异常对象的属性不会失败
+    value.setattr("kind", format!("{:?}", err.kind())).unwrap();
+    value.setattr("line", line).unwrap();
+    value.setattr("col", col).unwrap();
+    value.setattr("expected", expected).unwrap();
+
+    pyerr
+}
+
+
+#[pyclass(frozen, module = "ll1engine")]
+pub struct Grammar {
+    gram: Gram,
+}
+
+#[pymethods]
+impl Grammar {
+    /// 从`grammar![name| ... |]`形式的DSL文本构建
+    #[new]
+    fn new(dsl: &str) -> PyResult<Self> {
+        let gram = GramBuilder::from_dsl(dsl)
+            .map_err(|err| GrammarError::new_err(err.to_string()))?
+            .build()
+            .map_err(|diags| GrammarError::new_err(diags.to_string()))?;
+
+        Ok(Self { gram })
+    }
+
+    #[getter]
+    fn name(&self) -> &str {
+        self.gram.name()
+    }
+
+    /// 非终结符名， 开始符号在前
+    fn nonterminals(&self) -> Vec<String> {
+        self.gram.nonterminals().map(|sym| sym.name().to_string()).collect()
+    }
+
+    fn to_dsl(&self) -> String {
+        self.gram.to_dsl()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Grammar({:?})", self.gram.name())
+    }
+}
+
+
+#[pyclass(frozen, module = "ll1engine")]
+pub struct Parser {
+    parser: LL1Parser,
+    lexer: Lexer,
+}
+
+impl Parser {
+    fn parse_tokens(&self, py: Python<'_>, tokens: Vec<Token>) -> (Ast, Vec<PyErr>) {
+        let (root, errors) = self.parser.parse_with(tokens, &ParseOptions::default());
+        let ast = Ast::new(root.as_ref().borrow().flatten());
+
+        (ast, errors.iter().map(|err| parse_error(py, err)).collect())
+    }
+
+    fn parse_strict(&self, py: Python<'_>, tokens: Vec<Token>) -> PyResult<Ast> {
+        let (ast, mut errors) = self.parse_tokens(py, tokens);
+
+        if errors.is_empty() {
+            Ok(ast)
+        }
+        else {
+            Err(errors.remove(0))
+        }
+    }
+}
+
+#[pymethods]
+impl Parser {
+    /// `tokens`是`(name, pattern)`的列表， 声明顺序就是优先级， `skip`里的token被丢弃
+    #[new]
+    #[pyo3(signature = (grammar, tokens, skip = vec![]))]
+    fn new(grammar: &Grammar, tokens: Vec<(String, String)>, skip: Vec<String>) -> PyResult<Self> {
+        let mut rules = vec![];
+        for (name, pattern) in tokens {
+            let matcher = RegexTokenMatcher::try_new(&pattern).map_err(|err| PyValueError::new_err(err.to_string()))?;
+            rules.push((matcher, name));
+        }
+
+        let mut lexer = Lexer::new(rules);
+        for name in skip.iter() {
+            lexer = lexer.with_skip(name);
+        }
+
+        let parser = LL1Parser::try_new(grammar.gram.clone())
+            .map_err(|err| GrammarError::new_err(err.to_string()))?;
+
+        let lexer = lexer.compile().map_err(|err| PyValueError::new_err(err.to_string()))?;
+
+        Ok(Self { parser, lexer })
+    }
+
+    /// 遇到第一个语法错误就抛出`ParseError`
+    fn parse(&self, py: Python<'_>, src: &str) -> PyResult<Ast> {
+        let tokens = self.lexer.tokenize_str(src).map_err(|err| LexError::new_err(err.to_string()))?;
+
+        self.parse_strict(py, tokens)
+    }
+
+    fn parse_file(&self, py: Python<'_>, path: PathBuf) -> PyResult<Ast> {
+        let srcfile = SrcFileInfo::new(path).map_err(|err| PyOSError::new_err(err.to_string()))?;
+        let tokens = self.lexer.tokenize(&srcfile).map_err(|err| LexError::new_err(err.to_string()))?;
+
+        self.parse_strict(py, tokens)
+    }
+
+    /// 做错误恢复， 返回`(ast, [ParseError, ...])`
+    fn parse_recover(&self, py: Python<'_>, src: &str) -> PyResult<(Ast, Vec<PyErr>)> {
+        let tokens = self.lexer.tokenize_str(src).map_err(|err| LexError::new_err(err.to_string()))?;
+
+        Ok(self.parse_tokens(py, tokens))
+    }
+}
+
+
+/// AST的一个节点， 同一棵树的节点共享展平的节点表
+#[pyclass(frozen, module = "ll1engine")]
+#[derive(Clone)]
+pub struct Ast {
+    nodes: Arc<Vec<FlatNode>>,
+    id: usize,
+}
+
+impl Ast {
+    fn new(nodes: Vec<FlatNode>) -> Self {
+        Self { nodes: Arc::new(nodes), id: 0 }
+    }
+
+    fn node(&self) -> &FlatNode {
+        &self.nodes[self.id]
+    }
+
+    fn at(&self, id: usize) -> Self {
+        Self { nodes: self.nodes.clone(), id }
+    }
+}
+
+#[pymethods]
+impl Ast {
+    /// 非终结符名或者token名
+    #[getter]
+    fn name(&self) -> &str {
+        &self.node().name
+    }
+
+    /// 叶子的token值， 子树为None
+    #[getter]
+    fn value(&self) -> Option<&str> {
+        self.node().value.as_deref()
+    }
+
+    #[getter]
+    fn is_leaf(&self) -> bool {
+        self.node().value.is_some()
+    }
+
+    /// `(line, col)`， 从1开始， 没有token的子树为None
+    #[getter]
+    fn loc(&self) -> Option<(usize, usize)> {
+        self.node().loc.as_ref().map(|loc| (loc.ln, loc.col))
+    }
+
+    #[getter]
+    fn children(&self) -> Vec<Ast> {
+        self.node().children.iter().map(|&id| self.at(id)).collect()
+    }
+
+    #[getter]
+    fn parent(&self) -> Option<Ast> {
+        self.node().parent.map(|id| self.at(id))
+    }
+
+    /// 先序遍历这个节点和它的所有后代
+    fn walk(&self) -> Vec<Ast> {
+        let mut nodes = vec![];
+        let mut stack = vec![self.id];
+
+        while let Some(id) = stack.pop() {
+            nodes.push(self.at(id));
+            stack.extend(self.nodes[id].children.iter().rev());
+        }
+
+        nodes
+    }
+
+    /// 先序第一个名为`name`的后代(包括自己)
+    fn find(&self, name: &str) -> Option<Ast> {
+        self.walk().into_iter().find(|node| node.node().name == name)
+    }
+
+    fn __len__(&self) -> usize {
+        self.node().children.len()
+    }
+
+    fn __getitem__(&self, i: isize) -> PyResult<Ast> {
+        let children = &self.node().children;
+        let i = if i < 0 { i + children.len() as isize } else { i };
+
+        usize::try_from(i)
+            .ok()
+            .and_then(|i| children.get(i))
+            .map(|&id| self.at(id))
+            .ok_or_else(|| PyIndexError::new_err("child index out of range"))
+    }
+
+    fn __repr__(&self) -> String {
+        match &self.node().value {
+            Some(value) => format!("Ast({}, {:?})", self.node().name, value),
+            None => format!("Ast({}, len={})", self.node().name, self.node().children.len()),
+        }
+    }
+}
+
+
+#[pymodule]
+fn ll1engine(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+
+    m.add_class::<Grammar>()?;
+    m.add_class::<Parser>()?;
+    m.add_class::<Ast>()?;
+    m.add("Error", py.get_type::<Error>())?;
+    m.add("GrammarError", py.get_type::<GrammarError>())?;
+    m.add("LexError", py.get_type::<LexError>())?;
+    m.add("ParseError", py.get_type::<ParseError>())?;
+
+    Ok(())
+}
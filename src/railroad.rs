@@ -0,0 +1,272 @@
+//! Railroad Diagram: 把一条规则画成SVG铁路图
+//!
+//! 右递归加epsilon的规则(`A -> α A | ε`)， 也就是EBNF里`{α}`展开后的样子，
+//! 会画成循环
+
+use std::fmt::Write;
+
+use crate::gram::*;
+
+
+const BOX_HEIGHT: f64 = 22.0;
+const CHAR_WIDTH: f64 = 8.5;
+const GAP: f64 = 10.0;
+const ARC: f64 = 10.0;
+const PADDING: f64 = 20.0;
+
+
+#[derive(Debug, Clone)]
+enum Railroad {
+    Terminal(String),
+    NonTerminal(String),
+    Skip,
+    Sequence(Vec<Railroad>),
+    Choice(Vec<Railroad>),
+    OneOrMore(Box<Railroad>),
+}
+
+impl Railroad {
+    fn from_symstr(symstr: &GramSymStr) -> Self {
+        match symstr {
+            GramSymStr::Epsilon => Self::Skip,
+            GramSymStr::Str(syms) => Self::from_syms(syms),
+        }
+    }
+
+    fn from_syms(syms: &[GramSym]) -> Self {
+        let mut items: Vec<Self> = syms
+            .iter()
+            .map(|sym| {
+                if sym.is_terminal() {
+                    Self::Terminal(sym.name().to_string())
+                } else {
+                    Self::NonTerminal(sym.name().to_string())
+                }
+            })
+            .collect();
+
+        match items.len() {
+            0 => Self::Skip,
+            1 => items.pop().unwrap(),
+            _ => Self::Sequence(items),
+        }
+    }
+
+    fn from_rule(lfsym: &GramSym, prods: &[GramProd]) -> Self {
+        let epsilon_cnt = prods.iter().filter(|prod| prod.rhstr.is_epsilon()).count();
+        let recur_alts: Vec<&[GramSym]> = prods
+            .iter()
+            .filter_map(|prod| prod.rhstr.get_normal())
+            .filter(|syms| syms.len() > 1 && syms.last() == Some(lfsym))
+            .map(|syms| &syms[..syms.len() - 1])
+            .collect();
+
+        // A -> α1 A | α2 A | ε  ==>  {α1 | α2}
+        if epsilon_cnt == 1 && recur_alts.len() + 1 == prods.len() {
+            let body = if recur_alts.len() == 1 {
+                Self::from_syms(recur_alts[0])
+            } else {
+                Self::Choice(recur_alts.into_iter().map(Self::from_syms).collect())
+            };
+
+            return Self::Choice(vec![Self::Skip, Self::OneOrMore(Box::new(body))]);
+        }
+
+        if prods.len() == 1 {
+            Self::from_symstr(&prods[0].rhstr)
+        } else {
+            Self::Choice(prods.iter().map(|prod| Self::from_symstr(&prod.rhstr)).collect())
+        }
+    }
+
+    fn width(&self) -> f64 {
+        match self {
+            Self::Terminal(name) | Self::NonTerminal(name) => {
+                name.chars().count() as f64 * CHAR_WIDTH + 2.0 * GAP
+            },
+            Self::Skip => 0.0,
+            Self::Sequence(items) => {
+                items.iter().map(|item| item.width()).sum::<f64>()
+                + GAP * (items.len() as f64 - 1.0)
+            },
+            Self::Choice(items) => {
+                items.iter().map(|item| item.width()).fold(0.0, f64::max) + 4.0 * ARC
+            },
+            Self::OneOrMore(item) => item.width() + 2.0 * ARC,
+        }
+    }
+
+    /// 主线以上的高度
+    fn up(&self) -> f64 {
+        match self {
+            Self::Terminal(_) | Self::NonTerminal(_) => BOX_HEIGHT / 2.0,
+            Self::Skip => 0.0,
+            Self::Sequence(items) => items.iter().map(|item| item.up()).fold(0.0, f64::max),
+            Self::Choice(items) => items.first().map_or(0.0, |item| item.up()),
+            Self::OneOrMore(item) => item.up(),
+        }
+    }
+
+    /// 主线以下的高度
+    fn down(&self) -> f64 {
+        match self {
+            Self::Terminal(_) | Self::NonTerminal(_) => BOX_HEIGHT / 2.0,
+            Self::Skip => 0.0,
+            Self::Sequence(items) => items.iter().map(|item| item.down()).fold(0.0, f64::max),
+            Self::Choice(_) => {
+                let ys = self.branch_ys(0.0);
+                match self {
+                    Self::Choice(items) => ys.last().unwrap() + items.last().unwrap().down(),
+                    _ => unreachable!(),
+                }
+            },
+            Self::OneOrMore(item) => item.down() + GAP + ARC,
+        }
+    }
+
+    /// Choice各个分支主线的纵坐标
+    fn branch_ys(&self, y: f64) -> Vec<f64> {
+        let mut ys = vec![];
+
+        if let Self::Choice(items) = self {
+            let mut cur_y = y;
+
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    cur_y += (items[i - 1].down() + GAP + item.up()).max(2.0 * ARC);
+                }
+                ys.push(cur_y);
+            }
+        }
+
+        ys
+    }
+
+    /// (x, y)是左端点和主线的位置
+    fn render(&self, x: f64, y: f64, out: &mut String) {
+        match self {
+            Self::Terminal(name) | Self::NonTerminal(name) => {
+                let rx = if let Self::Terminal(_) = self { BOX_HEIGHT / 2.0 } else { 0.0 };
+                let class = if let Self::Terminal(_) = self { "terminal" } else { "nonterminal" };
+
+                writeln!(
+                    out,
+                    r#"<g class="{}"><rect x="{}" y="{}" width="{}" height="{}" rx="{}"/><text x="{}" y="{}">{}</text></g>"#,
+                    class, x, y - BOX_HEIGHT / 2.0, self.width(), BOX_HEIGHT, rx,
+                    x + self.width() / 2.0, y + 4.0, escape_xml(name)
+                ).unwrap();
+            },
+            Self::Skip => {},
+            Self::Sequence(items) => {
+                let mut cur_x = x;
+
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        line(out, cur_x, y, cur_x + GAP, y);
+                        cur_x += GAP;
+                    }
+                    item.render(cur_x, y, out);
+                    cur_x += item.width();
+                }
+            },
+            Self::Choice(items) => {
+                let width = self.width();
+                let inner_width = width - 4.0 * ARC;
+                let xr = x + 2.0 * ARC + inner_width;
+
+                for (item, item_y) in items.iter().zip(self.branch_ys(y)) {
+                    if item_y == y {
+                        line(out, x, y, x + 2.0 * ARC, y);
+                        line(out, xr, y, x + width, y);
+                    }
+                    else {
+                        writeln!(
+                            out,
+                            r#"<path d="M{} {} q{} 0 {} {} v{} q0 {} {} {}"/>"#,
+                            x, y, ARC, ARC, ARC, item_y - y - 2.0 * ARC, ARC, ARC, ARC
+                        ).unwrap();
+                        writeln!(
+                            out,
+                            r#"<path d="M{} {} q{} 0 {} {} v{} q0 {} {} {}"/>"#,
+                            xr, item_y, ARC, ARC, -ARC, -(item_y - y - 2.0 * ARC), -ARC, ARC, -ARC
+                        ).unwrap();
+                    }
+
+                    item.render(x + 2.0 * ARC, item_y, out);
+                    line(out, x + 2.0 * ARC + item.width(), item_y, xr, item_y);
+                }
+            },
+            Self::OneOrMore(item) => {
+                let xr = x + ARC + item.width();
+                let loop_y = y + item.down() + GAP + ARC;
+
+                line(out, x, y, x + ARC, y);
+                item.render(x + ARC, y, out);
+                line(out, xr, y, xr + ARC, y);
+
+                // 从右侧绕回左侧
+                writeln!(
+                    out,
+                    r#"<path d="M{} {} q{} 0 {} {} v{} q0 {} {} {} h{} q{} 0 {} {} v{} q0 {} {} {}"/>"#,
+                    xr, y, ARC / 2.0, ARC / 2.0, ARC / 2.0, loop_y - y - ARC, ARC / 2.0,
+                    -ARC / 2.0, ARC / 2.0, -(item.width()), -ARC / 2.0, -ARC / 2.0, -ARC / 2.0,
+                    -(loop_y - y - ARC), -ARC / 2.0, ARC / 2.0, -ARC / 2.0
+                ).unwrap();
+            },
+        }
+    }
+}
+
+fn line(out: &mut String, x1: f64, y1: f64, x2: f64, y2: f64) {
+    if x1 != x2 || y1 != y2 {
+        writeln!(out, r#"<path d="M{} {} L{} {}"/>"#, x1, y1, x2, y2).unwrap();
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+
+impl Gram {
+    /// 画出sym对应的规则， sym没有产生式时返回None
+    pub fn to_railroad_svg(&self, sym: &GramSym) -> Option<String> {
+        let prods = self.find_prod(sym);
+        if prods.is_empty() {
+            return None;
+        }
+
+        let diagram = Railroad::from_rule(sym, &prods);
+
+        let width = diagram.width() + 2.0 * PADDING + 2.0 * GAP;
+        let height = diagram.up() + diagram.down() + 2.0 * PADDING;
+        let y = PADDING + diagram.up();
+
+        let mut out = String::new();
+        writeln!(
+            &mut out,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}">"#,
+            width, height, width, height
+        ).unwrap();
+        writeln!(&mut out, "<title>{}</title>", escape_xml(&sym.name())).unwrap();
+        writeln!(
+            &mut out,
+            "<style>path {{ fill: none; stroke: #333; stroke-width: 2; }} \
+            rect {{ fill: #fff; stroke: #333; stroke-width: 2; }} \
+            .terminal rect {{ fill: #e8f4e8; }} \
+            text {{ font: 14px monospace; text-anchor: middle; }}</style>"
+        ).unwrap();
+
+        // 起点和终点的竖线
+        writeln!(&mut out, r#"<path d="M{} {} v{}"/>"#, PADDING, y - ARC, 2.0 * ARC).unwrap();
+        line(&mut out, PADDING, y, PADDING + GAP, y);
+        diagram.render(PADDING + GAP, y, &mut out);
+        let end_x = PADDING + GAP + diagram.width();
+        line(&mut out, end_x, y, end_x + GAP, y);
+        writeln!(&mut out, r#"<path d="M{} {} v{}"/>"#, end_x + GAP, y - ARC, 2.0 * ARC).unwrap();
+
+        writeln!(&mut out, "</svg>").unwrap();
+
+        Some(out)
+    }
+}
@@ -0,0 +1,71 @@
+//! Redaction: 把token的值换成哈希或者等长的`*`， 名字和位置不变，
+//! 解析含有敏感内容(配置里的密码)的输入时， 错误信息、 跟踪记录和保存的token流都可以放心地分享
+//!
+//! - `Hash { key }`: 同一个`key`下相同的值得到相同的哈希， 还能看出哪些token是同一个值，
+//!   值的取值范围很小时可以被穷举， 分享给不同的人时换一个`key`
+//! - `Elide`: 每个字符换成`*`， 只保留长度(区间里本来就有)
+//!
+//! ```ignore
+//! let options = ParseOptions { redact: Redaction::Elide, ..Default::default() };
+//! let (root, errors) = parser.parse_with(tokens, &options);
+//! // Unmatched token<id>: ****** (3, 10), a <semi> expected
+//!
+//! stream.save_with("bug.tokens", Redaction::Hash { key: 42 })?;
+//! ```
+//!
+//! 解析出的语法树和事件里还是原来的值
+
+use std::fmt::Write as _;
+
+use crate::parser::Token;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Redaction {
+    #[default]
+    Keep,
+    Hash { key: u64 },
+    Elide,
+}
+
+impl Redaction {
+    pub fn is_keep(&self) -> bool {
+        matches!(self, Self::Keep)
+    }
+
+    /// `Hash`下形如`#5f0c3a9e81d2b74c`
+    pub fn apply(&self, value: &str) -> String {
+        match self {
+            Self::Keep => value.to_string(),
+            Self::Hash { key } => {
+                // 带key的FNV-1a
+                let mut hash = 0xcbf2_9ce4_8422_2325u64;
+
+                for b in key.to_le_bytes().iter().chain(value.as_bytes()) {
+                    hash ^= *b as u64;
+                    hash = hash.wrapping_mul(0x0100_0000_01b3);
+                }
+
+                let mut out = String::from("#");
+                write!(out, "{:016x}", hash).unwrap();
+
+                out
+            }
+            Self::Elide => "*".repeat(value.chars().count()),
+        }
+    }
+
+    /// 换掉值的token， 其余不变(附加值除外)
+    pub fn token(&self, token: &Token) -> Token {
+        if self.is_keep() {
+            return token.clone();
+        }
+
+        let mut redacted = Token::new(token.name(), &self.apply(token.value()), token.loc());
+        if token.is_synthesized() {
+            redacted.set_synthesized();
+        }
+
+        redacted
+    }
+}
@@ -0,0 +1,210 @@
+//! Grammar Refactoring: 内联/提取非终结符， 改写不能引入新的LL(1)冲突
+
+use std::error::Error;
+
+use indexmap::IndexSet;
+use itertools::Itertools;
+
+use crate::error::{Trap, TrapCode};
+use crate::gram::{Gram, GramProd, GramSym, GramSymStr};
+
+
+impl Gram {
+    /// 把`sym`的各个分支代入所有用到它的地方， 然后删掉这条规则，
+    /// 用到它的产生式的注解复制到展开后的每个产生式上
+    ///
+    /// `sym`是开始符号、 直接或间接递归、 带有错误产生式， 或者内联后会产生新的LL(1)冲突时报错，
+    /// 此时语法不变
+    pub fn inline(&mut self, sym: &GramSym) -> Result<(), Box<dyn Error>> {
+        if !self.nonterminals().any(|x| x == sym) {
+            return Err(Trap::new_box_err(&format!("{} is not a nonterminal of {}", sym, self.name())));
+        }
+        if self.start_sym() == Some(sym) {
+            return Err(Trap::new_box_err(&format!("can't inline start symbol {}", sym)));
+        }
+        if self.alts_of(sym).any(|prod| prod.is_error_prod()) {
+            return Err(Trap::new_box_err(&format!("can't inline {} with error production", sym)));
+        }
+        if self.reachable_from(sym).contains(sym) {
+            return Err(Trap::new_box_err(&format!("can't inline recursive {}", sym)));
+        }
+
+        let alts = self
+            .alts_of(sym)
+            .map(|prod| prod.rhstr.get_normal().cloned().unwrap_or_default())
+            .collect_vec();
+
+        let mut inlined = Gram::new(self.name());
+        for prod in self.productions().filter(|prod| &prod.lfsym != sym) {
+            let normal_str = match &prod.rhstr {
+                GramSymStr::Str(normal_str) => normal_str,
+                GramSymStr::Epsilon => {
+                    inlined.insert_prod(prod.clone());
+                    continue;
+                }
+            };
+
+            let mut partials = vec![vec![]];
+            for rhs_sym in normal_str.iter() {
+                partials = if rhs_sym == sym {
+                    partials
+                        .iter()
+                        .cartesian_product(alts.iter())
+                        .map(|(head, alt)| [head.clone(), alt.clone()].concat())
+                        .collect()
+                } else {
+                    partials
+                        .into_iter()
+                        .map(|mut partial| {
+                            partial.push(rhs_sym.clone());
+                            partial
+                        })
+                        .collect()
+                };
+            }
+
+            for partial in partials {
+                let rhstr = if partial.is_empty() {
+                    GramSymStr::Epsilon
+                } else {
+                    GramSymStr::Str(partial)
+                };
+
+                let mut new_prod = GramProd::new(prod.lfsym.clone(), rhstr);
+                new_prod.attrs = prod.attrs.clone();
+                inlined.insert_prod(new_prod);
+            }
+        }
+
+        for (doc_sym, doc) in self.docs().iter().filter(|(doc_sym, _)| *doc_sym != sym) {
+            inlined.add_doc(doc_sym, doc);
+        }
+
+        let introduced = inlined
+            .ll1_conflicts()
+            .difference(&self.ll1_conflicts())
+            .cloned()
+            .collect_vec();
+        if !introduced.is_empty() {
+            return Err(Trap::new_box_err(&format!(
+                "inlining {} introduces LL(1) conflicts in {}",
+                sym,
+                introduced.iter().join(", ")
+            )));
+        }
+
+        *self = inlined;
+
+        Ok(())
+    }
+
+    /// 把各个产生式右边出现的`fragment`(从左到右， 不重叠)替换成新的非终结符`sym_name`，
+    /// 并追加规则`sym_name -> fragment`， 返回新的非终结符
+    ///
+    /// 名字已被占用、 `fragment`为空或者包含`error`、 没有出现过，
+    /// 或者提取后会产生新的LL(1)冲突时报错， 此时语法不变
+    pub fn extract(&mut self, sym_name: &str, fragment: &[GramSym]) -> Result<GramSym, Box<dyn Error>> {
+        if let Some(sym) = self.symbols().find(|sym| sym.name() == sym_name) {
+            return Err(TrapCode::SymCollision(&format!("{} already exists", sym)).emit_box_err());
+        }
+        if fragment.is_empty() || fragment.iter().any(|sym| sym.is_error()) {
+            return Err(Trap::new_box_err("fragment should be nonempty and without `error`"));
+        }
+
+        let new_sym = GramSym::NonTerminal(sym_name.to_string());
+        let mut extracted = Gram::new(self.name());
+        let mut found = false;
+
+        for prod in self.productions() {
+            let normal_str = match &prod.rhstr {
+                GramSymStr::Str(normal_str) => normal_str,
+                GramSymStr::Epsilon => {
+                    extracted.insert_prod(prod.clone());
+                    continue;
+                }
+            };
+
+            let mut new_str = vec![];
+            let mut i = 0;
+            while i < normal_str.len() {
+                if normal_str[i..].starts_with(fragment) {
+                    new_str.push(new_sym.clone());
+                    i += fragment.len();
+                    found = true;
+                } else {
+                    new_str.push(normal_str[i].clone());
+                    i += 1;
+                }
+            }
+
+            let mut new_prod = GramProd::new(prod.lfsym.clone(), GramSymStr::Str(new_str));
+            new_prod.attrs = prod.attrs.clone();
+            extracted.insert_prod(new_prod);
+        }
+
+        if !found {
+            return Err(Trap::new_box_err(&format!(
+                "`{}` doesn't occur in {}",
+                fragment.iter().map(|sym| sym.name()).join(" "),
+                self.name()
+            )));
+        }
+
+        extracted.insert_prod(GramProd::new(new_sym.clone(), GramSymStr::Str(fragment.to_vec())));
+        for (doc_sym, doc) in self.docs().iter() {
+            extracted.add_doc(doc_sym, doc);
+        }
+
+        let introduced = extracted
+            .ll1_conflicts()
+            .difference(&self.ll1_conflicts())
+            .cloned()
+            .collect_vec();
+        if !introduced.is_empty() {
+            return Err(Trap::new_box_err(&format!(
+                "extracting {} introduces LL(1) conflicts in {}",
+                new_sym,
+                introduced.iter().join(", ")
+            )));
+        }
+
+        *self = extracted;
+
+        Ok(new_sym)
+    }
+
+    /// 从`sym`出发(至少经过一步推导)能到达的非终结符
+    pub(crate) fn reachable_from(&self, sym: &GramSym) -> IndexSet<GramSym> {
+        let mut reachable = IndexSet::new();
+        let mut stack = vec![sym.clone()];
+
+        while let Some(cur) = stack.pop() {
+            for prod in self.alts_of(&cur) {
+                for rhs_sym in prod.rhstr.get_normal().into_iter().flatten() {
+                    if rhs_sym.is_nonterminal() && reachable.insert(rhs_sym.clone()) {
+                        stack.push(rhs_sym.clone());
+                    }
+                }
+            }
+        }
+
+        reachable
+    }
+
+    /// 有LL(1)冲突的非终结符， 有未定义的非终结符时没法计算， 当作没有
+    pub(crate) fn ll1_conflicts(&self) -> IndexSet<GramSym> {
+        let defined = self.nonterminals().cloned().collect::<IndexSet<GramSym>>();
+        let undefined = self
+            .symbols()
+            .any(|sym| sym.is_nonterminal() && !defined.contains(sym));
+
+        if undefined {
+            return IndexSet::new();
+        }
+
+        let fstsets = self.first_sets();
+        let follsets = self.follow_sets(&fstsets);
+
+        self.duplicate_dt(&fstsets, &follsets).into_iter().map(|(sym, _)| sym).collect()
+    }
+}
@@ -0,0 +1,176 @@
+//! Grammar Registry: 按语法的描述信息(`GramMeta`)里的扩展名和MIME类型挑选解析器，
+//! 一个工具处理多种语言时自动分派
+//!
+//! ```ignore
+//! let gram = GramBuilder::from_dsl(JSON_DSL)?
+//!     .extension("json")
+//!     .mime_type("application/json")
+//!     .build()?;
+//!
+//! let mut registry = GrammarRegistry::new();
+//! registry.register(LL1Parser::try_new(gram)?, json_lexer);
+//!
+//! let lang = registry.for_path("config.json").unwrap();
+//! let root = lang.parser.parse(lang.lexer.tokenize_str(src)?)?;
+//!
+//! // 挑选语法、 读文件、 词法分析和解析一步完成
+//! let session = registry.parse_file("config.json")?;
+//! ```
+//!
+//! 扩展名和MIME类型不分大小写， 被多个语法声明时后注册的优先
+
+use std::path::Path;
+
+use indexmap::IndexMap;
+
+use crate::{
+    diagnostic::{Diagnostic, Diagnostics},
+    error::{LlResult, Trap},
+    lexer::Lexer,
+    parser::{LL1Parser, ParseOptions, SrcFileInfo},
+    session::ParseSession,
+    suppress::{SuppressConfig, Suppressions},
+};
+
+
+/// 一种语言的解析器和它的词法规则
+pub struct Language {
+    pub parser: LL1Parser,
+    pub lexer: Lexer,
+    /// 设置了时`diagnose_file`按注释里的标记静默诊断
+    pub suppress: Option<SuppressConfig>,
+}
+
+impl Language {
+    pub fn new(parser: LL1Parser, lexer: Lexer) -> Self {
+        Self { parser: parser.with_keywords(&lexer), lexer, suppress: None }
+    }
+
+    pub fn with_suppress(mut self, config: SuppressConfig) -> Self {
+        self.suppress = Some(config);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        self.parser.gram().name()
+    }
+
+    /// 读不出来(`unreadable-file`)或者词法分析失败(`unrecognized-token`)时也作为诊断， 这时没有`ParseSession`
+    pub fn diagnose_file(&self, path: &Path, options: &ParseOptions) -> (Option<ParseSession>, Diagnostics) {
+        let mut diags = Diagnostics::new();
+
+        let srcfile = match SrcFileInfo::new(path.to_path_buf()) {
+            Ok(srcfile) => srcfile,
+            Err(err) => {
+                diags.push(Diagnostic::error("unreadable-file", &err.to_string()));
+                return (None, diags);
+            }
+        };
+
+        match ParseSession::parse(&self.parser, &self.lexer, srcfile, options) {
+            Ok(session) => {
+                diags.extend(session.errors().iter().map(Diagnostic::from));
+
+                if let Some(config) = &self.suppress {
+                    diags = Suppressions::scan(session.tokens().tokens(), config).apply(diags);
+                }
+
+                (Some(session), diags)
+            }
+            Err(err) => {
+                diags.push(Diagnostic::error("unrecognized-token", &err.to_string()));
+                (None, diags)
+            }
+        }
+    }
+}
+
+
+#[derive(Default)]
+pub struct GrammarRegistry {
+    langs: IndexMap<String, Language>,
+    /// 小写的扩展名 => 语法名
+    by_ext: IndexMap<String, String>,
+    /// 小写的MIME类型 => 语法名
+    by_mime: IndexMap<String, String>,
+}
+
+impl GrammarRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 按语法名登记， 同名的语法被替换
+    pub fn register(&mut self, parser: LL1Parser, lexer: Lexer) {
+        self.add(Language::new(parser, lexer));
+    }
+
+    pub fn add(&mut self, lang: Language) {
+        let name = lang.name().to_string();
+        let meta = lang.parser.gram().meta();
+
+        self.unregister(&name);
+
+        for ext in meta.extensions.iter() {
+            self.by_ext.insert(ext.to_lowercase(), name.clone());
+        }
+        for mime in meta.mime_types.iter() {
+            self.by_mime.insert(mime.to_lowercase(), name.clone());
+        }
+
+        self.langs.insert(name, lang);
+    }
+
+    pub fn unregister(&mut self, name: &str) -> Option<Language> {
+        let lang = self.langs.shift_remove(name)?;
+
+        self.by_ext.retain(|_, each| each != name);
+        self.by_mime.retain(|_, each| each != name);
+
+        Some(lang)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Language> {
+        self.langs.get(name)
+    }
+
+    /// 按登记的顺序
+    pub fn languages(&self) -> impl Iterator<Item = &Language> {
+        self.langs.values()
+    }
+
+    /// 可以带点(`.json`)
+    pub fn for_extension(&self, ext: &str) -> Option<&Language> {
+        let name = self.by_ext.get(&ext.trim_start_matches('.').to_lowercase())?;
+
+        self.langs.get(name)
+    }
+
+    /// 忽略`;`后面的参数， 比如`application/json; charset=utf-8`
+    pub fn for_mime(&self, mime: &str) -> Option<&Language> {
+        let essence = mime.split(';').next().unwrap_or_default().trim();
+        let name = self.by_mime.get(&essence.to_lowercase())?;
+
+        self.langs.get(name)
+    }
+
+    /// 按文件的扩展名， 没有扩展名或者不是UTF-8时为None
+    pub fn for_path<P: AsRef<Path>>(&self, path: P) -> Option<&Language> {
+        self.for_extension(path.as_ref().extension()?.to_str()?)
+    }
+
+    /// 按扩展名挑选语法解析文件， 没有对应的语法、 读文件或者词法分析失败时报错，
+    /// 语法错误记录在`ParseSession::errors`里
+    pub fn parse_file<P: AsRef<Path>>(&self, path: P) -> LlResult<ParseSession> {
+        self.parse_file_with(path, &ParseOptions::default())
+    }
+
+    pub fn parse_file_with<P: AsRef<Path>>(&self, path: P, options: &ParseOptions) -> LlResult<ParseSession> {
+        let path = path.as_ref();
+        let lang = self
+            .for_path(path)
+            .ok_or_else(|| Trap::new_box_err(&format!("no grammar registered for {}", path.display())))?;
+
+        ParseSession::parse(&lang.parser, &lang.lexer, SrcFileInfo::new(path.to_path_buf())?, options)
+    }
+}
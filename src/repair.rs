@@ -0,0 +1,255 @@
+//! Repair Suggestions: 对出错位置尝试小的编辑(插入/删除/交换一个token)，
+//! 找出能让解析继续下去的修复方式
+
+use std::fmt;
+
+use indexmap::IndexMap;
+use itertools::Itertools;
+
+use crate::gram::*;
+use crate::error::ParseError;
+use crate::lexer::is_ident;
+use crate::parser::{LL1Parser, Token};
+
+
+#[derive(Debug, Clone)]
+pub enum RepairKind {
+    /// 在pos处插入一个期待的终结符
+    Insert(GramSym),
+    /// 删除pos处的token
+    Delete(Token),
+    /// 交换pos和pos+1处的token
+    Swap(Token, Token),
+}
+
+#[derive(Debug, Clone)]
+pub struct Repair {
+    pub kind: RepairKind,
+    pub pos: usize,
+    /// 修复后整个输入都能通过解析
+    pub complete: bool,
+    /// 修复后解析到达的位置(以原token序列计)
+    pub reach: usize,
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            RepairKind::Insert(sym) => {
+                write!(f, "did you forget a `{}`?", sym.name())
+            },
+            RepairKind::Delete(token) => {
+                write!(f, "unexpected `{}`, try removing it", token.value())
+            },
+            RepairKind::Swap(fst, snd) => {
+                write!(f, "try swapping `{}` and `{}`", fst.value(), snd.value())
+            }
+        }
+    }
+}
+
+
+impl LL1Parser {
+    /// 按效果从好到坏排列: 能完整解析的优先， 其次是解析走得更远的。
+    /// 解析本身成功时返回空
+    pub fn suggest_repairs(&self, tokens: &[Token]) -> Vec<Repair> {
+        let (errpos, err) = match self.try_reach(tokens) {
+            Ok(()) => return vec![],
+            Err(res) => res,
+        };
+
+        let mut candidates = vec![];
+
+        let loc = match tokens.get(errpos).or(tokens.last()) {
+            Some(token) => token.loc(),
+            None => return vec![],
+        };
+        for la in err.expected().iter().filter(|la| la.is_sym()) {
+            let sym = GramSym::Terminal(la.to_string());
+            let mut edited = tokens.to_vec();
+            edited.insert(errpos, Token::from_sym(&sym, loc.clone()));
+
+            candidates.push((RepairKind::Insert(sym), errpos, edited));
+        }
+
+        if errpos < tokens.len() {
+            let mut edited = tokens.to_vec();
+            let token = edited.remove(errpos);
+
+            candidates.push((RepairKind::Delete(token), errpos, edited));
+        }
+
+        // 出错的是第一个token时前面没有可交换的
+        for swappos in errpos.checked_sub(1).into_iter().chain(Some(errpos)) {
+            if swappos + 1 < tokens.len() {
+                let mut edited = tokens.to_vec();
+                edited.swap(swappos, swappos + 1);
+
+                let kind = RepairKind::Swap(
+                    tokens[swappos].clone(), tokens[swappos + 1].clone()
+                );
+                candidates.push((kind, swappos, edited));
+            }
+        }
+
+        let mut repairs = vec![];
+
+        for (kind, pos, edited) in candidates {
+            let (complete, edited_reach) = match self.try_reach(&edited) {
+                Ok(()) => (true, edited.len()),
+                Err((reach, _)) => (false, reach),
+            };
+
+            // 换算回原token序列的位置
+            let reach = match &kind {
+                RepairKind::Insert(_) => edited_reach.saturating_sub(1),
+                RepairKind::Delete(_) => edited_reach + 1,
+                RepairKind::Swap(..) => edited_reach,
+            };
+
+            if complete || reach > errpos {
+                repairs.push(Repair { kind, pos, complete, reach });
+            }
+        }
+
+        repairs.sort_by(|x, y| {
+            y.complete.cmp(&x.complete).then(y.reach.cmp(&x.reach))
+        });
+
+        repairs
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+////// Similarity Hints
+
+/// Levenshtein距离(按char计)
+pub fn edit_distance(x: &str, y: &str) -> usize {
+    let y_chars: Vec<char> = y.chars().collect();
+    let mut prev_row: Vec<usize> = (0..=y_chars.len()).collect();
+
+    for (i, xc) in x.chars().enumerate() {
+        let mut row = vec![i + 1];
+
+        for (j, yc) in y_chars.iter().enumerate() {
+            let cost = if xc == *yc { 0 } else { 1 };
+
+            row.push(
+                (prev_row[j] + cost).min(prev_row[j + 1] + 1).min(row[j] + 1)
+            );
+        }
+
+        prev_row = row;
+    }
+
+    prev_row[y_chars.len()]
+}
+
+/// 从候选终结符的关键字文本里找出和value足够相似的， 按距离从近到远排列；
+/// 关键字文本是值约束的值， 或者`keywords`(token名 => 字面串)里的字面串。
+/// 只比较标识符样子的文本， 标点、 数字没有提示
+pub fn similar_syms<'a>(
+    value: &str,
+    candidates: &'a [PredSetSym],
+    keywords: &'a IndexMap<String, String>
+) -> Vec<&'a str> {
+    if !is_ident(value) {
+        return vec![];
+    }
+
+    let value = value.to_lowercase();
+    let threshold = (value.chars().count() / 3).max(1);
+
+    candidates
+        .iter()
+        .filter_map(|la| match la {
+            PredSetSym::Sym(GramSym::Guarded(_name, value)) => Some(value.as_str()),
+            PredSetSym::Sym(GramSym::Terminal(name)) => keywords.get(name).map(|literal| literal.as_str()),
+            _ => None,
+        })
+        .filter(|text| is_ident(text) && text.to_lowercase() != value)
+        .map(|name| (edit_distance(&value, &name.to_lowercase()), name))
+        .filter(|(dist, _)| *dist <= threshold)
+        .sorted_by_key(|(dist, _)| *dist)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+/// 出错token的值和某个期待的终结符(通常是关键字)拼写接近时， 加上提示
+pub(crate) fn hint_similar(err: &mut ParseError, keywords: &IndexMap<String, String>) {
+    let value = match err.token() {
+        Some(token) => token.value().to_string(),
+        None => return,
+    };
+
+    let hints = similar_syms(&value, err.expected(), keywords)
+        .into_iter()
+        .map(|name| format!("did you mean `{}`?", name))
+        .collect_vec();
+
+    for hint in hints {
+        err.add_hint(&hint);
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::builder::GramBuilder;
+    use crate::lexer::{Lexer, RegexTokenMatcher};
+    use crate::parser::SrcLoc;
+
+    fn tokens(names: &[&str]) -> Vec<Token> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| Token::new(name, name, SrcLoc::new((1, i + 1))))
+            .collect()
+    }
+
+    #[test]
+    fn test_repair_first_token() {
+        let gram = GramBuilder::from_dsl("grammar![pair| S: | a b; |]")
+            .unwrap()
+            .build()
+            .unwrap();
+        let parser = LL1Parser::builder(gram).build().unwrap();
+
+        let repairs = parser.suggest_repairs(&tokens(&["b", "a"]));
+        assert!(repairs.iter().any(|repair| repair.complete && matches!(repair.kind, RepairKind::Swap(..))));
+
+        let repairs = parser.suggest_repairs(&tokens(&["b"]));
+        assert!(repairs.iter().all(|repair| !matches!(repair.kind, RepairKind::Swap(..))));
+        assert!(repairs.iter().any(|repair| repair.complete && matches!(repair.kind, RepairKind::Insert(_))));
+    }
+
+    #[test]
+    fn test_similar_keyword_literal() {
+        let gram = GramBuilder::from_dsl("grammar![loop| S: | kw_while id; | num id; |]")
+            .unwrap()
+            .build()
+            .unwrap();
+        let lexer = Lexer::new(vec![
+            (RegexTokenMatcher::new("while"), "kw_while".to_string()),
+            (RegexTokenMatcher::new("[a-z]+"), "id".to_string()),
+            (RegexTokenMatcher::new("[0-9]+"), "num".to_string()),
+            (RegexTokenMatcher::new(";"), "semi".to_string()),
+        ]);
+        let parser = LL1Parser::builder(gram).build().unwrap().with_keywords(&lexer);
+
+        // 比较关键字的文本， 不是token名`kw_while`
+        let err = parser.parse(lexer.tokenize_str("whle x").unwrap()).unwrap_err();
+        assert_eq!(err.hints(), ["did you mean `while`?"]);
+
+        // 标点不是标识符， 没有提示
+        let err = parser.parse(lexer.tokenize_str("; x").unwrap()).unwrap_err();
+        assert!(err.hints().is_empty());
+
+        // 没有关键字的字面串时不拿token名比较
+        let expected = [PredSetSym::Sym(GramSym::Terminal("kw_while".to_string()))];
+        assert!(similar_syms("kw_whle", &expected, &IndexMap::new()).is_empty());
+    }
+}
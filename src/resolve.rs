@@ -0,0 +1,324 @@
+//! Conflict Resolution: 预测表的LL(1)冲突交给回调决定用哪个产生式，
+//! 而不是默认的后定义者优先， 常见的良性冲突可以直接选用`ConflictPolicy`
+//!
+//! ```ignore
+//! // 优先先定义的分支
+//! let parser = LL1Parser::try_new(gram)?.with_resolver(|_lfsym, _la, _prods| 0);
+//! for diag in parser.resolution_diags().iter() {
+//!     println!("{}", diag);
+//! }
+//!
+//! // 有冲突时报错， 除非明确允许
+//! let parser = LL1Parser::builder(gram)
+//!     .allow_conflicts(ConflictPolicy::PreferNonEpsilon)
+//!     .build()?;
+//! for diag in parser.warnings().iter() {
+//!     println!("{}", diag);
+//! }
+//! ```
+
+use indexmap::IndexMap;
+
+use crate::{
+    analysis::GramAnalysis,
+    diagnostic::{Diagnostic, Diagnostics},
+    error::GrammarError,
+    gram::{Gram, GramProd, GramSym, GramSymStr, PredSetSym},
+    parser::LL1Parser,
+};
+
+
+/// 一处冲突的裁决
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    pub lfsym: GramSym,
+    pub la: PredSetSym,
+    /// 冲突的产生式， 按定义的顺序
+    pub candidates: Vec<GramProd>,
+    pub chosen: usize,
+}
+
+impl Resolution {
+    pub fn chosen_prod(&self) -> &GramProd {
+        &self.candidates[self.chosen]
+    }
+}
+
+impl From<&Resolution> for Diagnostic {
+    fn from(resolution: &Resolution) -> Self {
+        let mut diag = Self::warning(
+            "ll1-resolved",
+            &format!(
+                "conflict of {} on {} resolved to {}",
+                resolution.lfsym,
+                resolution.la,
+                resolution.chosen_prod()
+            )
+        );
+
+        for (i, prod) in resolution.candidates.iter().enumerate() {
+            let mark = if i == resolution.chosen { "*" } else { " " };
+            diag = diag.with_note(&format!("{} {}", mark, prod));
+        }
+
+        diag
+    }
+}
+
+
+/// 常见的良性冲突的现成裁决
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConflictPolicy {
+    /// 先定义的产生式优先
+    PreferFirstDeclared,
+    /// 后定义的产生式优先， 同`LL1Parser::try_new`
+    PreferLastDeclared,
+    /// 不推出ε的产生式优先
+    PreferNonEpsilon,
+    /// 直接匹配当前向前看符号的产生式优先(最长匹配)， `else`归属最近的`if`
+    DanglingElse,
+}
+
+impl ConflictPolicy {
+    pub fn choose(&self, la: &PredSetSym, prods: &[GramProd]) -> usize {
+        let non_epsilon = || prods.iter().position(|prod| prod.rhstr != GramSymStr::Epsilon);
+
+        match self {
+            Self::PreferFirstDeclared => Some(0),
+            Self::PreferLastDeclared => prods.len().checked_sub(1),
+            Self::PreferNonEpsilon => non_epsilon(),
+            Self::DanglingElse => prods
+                .iter()
+                .position(|prod| {
+                    prod.rhstr
+                        .get_normal()
+                        .and_then(|normal_str| normal_str.first())
+                        .is_some_and(|sym| sym.is_terminal() && &sym.to_pred_set_sym() == la)
+                })
+                .or_else(non_epsilon),
+        }
+        .unwrap_or(0)
+    }
+}
+
+
+impl LL1Parser {
+    /// 对预测表里每个冲突的(非终结符， 向前看符号)调用`resolver`， 返回选中的候选产生式的下标，
+    /// 超出范围时保持默认的选择(最后一个)
+    ///
+    /// 裁决写进预测表， 并记录在`resolutions`里
+    pub fn with_resolver<F>(mut self, resolver: F) -> Self
+    where
+        F: Fn(&GramSym, &PredSetSym, &[GramProd]) -> usize
+    {
+        let fstsets = self.gram().first_sets();
+        let follsets = self.gram().follow_sets(&fstsets);
+        let mut resolutions = vec![];
+
+        for (lfsym, prods) in self.gram().derivation_tree() {
+            let mut candidates: IndexMap<PredSetSym, Vec<GramProd>> = IndexMap::new();
+
+            for prod in prods {
+                for la in prod.lookahead(&fstsets, &follsets) {
+                    candidates.entry(la).or_default().push(prod.clone());
+                }
+            }
+
+            for (la, candidates) in candidates.into_iter().filter(|(_, candidates)| candidates.len() > 1) {
+                let chosen = match resolver(&lfsym, &la, &candidates) {
+                    chosen if chosen < candidates.len() => chosen,
+                    _ => candidates.len() - 1,
+                };
+
+                resolutions.push(Resolution {
+                    lfsym: lfsym.clone(),
+                    la,
+                    candidates,
+                    chosen,
+                });
+            }
+        }
+
+        for resolution in resolutions.iter() {
+            self.prediction_sets.insert(
+                &resolution.lfsym,
+                resolution.la.clone(),
+                resolution.chosen_prod().clone()
+            );
+        }
+        self.resolutions = resolutions;
+
+        self
+    }
+
+    /// 按非终结符选用现成的裁决， 其余的冲突保持默认
+    pub fn with_policies<I>(self, policies: I) -> Self
+    where
+        I: IntoIterator<Item = (GramSym, ConflictPolicy)>
+    {
+        let policies = policies.into_iter().collect::<IndexMap<GramSym, ConflictPolicy>>();
+
+        self.with_resolver(|lfsym, la, prods| match policies.get(lfsym) {
+            Some(policy) => policy.choose(la, prods),
+            None => prods.len() - 1,
+        })
+    }
+
+    /// `with_resolver`做出的裁决
+    pub fn resolutions(&self) -> &[Resolution] {
+        &self.resolutions
+    }
+
+    /// 每个裁决一条警告， 候选产生式作为注释， 选中的标上`*`
+    pub fn resolution_diags(&self) -> Diagnostics {
+        let mut diags = Diagnostics::new();
+        diags.extend(self.resolutions.iter().map(Diagnostic::from));
+
+        diags
+    }
+
+    /// 构建时的警告： `builder`检查语法的警告和裁决过的冲突
+    pub fn warnings(&self) -> &Diagnostics {
+        &self.warnings
+    }
+
+    /// 按后定义者优先裁决冲突(和不裁决时的预测表一样)， 只是把裁决记下来
+    pub(crate) fn resolve_by_default(self) -> Self {
+        let mut parser = self.with_resolver(|_lfsym, la, prods| {
+            ConflictPolicy::PreferLastDeclared.choose(la, prods)
+        });
+        parser.warnings = parser.resolution_diags();
+
+        parser
+    }
+}
+
+
+/// 检查过语法再构建解析器， 见`LL1Parser::builder`
+#[derive(Debug, Clone)]
+pub struct LL1ParserBuilder {
+    gram: Gram,
+    conflicts: Option<ConflictPolicy>,
+    max_lookahead: usize,
+}
+
+impl LL1ParserBuilder {
+    /// 按`policy`裁决LL(1)冲突， 默认冲突是错误
+    pub fn allow_conflicts(mut self, policy: ConflictPolicy) -> Self {
+        self.conflicts = Some(policy);
+        self
+    }
+
+    /// 预测时最多看几个token， 目前只支持1， 其它的值在`build`时报错
+    pub fn max_lookahead(mut self, k: usize) -> Self {
+        self.max_lookahead = k;
+        self
+    }
+
+    /// 语法有错误(见`Gram::validate`)时返回全部的诊断， 允许的冲突不算错误；
+    /// 成功时警告留在`LL1Parser::warnings`里
+    pub fn build(self) -> Result<LL1Parser, GrammarError> {
+        let mut diags = Diagnostics::new();
+        diags.extend(
+            self.gram
+                .validate()
+                .iter()
+                .filter(|diag| self.conflicts.is_none() || diag.code != "ll1-conflict")
+                .cloned()
+        );
+
+        if self.max_lookahead != 1 {
+            diags.push(Diagnostic::error(
+                "bad-lookahead",
+                &format!("max lookahead {} is not supported, only LL(1) prediction is implemented", self.max_lookahead)
+            ));
+        }
+
+        if diags.has_errors() {
+            return Err(GrammarError::new(diags));
+        }
+
+        let parser = LL1Parser::from_analysis(GramAnalysis::new(self.gram));
+        let mut parser = match self.conflicts {
+            Some(policy) => parser.with_resolver(|_lfsym, la, prods| policy.choose(la, prods)),
+            None => parser,
+        };
+        diags.extend(parser.resolution_diags().iter().cloned());
+        parser.warnings = diags;
+
+        Ok(parser)
+    }
+}
+
+impl LL1Parser {
+    pub fn builder(gram: Gram) -> LL1ParserBuilder {
+        LL1ParserBuilder {
+            gram,
+            conflicts: None,
+            max_lookahead: 1,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::builder::GramBuilder;
+
+    fn gram(dsl: &str) -> Gram {
+        GramBuilder::from_dsl(dsl).unwrap().build().unwrap()
+    }
+
+    #[test]
+    fn test_builder_rejects_lookahead() {
+        for k in [0, 2] {
+            let err = match LL1Parser::builder(gram("grammar![one| S: | a; |]")).max_lookahead(k).build() {
+                Ok(_) => panic!("k = {} accepted", k),
+                Err(err) => err,
+            };
+            assert!(err.errors().any(|diag| diag.code == "bad-lookahead"), "k = {}", k);
+        }
+
+        assert!(LL1Parser::builder(gram("grammar![one| S: | a; |]")).max_lookahead(1).build().is_ok());
+    }
+
+    #[test]
+    fn test_builder_returns_warnings() {
+        let parser = LL1Parser::builder(gram("grammar![unused| S: | a; U: | b; |]"))
+            .build()
+            .unwrap();
+
+        assert_eq!(parser.warnings().len(), 1);
+        assert_eq!(parser.warnings().iter().next().unwrap().code, "unused-nonterminal");
+    }
+
+    /// 不检查的语法， 可以有冲突
+    fn conflicting_gram() -> Gram {
+        GramBuilder::from_dsl("grammar![opt| S: | A b; A: | b; | ε; |]").unwrap().assemble().0
+    }
+
+    #[test]
+    fn test_builder_rejects_conflicts() {
+
+        let err = match LL1Parser::builder(conflicting_gram()).build() {
+            Ok(_) => panic!("conflict accepted"),
+            Err(err) => err,
+        };
+        assert!(err.errors().any(|diag| diag.code == "ll1-conflict"));
+        assert!(err.to_string().contains("ambiguous LL(1) rule for"));
+
+        // 允许的冲突是警告
+        let parser = LL1Parser::builder(conflicting_gram())
+            .allow_conflicts(ConflictPolicy::PreferNonEpsilon)
+            .build()
+            .unwrap();
+        assert_eq!(parser.resolutions().len(), 1);
+        assert!(parser.warnings().warnings().any(|diag| diag.code == "ll1-resolved"));
+
+        // `try_new`照旧接受冲突， 但不是悄悄的
+        let parser = LL1Parser::try_new(conflicting_gram()).unwrap();
+        assert_eq!(parser.resolutions().len(), 1);
+        assert_eq!(parser.warnings().len(), 1);
+    }
+}
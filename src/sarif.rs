@@ -0,0 +1,327 @@
+//! SARIF Export: 把诊断输出成SARIF 2.1.0， CI和代码评审工具可以直接读入语法错误和lint结果
+//!
+//! ```ignore
+//! let mut log = SarifLog::new("jsonlint").with_version("0.3.0");
+//! for file in batch.files.iter() {
+//!     log.add(Some(&file.path), &file.diags);
+//! }
+//! fs::write("jsonlint.sarif", log.to_json())?;
+//! ```
+//!
+//! 诊断的`code`是规则id， 附注接在消息后面(每条一行)。 行、 列按`SrcLoc`原样输出，
+//! 默认的配置下都从1开始， 和SARIF一致。 没有位置的诊断(比如语法本身的问题)只有文件或者没有`locations`。
+//! 同`schema`， 输出是确定的
+
+use std::path::Path;
+
+use indexmap::IndexSet;
+use itertools::Itertools;
+
+use crate::{
+    batch::BatchResult,
+    diagnostic::{Diagnostic, Diagnostics, Severity},
+    trace::json_str,
+};
+
+
+pub const SARIF_VERSION: &str = "2.1.0";
+pub const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+
+/// 一次运行(一个工具)的全部结果
+#[derive(Debug, Clone)]
+pub struct SarifLog {
+    tool: String,
+    version: Option<String>,
+    information_uri: Option<String>,
+    /// (文件的URI, 诊断)
+    results: Vec<(Option<String>, Diagnostic)>,
+}
+
+impl SarifLog {
+    pub fn new(tool: &str) -> Self {
+        Self {
+            tool: tool.to_string(),
+            version: None,
+            information_uri: None,
+            results: vec![],
+        }
+    }
+
+    pub fn with_version(mut self, version: &str) -> Self {
+        self.version = Some(version.to_string());
+        self
+    }
+
+    /// 工具的主页
+    pub fn with_information_uri(mut self, uri: &str) -> Self {
+        self.information_uri = Some(uri.to_string());
+        self
+    }
+
+    /// `file`是诊断所在的文件， 相对路径原样作为URI， 绝对路径转成`file://`
+    pub fn add(&mut self, file: Option<&Path>, diags: &Diagnostics) {
+        let uri = file.map(path_uri);
+
+        self.results.extend(diags.iter().map(|diag| (uri.clone(), diag.clone())));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+
+    pub fn to_json(&self) -> String {
+        let rules = self.results.iter().map(|(_, diag)| diag.code.as_str()).collect::<IndexSet<&str>>();
+
+        let mut driver = format!(r#"{{"name":{}"#, json_str(&self.tool));
+        if let Some(version) = &self.version {
+            driver.push_str(&format!(r#","version":{}"#, json_str(version)));
+        }
+        if let Some(uri) = &self.information_uri {
+            driver.push_str(&format!(r#","informationUri":{}"#, json_str(uri)));
+        }
+        driver.push_str(&format!(
+            r#","rules":[{}]}}"#,
+            rules.iter().map(|id| format!(r#"{{"id":{}}}"#, json_str(id))).join(",")
+        ));
+
+        let results = self
+            .results
+            .iter()
+            .map(|(uri, diag)| result_json(uri.as_deref(), diag, rules.get_index_of(diag.code.as_str()).unwrap()))
+            .join(",");
+
+        format!(
+            r#"{{"$schema":{},"version":{},"runs":[{{"tool":{{"driver":{}}},"results":[{}]}}]}}"#,
+            json_str(SARIF_SCHEMA),
+            json_str(SARIF_VERSION),
+            driver,
+            results
+        )
+    }
+}
+
+
+impl<T> BatchResult<T> {
+    /// 每个文件的诊断， 见`SarifLog`
+    pub fn to_sarif(&self, tool: &str) -> String {
+        let mut log = SarifLog::new(tool);
+
+        for file in self.files.iter() {
+            log.add(Some(&file.path), &file.diags);
+        }
+
+        log.to_json()
+    }
+}
+
+
+fn result_json(uri: Option<&str>, diag: &Diagnostic, rule_index: usize) -> String {
+    let level = match diag.severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note => "note",
+    };
+
+    let text = diag.notes.iter().fold(diag.msg.clone(), |text, note| format!("{}\n{}", text, note));
+
+    let mut physical = vec![];
+    if let Some(uri) = uri {
+        physical.push(format!(r#""artifactLocation":{{"uri":{}}}"#, json_str(uri)));
+    }
+    if let Some(loc) = &diag.loc {
+        physical.push(format!(r#""region":{{"startLine":{},"startColumn":{}}}"#, loc.ln, loc.col));
+    }
+
+    let locations = if physical.is_empty() {
+        String::new()
+    }
+    else {
+        format!(r#","locations":[{{"physicalLocation":{{{}}}}}]"#, physical.join(","))
+    };
+
+    format!(
+        r#"{{"ruleId":{},"ruleIndex":{},"level":"{}","message":{{"text":{}}}{}}}"#,
+        json_str(&diag.code),
+        rule_index,
+        level,
+        json_str(&text),
+        locations
+    )
+}
+
+/// 按RFC 3986转义， 分隔符统一为`/`
+fn path_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::new();
+
+    if path.starts_with('/') {
+        uri.push_str("file://");
+    }
+    // Windows的盘符
+    else if path.as_bytes().get(1) == Some(&b':') {
+        uri.push_str("file:///");
+    }
+
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => uri.push(b as char),
+            b => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+
+    uri
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::parser::SrcLoc;
+
+    /// SARIF 2.1.0 schema里用到的部分， 和原文一样不允许多余的字段
+    const SARIF_SUBSET_SCHEMA: &str = r##"{
+  "$schema": "http://json-schema.org/draft-07/schema#",
+  "type": "object",
+  "required": ["version", "runs"],
+  "additionalProperties": false,
+  "properties": {
+    "$schema": { "type": "string", "format": "uri" },
+    "version": { "enum": ["2.1.0"] },
+    "runs": { "type": "array", "items": { "$ref": "#/definitions/run" } }
+  },
+  "definitions": {
+    "run": {
+      "type": "object",
+      "required": ["tool"],
+      "additionalProperties": false,
+      "properties": {
+        "tool": {
+          "type": "object",
+          "required": ["driver"],
+          "additionalProperties": false,
+          "properties": { "driver": { "$ref": "#/definitions/toolComponent" } }
+        },
+        "results": { "type": "array", "items": { "$ref": "#/definitions/result" } }
+      }
+    },
+    "toolComponent": {
+      "type": "object",
+      "required": ["name"],
+      "additionalProperties": false,
+      "properties": {
+        "name": { "type": "string" },
+        "version": { "type": "string" },
+        "informationUri": { "type": "string", "format": "uri" },
+        "rules": {
+          "type": "array",
+          "uniqueItems": true,
+          "items": {
+            "type": "object",
+            "required": ["id"],
+            "additionalProperties": false,
+            "properties": { "id": { "type": "string" } }
+          }
+        }
+      }
+    },
+    "result": {
+      "type": "object",
+      "required": ["message"],
+      "additionalProperties": false,
+      "properties": {
+        "ruleId": { "type": "string" },
+        "ruleIndex": { "type": "integer", "minimum": -1 },
+        "level": { "enum": ["none", "note", "warning", "error"] },
+        "message": {
+          "type": "object",
+          "required": ["text"],
+          "additionalProperties": false,
+          "properties": { "text": { "type": "string" } }
+        },
+        "locations": {
+          "type": "array",
+          "items": {
+            "type": "object",
+            "additionalProperties": false,
+            "properties": { "physicalLocation": { "$ref": "#/definitions/physicalLocation" } }
+          }
+        }
+      }
+    },
+    "physicalLocation": {
+      "type": "object",
+      "anyOf": [{ "required": ["artifactLocation"] }, { "required": ["region"] }],
+      "additionalProperties": false,
+      "properties": {
+        "artifactLocation": {
+          "type": "object",
+          "additionalProperties": false,
+          "properties": { "uri": { "type": "string", "format": "uri-reference" } }
+        },
+        "region": {
+          "type": "object",
+          "additionalProperties": false,
+          "properties": {
+            "startLine": { "type": "integer", "minimum": 1 },
+            "startColumn": { "type": "integer", "minimum": 1 }
+          }
+        }
+      }
+    }
+  }
+}"##;
+
+    #[test]
+    fn test_sarif_matches_schema() {
+        let mut parse_diags = Diagnostics::new();
+        parse_diags.push(
+            Diagnostic::error("unexpected-token", "expected `;`, found `\"}\"`")
+                .with_loc(SrcLoc::new((3, 7)))
+                .with_note("while parsing Stmt")
+        );
+        parse_diags.push(Diagnostic::warning("deprecated", "use of deprecated production").with_loc(SrcLoc::new((1, 1))));
+
+        let mut gram_diags = Diagnostics::new();
+        gram_diags.push(Diagnostic::warning("unused-nonterminal", "U is never used"));
+
+        let mut log = SarifLog::new("jsonlint")
+            .with_version("0.3.0")
+            .with_information_uri("https://example.com/jsonlint");
+        log.add(Some(&PathBuf::from("samples/bad input.json")), &parse_diags);
+        log.add(Some(&PathBuf::from("/tmp/abs.json")), &gram_diags);
+        log.add(None, &gram_diags);
+
+        let schema: Value = serde_json::from_str(SARIF_SUBSET_SCHEMA).unwrap();
+        let sarif: Value = serde_json::from_str(&log.to_json()).unwrap();
+        let validator = jsonschema::validator_for(&schema).unwrap();
+        let errors = validator.iter_errors(&sarif).map(|err| err.to_string()).collect_vec();
+        assert!(errors.is_empty(), "{:#?}\n{}", errors, sarif);
+
+        let run = &sarif["runs"][0];
+        let rules = run["tool"]["driver"]["rules"].as_array().unwrap();
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(rules.len(), 3);
+        assert_eq!(results.len(), 4);
+
+        for result in results.iter() {
+            let index = result["ruleIndex"].as_u64().unwrap() as usize;
+            assert_eq!(rules[index]["id"], result["ruleId"]);
+        }
+
+        assert_eq!(results[0]["level"], "error");
+        assert_eq!(results[0]["message"]["text"], "expected `;`, found `\"}\"`\nwhile parsing Stmt");
+        let physical = &results[0]["locations"][0]["physicalLocation"];
+        assert_eq!(physical["artifactLocation"]["uri"], "samples/bad%20input.json");
+        assert_eq!(physical["region"]["startLine"], 3);
+        assert_eq!(physical["region"]["startColumn"], 7);
+
+        assert_eq!(results[2]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"], "file:///tmp/abs.json");
+        assert!(results[2]["locations"][0]["physicalLocation"].get("region").is_none());
+        assert!(results[3].get("locations").is_none());
+    }
+}
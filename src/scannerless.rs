@@ -0,0 +1,199 @@
+//! Scannerless Parsing: 不写词法分析器， 终结符直接对应源码里的字面量和字符类，
+//! 适合快速试验小的DSL
+//!
+//! 普通的终结符就是字面量(DSL里写作`"+"`、 `"let"`)， 每次取能匹配的最长字面量；
+//! 字符类`["a-z_"]`、 `["^\n"]`(见`GramSym::CharClass`)匹配剩下的单个字符
+//!
+//! ```ignore
+//! let scanner = Scanner::new(parser.gram()).with_skip_whitespace();
+//! let (root, errors) = scanner.parse(&parser, "let x = 1", &ParseOptions::default());
+//! ```
+
+use std::{cell::RefCell, fmt, path::PathBuf, rc::Rc};
+
+use itertools::Itertools;
+
+use crate::{
+    error::ParseError,
+    gram::{Gram, GramSym},
+    parser::{LL1Parser, ParseOptions, SrcFileInfo, Token, AST},
+};
+
+
+/// `[a-z_]`， `[^"\n]`， 支持`\n` `\t` `\\` `\]` `\-`转义
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CharClass {
+    pub negated: bool,
+    pub ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+    /// 不是字符类时为None
+    pub fn parse(name: &str) -> Option<Self> {
+        let body = name.strip_prefix('[')?.strip_suffix(']')?;
+        let (negated, body) = match body.strip_prefix('^') {
+            Some(rest) => (true, rest),
+            None => (false, body),
+        };
+
+        let mut chars = vec![];
+        let mut escaped = vec![];
+        let mut iter = body.chars();
+        while let Some(c) = iter.next() {
+            if c == '\\' {
+                let c = match iter.next()? {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    other => other,
+                };
+                chars.push(c);
+                escaped.push(true);
+            } else {
+                chars.push(c);
+                escaped.push(false);
+            }
+        }
+
+        if chars.is_empty() {
+            return None;
+        }
+
+        let mut ranges = vec![];
+        let mut i = 0;
+        while i < chars.len() {
+            let is_range = i + 2 < chars.len() && chars[i + 1] == '-' && !escaped[i + 1];
+
+            if is_range {
+                ranges.push((chars[i], chars[i + 2]));
+                i += 3;
+            } else {
+                ranges.push((chars[i], chars[i]));
+                i += 1;
+            }
+        }
+
+        Some(Self { negated, ranges })
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        self.ranges.iter().any(|(lo, hi)| *lo <= c && c <= *hi) != self.negated
+    }
+
+    /// 恰好是一个字符并且在类里
+    pub fn matches(&self, text: &str) -> bool {
+        match text.chars().exactly_one() {
+            Ok(c) => self.contains(c),
+            Err(_) => false,
+        }
+    }
+}
+
+/// 写回`parse`能读的形式
+impl fmt::Display for CharClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let escape = |c: char| match c {
+            '\\' | ']' | '-' | '^' => format!("\\{}", c),
+            '\n' => "\\n".to_string(),
+            '\t' => "\\t".to_string(),
+            '\r' => "\\r".to_string(),
+            c => c.to_string(),
+        };
+
+        write!(f, "[{}", if self.negated { "^" } else { "" })?;
+        for (lo, hi) in self.ranges.iter() {
+            if lo == hi {
+                write!(f, "{}", escape(*lo))?;
+            } else {
+                write!(f, "{}-{}", escape(*lo), escape(*hi))?;
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Scanner {
+    /// 语法里的字面量， 长的在前
+    literals: Vec<String>,
+    skip_whitespace: bool,
+}
+
+impl Scanner {
+    pub fn new(gram: &Gram) -> Self {
+        let literals = gram
+            .term_syms()
+            .into_iter()
+            .filter(is_literal)
+            .map(|sym| sym.name().to_string())
+            .sorted_by_key(|name| std::cmp::Reverse(name.chars().count()))
+            .collect();
+
+        Self {
+            literals,
+            skip_whitespace: false,
+        }
+    }
+
+    /// 字面量之外的空白直接跳过， 否则空白也要由语法处理
+    pub fn with_skip_whitespace(mut self) -> Self {
+        self.skip_whitespace = true;
+        self
+    }
+
+    pub fn literals(&self) -> &[String] {
+        &self.literals
+    }
+
+    /// 能匹配的最长字面量作为一个token， 否则单个字符作为一个token(名字和值都是这个字符)
+    pub fn scan(&self, srcfile: &SrcFileInfo) -> Vec<Token> {
+        let srcstr = srcfile.get_srcstr();
+        let mut tokens = vec![];
+        let mut pos = 0usize;
+        // SrcFileInfo按char计算位置
+        let mut offset = 0usize;
+
+        while pos < srcstr.len() {
+            let rest = &srcstr[pos..];
+            let loc = srcfile.offset2srcloc(offset);
+
+            let text = match self.literals.iter().find(|literal| rest.starts_with(literal.as_str())) {
+                Some(literal) => {
+                    tokens.push(Token::new(literal, literal, loc));
+                    literal.as_str()
+                },
+                None => {
+                    let c = rest.chars().next().unwrap();
+                    let text = &rest[..c.len_utf8()];
+
+                    if !(self.skip_whitespace && c.is_whitespace()) {
+                        tokens.push(Token::new(text, text, loc));
+                    }
+                    text
+                }
+            };
+
+            offset += text.chars().count();
+            pos += text.len();
+        }
+
+        tokens
+    }
+
+    pub fn scan_str(&self, srcstr: &str) -> Vec<Token> {
+        self.scan(&SrcFileInfo::from_srcstr(PathBuf::new(), srcstr.to_string()))
+    }
+
+    /// 同`LL1Parser::parse_with`， 但直接解析源码
+    pub fn parse(&self, parser: &LL1Parser, srcstr: &str, options: &ParseOptions)
+    -> (Rc<RefCell<AST>>, Vec<ParseError>)
+    {
+        parser.parse_with(self.scan_str(srcstr), options)
+    }
+}
+
+/// 按原样匹配源码的终结符
+fn is_literal(sym: &GramSym) -> bool {
+    matches!(sym, GramSym::Terminal(name) if !sym.is_error() && !name.is_empty())
+}
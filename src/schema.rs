@@ -0,0 +1,408 @@
+//! JSON Schema: 导出AST和诊断的JSON格式， 有版本号并附带JSON Schema文档，
+//! 别的语言的使用者可以依赖它的形状
+//!
+//! ```none
+//! {"format":"ll1engine-ast","version":1,"root":
+//!   {"type":"tree","kind":"Stmt","span":{"start":{"line":1,"col":1},"end":{"line":1,"col":7}},"synthesized":false,"children":[
+//!     {"type":"token","kind":"id","text":"a","span":{...},"synthesized":false}, ...]}}
+//! ```
+//!
+//! 输出是确定的： 键的顺序固定， 没有多余的空白， 同样的AST总是得到同样的文本。
+//! 行、 列从1开始， `end`不包含在区间内， 没有token的子树`span`为`null`。
+//! 格式有不兼容的改变时`SCHEMA_VERSION`加一
+
+use std::{fmt::Write as _, rc::Rc};
+
+use itertools::Itertools;
+
+use crate::{
+    diagnostic::{Diagnostic, Diagnostics},
+    parser::{ASTNode, SrcLoc, Token, AST},
+    trace::json_str,
+};
+
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+pub const AST_FORMAT: &str = "ll1engine-ast";
+pub const DIAGNOSTICS_FORMAT: &str = "ll1engine-diagnostics";
+
+/// `AST::to_json`输出的JSON Schema(draft 2020-12)
+pub const AST_SCHEMA: &str = r##"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "$id": "https://github.com/minghu6/rust-ll1engine/schema/ast-v1.json",
+  "title": "ll1engine AST",
+  "type": "object",
+  "required": ["format", "version", "root"],
+  "additionalProperties": false,
+  "properties": {
+    "format": { "const": "ll1engine-ast" },
+    "version": { "const": 1 },
+    "root": { "$ref": "#/$defs/tree" }
+  },
+  "$defs": {
+    "pos": {
+      "type": "object",
+      "required": ["line", "col"],
+      "additionalProperties": false,
+      "properties": {
+        "line": { "type": "integer", "minimum": 0 },
+        "col": { "type": "integer", "minimum": 0 }
+      }
+    },
+    "span": {
+      "type": "object",
+      "required": ["start", "end"],
+      "additionalProperties": false,
+      "properties": {
+        "start": { "$ref": "#/$defs/pos" },
+        "end": { "$ref": "#/$defs/pos" }
+      }
+    },
+    "node": {
+      "oneOf": [{ "$ref": "#/$defs/tree" }, { "$ref": "#/$defs/token" }]
+    },
+    "tree": {
+      "type": "object",
+      "required": ["type", "kind", "span", "synthesized", "children"],
+      "additionalProperties": false,
+      "properties": {
+        "type": { "const": "tree" },
+        "kind": { "type": "string" },
+        "span": { "oneOf": [{ "$ref": "#/$defs/span" }, { "type": "null" }] },
+        "synthesized": { "type": "boolean" },
+        "children": { "type": "array", "items": { "$ref": "#/$defs/node" } }
+      }
+    },
+    "token": {
+      "type": "object",
+      "required": ["type", "kind", "text", "span", "synthesized"],
+      "additionalProperties": false,
+      "properties": {
+        "type": { "const": "token" },
+        "kind": { "type": "string" },
+        "text": { "type": "string" },
+        "span": { "$ref": "#/$defs/span" },
+        "synthesized": { "type": "boolean" }
+      }
+    }
+  }
+}
+"##;
+
+/// `Diagnostics::to_json`输出的JSON Schema(draft 2020-12)
+pub const DIAGNOSTICS_SCHEMA: &str = r##"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "$id": "https://github.com/minghu6/rust-ll1engine/schema/diagnostics-v1.json",
+  "title": "ll1engine diagnostics",
+  "type": "object",
+  "required": ["format", "version", "diagnostics"],
+  "additionalProperties": false,
+  "properties": {
+    "format": { "const": "ll1engine-diagnostics" },
+    "version": { "const": 1 },
+    "diagnostics": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "required": ["severity", "code", "message", "loc", "notes"],
+        "additionalProperties": false,
+        "properties": {
+          "severity": { "enum": ["error", "warning", "note"] },
+          "code": { "type": "string" },
+          "message": { "type": "string" },
+          "loc": {
+            "oneOf": [
+              {
+                "type": "object",
+                "required": ["line", "col"],
+                "additionalProperties": false,
+                "properties": {
+                  "line": { "type": "integer", "minimum": 0 },
+                  "col": { "type": "integer", "minimum": 0 }
+                }
+              },
+              { "type": "null" }
+            ]
+          },
+          "notes": { "type": "array", "items": { "type": "string" } }
+        }
+      }
+    }
+  }
+}
+"##;
+
+
+/// 展平的节点， 先序排列， 子节点的下标总是大于父节点
+pub(crate) enum Entry {
+    Tree {
+        kind: String,
+        synthesized: bool,
+        children: Vec<usize>,
+    },
+    Leaf(Rc<Token>),
+}
+
+enum Item {
+    Open(usize),
+    Sep,
+    Close,
+}
+
+
+impl AST {
+    /// 按`AST_SCHEMA`输出， 不递归， 很深的树也不会栈溢出
+    pub fn to_json(&self) -> String {
+        let entries = entries(self);
+        let spans = spans(&entries);
+
+        let mut out = format!(r#"{{"format":"{}","version":{},"root":"#, AST_FORMAT, SCHEMA_VERSION);
+        let mut stack = vec![Item::Open(0)];
+
+        while let Some(item) = stack.pop() {
+            match item {
+                Item::Open(id) => match &entries[id] {
+                    Entry::Tree { kind, synthesized, children } => {
+                        write!(
+                            out,
+                            r#"{{"type":"tree","kind":{},"span":{},"synthesized":{},"children":["#,
+                            json_str(kind),
+                            span_json(&spans[id]),
+                            synthesized
+                        )
+                        .unwrap();
+
+                        stack.push(Item::Close);
+                        for (i, &child) in children.iter().enumerate().rev() {
+                            stack.push(Item::Open(child));
+                            if i > 0 {
+                                stack.push(Item::Sep);
+                            }
+                        }
+                    }
+                    Entry::Leaf(token) => {
+                        write!(
+                            out,
+                            r#"{{"type":"token","kind":{},"text":{},"span":{},"synthesized":{}}}"#,
+                            json_str(token.name()),
+                            json_str(token.value()),
+                            span_json(&spans[id]),
+                            token.is_synthesized()
+                        )
+                        .unwrap();
+                    }
+                },
+                Item::Sep => out.push(','),
+                Item::Close => out.push_str("]}"),
+            }
+        }
+
+        out.push('}');
+        out
+    }
+}
+
+
+impl Diagnostics {
+    /// 按`DIAGNOSTICS_SCHEMA`输出， 保持诊断原来的顺序
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"format":"{}","version":{},"diagnostics":[{}]}}"#,
+            DIAGNOSTICS_FORMAT,
+            SCHEMA_VERSION,
+            self.iter().map(diagnostic_json).join(",")
+        )
+    }
+}
+
+
+pub(crate) fn entries(root: &AST) -> Vec<Entry> {
+    let mut entries = vec![Entry::Tree {
+        kind: root.sym().name().to_string(),
+        synthesized: root.is_synthesized(),
+        children: vec![],
+    }];
+    let mut stack = root.elems_vec().into_iter().rev().map(|(_, node)| (node.clone(), 0)).collect_vec();
+
+    while let Some((node, parent)) = stack.pop() {
+        let id = entries.len();
+        if let Entry::Tree { children, .. } = &mut entries[parent] {
+            children.push(id);
+        }
+
+        match node {
+            ASTNode::Leaf(token) => entries.push(Entry::Leaf(token)),
+            ASTNode::Tree(tree) => {
+                let tree = tree.as_ref().borrow();
+                entries.push(Entry::Tree {
+                    kind: tree.sym().name().to_string(),
+                    synthesized: tree.is_synthesized(),
+                    children: vec![],
+                });
+                stack.extend(tree.elems_vec().into_iter().rev().map(|(_, node)| (node.clone(), id)));
+            }
+        }
+    }
+
+    entries
+}
+
+/// 子树的区间从第一个token的开头到最后一个token的结尾
+pub(crate) fn spans(entries: &[Entry]) -> Vec<Option<(SrcLoc, SrcLoc)>> {
+    let mut spans: Vec<Option<(SrcLoc, SrcLoc)>> = vec![None; entries.len()];
+
+    for (id, entry) in entries.iter().enumerate().rev() {
+        spans[id] = match entry {
+            Entry::Leaf(token) => Some((token.loc(), token_end(token))),
+            Entry::Tree { children, .. } => {
+                let start = children.iter().find_map(|&child| spans[child].as_ref().map(|span| span.0.clone()));
+                let end = children.iter().rev().find_map(|&child| spans[child].as_ref().map(|span| span.1.clone()));
+
+                start.zip(end)
+            }
+        };
+    }
+
+    spans
+}
+
+/// token文本之后的位置
+fn token_end(token: &Token) -> SrcLoc {
+    let mut end = token.loc();
+
+    for c in token.value().chars() {
+        if c == '\n' {
+            end.ln += 1;
+            end.col = 1;
+        }
+        else {
+            end.col += 1;
+        }
+    }
+
+    end
+}
+
+fn pos_json(loc: &SrcLoc) -> String {
+    format!(r#"{{"line":{},"col":{}}}"#, loc.ln, loc.col)
+}
+
+fn span_json(span: &Option<(SrcLoc, SrcLoc)>) -> String {
+    match span {
+        Some((start, end)) => format!(r#"{{"start":{},"end":{}}}"#, pos_json(start), pos_json(end)),
+        None => "null".to_string(),
+    }
+}
+
+fn diagnostic_json(diag: &Diagnostic) -> String {
+    format!(
+        r#"{{"severity":{},"code":{},"message":{},"loc":{},"notes":[{}]}}"#,
+        json_str(&diag.severity.to_string()),
+        json_str(&diag.code),
+        json_str(&diag.msg),
+        diag.loc.as_ref().map_or("null".to_string(), pos_json),
+        diag.notes.iter().map(|note| json_str(note)).join(",")
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        builder::GramBuilder,
+        diagnostic::Severity,
+        gram::GramSym,
+        parser::{LL1Parser, ParseOptions},
+    };
+
+    fn parser() -> LL1Parser {
+        let gram = GramBuilder::from_dsl(
+            "grammar![lang| Prog: | Stmts; Stmts: | Stmt Stmts; | ε; Stmt: | id eq Expr semi; | error semi; Expr: | intlit; | id; |]"
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+
+        LL1Parser::builder(gram).build().unwrap()
+    }
+
+    fn tokens(src: &[(&str, &str, usize, usize)]) -> Vec<Token> {
+        src.iter()
+            .map(|(name, value, ln, col)| Token::new(name, value, SrcLoc::new((*ln, *col))))
+            .collect()
+    }
+
+    fn validate(schema: &str, instance: &str) -> Value {
+        let schema: Value = serde_json::from_str(schema).unwrap();
+        let instance: Value = serde_json::from_str(instance).unwrap();
+        let validator = jsonschema::validator_for(&schema).unwrap();
+
+        let errors = validator.iter_errors(&instance).map(|err| err.to_string()).collect_vec();
+        assert!(errors.is_empty(), "{:#?}\n{}", errors, instance);
+
+        instance
+    }
+
+    #[test]
+    fn test_ast_json_matches_schema() {
+        let tokens = tokens(&[
+            ("id", "a", 1, 1),
+            ("eq", "=", 1, 3),
+            ("intlit", "10", 1, 5),
+            ("semi", ";", 1, 7),
+            ("id", "b", 2, 1),
+            ("eq", "=", 2, 3),
+            ("eq", "=", 2, 5),
+            ("semi", ";", 2, 6),
+        ]);
+        let options = ParseOptions { error_prods: true, ..ParseOptions::default() };
+        let (root, errors) = parser().parse_with(tokens, &options);
+        assert_eq!(errors.len(), 1);
+
+        let json = root.as_ref().borrow().to_json();
+        let value = validate(AST_SCHEMA, &json);
+
+        assert_eq!(value["version"], SCHEMA_VERSION);
+        assert_eq!(value["root"]["kind"], "Prog");
+        assert_eq!(value["root"]["span"], json!({ "start": { "line": 1, "col": 1 }, "end": { "line": 2, "col": 7 } }));
+
+        let stmt = &value["root"]["children"][0]["children"][0];
+        assert_eq!(stmt["kind"], "Stmt");
+        assert_eq!(stmt["children"][2]["children"][0]["text"], "10");
+        assert_eq!(stmt["children"][2]["children"][0]["span"]["end"], json!({ "line": 1, "col": 7 }));
+
+        // 确定的输出
+        assert_eq!(json, root.as_ref().borrow().to_json());
+    }
+
+    #[test]
+    fn test_empty_ast_json_matches_schema() {
+        let root = AST::new(&GramSym::NonTerminal("Prog".to_string()));
+        let value = validate(AST_SCHEMA, &root.to_json());
+
+        assert_eq!(value["root"]["span"], Value::Null);
+        assert_eq!(value["root"]["children"], json!([]));
+    }
+
+    #[test]
+    fn test_diagnostics_json_matches_schema() {
+        let mut diags = Diagnostics::new();
+
+        let mut diag = Diagnostic::error("unexpected-token", "Unexpected \"token\"\n");
+        diag.loc = Some(SrcLoc::new((2, 5)));
+        diag.notes.push("in: Prog > Stmt".to_string());
+        diags.push(diag);
+        diags.push(Diagnostic::new(Severity::Warning, "unused-nonterminal", "[Unused] is never used"));
+
+        let value = validate(DIAGNOSTICS_SCHEMA, &diags.to_json());
+
+        assert_eq!(value["diagnostics"][0]["message"], "Unexpected \"token\"\n");
+        assert_eq!(value["diagnostics"][0]["loc"], json!({ "line": 2, "col": 5 }));
+        assert_eq!(value["diagnostics"][1]["severity"], "warning");
+        assert_eq!(value["diagnostics"][1]["loc"], Value::Null);
+    }
+}
@@ -0,0 +1,312 @@
+//! Scope Resolution: 用产生式上的注解声明作用域、 定义和引用， 解析之后得到带定义/引用链接的符号表
+//!
+//! - `#[scope]`: 这个分支推导出的节点开启新的作用域
+//! - `#[def="id"]`: 名为`id`的子节点是定义
+//! - `#[ref="id"]`: 名为`id`的子节点是引用
+//!
+//! 子节点是子树时取它的第一个token作为名字， 多个符号名用空格隔开或者写多个注解。
+//! 开启作用域的节点自己的定义和引用属于外层的作用域， 它的子树属于新的作用域
+//!
+//! ```ignore
+//! let gram = GramBuilder::from_dsl(r#"grammar![lang|
+//!     Item:
+//!         #[scope] #[def="id"]
+//!         | fn id lb Items rb;
+//!         #[def="id"]
+//!         | let id eq Expr semi;
+//!     Expr:
+//!         #[ref="id"]
+//!         | id;
+//!         ...
+//! |]"#)?.build()?;
+//!
+//! let table = SymbolTable::build(&root, &ScopeConfig::default());
+//! for diag in table.diags().iter() {
+//!     println!("{}", diag);
+//! }
+//! ```
+
+use std::{cell::RefCell, rc::Rc};
+
+use indexmap::IndexMap;
+
+use crate::{
+    diagnostic::{Diagnostic, Diagnostics},
+    parser::{ASTNode, SrcLoc, Token, AST},
+};
+
+
+#[derive(Debug, Clone)]
+pub struct ScopeConfig {
+    /// 为真时定义在整个作用域里可见， 否则只有后面的引用能看到
+    pub hoisting: bool,
+}
+
+impl Default for ScopeConfig {
+    fn default() -> Self {
+        Self { hoisting: true }
+    }
+}
+
+impl ScopeConfig {
+    pub fn with_hoisting(mut self, hoisting: bool) -> Self {
+        self.hoisting = hoisting;
+        self
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Scope {
+    /// 0是整棵树的作用域， 没有父作用域
+    pub parent: Option<usize>,
+    /// 开启作用域的节点
+    pub node: Rc<RefCell<AST>>,
+    /// 名字 => 第一个定义
+    pub defs: IndexMap<String, usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Def {
+    pub name: String,
+    pub token: Rc<Token>,
+    pub scope: usize,
+    /// 解析到这个定义的引用
+    pub refs: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Ref {
+    pub name: String,
+    pub token: Rc<Token>,
+    pub scope: usize,
+    /// 没找到定义时为None
+    pub def: Option<usize>,
+}
+
+
+/// 作用域、 定义和引用都按在树里出现的顺序编号
+#[derive(Debug, Clone)]
+pub struct SymbolTable {
+    scopes: Vec<Scope>,
+    defs: Vec<Def>,
+    refs: Vec<Ref>,
+    diags: Diagnostics,
+}
+
+impl SymbolTable {
+    /// 重复的定义报告为`duplicate-definition`错误， 找不到定义的引用报告为`unresolved-reference`警告
+    pub fn build(root: &Rc<RefCell<AST>>, config: &ScopeConfig) -> Self {
+        let mut table = Self {
+            scopes: vec![Scope {
+                parent: None,
+                node: root.clone(),
+                defs: IndexMap::new(),
+            }],
+            defs: vec![],
+            refs: vec![],
+            diags: Diagnostics::new(),
+        };
+
+        // 定义和引用在树里的先后， 不提升时用来判断可见性
+        let mut order = Orders::default();
+        table.collect(root, &mut order);
+
+        for i in 0..table.refs.len() {
+            let def = table.lookup(i, config, &order);
+
+            table.refs[i].def = def;
+            match def {
+                Some(def) => table.defs[def].refs.push(i),
+                None => {
+                    let reference = &table.refs[i];
+                    table.diags.push(
+                        Diagnostic::warning(
+                            "unresolved-reference",
+                            &format!("cannot find `{}` in scope", reference.name),
+                        )
+                        .with_loc(reference.token.loc()),
+                    );
+                }
+            }
+        }
+
+        table
+    }
+
+    /// 按先序处理， 不递归： 栈里是还没处理的子节点， 处理一个子节点时先登记它的定义和引用，
+    /// 再把它的子节点压栈， 所以先后次序和递归时一样
+    fn collect(&mut self, root: &Rc<RefCell<AST>>, order: &mut Orders) {
+        let mut stack = vec![];
+        self.push_children(root, 0, &mut stack);
+
+        while let Some(pending) = stack.pop() {
+            let name_token = match &pending.child {
+                ASTNode::Leaf(token) => Some(token.clone()),
+                ASTNode::Tree(subtree) => subtree.as_ref().borrow().first_token(),
+            }
+            .filter(|token| !token.is_synthesized());
+
+            if let Some(token) = name_token {
+                if pending.is_def {
+                    order.defs.push(order.next);
+                    order.next += 1;
+                    self.define(token.clone(), pending.scope);
+                }
+                if pending.is_ref {
+                    order.refs.push(order.next);
+                    order.next += 1;
+                    self.refs.push(Ref {
+                        name: token.value().to_string(),
+                        token,
+                        scope: pending.scope,
+                        def: None,
+                    });
+                }
+            }
+
+            if let ASTNode::Tree(subtree) = &pending.child {
+                self.push_children(subtree, pending.inner, &mut stack);
+            }
+        }
+    }
+
+    /// `node`在作用域`scope`里， 它带`scope`注解时为子节点开一个新的作用域
+    fn push_children(&mut self, node: &Rc<RefCell<AST>>, scope: usize, stack: &mut Vec<Pending>) {
+        let tree = node.as_ref().borrow();
+
+        let inner = if tree.attr("scope").is_some() && !Rc::ptr_eq(node, &self.scopes[0].node) {
+            self.scopes.push(Scope {
+                parent: Some(scope),
+                node: node.clone(),
+                defs: IndexMap::new(),
+            });
+            self.scopes.len() - 1
+        }
+        else {
+            scope
+        };
+
+        let def_names = annotated(&tree, "def");
+        let ref_names = annotated(&tree, "ref");
+
+        for (sym, child) in tree.elems_vec().into_iter().rev() {
+            stack.push(Pending {
+                child: child.clone(),
+                is_def: def_names.contains(&sym.name().as_ref()),
+                is_ref: ref_names.contains(&sym.name().as_ref()),
+                scope,
+                inner,
+            });
+        }
+    }
+
+    fn define(&mut self, token: Rc<Token>, scope: usize) {
+        let name = token.value().to_string();
+
+        if let Some(first) = self.scopes[scope].defs.get(&name) {
+            self.diags.push(
+                Diagnostic::error(
+                    "duplicate-definition",
+                    &format!("`{}` is defined multiple times in the same scope", name),
+                )
+                .with_loc(token.loc())
+                .with_note(&format!("first defined at {}", self.defs[*first].token.loc())),
+            );
+        }
+        else {
+            self.scopes[scope].defs.insert(name.clone(), self.defs.len());
+        }
+
+        self.defs.push(Def {
+            name,
+            token,
+            scope,
+            refs: vec![],
+        });
+    }
+
+    /// 从引用所在的作用域往外找
+    fn lookup(&self, i: usize, config: &ScopeConfig, order: &Orders) -> Option<usize> {
+        let reference = &self.refs[i];
+        let mut cur = Some(reference.scope);
+
+        while let Some(scope) = cur {
+            let def = self.scopes[scope]
+                .defs
+                .get(&reference.name)
+                .copied()
+                .filter(|def| config.hoisting || order.defs[*def] < order.refs[i]);
+
+            if def.is_some() {
+                return def;
+            }
+
+            cur = self.scopes[scope].parent;
+        }
+
+        None
+    }
+
+    pub fn scopes(&self) -> &[Scope] {
+        &self.scopes
+    }
+
+    pub fn defs(&self) -> &[Def] {
+        &self.defs
+    }
+
+    pub fn refs(&self) -> &[Ref] {
+        &self.refs
+    }
+
+    pub fn diags(&self) -> &Diagnostics {
+        &self.diags
+    }
+
+    /// 引用指向的定义
+    pub fn def_of(&self, reference: &Ref) -> Option<&Def> {
+        reference.def.map(|def| &self.defs[def])
+    }
+
+    /// 解析到这个定义的全部引用
+    pub fn refs_of<'a>(&'a self, def: &'a Def) -> impl Iterator<Item = &'a Ref> + 'a {
+        def.refs.iter().map(move |i| &self.refs[*i])
+    }
+
+    /// 位置正好在`loc`的定义
+    pub fn def_at(&self, loc: &SrcLoc) -> Option<&Def> {
+        self.defs.iter().find(|def| def.token.loc() == *loc)
+    }
+
+    /// 位置正好在`loc`的引用
+    pub fn ref_at(&self, loc: &SrcLoc) -> Option<&Ref> {
+        self.refs.iter().find(|reference| reference.token.loc() == *loc)
+    }
+}
+
+
+#[derive(Default)]
+struct Orders {
+    next: usize,
+    defs: Vec<usize>,
+    refs: Vec<usize>,
+}
+
+
+/// `collect`里还没处理的子节点
+struct Pending {
+    child: ASTNode,
+    is_def: bool,
+    is_ref: bool,
+    /// 父节点所在的作用域， 子节点的定义和引用属于它
+    scope: usize,
+    /// 父节点为子树开的作用域
+    inner: usize,
+}
+
+fn annotated<'a>(tree: &'a AST, key: &str) -> Vec<&'a str> {
+    tree.attr(key)
+        .map(|value| value.split_whitespace().collect())
+        .unwrap_or_default()
+}
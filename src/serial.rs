@@ -0,0 +1,1104 @@
+//! Serialized Format: 预测表(`GramAnalysis`)和AST的二进制格式， 用来做跨进程、 跨版本的缓存
+//!
+//! ```text
+//! magic "LL1E" | version: u16 | kind: u8 | fingerprint: u64 | payload
+//! ```
+//!
+//! 整数都是小端， 字符串是u32长度加UTF-8字节。 fingerprint是生成它的语法的`Gram::fingerprint`，
+//! 读的时候和手上的语法对不上、 或者版本不在`MIN_FORMAT_VERSION..=FORMAT_VERSION`里都直接报错，
+//! 不会读出一张错的表。 token的附加值、 语法的描述信息(`Gram::meta`)和节点的产生式(`AST::prod`)不会被保存
+//!
+//! token流(`TokenStream`)不依赖语法， fingerprint为0， token名集中存一次， 区间和位置存和前一个token的差值(LEB128变长整数)，
+//! 值可以按`Redaction`换成哈希或者不存， 用来在报告解析器的bug时附上触发它的token流而不泄露源码
+//!
+//! ```ignore
+//! stream.save_with("bug.tokens", Redaction::Elide)?;
+//! let stream = TokenStream::load("bug.tokens")?;
+//! let (root, errors) = parser.parse_with(stream.into(), &ParseOptions::default());
+//! ```
+
+use std::{cell::RefCell, convert::TryFrom, error::Error, fmt, fs, path::Path, rc::Rc};
+
+use indexmap::IndexSet;
+
+use crate::{
+    analysis::GramAnalysis,
+    error::LlResult,
+    gram::{FollSetSym, FstSetSym, Gram, GramProd, GramSym, GramSymStr, PredSet, PredSetSym, ProdAttrs},
+    parser::{ASTNode, SrcLoc, Token, AST},
+    redact::Redaction,
+    scannerless::CharClass,
+    tokens::{Span, TokenStream},
+    VerboseLv,
+};
+
+
+pub const MAGIC: [u8; 4] = *b"LL1E";
+/// 写出的格式版本， 格式改变时加一
+///
+/// 2: 符号表按种类存`$`、 `_`、 `~{...}`、 带值约束的终结符和字符类
+pub const FORMAT_VERSION: u16 = 2;
+/// 还能读的最早的格式版本
+pub const MIN_FORMAT_VERSION: u16 = 2;
+
+/// 表里代表ε或者结束符的符号编号
+const END: u32 = u32::MAX;
+
+/// token流的值被略去
+const TOKENS_ELIDED: u8 = 1;
+
+/// 读回来的略去的值(`*`)总共最多这么多字符， 超过时数据当作损坏， 不会照着坏数据分配内存
+pub const MAX_ELIDED_LEN: usize = 1 << 24;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PayloadKind {
+    Table,
+    Ast,
+    Tokens,
+}
+
+impl PayloadKind {
+    fn tag(&self) -> u8 {
+        match self {
+            Self::Table => 1,
+            Self::Ast => 2,
+            Self::Tokens => 3,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            1 => Some(Self::Table),
+            2 => Some(Self::Ast),
+            3 => Some(Self::Tokens),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SerialError {
+    BadMagic,
+    /// 写它的版本太新， 或者旧到不再支持
+    UnsupportedVersion(u16),
+    KindMismatch { expected: PayloadKind, found: u8 },
+    FingerprintMismatch { expected: u64, found: u64 },
+    Truncated,
+    Corrupt(String),
+}
+
+impl fmt::Display for SerialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not a ll1engine serialized file"),
+            Self::UnsupportedVersion(found) => write!(
+                f,
+                "unsupported format version {}, supported {}..={}",
+                found, MIN_FORMAT_VERSION, FORMAT_VERSION
+            ),
+            Self::KindMismatch { expected, found } => {
+                write!(f, "expect {:?} payload, found kind tag {}", expected, found)
+            }
+            Self::FingerprintMismatch { expected, found } => write!(
+                f,
+                "grammar fingerprint mismatch, expect {:016x}, found {:016x}",
+                expected, found
+            ),
+            Self::Truncated => write!(f, "unexpected end of data"),
+            Self::Corrupt(msg) => write!(f, "corrupt data: {}", msg),
+        }
+    }
+}
+
+impl Error for SerialError {}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u16,
+    pub kind: PayloadKind,
+    pub fingerprint: u64,
+}
+
+impl Header {
+    /// 读出头部和剩下的数据， 只检查magic和版本
+    pub fn read(bytes: &[u8]) -> Result<(Self, &[u8]), SerialError> {
+        let mut reader = Reader::new(bytes);
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(SerialError::BadMagic);
+        }
+
+        let version = reader.u16()?;
+        check_version(version)?;
+
+        let tag = reader.u8()?;
+        let kind = PayloadKind::from_tag(tag)
+            .ok_or_else(|| SerialError::Corrupt(format!("unknown kind tag {}", tag)))?;
+        let fingerprint = reader.u64()?;
+
+        Ok((Self { version, kind, fingerprint }, reader.rest()))
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        writer.bytes(&MAGIC);
+        writer.u16(self.version);
+        writer.u8(self.kind.tag());
+        writer.u64(self.fingerprint);
+    }
+}
+
+
+/// 和能读`peer_min..=peer_max`的对方协商写出的版本， 取双方都支持的最高版本
+pub fn negotiate(peer_min: u16, peer_max: u16) -> Option<u16> {
+    let version = peer_max.min(FORMAT_VERSION);
+
+    Some(version).filter(|version| *version >= peer_min.max(MIN_FORMAT_VERSION))
+}
+
+fn check_version(version: u16) -> Result<(), SerialError> {
+    if (MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&version) {
+        Ok(())
+    }
+    else {
+        Err(SerialError::UnsupportedVersion(version))
+    }
+}
+
+
+impl Gram {
+    /// 产生式(包括注解)的FNV-1a哈希， 与crate版本和进程无关， 规则的文档不参与
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash = 0xcbf2_9ce4_8422_2325u64;
+
+        for prod in self.productions() {
+            let mut line = prod.to_string();
+            for (key, value) in prod.attrs.iter() {
+                line.push_str(&format!(" {}={:?}", key, value));
+            }
+            line.push('\n');
+
+            for b in line.bytes() {
+                hash ^= b as u64;
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+
+        hash
+    }
+}
+
+
+impl GramAnalysis {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_version(FORMAT_VERSION).unwrap()
+    }
+
+    /// 按协商出的版本写(见`negotiate`)
+    pub fn to_bytes_version(&self, version: u16) -> Result<Vec<u8>, SerialError> {
+        check_version(version)?;
+
+        let mut writer = Writer::default();
+        let header = Header {
+            version,
+            kind: PayloadKind::Table,
+            fingerprint: self.gram.fingerprint(),
+        };
+        header.write(&mut writer);
+
+        let mut syms = SymTable::new(&self.gram);
+        let mut body = Writer::default();
+
+        body.str(self.gram.name());
+
+        let prods = self.gram.productions().collect::<Vec<&GramProd>>();
+        body.len(prods.len());
+        for prod in prods.iter() {
+            write_prod(&mut body, &mut syms, prod);
+        }
+
+        let docs = self
+            .gram
+            .derivation_tree()
+            .into_iter()
+            .filter_map(|(sym, _)| self.gram.doc(&sym).map(|doc| (sym.clone(), doc.to_string())))
+            .collect::<Vec<(GramSym, String)>>();
+        body.len(docs.len());
+        for (sym, doc) in docs.iter() {
+            body.u32(syms.index(sym));
+            body.str(doc);
+        }
+
+        body.len(self.fstsets.len());
+        for (sym, set) in self.fstsets.iter() {
+            body.u32(syms.index(sym));
+            body.len(set.len());
+            for fst in set.iter() {
+                body.u32(match fst {
+                    FstSetSym::Sym(sym) => syms.index(sym),
+                    FstSetSym::Epsilon => END,
+                });
+            }
+        }
+
+        body.len(self.follsets.len());
+        for (sym, set) in self.follsets.iter() {
+            body.u32(syms.index(sym));
+            body.len(set.len());
+            for foll in set.iter() {
+                body.u32(match foll {
+                    FollSetSym::Sym(sym) => syms.index(sym),
+                    FollSetSym::EndMarker => END,
+                });
+            }
+        }
+
+        let entries = self
+            .predsets
+            .iter()
+            .flat_map(|(lfsym, deriv_map)| deriv_map.iter().map(move |(la, prod)| (lfsym, la, prod)))
+            .collect::<Vec<(&GramSym, &PredSetSym, &GramProd)>>();
+        body.len(entries.len());
+        for (lfsym, la, prod) in entries {
+            body.u32(syms.index(lfsym));
+            body.u32(match la {
+                PredSetSym::Sym(sym) => syms.index(sym),
+                PredSetSym::EndMarker => END,
+            });
+            let i = prods.iter().position(|each| *each == prod).unwrap();
+            body.u32(i as u32);
+        }
+
+        syms.write(&mut writer);
+        writer.bytes(&body.buf);
+
+        Ok(writer.buf)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SerialError> {
+        let (header, rest) = Header::read(bytes)?;
+        expect_kind(&header, PayloadKind::Table)?;
+
+        let mut reader = Reader::new(rest);
+        let syms = SymTable::read(&mut reader)?;
+
+        let mut gram = Gram::new(&reader.str()?);
+        let mut prods = vec![];
+        for _ in 0..reader.len()? {
+            let prod = read_prod(&mut reader, &syms)?;
+            gram.insert_prod(prod.clone());
+            prods.push(prod);
+        }
+
+        for _ in 0..reader.len()? {
+            let sym = syms.sym(reader.u32()?)?;
+            for line in reader.str()?.lines() {
+                gram.add_doc(&sym, line);
+            }
+        }
+
+        let mut fstsets = indexmap::IndexMap::new();
+        for _ in 0..reader.len()? {
+            let sym = syms.sym(reader.u32()?)?;
+            let mut set = IndexSet::new();
+            for _ in 0..reader.len()? {
+                set.insert(match reader.u32()? {
+                    END => FstSetSym::Epsilon,
+                    i => FstSetSym::Sym(syms.sym(i)?),
+                });
+            }
+            fstsets.insert(sym, set);
+        }
+
+        let mut follsets = indexmap::IndexMap::new();
+        for _ in 0..reader.len()? {
+            let sym = syms.sym(reader.u32()?)?;
+            let mut set = IndexSet::new();
+            for _ in 0..reader.len()? {
+                set.insert(match reader.u32()? {
+                    END => FollSetSym::EndMarker,
+                    i => FollSetSym::Sym(syms.sym(i)?),
+                });
+            }
+            follsets.insert(sym, set);
+        }
+
+        let mut predsets = PredSet::default();
+        for _ in 0..reader.len()? {
+            let lfsym = syms.sym(reader.u32()?)?;
+            let la = match reader.u32()? {
+                END => PredSetSym::EndMarker,
+                i => PredSetSym::Sym(syms.sym(i)?),
+            };
+            let i = reader.u32()? as usize;
+            let prod = prods
+                .get(i)
+                .ok_or_else(|| SerialError::Corrupt(format!("production index {} out of range", i)))?;
+
+            predsets.insert(&lfsym, la, prod.clone());
+        }
+
+        expect_fingerprint(header.fingerprint, gram.fingerprint())?;
+
+        Ok(Self {
+            gram,
+            fstsets,
+            follsets,
+            predsets,
+            verbose: VerboseLv::V0,
+        })
+    }
+
+    /// 同`from_bytes`， 并且要求数据是由`gram`生成的
+    pub fn from_bytes_for(bytes: &[u8], gram: &Gram) -> Result<Self, SerialError> {
+        let (header, _) = Header::read(bytes)?;
+        expect_fingerprint(gram.fingerprint(), header.fingerprint)?;
+
+        Self::from_bytes(bytes)
+    }
+}
+
+
+impl AST {
+    /// `gram`是解析出这棵树的语法， 只用来计算fingerprint
+    pub fn to_bytes(&self, gram: &Gram) -> Vec<u8> {
+        self.to_bytes_version(gram, FORMAT_VERSION).unwrap()
+    }
+
+    pub fn to_bytes_version(&self, gram: &Gram, version: u16) -> Result<Vec<u8>, SerialError> {
+        check_version(version)?;
+
+        let mut writer = Writer::default();
+        let header = Header {
+            version,
+            kind: PayloadKind::Ast,
+            fingerprint: gram.fingerprint(),
+        };
+        header.write(&mut writer);
+
+        let mut syms = SymTable::new(gram);
+        let mut body = Writer::default();
+        write_tree(&mut body, &mut syms, self);
+
+        syms.write(&mut writer);
+        writer.bytes(&body.buf);
+
+        Ok(writer.buf)
+    }
+
+    /// 数据必须是由`gram`解析出的树
+    pub fn from_bytes(bytes: &[u8], gram: &Gram) -> Result<Rc<RefCell<Self>>, SerialError> {
+        let (header, rest) = Header::read(bytes)?;
+        expect_kind(&header, PayloadKind::Ast)?;
+        expect_fingerprint(gram.fingerprint(), header.fingerprint)?;
+
+        let mut reader = Reader::new(rest);
+        let syms = SymTable::read(&mut reader)?;
+
+        read_tree(&mut reader, &syms)
+    }
+}
+
+/// 先序写出， 用显式的栈代替递归， 很深的树也不会爆栈
+fn write_tree(writer: &mut Writer, syms: &mut SymTable, ast: &AST) {
+    fn write_head(writer: &mut Writer, syms: &mut SymTable, ast: &AST, stack: &mut Vec<ASTNode>) {
+        writer.u32(syms.index(ast.sym()));
+        write_attrs(writer, ast.attrs());
+
+        let elems = ast.elems_vec();
+        writer.len(elems.len());
+        stack.extend(elems.into_iter().rev().map(|(_sym, node)| node.clone()));
+    }
+
+    let mut stack = vec![];
+    write_head(writer, syms, ast, &mut stack);
+
+    while let Some(node) = stack.pop() {
+        match node {
+            ASTNode::Tree(subtree) => {
+                writer.u8(0);
+                write_head(writer, syms, &subtree.as_ref().borrow(), &mut stack);
+            }
+            ASTNode::Leaf(token) => {
+                writer.u8(1);
+                writer.str(token.name());
+                writer.str(token.value());
+                writer.u64(token.loc().ln as u64);
+                writer.u64(token.loc().col as u64);
+            }
+        }
+    }
+}
+
+/// 和`write_tree`对应， 栈里是还没读完的树和它剩下的子节点数
+fn read_tree(reader: &mut Reader, syms: &SymTable) -> Result<Rc<RefCell<AST>>, SerialError> {
+    fn read_head(reader: &mut Reader, syms: &SymTable) -> Result<(AST, usize), SerialError> {
+        let mut ast = AST::new(&syms.sym(reader.u32()?)?);
+        ast.set_attrs(read_attrs(reader)?);
+
+        Ok((ast, reader.len()?))
+    }
+
+    let mut stack = vec![read_head(reader, syms)?];
+
+    while let Some((ast, rest)) = stack.last_mut() {
+        if *rest == 0 {
+            let (ast, _) = stack.pop().unwrap();
+            let tree = Rc::new(RefCell::new(ast));
+
+            match stack.last_mut() {
+                Some((parent, _)) => parent.insert_tree(tree),
+                None => return Ok(tree),
+            }
+            continue;
+        }
+        *rest -= 1;
+
+        match reader.u8()? {
+            0 => stack.push(read_head(reader, syms)?),
+            1 => {
+                let name = reader.str()?;
+                let value = reader.str()?;
+                let ln = reader.u64()? as usize;
+                let col = reader.u64()? as usize;
+
+                ast.insert_leaf(Token::new(&name, &value, SrcLoc::new((ln, col))));
+            }
+            tag => return Err(SerialError::Corrupt(format!("unknown node tag {}", tag))),
+        }
+    }
+
+    unreachable!()
+}
+
+
+impl TokenStream {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_with(Redaction::Keep)
+    }
+
+    /// `Redaction::Elide`时不存token的值， 读回来的值是和区间等长的`*`
+    pub fn to_bytes_with(&self, redact: Redaction) -> Vec<u8> {
+        let elided = redact == Redaction::Elide;
+
+        let mut writer = Writer::default();
+        let header = Header {
+            version: FORMAT_VERSION,
+            kind: PayloadKind::Tokens,
+            fingerprint: 0,
+        };
+        header.write(&mut writer);
+        writer.u8(if elided { TOKENS_ELIDED } else { 0 });
+
+        let names: IndexSet<&str> = self.tokens().iter().map(|token| token.name()).collect();
+        writer.len(names.len());
+        for name in names.iter() {
+            writer.str(name);
+        }
+
+        writer.varint(self.len() as u64);
+
+        let (mut last_end, mut last_ln, mut last_col) = (0, 0, 0);
+        for (token, span) in self.tokens().iter().zip(self.spans()) {
+            let name = names.get_index_of(token.name()).unwrap() as u64;
+            writer.varint(name << 1 | token.is_synthesized() as u64);
+
+            let loc = token.loc();
+            writer.svarint(span.start as i64 - last_end as i64);
+            writer.varint((span.end - span.start) as u64);
+            writer.svarint(loc.ln as i64 - last_ln as i64);
+            writer.svarint(loc.col as i64 - last_col as i64);
+
+            if !elided {
+                writer.vstr(&redact.apply(token.value()));
+            }
+
+            last_end = span.end;
+            last_ln = loc.ln;
+            last_col = loc.col;
+        }
+
+        writer.buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SerialError> {
+        let (header, rest) = Header::read(bytes)?;
+        expect_kind(&header, PayloadKind::Tokens)?;
+
+        let mut reader = Reader::new(rest);
+        let elided = reader.u8()? & TOKENS_ELIDED != 0;
+
+        let mut names = vec![];
+        for _ in 0..reader.len()? {
+            names.push(reader.str()?);
+        }
+
+        let mut tokens = vec![];
+        let (mut last_end, mut last_ln, mut last_col) = (0, 0, 0);
+        let mut elided_len = 0usize;
+
+        for _ in 0..reader.varint()? {
+            let tag = reader.varint()?;
+            let name = usize::try_from(tag >> 1)
+                .ok()
+                .and_then(|i| names.get(i))
+                .ok_or_else(|| SerialError::Corrupt(format!("token name index {} out of range", tag >> 1)))?;
+
+            let start = offset(last_end, reader.svarint()?)?;
+            let len = reader.varint()?;
+            let end = usize::try_from(len)
+                .ok()
+                .and_then(|len| start.checked_add(len))
+                .ok_or_else(|| SerialError::Corrupt(format!("bad span {}+{}", start, len)))?;
+            let ln = offset(last_ln, reader.svarint()?)?;
+            let col = offset(last_col, reader.svarint()?)?;
+
+            let value = if elided {
+                elided_len = elided_len.saturating_add(end - start);
+                if elided_len > MAX_ELIDED_LEN {
+                    return Err(SerialError::Corrupt(format!(
+                        "elided values longer than {} chars", MAX_ELIDED_LEN
+                    )));
+                }
+
+                "*".repeat(end - start)
+            }
+            else {
+                reader.vstr()?
+            };
+
+            let mut token = Token::new(name, &value, SrcLoc::new((ln, col)));
+            if tag & 1 != 0 {
+                token.set_synthesized();
+            }
+
+            tokens.push((token, Span::new(start, end)));
+            last_end = end;
+            last_ln = ln;
+            last_col = col;
+        }
+
+        Ok(Self::new(tokens))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> LlResult<()> {
+        fs::write(path, self.to_bytes())?;
+
+        Ok(())
+    }
+
+    pub fn save_with<P: AsRef<Path>>(&self, path: P, redact: Redaction) -> LlResult<()> {
+        fs::write(path, self.to_bytes_with(redact))?;
+
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> LlResult<Self> {
+        Ok(Self::from_bytes(&fs::read(path)?)?)
+    }
+}
+
+/// `base`加上差值， 不能为负
+fn offset(base: usize, delta: i64) -> Result<usize, SerialError> {
+    (base as i64)
+        .checked_add(delta)
+        .and_then(|value| usize::try_from(value).ok())
+        .ok_or_else(|| SerialError::Corrupt(format!("bad offset {}{:+}", base, delta)))
+}
+
+
+fn expect_kind(header: &Header, expected: PayloadKind) -> Result<(), SerialError> {
+    if header.kind == expected {
+        Ok(())
+    }
+    else {
+        Err(SerialError::KindMismatch { expected, found: header.kind.tag() })
+    }
+}
+
+fn expect_fingerprint(expected: u64, found: u64) -> Result<(), SerialError> {
+    if expected == found {
+        Ok(())
+    }
+    else {
+        Err(SerialError::FingerprintMismatch { expected, found })
+    }
+}
+
+fn write_prod(writer: &mut Writer, syms: &mut SymTable, prod: &GramProd) {
+    writer.u32(syms.index(&prod.lfsym));
+
+    match &prod.rhstr {
+        GramSymStr::Str(normal_str) => {
+            writer.len(normal_str.len());
+            for sym in normal_str.iter() {
+                writer.u32(syms.index(sym));
+            }
+        }
+        GramSymStr::Epsilon => writer.len(0),
+    }
+
+    write_attrs(writer, &prod.attrs);
+}
+
+fn read_prod(reader: &mut Reader, syms: &SymTable) -> Result<GramProd, SerialError> {
+    let lfsym = syms.sym(reader.u32()?)?;
+
+    let mut rhs = vec![];
+    for _ in 0..reader.len()? {
+        rhs.push(syms.sym(reader.u32()?)?);
+    }
+    let rhstr = if rhs.is_empty() { GramSymStr::Epsilon } else { GramSymStr::Str(rhs) };
+
+    let mut prod = GramProd::new(lfsym, rhstr);
+    prod.attrs = read_attrs(reader)?;
+
+    Ok(prod)
+}
+
+fn write_attrs(writer: &mut Writer, attrs: &ProdAttrs) {
+    writer.len(attrs.len());
+    for (key, value) in attrs.iter() {
+        writer.str(key);
+        writer.str(value);
+    }
+}
+
+fn read_attrs(reader: &mut Reader) -> Result<ProdAttrs, SerialError> {
+    let mut attrs = ProdAttrs::new();
+    for _ in 0..reader.len()? {
+        let key = reader.str()?;
+        attrs.insert(key, reader.str()?);
+    }
+
+    Ok(attrs)
+}
+
+
+/// 符号表写在payload最前面， 之后的符号都是它的下标
+struct SymTable {
+    syms: IndexSet<GramSym>,
+}
+
+impl SymTable {
+    fn new(gram: &Gram) -> Self {
+        Self { syms: gram.syms().into_iter().collect() }
+    }
+
+    fn index(&mut self, sym: &GramSym) -> u32 {
+        self.syms.insert_full(sym.clone()).0 as u32
+    }
+
+    fn sym(&self, i: u32) -> Result<GramSym, SerialError> {
+        self.syms
+            .get_index(i as usize)
+            .cloned()
+            .ok_or_else(|| SerialError::Corrupt(format!("symbol index {} out of range", i)))
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        writer.len(self.syms.len());
+        for sym in self.syms.iter() {
+            match sym {
+                GramSym::NonTerminal(name) => {
+                    writer.u8(0);
+                    writer.str(name);
+                },
+                GramSym::Terminal(name) => {
+                    writer.u8(1);
+                    writer.str(name);
+                },
+                GramSym::Eof => writer.u8(2),
+                GramSym::Any => writer.u8(3),
+                GramSym::NotSet(names) => {
+                    writer.u8(4);
+                    writer.len(names.len());
+                    for name in names.iter() {
+                        writer.str(name);
+                    }
+                },
+                GramSym::Guarded(name, value) => {
+                    writer.u8(5);
+                    writer.str(name);
+                    writer.str(value);
+                },
+                GramSym::CharClass(class) => {
+                    writer.u8(6);
+                    writer.u8(class.negated as u8);
+                    writer.len(class.ranges.len());
+                    for (lo, hi) in class.ranges.iter() {
+                        writer.u32(*lo as u32);
+                        writer.u32(*hi as u32);
+                    }
+                },
+            }
+        }
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, SerialError> {
+        let mut syms = IndexSet::new();
+        for _ in 0..reader.len()? {
+            let sym = match reader.u8()? {
+                0 => GramSym::NonTerminal(reader.str()?),
+                1 => GramSym::Terminal(reader.str()?),
+                2 => GramSym::Eof,
+                3 => GramSym::Any,
+                4 => {
+                    let mut names = vec![];
+                    for _ in 0..reader.len()? {
+                        names.push(reader.str()?);
+                    }
+
+                    GramSym::NotSet(names)
+                },
+                5 => GramSym::Guarded(reader.str()?, reader.str()?),
+                6 => {
+                    let negated = reader.u8()? != 0;
+                    let mut ranges = vec![];
+                    for _ in 0..reader.len()? {
+                        ranges.push((reader.char()?, reader.char()?));
+                    }
+
+                    GramSym::CharClass(CharClass { negated, ranges })
+                },
+                tag => return Err(SerialError::Corrupt(format!("unknown symbol tag {}", tag))),
+            };
+
+            syms.insert(sym);
+        }
+
+        Ok(Self { syms })
+    }
+}
+
+
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u32(len as u32);
+    }
+
+    fn str(&mut self, value: &str) {
+        self.len(value.len());
+        self.bytes(value.as_bytes());
+    }
+
+    /// LEB128
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.u8(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.u8(value as u8);
+    }
+
+    /// zigzag之后按LEB128写
+    fn svarint(&mut self, value: i64) {
+        self.varint(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    /// 变长整数长度加UTF-8字节
+    fn vstr(&mut self, value: &str) {
+        self.varint(value.len() as u64);
+        self.bytes(value.as_bytes());
+    }
+}
+
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], SerialError> {
+        let end = self.pos.checked_add(n).filter(|end| *end <= self.bytes.len());
+        let end = end.ok_or(SerialError::Truncated)?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+
+        Ok(bytes)
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.bytes[self.pos..]
+    }
+
+    fn u8(&mut self) -> Result<u8, SerialError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SerialError> {
+        let mut buf = [0u8; 2];
+        buf.copy_from_slice(self.take(2)?);
+
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn u32(&mut self) -> Result<u32, SerialError> {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(self.take(4)?);
+
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64, SerialError> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.take(8)?);
+
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn len(&mut self) -> Result<usize, SerialError> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn str(&mut self) -> Result<String, SerialError> {
+        let len = self.len()?;
+
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|err| SerialError::Corrupt(err.to_string()))
+    }
+
+    fn char(&mut self) -> Result<char, SerialError> {
+        let value = self.u32()?;
+
+        char::from_u32(value).ok_or_else(|| SerialError::Corrupt(format!("bad char {:#x}", value)))
+    }
+
+    fn varint(&mut self) -> Result<u64, SerialError> {
+        let mut value = 0u64;
+
+        for shift in (0..64).step_by(7) {
+            let b = self.u8()?;
+            value |= ((b & 0x7f) as u64) << shift;
+
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(SerialError::Corrupt("varint too long".to_string()))
+    }
+
+    fn svarint(&mut self) -> Result<i64, SerialError> {
+        let value = self.varint()?;
+
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    fn vstr(&mut self) -> Result<String, SerialError> {
+        let len = self.varint()?;
+        let len = usize::try_from(len).map_err(|_| SerialError::Truncated)?;
+
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|err| SerialError::Corrupt(err.to_string()))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+    use crate::builder::GramBuilder;
+
+    fn elided_stream(len: u64) -> Vec<u8> {
+        let mut writer = Writer::default();
+        Header { version: FORMAT_VERSION, kind: PayloadKind::Tokens, fingerprint: 0 }.write(&mut writer);
+        writer.u8(TOKENS_ELIDED);
+        writer.len(1);
+        writer.str("x");
+        writer.varint(1);
+        writer.varint(0);
+        writer.svarint(0);
+        writer.varint(len);
+        writer.svarint(1);
+        writer.svarint(1);
+
+        writer.buf
+    }
+
+    #[test]
+    fn test_elided_large_span() {
+        assert!(TokenStream::from_bytes(&elided_stream(3)).is_ok());
+
+        for len in [1 << 56, u64::MAX, MAX_ELIDED_LEN as u64 + 1] {
+            let bytes = elided_stream(len);
+            assert!(bytes.len() < 64);
+            assert!(matches!(TokenStream::from_bytes(&bytes), Err(SerialError::Corrupt(_))), "len {}", len);
+        }
+
+        // 改动任何一个字节或者截断都只会报错， 不会panic
+        let tokens = (0..20)
+            .map(|i| (Token::new("x", "abc", SrcLoc::new((1, 4 * i + 1))), Span::new(4 * i, 4 * i + 3)))
+            .collect();
+        let bytes = TokenStream::new(tokens).to_bytes_with(Redaction::Elide);
+
+        for i in 0..bytes.len() {
+            for value in [0u8, 1, 0x7f, 0x80, 0xff] {
+                let mut mutated = bytes.clone();
+                mutated[i] = value;
+                let _ = TokenStream::from_bytes(&mutated);
+            }
+            let _ = TokenStream::from_bytes(&bytes[..i]);
+        }
+    }
+
+    fn token_stream() -> TokenStream {
+        let mut semi = Token::new("semi", ";", SrcLoc::new((2, 1)));
+        semi.set_synthesized();
+
+        TokenStream::new(vec![
+            (Token::new("id", "secret", SrcLoc::new((1, 1))), Span::new(0, 6)),
+            (Token::new("eq", "=", SrcLoc::new((1, 8))), Span::new(7, 8)),
+            (Token::new("id", "secret", SrcLoc::new((1, 10))), Span::new(9, 15)),
+            (semi, Span::new(15, 15)),
+        ])
+    }
+
+    #[test]
+    fn test_token_stream_round_trip() {
+        let stream = token_stream();
+
+        for redact in [Redaction::Keep, Redaction::Hash { key: 42 }, Redaction::Elide] {
+            let read = TokenStream::from_bytes(&stream.to_bytes_with(redact)).unwrap();
+
+            // 只有值按`redact`变化
+            assert_eq!(read.spans(), stream.spans(), "{:?}", redact);
+            for (read_token, token) in read.tokens().iter().zip(stream.tokens()) {
+                assert_eq!(read_token.name(), token.name());
+                assert_eq!(read_token.loc(), token.loc());
+                assert_eq!(read_token.is_synthesized(), token.is_synthesized());
+            }
+
+            let values = read.tokens().iter().map(|token| token.value()).collect::<Vec<_>>();
+            match redact {
+                Redaction::Keep => assert_eq!(values, ["secret", "=", "secret", ";"]),
+                Redaction::Hash { .. } => {
+                    assert!(values.iter().all(|value| value.starts_with('#') && !value.contains("secret")));
+                    assert_eq!(values[0], values[2]);
+                    assert_ne!(values[0], values[1]);
+                }
+                // 不存值， 读回来是和区间等长的`*`
+                Redaction::Elide => assert_eq!(values, ["******", "*", "******", ""]),
+            }
+        }
+    }
+
+    #[test]
+    fn test_token_stream_corrupt() {
+        for redact in [Redaction::Keep, Redaction::Hash { key: 42 }, Redaction::Elide] {
+            let bytes = token_stream().to_bytes_with(redact);
+
+            for i in 0..bytes.len() {
+                assert!(TokenStream::from_bytes(&bytes[..i]).is_err(), "{:?} truncated at {}", redact, i);
+            }
+
+        }
+
+        // token名的下标越界
+        let mut writer = Writer::default();
+        Header { version: FORMAT_VERSION, kind: PayloadKind::Tokens, fingerprint: 0 }.write(&mut writer);
+        writer.u8(0);
+        writer.len(1);
+        writer.str("x");
+        writer.varint(1);
+        writer.varint(3 << 1);
+        assert!(matches!(TokenStream::from_bytes(&writer.buf), Err(SerialError::Corrupt(_))));
+
+        let mut bytes = token_stream().to_bytes();
+        bytes[0] = b'X';
+        assert!(matches!(TokenStream::from_bytes(&bytes), Err(SerialError::BadMagic)));
+    }
+
+    fn chain(depth: usize) -> Rc<RefCell<AST>> {
+        let sym = GramSym::NonTerminal("L".to_string());
+
+        let mut tree = AST::new(&sym);
+        tree.insert_leaf(Token::new("x", "x", SrcLoc::new((1, 1))));
+        let mut tree = Rc::new(RefCell::new(tree));
+        for _ in 0..depth {
+            let mut parent = AST::new(&sym);
+            parent.insert_tree(tree);
+            tree = Rc::new(RefCell::new(parent));
+        }
+
+        tree
+    }
+
+    fn chain_depth(root: &Rc<RefCell<AST>>) -> usize {
+        let mut depth = 0;
+        let mut cur = root.clone();
+        loop {
+            let next = match cur.as_ref().borrow().elems_vec().first() {
+                Some((_, ASTNode::Tree(subtree))) => subtree.clone(),
+                Some((_, ASTNode::Leaf(token))) => {
+                    assert_eq!(token.value(), "x");
+                    break;
+                }
+                None => panic!("empty node at {}", depth),
+            };
+            cur = next;
+            depth += 1;
+        }
+
+        depth
+    }
+
+    #[test]
+    fn test_deep_tree_round_trip() {
+        // 用很小的栈检查读写都没有递归
+        thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(|| {
+                let depth = 100_000;
+                let gram = GramBuilder::from_dsl("grammar![nested| L: | lp L rp; | x; |]")
+                    .unwrap()
+                    .build()
+                    .unwrap();
+
+                let bytes = chain(depth).as_ref().borrow().to_bytes(&gram);
+                let root = AST::from_bytes(&bytes, &gram).unwrap();
+                assert_eq!(chain_depth(&root), depth);
+
+                // 少了结尾的深层输入只报错
+                let err = AST::from_bytes(&bytes[..bytes.len() - 1], &gram);
+                assert!(err.is_err());
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
@@ -0,0 +1,474 @@
+//! Parse Session: 一份源码的解析结果， 源码、 token流、 AST和错误放在一起，
+//! 并记录每个节点的区间和父节点， 作为编辑器功能的基础
+//!
+//! ```ignore
+//! let session = ParseSession::parse(&parser, &lexer, srcfile, &ParseOptions::default())?;
+//! let selection = session.extend_selection(Span::new(offset, offset));
+//! ```
+
+use std::{cell::RefCell, rc::Rc};
+
+use indexmap::IndexMap;
+#[cfg(feature = "lsp")]
+use serde_json::{json, Value};
+
+use crate::{
+    error::{LlResult, ParseError},
+    lexer::Lexer,
+    parser::{ASTNode, LL1Parser, ParseOptions, SrcFileInfo, SrcLoc, Token, AST},
+    scope::{Def, ScopeConfig, SymbolTable},
+    tokens::{Span, TokenStream},
+};
+
+
+struct NodeInfo {
+    node: Rc<RefCell<AST>>,
+    /// 没有来自源码的token的节点(ε、 合成的)没有区间
+    span: Option<Span>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+
+/// LSP的`FoldingRange`， 行从0开始， 列按UTF-16计
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoldingRange {
+    pub start_line: usize,
+    pub start_character: usize,
+    pub end_line: usize,
+    pub end_character: usize,
+    /// `comment`、 `imports`、 `region`或者自定义的种类
+    pub kind: Option<String>,
+}
+
+#[cfg(feature = "lsp")]
+impl FoldingRange {
+    pub fn to_json(&self) -> Value {
+        let mut value = json!({
+            "startLine": self.start_line,
+            "startCharacter": self.start_character,
+            "endLine": self.end_line,
+            "endCharacter": self.end_character,
+        });
+        if let Some(kind) = &self.kind {
+            value["kind"] = json!(kind);
+        }
+
+        value
+    }
+}
+
+
+/// 文档符号的提取方式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolRule {
+    pub kind: String,
+    /// 从节点出发到名字的符号名路径(每一步取第一个匹配的子节点)，
+    /// 终点是token时取它的值， 是子树时取它盖住的源码
+    pub name_path: Vec<String>,
+}
+
+
+/// 大纲、 面包屑用的文档符号
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentSymbol {
+    pub name: String,
+    pub kind: String,
+    /// 整个节点的区间
+    pub span: Span,
+    /// 名字的区间
+    pub selection_span: Span,
+    pub children: Vec<DocumentSymbol>,
+}
+
+#[cfg(feature = "lsp")]
+impl DocumentSymbol {
+    /// `kind`按LSP的`SymbolKind`名字(不分大小写)换成编号， 认不出来的当作`Variable`
+    pub fn to_json(&self, session: &ParseSession) -> Value {
+        json!({
+            "name": self.name,
+            "kind": lsp_symbol_kind(&self.kind),
+            "range": session.lsp_range_json(self.span),
+            "selectionRange": session.lsp_range_json(self.selection_span),
+            "children": self.children.iter().map(|child| child.to_json(session)).collect::<Vec<Value>>(),
+        })
+    }
+}
+
+#[cfg(feature = "lsp")]
+fn lsp_symbol_kind(kind: &str) -> usize {
+    const KINDS: [&str; 26] = [
+        "file", "module", "namespace", "package", "class", "method", "property",
+        "field", "constructor", "enum", "interface", "function", "variable",
+        "constant", "string", "number", "boolean", "array", "object", "key",
+        "null", "enummember", "struct", "event", "operator", "typeparameter",
+    ];
+    let kind = kind.to_lowercase().replace(['_', '-'], "");
+
+    KINDS.iter().position(|each| *each == kind).unwrap_or(12) + 1
+}
+
+
+pub struct ParseSession {
+    srcfile: SrcFileInfo,
+    tokens: TokenStream,
+    root: Rc<RefCell<AST>>,
+    errors: Vec<ParseError>,
+    /// 先序排列， 0是根
+    nodes: Vec<NodeInfo>,
+    /// 节点的地址 => nodes里的下标
+    index: IndexMap<usize, usize>,
+    /// 可以折叠的非终结符 => 折叠的种类
+    foldable: IndexMap<String, Option<String>>,
+    /// 作为文档符号的非终结符 => 提取方式， 按顺序取第一个找得到名字的
+    symbol_rules: IndexMap<String, Vec<SymbolRule>>,
+    symbol_table: SymbolTable,
+}
+
+impl ParseSession {
+    /// 词法分析失败时报错， 语法错误记录在`errors`里
+    pub fn parse(
+        parser: &LL1Parser,
+        lexer: &Lexer,
+        srcfile: SrcFileInfo,
+        options: &ParseOptions
+    ) -> LlResult<Self>
+    {
+        let tokens = lexer.tokenize_stream(&srcfile)?;
+        let (root, errors) = parser.parse_with(tokens.tokens().to_vec(), options);
+
+        Ok(Self::from_parts(srcfile, tokens, root, errors))
+    }
+
+    /// `root`需要是由`tokens`解析出来的
+    pub fn from_parts(
+        srcfile: SrcFileInfo,
+        tokens: TokenStream,
+        root: Rc<RefCell<AST>>,
+        errors: Vec<ParseError>
+    ) -> Self
+    {
+        let mut session = Self {
+            srcfile,
+            tokens,
+            root: root.clone(),
+            errors,
+            nodes: vec![],
+            index: IndexMap::new(),
+            foldable: IndexMap::new(),
+            symbol_rules: IndexMap::new(),
+            symbol_table: SymbolTable::build(&root, &ScopeConfig::default()),
+        };
+        session.index_tree(&root);
+
+        session
+    }
+
+    /// 先序编号， 子节点的编号总比父节点大， 所以倒着算区间时子节点已经算好了
+    fn index_tree(&mut self, root: &Rc<RefCell<AST>>) {
+        let mut stack = vec![(root.clone(), None::<usize>)];
+
+        while let Some((node, parent)) = stack.pop() {
+            let i = self.nodes.len();
+            if let Some(parent) = parent {
+                self.nodes[parent].children.push(i);
+            }
+            self.nodes.push(NodeInfo {
+                node: node.clone(),
+                span: None,
+                parent,
+                children: vec![],
+            });
+            self.index.insert(Rc::as_ptr(&node) as usize, i);
+
+            let ast = node.as_ref().borrow();
+            for (_sym, child) in ast.elems_vec().into_iter().rev() {
+                if let ASTNode::Tree(subtree) = child {
+                    stack.push((subtree.clone(), Some(i)));
+                }
+            }
+        }
+
+        for i in (0..self.nodes.len()).rev() {
+            let node = self.nodes[i].node.clone();
+            let ast = node.as_ref().borrow();
+
+            let leaf_spans = ast.elems_vec().into_iter().filter_map(|(_sym, child)| match child {
+                ASTNode::Leaf(token) => self.token_span(token),
+                ASTNode::Tree(_) => None,
+            });
+            let child_spans = self.nodes[i].children.iter().filter_map(|child| self.nodes[*child].span);
+
+            self.nodes[i].span = leaf_spans
+                .chain(child_spans)
+                .reduce(|span, child_span| span.cover(&child_span));
+        }
+    }
+
+    /// 把非终结符`name`(比如代码块、 列表)的节点作为可折叠的区域
+    pub fn with_foldable(mut self, name: &str, kind: Option<&str>) -> Self {
+        self.foldable.insert(name.to_string(), kind.map(|kind| kind.to_string()));
+        self
+    }
+
+    /// 把非终结符`name`的节点作为`kind`类的文档符号， 名字沿着`name_path`找，
+    /// 同一个非终结符可以有多条(比如不同的分支)
+    pub fn with_symbol(mut self, name: &str, kind: &str, name_path: &[&str]) -> Self {
+        self.symbol_rules.entry(name.to_string()).or_default().push(SymbolRule {
+            kind: kind.to_string(),
+            name_path: name_path.iter().map(|step| step.to_string()).collect(),
+        });
+        self
+    }
+
+    /// 按`config`重新解析作用域， 默认是`ScopeConfig::default()`
+    pub fn with_scope_config(mut self, config: &ScopeConfig) -> Self {
+        self.symbol_table = SymbolTable::build(&self.root, config);
+        self
+    }
+
+    pub fn srcfile(&self) -> &SrcFileInfo {
+        &self.srcfile
+    }
+
+    pub fn tokens(&self) -> &TokenStream {
+        &self.tokens
+    }
+
+    pub fn root(&self) -> &Rc<RefCell<AST>> {
+        &self.root
+    }
+
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    /// 按语法里的`#[scope]`、 `#[def]`、 `#[ref]`注解解析出的符号表
+    pub fn symbol_table(&self) -> &SymbolTable {
+        &self.symbol_table
+    }
+
+    fn token_span(&self, token: &Token) -> Option<Span> {
+        if token.is_synthesized() {
+            return None;
+        }
+        let start = self.srcfile.srcloc2offset(&token.loc())?;
+
+        Some(Span::new(start, start + token.value().chars().count()))
+    }
+
+    /// 节点盖住的源码区间， 节点不在这棵树里或者没有token时为None
+    pub fn span_of(&self, node: &Rc<RefCell<AST>>) -> Option<Span> {
+        self.info(node)?.span
+    }
+
+    pub fn parent_of(&self, node: &Rc<RefCell<AST>>) -> Option<Rc<RefCell<AST>>> {
+        let parent = self.info(node)?.parent?;
+
+        Some(self.nodes[parent].node.clone())
+    }
+
+    fn info(&self, node: &Rc<RefCell<AST>>) -> Option<&NodeInfo> {
+        let i = self.index.get(&(Rc::as_ptr(node) as usize))?;
+
+        Some(&self.nodes[*i])
+    }
+
+    /// 盖住`span`的最深的节点
+    pub fn node_covering(&self, span: Span) -> Option<Rc<RefCell<AST>>> {
+        self.covering(span).map(|i| self.nodes[i].node.clone())
+    }
+
+    fn covering(&self, span: Span) -> Option<usize> {
+        let covers = |i: usize| {
+            self.nodes[i].span.is_some_and(|each| each.start <= span.start && span.end <= each.end)
+        };
+
+        if !covers(0) {
+            return None;
+        }
+
+        let mut cur = 0;
+        while let Some(child) = self.nodes[cur].children.iter().copied().find(|child| covers(*child)) {
+            cur = child;
+        }
+
+        Some(cur)
+    }
+
+    /// 编辑器的"扩展选区"： 先扩展到所在的token， 再沿着父节点往上，
+    /// 返回第一个严格大于`span`的区间， 已经是整棵树时原样返回
+    pub fn extend_selection(&self, span: Span) -> Span {
+        let token_span = self
+            .tokens
+            .token_at_offset(span.start)
+            .and_then(|(i, _)| self.tokens.get(i))
+            .map(|(_, token_span)| token_span)
+            .filter(|token_span| span.end <= token_span.end && *token_span != span);
+
+        if let Some(token_span) = token_span {
+            return token_span;
+        }
+
+        let mut cur = self.covering(span);
+        while let Some(i) = cur {
+            match self.nodes[i].span {
+                Some(node_span) if node_span != span => return node_span,
+                _ => cur = self.nodes[i].parent,
+            }
+        }
+
+        span
+    }
+
+    /// 偏移处的LSP位置(行从0开始， 列按UTF-16计)
+    pub fn lsp_position(&self, offset: usize) -> (usize, usize) {
+        let loc = self.srcfile.offset2srcloc(offset);
+        let config = self.srcfile.config();
+        let line_start = self
+            .srcfile
+            .srcloc2offset(&SrcLoc::new((loc.ln, config.first_col())))
+            .unwrap_or(offset);
+
+        let character = self
+            .srcfile
+            .line_text(loc.ln)
+            .unwrap_or_default()
+            .chars()
+            .take(offset - line_start)
+            .map(|c| c.len_utf16())
+            .sum();
+
+        (loc.ln - config.first_line(), character)
+    }
+
+    /// `with_foldable`指定的节点中跨行的部分， 按出现的顺序， 同样的行范围只保留外层的
+    pub fn folding_ranges(&self) -> Vec<FoldingRange> {
+        let mut ranges: Vec<FoldingRange> = vec![];
+
+        for info in self.nodes.iter() {
+            let kind = match self.foldable.get(info.node.as_ref().borrow().sym().name().as_ref()) {
+                Some(kind) => kind,
+                None => continue,
+            };
+            let span = match info.span {
+                Some(span) => span,
+                None => continue,
+            };
+
+            let (start_line, start_character) = self.lsp_position(span.start);
+            let (end_line, end_character) = self.lsp_position(span.end);
+
+            let folded = ranges
+                .iter()
+                .any(|range| range.start_line == start_line && range.end_line == end_line);
+
+            if end_line > start_line && !folded {
+                ranges.push(FoldingRange {
+                    start_line,
+                    start_character,
+                    end_line,
+                    end_character,
+                    kind: kind.clone(),
+                });
+            }
+        }
+
+        ranges
+    }
+
+    /// `with_symbol`指定的节点组成的树， 找不到名字的节点跳过， 它下面的符号归到上一层
+    pub fn document_symbols(&self) -> Vec<DocumentSymbol> {
+        // 倒着按编号自底向上收集， 每个节点取走子节点收集到的符号
+        let mut collected: Vec<Vec<DocumentSymbol>> = vec![vec![]; self.nodes.len()];
+
+        for i in (0..self.nodes.len()).rev() {
+            let children = self.nodes[i]
+                .children
+                .iter()
+                .flat_map(|child| std::mem::take(&mut collected[*child]))
+                .collect::<Vec<DocumentSymbol>>();
+
+            let node = self.nodes[i].node.as_ref().borrow();
+            let symbol = self.nodes[i].span.and_then(|span| {
+                self.symbol_rules.get(node.sym().name().as_ref())?.iter().find_map(|rule| {
+                    let (name, selection_span) = self.symbol_name(&node, &rule.name_path)?;
+
+                    Some(DocumentSymbol {
+                        name,
+                        kind: rule.kind.clone(),
+                        span,
+                        selection_span,
+                        children: vec![],
+                    })
+                })
+            });
+
+            collected[i] = match symbol {
+                Some(symbol) => vec![DocumentSymbol { children, ..symbol }],
+                None => children,
+            };
+        }
+
+        collected.into_iter().next().unwrap_or_default()
+    }
+
+    fn symbol_name(&self, node: &AST, name_path: &[String]) -> Option<(String, Span)> {
+        let (step, rest) = name_path.split_first()?;
+        let (_, child) = node.elems_vec().into_iter().find(|(sym, _)| sym.name() == step.as_str())?;
+
+        match child {
+            ASTNode::Tree(subtree) if !rest.is_empty() => self.symbol_name(&subtree.as_ref().borrow(), rest),
+            ASTNode::Tree(subtree) => {
+                let span = self.span_of(subtree)?;
+
+                Some((self.text_of(span), span))
+            }
+            ASTNode::Leaf(token) if rest.is_empty() => {
+                Some((token.value().to_string(), self.token_span(token)?))
+            }
+            ASTNode::Leaf(_) => None,
+        }
+    }
+
+    /// 区间里的源码
+    pub fn text_of(&self, span: Span) -> String {
+        self.srcfile.get_srcstr().chars().skip(span.start).take(span.len()).collect()
+    }
+
+    #[cfg(feature = "lsp")]
+    fn lsp_range_json(&self, span: Span) -> Value {
+        let (start_line, start_character) = self.lsp_position(span.start);
+        let (end_line, end_character) = self.lsp_position(span.end);
+
+        json!({
+            "start": { "line": start_line, "character": start_character },
+            "end": { "line": end_line, "character": end_character },
+        })
+    }
+
+    /// 偏移处的定义或者引用所指的定义
+    fn def_at_offset(&self, offset: usize) -> Option<&Def> {
+        let (_, token) = self.tokens.token_at_offset(offset)?;
+        let loc = token.loc();
+
+        match self.symbol_table.ref_at(&loc) {
+            Some(reference) => self.symbol_table.def_of(reference),
+            None => self.symbol_table.def_at(&loc),
+        }
+    }
+
+    /// 转到定义： 偏移处的名字的定义的区间， 在定义上时就是它自己
+    pub fn definition_of(&self, offset: usize) -> Option<Span> {
+        self.token_span(&self.def_at_offset(offset)?.token)
+    }
+
+    /// 查找引用： 和偏移处的名字指向同一个定义的全部引用的区间， 不包括定义本身
+    pub fn references_of(&self, offset: usize) -> Vec<Span> {
+        self.def_at_offset(offset)
+            .into_iter()
+            .flat_map(|def| self.symbol_table.refs_of(def))
+            .filter_map(|reference| self.token_span(&reference.token))
+            .collect()
+    }
+}
@@ -0,0 +1,291 @@
+//! Counterexample Shrinking: 让解析失败(或者panic)的输入在保持失败的前提下尽量变小， 得到最小的复现
+//!
+//! ```ignore
+//! let tokens = lexer.tokenize_str(&src)?;
+//! let failure = Failure::Code("unexpected-token".to_string());
+//!
+//! let shrunk = parser.shrink_failure(&tokens, &failure, &ParseOptions::default());
+//! println!("{} ({} tests)", shrunk.source(), shrunk.tests);
+//! ```
+//!
+//! 先按(部分的)语法树整棵删掉子树， 大的先删， 再对剩下的token做delta debugging，
+//! 结果是1-minimal的： 再删掉任何一个token都不再失败。
+//! 判断是否失败的次数有上限(`MAX_TESTS`)， 到了就返回当前的结果。
+//! `Failure::Panic`每次panic都会经过panic hook， 需要安静时先换掉hook
+
+use std::{
+    cell::RefCell,
+    cmp,
+    ops::Range,
+    panic::{self, AssertUnwindSafe},
+    rc::Rc,
+};
+
+use indexmap::IndexMap;
+use itertools::Itertools;
+
+use crate::parser::{ASTNode, LL1Parser, ParseOptions, SrcLoc, Token, AST};
+
+
+/// 判断是否失败的次数上限
+pub const MAX_TESTS: usize = 10_000;
+
+
+/// 要保持的失败
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Failure {
+    /// 有任何语法错误， 空输入的`empty-tokens`除外， 不然总是缩成空的
+    Rejected,
+    /// 有这个代码的错误(见`Diagnostic::code`)
+    Code(String),
+    Panic,
+}
+
+impl Failure {
+    pub fn reproduces(&self, parser: &LL1Parser, tokens: &[Token], options: &ParseOptions) -> bool {
+        let res = panic::catch_unwind(AssertUnwindSafe(|| parser.parse_diag(tokens.to_vec(), options).1));
+
+        match (self, res) {
+            (Self::Panic, res) => res.is_err(),
+            (_, Err(_)) => false,
+            (Self::Rejected, Ok(diags)) => diags.errors().any(|diag| diag.code != "empty-tokens"),
+            (Self::Code(code), Ok(diags)) => diags.errors().any(|diag| diag.code == *code),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Shrunk {
+    pub tokens: Vec<Token>,
+    /// 判断是否失败的次数
+    pub tests: usize,
+}
+
+impl Shrunk {
+    /// token的值用空格连接， 贴在bug报告里
+    pub fn source(&self) -> String {
+        self.tokens.iter().map(|token| token.value()).join(" ")
+    }
+}
+
+
+struct Shrinker<F> {
+    still_fails: F,
+    tests: usize,
+}
+
+impl<F: FnMut(&[Token]) -> bool> Shrinker<F> {
+    fn test(&mut self, tokens: &[Token]) -> bool {
+        self.tests += 1;
+        (self.still_fails)(tokens)
+    }
+
+    fn exhausted(&self) -> bool {
+        self.tests >= MAX_TESTS
+    }
+
+    /// 依次尝试删掉每个区间， 成功就采用
+    fn remove_ranges(&mut self, cur: &mut Vec<Token>, ranges: &[Range<usize>]) -> bool {
+        for range in ranges {
+            if self.exhausted() {
+                break;
+            }
+
+            let candidate = without(cur, range.clone());
+            if self.test(&candidate) {
+                *cur = candidate;
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// ddmin， 只尝试删掉一块(补集)
+    fn ddmin(&mut self, mut cur: Vec<Token>) -> Vec<Token> {
+        let mut n = 2;
+
+        while !cur.is_empty() && !self.exhausted() {
+            let n_chunks = cmp::min(n, cur.len());
+            let chunk = cur.len().div_ceil(n_chunks);
+            let ranges = (0..cur.len())
+                .step_by(chunk)
+                .map(|start| start..cmp::min(start + chunk, cur.len()))
+                .collect_vec();
+
+            if self.remove_ranges(&mut cur, &ranges) {
+                n = cmp::max(n_chunks - 1, 2);
+            }
+            else if n_chunks == cur.len() {
+                break;
+            }
+            else {
+                n = cmp::min(n_chunks * 2, cur.len());
+            }
+        }
+
+        cur
+    }
+}
+
+
+/// 只按token删， `still_fails`对原输入为false时原样返回
+pub fn shrink<F: FnMut(&[Token]) -> bool>(tokens: &[Token], still_fails: F) -> Shrunk {
+    let mut shrinker = Shrinker { still_fails, tests: 0 };
+
+    if !shrinker.test(tokens) {
+        return Shrunk { tokens: tokens.to_vec(), tests: shrinker.tests };
+    }
+
+    let tokens = shrinker.ddmin(tokens.to_vec());
+
+    Shrunk { tokens, tests: shrinker.tests }
+}
+
+
+impl LL1Parser {
+    /// 先删子树再删token， 原输入没有这个失败时原样返回
+    pub fn shrink_failure(&self, tokens: &[Token], failure: &Failure, options: &ParseOptions) -> Shrunk {
+        let mut shrinker = Shrinker {
+            still_fails: |tokens: &[Token]| failure.reproduces(self, tokens, options),
+            tests: 0,
+        };
+
+        if !shrinker.test(tokens) {
+            return Shrunk { tokens: tokens.to_vec(), tests: shrinker.tests };
+        }
+
+        let mut cur = tokens.to_vec();
+        loop {
+            let ranges = self.subtree_ranges(&cur, options);
+
+            if !shrinker.remove_ranges(&mut cur, &ranges) {
+                break;
+            }
+        }
+
+        let tokens = shrinker.ddmin(cur);
+
+        Shrunk { tokens, tests: shrinker.tests }
+    }
+
+    /// 每棵子树盖住的token的下标区间， 长的在前； 解析时panic就没有
+    fn subtree_ranges(&self, tokens: &[Token], options: &ParseOptions) -> Vec<Range<usize>> {
+        let root = match panic::catch_unwind(AssertUnwindSafe(|| self.parse_with(tokens.to_vec(), options).0)) {
+            Ok(root) => root,
+            Err(_) => return vec![],
+        };
+
+        // 位置 => 下标， 合成的token不在输入里
+        let index = tokens
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, token)| (token.loc(), i))
+            .collect::<IndexMap<SrcLoc, usize>>();
+
+        let mut ranges = vec![];
+        let mut stack: Vec<Rc<RefCell<AST>>> = vec![root];
+
+        while let Some(tree) = stack.pop() {
+            let tree = tree.as_ref().borrow();
+
+            for (_, node) in tree.elems_vec() {
+                if let ASTNode::Tree(subtree) = node {
+                    if let Some(range) = leaf_range(&subtree.as_ref().borrow(), &index) {
+                        ranges.push(range);
+                    }
+                    stack.push(subtree.clone());
+                }
+            }
+        }
+
+        ranges.sort_by_key(|range| cmp::Reverse(range.len()));
+        ranges.dedup();
+
+        ranges
+    }
+}
+
+/// 子树里第一个到最后一个来自输入的token
+fn leaf_range(tree: &AST, index: &IndexMap<SrcLoc, usize>) -> Option<Range<usize>> {
+    let mut indices = vec![];
+    let mut stack = tree.elems_vec().into_iter().map(|(_, node)| node.clone()).collect_vec();
+
+    while let Some(node) = stack.pop() {
+        match node {
+            ASTNode::Leaf(token) if !token.is_synthesized() => indices.extend(index.get(&token.loc())),
+            ASTNode::Leaf(_) => (),
+            ASTNode::Tree(subtree) => {
+                stack.extend(subtree.as_ref().borrow().elems_vec().into_iter().map(|(_, node)| node.clone()));
+            }
+        }
+    }
+
+    let (min, max) = indices.into_iter().minmax().into_option()?;
+
+    Some(min..max + 1)
+}
+
+fn without(tokens: &[Token], range: Range<usize>) -> Vec<Token> {
+    tokens[..range.start].iter().chain(tokens[range.end..].iter()).cloned().collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::builder::GramBuilder;
+
+    fn tokens(names: &[&str]) -> Vec<Token> {
+        names.iter().enumerate().map(|(i, name)| Token::new(name, name, SrcLoc::new((1, i + 1)))).collect()
+    }
+
+    /// 再删掉任何一个token都不再失败
+    fn is_one_minimal<F: Fn(&[Token]) -> bool>(tokens: &[Token], still_fails: F) -> bool {
+        still_fails(tokens) && (0..tokens.len()).all(|i| !still_fails(&without(tokens, i..i + 1)))
+    }
+
+    #[test]
+    fn test_shrink_failure() {
+        let gram = GramBuilder::from_dsl("grammar![s| S: | Stmt S; | ε; Stmt: | id eq Expr semi; | error semi; Expr: | id; | lp Expr rp; |]")
+            .unwrap()
+            .build()
+            .unwrap();
+        let parser = LL1Parser::builder(gram).build().unwrap();
+        let options = ParseOptions { error_prods: true, ..Default::default() };
+
+        let mut names = vec![];
+        for _ in 0..10 {
+            names.extend(["id", "eq", "lp", "lp", "id", "rp", "rp", "semi"]);
+        }
+        // 中间藏着一个多出来的`eq`
+        names.splice(44..44, ["id", "eq", "lp", "eq", "id", "rp", "semi"]);
+        let input = tokens(&names);
+
+        let failure = Failure::Code("unmatched-token".to_string());
+        assert!(failure.reproduces(&parser, &input, &options));
+
+        let shrunk = parser.shrink_failure(&input, &failure, &options);
+        assert!(shrunk.tests < MAX_TESTS);
+        assert!(is_one_minimal(&shrunk.tokens, |tokens| failure.reproduces(&parser, tokens, &options)));
+        assert!(shrunk.tokens.len() <= 2, "{}", shrunk.source());
+
+        // 原输入不失败就原样返回
+        let valid = tokens(&["id", "eq", "id", "semi"]);
+        let shrunk = parser.shrink_failure(&valid, &failure, &options);
+        assert_eq!(shrunk.tests, 1);
+        assert_eq!(shrunk.source(), "id eq id semi");
+    }
+
+    #[test]
+    fn test_shrink_by_predicate() {
+        let input = tokens(&["a", "x", "b", "c", "x", "d", "e", "x", "f"]);
+        let two_x = |tokens: &[Token]| tokens.iter().filter(|token| token.name() == "x").count() >= 2;
+
+        let shrunk = shrink(&input, two_x);
+        assert!(is_one_minimal(&shrunk.tokens, two_x));
+        assert_eq!(shrunk.source(), "x x");
+    }
+}
@@ -0,0 +1,107 @@
+//! Parse Profiling: 每个非终结符的展开次数、 直接吃掉的token数和耗时
+
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use indexmap::IndexMap;
+
+use crate::gram::{GramProd, GramSym};
+
+
+#[derive(Debug, Clone, Default)]
+pub struct RuleStats {
+    pub expansions: usize,
+    /// 直接匹配的终结符数， 不包括子规则的
+    pub tokens: usize,
+    /// 包含子规则的耗时， 递归的规则只算最外层的那次
+    pub time: Duration,
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct ParseStats {
+    rules: IndexMap<GramSym, RuleStats>,
+    prods: IndexMap<GramProd, usize>,
+    /// 展开中的节点(以地址为键)， 和它开始的时间
+    opened: IndexMap<usize, (GramSym, Option<Instant>)>,
+    /// 每个规则当前嵌套的层数
+    depth: IndexMap<GramSym, usize>,
+}
+
+impl ParseStats {
+    pub fn rule(&self, sym: &GramSym) -> Option<&RuleStats> {
+        self.rules.get(sym)
+    }
+
+    /// 按耗时从高到低排列
+    pub fn by_rule(&self) -> Vec<(&GramSym, &RuleStats)> {
+        let mut rules = self.rules.iter().collect::<Vec<_>>();
+        rules.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.time));
+
+        rules
+    }
+
+    /// 按展开次数从高到低排列
+    pub fn by_prod(&self) -> Vec<(&GramProd, usize)> {
+        let mut prods = self.prods.iter().map(|(prod, n)| (prod, *n)).collect::<Vec<_>>();
+        prods.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+
+        prods
+    }
+
+    pub(crate) fn open(&mut self, key: usize, prod: &GramProd) {
+        let sym = &prod.lfsym;
+
+        self.rules.entry(sym.clone()).or_default().expansions += 1;
+        *self.prods.entry(prod.clone()).or_default() += 1;
+
+        let depth = self.depth.entry(sym.clone()).or_default();
+        *depth += 1;
+
+        let start = if *depth == 1 { Some(Instant::now()) } else { None };
+        self.opened.insert(key, (sym.clone(), start));
+    }
+
+    pub(crate) fn close(&mut self, key: usize) {
+        if let Some((sym, start)) = self.opened.swap_remove(&key) {
+            *self.depth.get_mut(&sym).unwrap() -= 1;
+
+            if let Some(start) = start {
+                self.rules.get_mut(&sym).unwrap().time += start.elapsed();
+            }
+        }
+    }
+
+    pub(crate) fn eat(&mut self, sym: &GramSym) {
+        self.rules.entry(sym.clone()).or_default().tokens += 1;
+    }
+}
+
+impl fmt::Display for ParseStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self.rules
+            .keys()
+            .map(|sym| sym.to_string().len())
+            .max()
+            .unwrap_or(0)
+            .max("rule".len());
+
+        writeln!(f, "{:<width$}  {:>10}  {:>8}  {:>12}", "rule", "expansions", "tokens", "time", width = width)?;
+
+        for (sym, stats) in self.by_rule() {
+            writeln!(
+                f,
+                "{:<width$}  {:>10}  {:>8}  {:>12}",
+                sym.to_string(),
+                stats.expansions,
+                stats.tokens,
+                format!("{:?}", stats.time),
+                width = width
+            )?;
+        }
+
+        Ok(())
+    }
+}
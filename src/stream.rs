@@ -0,0 +1,37 @@
+//! 异步解析， token从`Stream`中陆续到达(比如网络上的协议帧)
+
+use std::{
+    cell::RefCell,
+    future::poll_fn,
+    pin::Pin,
+    rc::Rc,
+};
+
+use futures_core::Stream;
+
+use crate::{
+    error::ParseError,
+    parser::{LL1Parser, ParseOptions, Token, AST},
+};
+
+
+impl LL1Parser {
+    /// 边等待token边解析， 流结束时返回语法树，
+    /// 遇到无法恢复的错误时不再等待后面的token。
+    ///
+    /// AST用的是`Rc`， 所以返回的future不是`Send`
+    pub async fn parse_stream<S>(&self, mut tokens: S, options: &ParseOptions)
+    -> (Rc<RefCell<AST>>, Vec<ParseError>)
+    where S: Stream<Item = Token> + Unpin
+    {
+        let mut machine = self.machine(options);
+
+        while let Some(token) = poll_fn(|cx| Pin::new(&mut tokens).poll_next(cx)).await {
+            if machine.feed(token).is_err() {
+                break;
+            }
+        }
+
+        machine.finish()
+    }
+}
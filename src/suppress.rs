@@ -0,0 +1,252 @@
+//! Suppression Comments: 注释里的标记让一段区域里的诊断静默， 没有静默任何诊断的标记报`unused-suppression`警告
+//!
+//! ```text
+//! # ll1: ignore-next-line                     下一行的全部诊断
+//! a = = b  # ll1: ignore-line unmatched-token 本行的`unmatched-token`
+//! # ll1: ignore-start unused-key, dup-key     直到`ignore-end`， 没有结束时到文件末尾
+//! # ll1: ignore-end
+//! ```
+//!
+//! ```ignore
+//! let config = SuppressConfig::new(&["comment"]).with_marker("ll1:");
+//! let diags = Suppressions::scan(session.tokens().tokens(), &config).apply(diags);
+//! ```
+//!
+//! 标记后面可以跟用空格或者`,`分隔的诊断代码， 没有时对全部代码生效。
+//! 注释token不能被词法分析器丢弃(`Lexer::with_skip`)， 解析时用`ParseOptions::trivia`跳过。
+//! 按行匹配， 没有位置的诊断不会被静默
+
+use crate::{
+    diagnostic::{Diagnostic, Diagnostics},
+    parser::{SrcLoc, Token},
+};
+
+
+pub const DEFAULT_MARKER: &str = "ll1:";
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuppressConfig {
+    /// 作为注释的token名
+    pub comments: Vec<String>,
+    /// 注释里标记的开头
+    pub marker: String,
+}
+
+impl SuppressConfig {
+    pub fn new(comments: &[&str]) -> Self {
+        Self {
+            comments: comments.iter().map(|name| name.to_string()).collect(),
+            marker: DEFAULT_MARKER.to_string(),
+        }
+    }
+
+    pub fn with_marker(mut self, marker: &str) -> Self {
+        self.marker = marker.to_string();
+        self
+    }
+}
+
+
+#[derive(Debug, Clone)]
+struct Suppression {
+    /// 包含两端的行范围
+    first_ln: usize,
+    last_ln: usize,
+    /// 为空时对全部代码生效
+    codes: Vec<String>,
+    /// 标记所在的注释
+    loc: SrcLoc,
+    text: String,
+}
+
+impl Suppression {
+    fn mutes(&self, diag: &Diagnostic) -> bool {
+        diag.loc.as_ref().is_some_and(|loc| self.first_ln <= loc.ln && loc.ln <= self.last_ln)
+        && (self.codes.is_empty() || self.codes.contains(&diag.code))
+    }
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct Suppressions {
+    suppressions: Vec<Suppression>,
+    /// 标记本身的问题， 比如写错的指令、 没有开始的`ignore-end`
+    problems: Diagnostics,
+}
+
+impl Suppressions {
+    pub fn scan(tokens: &[Token], config: &SuppressConfig) -> Self {
+        let mut scanned = Self::default();
+        // 还没结束的`ignore-start`
+        let mut open: Vec<Suppression> = vec![];
+
+        let comments = tokens.iter().filter(|token| config.comments.iter().any(|name| name == token.name()));
+
+        for token in comments {
+            let rest = match token.value().find(&config.marker) {
+                Some(i) => &token.value()[i + config.marker.len()..],
+                None => continue,
+            };
+
+            let mut words = rest.split(|c: char| c.is_whitespace() || c == ',').filter(|word| !word.is_empty());
+            let directive = words.next().unwrap_or_default();
+            let codes = words.map(|code| code.to_string()).collect::<Vec<String>>();
+
+            let loc = token.loc();
+            // 注释可能跨行
+            let end_ln = loc.ln + token.value().matches('\n').count();
+
+            let (first_ln, last_ln) = match directive {
+                "ignore-line" => (loc.ln, end_ln),
+                "ignore-next-line" => (end_ln + 1, end_ln + 1),
+                "ignore-start" => (loc.ln, usize::MAX),
+                "ignore-end" => {
+                    match open.pop() {
+                        Some(mut suppression) => {
+                            suppression.last_ln = end_ln;
+                            scanned.suppressions.push(suppression);
+                        }
+                        None => scanned.problems.push(
+                            Diagnostic::warning("unmatched-suppression", "`ignore-end` without `ignore-start`")
+                                .with_loc(loc)
+                        ),
+                    }
+                    continue;
+                }
+                _ => {
+                    scanned.problems.push(
+                        Diagnostic::warning("bad-suppression", &format!("unknown suppression `{}`", directive))
+                            .with_loc(loc)
+                            .with_note("help: expected `ignore-line`, `ignore-next-line`, `ignore-start` or `ignore-end`")
+                    );
+                    continue;
+                }
+            };
+
+            let suppression = Suppression {
+                first_ln,
+                last_ln,
+                codes,
+                loc,
+                text: rest.trim().to_string(),
+            };
+
+            if directive == "ignore-start" {
+                open.push(suppression);
+            }
+            else {
+                scanned.suppressions.push(suppression);
+            }
+        }
+
+        // 没有结束的到文件末尾
+        scanned.suppressions.extend(open);
+
+        scanned
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.suppressions.is_empty() && self.problems.is_empty()
+    }
+
+    /// 去掉被静默的诊断， 追加标记本身的问题和没用上的标记
+    pub fn apply(&self, diags: Diagnostics) -> Diagnostics {
+        let mut kept = Diagnostics::new();
+        let mut used = vec![false; self.suppressions.len()];
+
+        for diag in diags {
+            let mut muted = false;
+
+            for (i, suppression) in self.suppressions.iter().enumerate() {
+                if suppression.mutes(&diag) {
+                    used[i] = true;
+                    muted = true;
+                }
+            }
+
+            if !muted {
+                kept.push(diag);
+            }
+        }
+
+        kept.extend(self.problems.iter().cloned());
+
+        for (suppression, _) in self.suppressions.iter().zip(used).filter(|(_, used)| !used) {
+            kept.push(
+                Diagnostic::warning(
+                    "unused-suppression",
+                    &format!("suppression `{}` matches no diagnostic", suppression.text)
+                )
+                .with_loc(suppression.loc.clone())
+            );
+        }
+
+        kept
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{Lexer, RegexTokenMatcher};
+
+    #[test]
+    fn test_suppressions() {
+        let lexer = Lexer::new(vec![
+            (RegexTokenMatcher::new("#[^\n]*"), "comment".to_string()),
+            (RegexTokenMatcher::new("[a-z]+"), "id".to_string()),
+            (RegexTokenMatcher::new("="), "eq".to_string()),
+            (RegexTokenMatcher::new(";"), "semi".to_string()),
+            (RegexTokenMatcher::new(r"\s+"), "ws".to_string()),
+        ])
+        .with_skip("ws");
+        let src = [
+            "# ll1: ignore-next-line",
+            "a = = b;",
+            "c = d;  # ll1: ignore-line unused-key",
+            "# ll1: ignore-start dup-key, unused-key",
+            "e = f;",
+            "# ll1: ignore-end",
+            "g = h;",
+            "# ll1: ignore-all",
+            "# ll1: ignore-end",
+        ]
+        .join("\n");
+        let tokens = lexer.tokenize_str(&src).unwrap();
+        let first_ln = tokens[0].loc().ln;
+        let at = |ln: usize| SrcLoc::new((first_ln + ln - 1, 1));
+
+        let mut diags = Diagnostics::new();
+        diags.push(Diagnostic::error("unexpected-token", "unexpected `=`").with_loc(at(2)));
+        diags.push(Diagnostic::warning("dup-key", "duplicated c").with_loc(at(3)));
+        diags.push(Diagnostic::warning("dup-key", "duplicated e").with_loc(at(5)));
+        diags.push(Diagnostic::warning("dup-key", "duplicated g").with_loc(at(7)));
+        diags.push(Diagnostic::warning("unused-nonterminal", "U is never used"));
+
+        let config = SuppressConfig::new(&["comment"]);
+        let kept = Suppressions::scan(&tokens, &config).apply(diags);
+
+        let kept = kept
+            .iter()
+            .map(|diag| (diag.code.as_str(), diag.loc.as_ref().map(|loc| loc.ln + 1 - first_ln)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kept,
+            [
+                ("dup-key", Some(3)),
+                ("dup-key", Some(7)),
+                ("unused-nonterminal", None),
+                ("bad-suppression", Some(8)),
+                ("unmatched-suppression", Some(9)),
+                // 行内的标记只针对`unused-key`， 没有用上
+                ("unused-suppression", Some(3)),
+            ]
+        );
+
+        // 换了标记就都不认了
+        let config = SuppressConfig::new(&["comment"]).with_marker("lint:");
+        assert!(Suppressions::scan(&tokens, &config).is_empty());
+    }
+}
@@ -0,0 +1,215 @@
+//! Tabular Extraction: 用产生式上的注解标出记录和字段， 把解析结果抽成表格(CSV/TSV)， 不用手写遍历
+//!
+//! - `#[record]`、 `#[record="table"]`: 这个分支推导出的节点是一行， 属于名为`table`的表(默认是空名字的表)
+//! - `#[field="id"]`: 名为`id`的子节点是当前行的一个字段， 列名就是符号名，
+//!   写作`id:name`时列名是`name`， 多个字段用空格隔开或者写多个注解
+//! - `#[field]`: 这个节点自己是外层记录的一个字段， 列名是非终结符名
+//!
+//! 字段的值是节点下所有token的值， 用空格连接。 一行里同一列出现多次时用`;`连接，
+//! 不在任何记录里的字段被忽略。 记录可以嵌套， 内层的记录是单独的一行， 它的字段不属于外层。
+//! 列按第一次出现的顺序排列， 行里没有的列为空
+//!
+//! ```ignore
+//! let gram = GramBuilder::from_dsl(r#"grammar![people|
+//!     People:
+//!         | Person People;
+//!         | ε;
+//!     Person:
+//!         #[record="person"] #[field="name age:years"]
+//!         | name age Email semi;
+//!     Email:
+//!         #[field]
+//!         | user at host;
+//! |]"#)?.build()?;
+//!
+//! let tables = extract_tables(&root.borrow());
+//! print!("{}", tables["person"].to_csv());
+//! // name,years,Email
+//! // alice,30,alice @ example.com
+//! ```
+
+use std::{cell::RefCell, iter, rc::Rc};
+
+use indexmap::{IndexMap, IndexSet};
+use itertools::Itertools;
+
+use crate::parser::{ASTNode, AST};
+
+
+/// 一行里重复的字段之间的分隔符
+const MULTI_SEP: &str = ";";
+
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Table {
+    pub name: String,
+    pub columns: Vec<String>,
+    /// 每行的长度都等于`columns`的长度
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    /// RFC 4180， 包含分隔符、 引号或者换行的值加引号， 第一行是列名
+    pub fn to_csv(&self) -> String {
+        self.write_lines(|value| {
+            if value.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", value.replace('"', "\"\""))
+            }
+            else {
+                value.to_string()
+            }
+        }, ",")
+    }
+
+    /// 值里的`\`、 制表符和换行转义成`\\`、 `\t`、 `\n`、 `\r`， 第一行是列名
+    pub fn to_tsv(&self) -> String {
+        self.write_lines(|value| {
+            value
+                .replace('\\', "\\\\")
+                .replace('\t', "\\t")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r")
+        }, "\t")
+    }
+
+    fn write_lines<F: Fn(&str) -> String>(&self, quote: F, sep: &str) -> String {
+        let mut out = String::new();
+
+        for line in iter::once(&self.columns).chain(self.rows.iter()) {
+            out.push_str(&line.iter().map(|value| quote(value)).join(sep));
+            out.push('\n');
+        }
+
+        out
+    }
+}
+
+
+struct Row {
+    table: String,
+    fields: IndexMap<String, Vec<String>>,
+}
+
+
+/// 表名 => 表， 表按第一行出现的顺序排列
+pub fn extract_tables(root: &AST) -> IndexMap<String, Table> {
+    let mut rows: Vec<Row> = vec![];
+
+    // (节点， 所属的行)
+    let mut stack: Vec<(Rc<RefCell<AST>>, Option<usize>)> = vec![];
+
+    let row = visit(root, None, &mut rows);
+    push_subtrees(&mut stack, root, row);
+
+    while let Some((node, outer)) = stack.pop() {
+        let tree = node.as_ref().borrow();
+        let row = visit(&tree, outer, &mut rows);
+
+        push_subtrees(&mut stack, &tree, row);
+    }
+
+    let mut tables: IndexMap<String, (IndexSet<String>, Vec<Row>)> = IndexMap::new();
+    for row in rows {
+        let (columns, table_rows) = tables.entry(row.table.clone()).or_default();
+
+        columns.extend(row.fields.keys().cloned());
+        table_rows.push(row);
+    }
+
+    tables
+        .into_iter()
+        .map(|(name, (columns, rows))| {
+            let rows = rows
+                .into_iter()
+                .map(|row| {
+                    columns
+                        .iter()
+                        .map(|column| row.fields.get(column).map(|values| values.join(MULTI_SEP)).unwrap_or_default())
+                        .collect_vec()
+                })
+                .collect_vec();
+
+            let table = Table {
+                name: name.clone(),
+                columns: columns.into_iter().collect(),
+                rows,
+            };
+
+            (name, table)
+        })
+        .collect()
+}
+
+/// 处理节点上的注解， 返回它的子树所属的行
+fn visit(tree: &AST, outer: Option<usize>, rows: &mut Vec<Row>) -> Option<usize> {
+    let row = match tree.attr("record") {
+        Some(table) => {
+            rows.push(Row { table: table.to_string(), fields: IndexMap::new() });
+            Some(rows.len() - 1)
+        }
+        None => outer,
+    };
+
+    // 多个注解用换行连接
+    let specs = tree.attr("field").map_or(vec![], |specs| specs.split('\n').collect_vec());
+
+    for spec in specs {
+        if spec.is_empty() {
+            if let Some(outer) = outer {
+                add_field(&mut rows[outer], &tree.sym().name(), tree_text(tree));
+            }
+            continue;
+        }
+
+        if let Some(row) = row {
+            for field in spec.split_whitespace() {
+                let (sym, column) = field.split_once(':').unwrap_or((field, field));
+
+                for (elem_sym, node) in tree.elems_vec() {
+                    if elem_sym.name() == sym {
+                        add_field(&mut rows[row], column, node_text(node));
+                    }
+                }
+            }
+        }
+    }
+
+    row
+}
+
+/// 先序， 子树倒着压栈
+fn push_subtrees(stack: &mut Vec<(Rc<RefCell<AST>>, Option<usize>)>, tree: &AST, row: Option<usize>) {
+    for (_, node) in tree.elems_vec().into_iter().rev() {
+        if let ASTNode::Tree(subtree) = node {
+            stack.push((subtree.clone(), row));
+        }
+    }
+}
+
+fn add_field(row: &mut Row, column: &str, value: String) {
+    row.fields.entry(column.to_string()).or_default().push(value);
+}
+
+fn node_text(node: &ASTNode) -> String {
+    match node {
+        ASTNode::Leaf(token) => token.value().to_string(),
+        ASTNode::Tree(tree) => tree_text(&tree.as_ref().borrow()),
+    }
+}
+
+/// 子树下所有token的值， 不递归
+fn tree_text(tree: &AST) -> String {
+    let mut values = vec![];
+    let mut stack = tree.elems_vec().into_iter().rev().map(|(_, node)| node.clone()).collect_vec();
+
+    while let Some(node) = stack.pop() {
+        match node {
+            ASTNode::Leaf(token) => values.push(token.value().to_string()),
+            ASTNode::Tree(subtree) => {
+                stack.extend(subtree.as_ref().borrow().elems_vec().into_iter().rev().map(|(_, node)| node.clone()));
+            }
+        }
+    }
+
+    values.join(" ")
+}
@@ -0,0 +1,211 @@
+//! Token Stream: 带位置索引的token序列， 按源码偏移二分查找token， 给悬停提示、 扩展选区之类的编辑器功能用
+//!
+//! 偏移和`SrcFileInfo::offset2srcloc`一样按char计算
+//!
+//! ```ignore
+//! let stream = lexer.tokenize_stream(&srcfile)?;
+//! let hovered = stream.token_at_offset(42);
+//! let (root, errors) = parser.parse_with(stream.into(), &ParseOptions::default());
+//! ```
+
+use std::{fmt, ops::Range};
+
+use crate::{
+    error::LlResult,
+    lexer::Lexer,
+    parser::{SrcFileInfo, Token},
+};
+
+
+/// 源码里的区间`[start, end)`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset < self.end
+    }
+
+    pub fn overlaps(&self, other: &Self) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+
+    /// 同时盖住两者的最小区间
+    pub fn cover(&self, other: &Self) -> Self {
+        Self::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct TokenStream {
+    tokens: Vec<Token>,
+    /// 和tokens一一对应， 按位置排好序
+    spans: Vec<Span>,
+}
+
+impl TokenStream {
+    /// token需要按源码的顺序， 彼此不重叠
+    pub fn new(tokens: Vec<(Token, Span)>) -> Self {
+        let (tokens, spans) = tokens.into_iter().unzip();
+
+        Self { tokens, spans }
+    }
+
+    /// 按token的位置和值在`srcfile`里算出区间， 位置不在源码里的token(比如合成的)区间为空
+    pub fn from_tokens(tokens: Vec<Token>, srcfile: &SrcFileInfo) -> Self {
+        let mut last_end = 0;
+
+        let tokens = tokens
+            .into_iter()
+            .map(|token| {
+                let span = match srcfile.srcloc2offset(&token.loc()) {
+                    Some(start) => Span::new(start, start + token.value().chars().count()),
+                    None => Span::new(last_end, last_end),
+                };
+                last_end = span.end;
+
+                (token, span)
+            })
+            .collect();
+
+        Self::new(tokens)
+    }
+
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    pub fn get(&self, i: usize) -> Option<(&Token, Span)> {
+        Some((self.tokens.get(i)?, self.spans[i]))
+    }
+
+    /// 盖住`offset`的token和它的下标， 落在token之间的空白里时为None
+    pub fn token_at_offset(&self, offset: usize) -> Option<(usize, &Token)> {
+        let i = self.spans.partition_point(|span| span.end <= offset);
+
+        self.spans
+            .get(i)
+            .filter(|span| span.contains(offset))
+            .map(|_| (i, &self.tokens[i]))
+    }
+
+    /// 和`span`重叠的token的下标范围
+    pub fn range_in_span(&self, span: Span) -> Range<usize> {
+        let start = self.spans.partition_point(|each| each.end <= span.start);
+        let end = self.spans.partition_point(|each| each.start < span.end);
+
+        start..end.max(start)
+    }
+
+    pub fn tokens_in_span(&self, span: Span) -> &[Token] {
+        &self.tokens[self.range_in_span(span)]
+    }
+
+    /// 下标范围内的token盖住的区间
+    pub fn span_of(&self, range: Range<usize>) -> Option<Span> {
+        let first = self.spans.get(range.start)?;
+        let last = self.spans.get(range.end.checked_sub(1)?)?;
+
+        Some(first.cover(last))
+    }
+
+    pub fn cursor(&self) -> TokenCursor<'_> {
+        TokenCursor { stream: self, pos: 0 }
+    }
+
+    pub fn into_tokens(self) -> Vec<Token> {
+        self.tokens
+    }
+}
+
+impl From<TokenStream> for Vec<Token> {
+    fn from(stream: TokenStream) -> Self {
+        stream.into_tokens()
+    }
+}
+
+
+/// 可以向前看任意多个token的迭代器
+#[derive(Debug, Clone)]
+pub struct TokenCursor<'a> {
+    stream: &'a TokenStream,
+    pos: usize,
+}
+
+impl<'a> TokenCursor<'a> {
+    /// 下一个要返回的token的下标
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn peek(&self) -> Option<&'a Token> {
+        self.peek_nth(0)
+    }
+
+    pub fn peek_nth(&self, n: usize) -> Option<&'a Token> {
+        self.stream.tokens.get(self.pos + n)
+    }
+
+    /// 下一个token的区间
+    pub fn peek_span(&self) -> Option<Span> {
+        self.stream.spans.get(self.pos).copied()
+    }
+
+    /// 跳到下标`pos`处
+    pub fn seek(&mut self, pos: usize) {
+        self.pos = pos.min(self.stream.len());
+    }
+}
+
+impl<'a> Iterator for TokenCursor<'a> {
+    type Item = &'a Token;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.peek()?;
+        self.pos += 1;
+
+        Some(token)
+    }
+}
+
+
+impl Lexer {
+    /// 同`tokenize`， 结果带着每个token的区间
+    pub fn tokenize_stream(&self, srcfile: &SrcFileInfo) -> LlResult<TokenStream> {
+        Ok(TokenStream::from_tokens(self.tokenize(srcfile)?, srcfile))
+    }
+}
@@ -0,0 +1,158 @@
+//! JSON Lines Trace: 把解析的每一步(预测、 匹配、 出错)写成一行JSON，
+//! 外部脚本可以据此统计决策、 画语法热力图或者做可视化， 不用链接Rust代码
+//!
+//! ```none
+//! {"step":"predict","pos":0,"rule":"Stmt","rhs":["id","eq","Expr","semi"],"lookahead":"id"}
+//! {"step":"match","pos":0,"rule":"Stmt","token":"id","value":"a","ln":1,"col":1}
+//! {"step":"error","pos":2,"kind":"UnexpectedToken","msg":"...","expected":["id","intlit"],"ln":1,"col":5}
+//! ```
+//!
+//! `pos`是当前token的下标， 输入耗尽时`lookahead`为`$`
+
+use std::{cell::RefCell, fmt::Write as _, io, rc::Rc};
+
+use itertools::Itertools;
+
+use crate::{
+    error::ParseError,
+    gram::{GramProd, GramSym, GramSymStr},
+    parser::{LL1Parser, ParseOptions, SrcLoc, Token, AST},
+};
+
+
+/// 解析的一步
+#[derive(Debug, Clone)]
+pub enum TraceStep {
+    /// 按向前看符号为`prod.lfsym`选择了`prod`
+    Predict {
+        pos: usize,
+        prod: GramProd,
+        lookahead: Option<Token>,
+    },
+    /// 在`rule`里匹配了一个token
+    Match {
+        pos: usize,
+        rule: GramSym,
+        token: Token,
+    },
+    Error {
+        pos: usize,
+        err: ParseError,
+    },
+}
+
+impl TraceStep {
+    /// 不带换行的一行JSON
+    pub fn to_json_line(&self) -> String {
+        let mut line = String::new();
+
+        match self {
+            Self::Predict { pos, prod, lookahead } => {
+                let rhs = match &prod.rhstr {
+                    GramSymStr::Str(normal_str) => normal_str.iter().map(|sym| json_str(&sym.name())).join(","),
+                    GramSymStr::Epsilon => String::new(),
+                };
+                let lookahead = lookahead.as_ref().map_or("$", |token| token.name());
+
+                write!(
+                    line,
+                    r#"{{"step":"predict","pos":{},"rule":{},"rhs":[{}],"lookahead":{}}}"#,
+                    pos,
+                    json_str(&prod.lfsym.name()),
+                    rhs,
+                    json_str(lookahead)
+                )
+                .unwrap();
+            }
+            Self::Match { pos, rule, token } => {
+                write!(
+                    line,
+                    r#"{{"step":"match","pos":{},"rule":{},"token":{},"value":{},{}}}"#,
+                    pos,
+                    json_str(&rule.name()),
+                    json_str(token.name()),
+                    json_str(token.value()),
+                    json_loc(&token.loc())
+                )
+                .unwrap();
+            }
+            Self::Error { pos, err } => {
+                write!(
+                    line,
+                    r#"{{"step":"error","pos":{},"kind":{},"msg":{},"expected":[{}]"#,
+                    pos,
+                    json_str(&format!("{:?}", err.kind())),
+                    json_str(err.msg()),
+                    err.expected().iter().map(|la| json_str(&la.to_string())).join(",")
+                )
+                .unwrap();
+
+                if let Some(loc) = err.loc() {
+                    write!(line, ",{}", json_loc(&loc)).unwrap();
+                }
+                line.push('}');
+            }
+        }
+
+        line
+    }
+}
+
+
+impl LL1Parser {
+    /// 同`parse_with`， 同时把每一步作为一行JSON写到`writer`，
+    /// 写出错时不再继续写， 解析完成后返回这个错误
+    pub fn parse_trace<W: io::Write>(&self, tokens: Vec<Token>, options: &ParseOptions, mut writer: W)
+    -> io::Result<(Rc<RefCell<AST>>, Vec<ParseError>)>
+    {
+        let mut write_err = None;
+
+        let (root, errors) = {
+            let mut machine = self.machine(options).with_trace(|step: TraceStep| {
+                if write_err.is_none() {
+                    if let Err(err) = writeln!(writer, "{}", step.to_json_line()) {
+                        write_err = Some(err);
+                    }
+                }
+            });
+
+            for token in tokens {
+                if machine.feed(token).is_err() {
+                    break;
+                }
+            }
+
+            machine.finish()
+        };
+
+        match write_err {
+            Some(err) => Err(err),
+            None => writer.flush().map(|_| (root, errors)),
+        }
+    }
+}
+
+
+fn json_loc(loc: &SrcLoc) -> String {
+    format!(r#""ln":{},"col":{}"#, loc.ln, loc.col)
+}
+
+pub(crate) fn json_str(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
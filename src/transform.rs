@@ -0,0 +1,476 @@
+//! Grammar Transforms: 生成等价的新语法(消除ε产生式、 范式转换)， 原语法不变
+//!
+//! 改写出来的产生式带有`origin`注解， 记录它来自原语法的哪个产生式或者符号，
+//! 生成的符号的产生式带有`synthetic`注解， 见`provenance`
+
+use std::error::Error;
+use std::fmt;
+
+use indexmap::{IndexMap, IndexSet};
+use itertools::Itertools;
+
+use crate::error::Trap;
+use crate::gram::{FstSetSym, Gram, GramProd, GramSym, GramSymStr};
+use crate::provenance::{SymOrigin, SYNTHETIC_ATTR};
+
+
+/// 记录来源的产生式注解
+pub const ORIGIN_ATTR: &str = "origin";
+
+/// 一个产生式里可省略的符号太多时， 展开的分支数会指数增长
+const MAX_NULLABLE_OCCURRENCES: usize = 12;
+
+
+#[derive(Debug, Clone, Default)]
+pub struct EpsilonReport {
+    /// 能推出ε的非终结符
+    pub nullable: Vec<GramSym>,
+    /// 可空的A， 以及FIRST(A)与FOLLOW(A)重叠的终结符， 这正是LL(1)冲突的来源
+    pub interactions: Vec<(GramSym, Vec<String>)>,
+}
+
+impl fmt::Display for EpsilonReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "nullable: {}", self.nullable.iter().join(" "))?;
+
+        for (sym, overlap) in self.interactions.iter() {
+            writeln!(f, "  {}: FIRST and FOLLOW share {}", sym, overlap.join(" "))?;
+        }
+
+        Ok(())
+    }
+}
+
+
+impl Gram {
+    /// 能推出ε的非终结符
+    pub fn nullable_syms(&self) -> IndexSet<GramSym> {
+        let mut nullable = IndexSet::new();
+
+        loop {
+            let more = self
+                .productions()
+                .filter(|prod| !nullable.contains(&prod.lfsym))
+                .filter(|prod| match &prod.rhstr {
+                    GramSymStr::Epsilon => true,
+                    GramSymStr::Str(normal_str) => normal_str.iter().all(|sym| nullable.contains(sym)),
+                })
+                .map(|prod| prod.lfsym.clone())
+                .collect_vec();
+
+            if more.is_empty() {
+                break nullable;
+            }
+            nullable.extend(more);
+        }
+    }
+
+    /// 可空的非终结符和它们引起的FIRST/FOLLOW重叠
+    pub fn epsilon_report(&self) -> EpsilonReport {
+        let nullable = self.nullable_syms();
+        let fstsets = self.first_sets();
+        let follsets = self.follow_sets(&fstsets);
+
+        let interactions = nullable
+            .iter()
+            .filter_map(|sym| {
+                let follset = follsets.get(sym)?;
+                let overlap = fstsets
+                    .get(sym)?
+                    .iter()
+                    .filter_map(|fstsym| match fstsym {
+                        FstSetSym::Sym(term) => Some(term),
+                        FstSetSym::Epsilon => None,
+                    })
+                    .filter(|term| follset.contains(&term.to_foll_set_sym()))
+                    .map(|term| term.name().to_string())
+                    .collect_vec();
+
+                if overlap.is_empty() {
+                    None
+                } else {
+                    Some((sym.clone(), overlap))
+                }
+            })
+            .collect();
+
+        EpsilonReport {
+            nullable: nullable.into_iter().collect(),
+            interactions,
+        }
+    }
+
+    /// 标准的ε消除: 每个产生式展开成省略或保留各个可空符号的所有组合， 然后去掉ε产生式；
+    /// 开始符号可空时加一个新的开始符号`S' -> S | ε`以保留空串
+    ///
+    /// 某个产生式里可空符号的出现次数太多时报错
+    pub fn remove_epsilon(&self) -> Result<Gram, Box<dyn Error>> {
+        let nullable = self.nullable_syms();
+        let mut gram = Gram::new(self.name());
+
+        if let Some(start_sym) = self.start_sym().filter(|sym| nullable.contains(*sym)) {
+            let new_start = FreshNames::new(self).sym(&start_sym.name());
+
+            for rhstr in [GramSymStr::Str(vec![start_sym.clone()]), GramSymStr::Epsilon].iter() {
+                let mut prod = GramProd::new(new_start.clone(), rhstr.clone());
+                prod.attrs.insert(ORIGIN_ATTR.to_string(), start_sym.name().to_string());
+                gram.insert_prod(mark(prod, SymOrigin::Alias(start_sym.clone())));
+            }
+        }
+
+        for prod in self.productions() {
+            let normal_str = match prod.rhstr.get_normal() {
+                Some(normal_str) => normal_str,
+                None => continue,
+            };
+
+            let occurrences = normal_str.iter().filter(|sym| nullable.contains(*sym)).count();
+            if occurrences > MAX_NULLABLE_OCCURRENCES {
+                return Err(Trap::new_box_err(&format!(
+                    "too many nullable symbols in {} to remove epsilon",
+                    prod
+                )));
+            }
+
+            let mut variants = vec![vec![]];
+            for sym in normal_str.iter() {
+                let mut next = variants
+                    .iter()
+                    .map(|variant| [variant.clone(), vec![sym.clone()]].concat())
+                    .collect_vec();
+
+                if nullable.contains(sym) {
+                    next.extend(variants);
+                }
+                variants = next;
+            }
+
+            for variant in variants.into_iter().filter(|variant| !variant.is_empty()) {
+                gram.insert_prod(derived(&prod.lfsym, variant, prod));
+            }
+        }
+
+        for (sym, doc) in self.docs().iter() {
+            gram.add_doc(sym, doc);
+        }
+
+        Ok(gram)
+    }
+
+    /// 乔姆斯基范式: 产生式都是`A -> B C`或者`A -> a`， 只有不出现在右边的开始符号可以推出ε。
+    /// 依次消除ε产生式、 单产生式， 把长产生式里的终结符换成`T_a -> a`， 再把长产生式拆成两两一组，
+    /// 最后去掉不可达的规则。 错误产生式只用于恢复， 不参与转换
+    pub fn to_cnf(&self) -> Result<Gram, Box<dyn Error>> {
+        let base = self.without_error_prods().remove_epsilon()?.without_unit_prods();
+        let mut fresh = FreshNames::new(&base);
+        let mut gram = Gram::new(self.name());
+        let mut term_syms: IndexMap<GramSym, GramSym> = IndexMap::new();
+
+        for prod in base.productions() {
+            let normal_str = match prod.rhstr.get_normal() {
+                Some(normal_str) if normal_str.len() > 1 => normal_str,
+                _ => {
+                    gram.insert_prod(prod.clone());
+                    continue;
+                }
+            };
+
+            let syms = normal_str
+                .iter()
+                .map(|sym| {
+                    if sym.is_nonterminal() {
+                        return sym.clone();
+                    }
+
+                    term_syms
+                        .entry(sym.clone())
+                        .or_insert_with(|| fresh.sym(&format!("T_{}", sym.token_name())))
+                        .clone()
+                })
+                .collect_vec();
+
+            // A -> X1 X2 ... Xn  =>  A -> X1 A_0, A_0 -> X2 A_1, ...
+            let mut lfsym = prod.lfsym.clone();
+            let mut rest = &syms[..];
+            while rest.len() > 2 {
+                let next = fresh.sym(&prod.lfsym.name());
+                gram.insert_prod(part(derived(&lfsym, vec![rest[0].clone(), next.clone()], prod), prod));
+
+                lfsym = next;
+                rest = &rest[1..];
+            }
+            gram.insert_prod(part(derived(&lfsym, rest.to_vec(), prod), prod));
+        }
+
+        for (term, term_sym) in term_syms {
+            let mut prod = GramProd::new(term_sym, GramSymStr::Str(vec![term.clone()]));
+            prod.attrs.insert(ORIGIN_ATTR.to_string(), term.name().to_string());
+            gram.insert_prod(mark(prod, SymOrigin::Alias(term)));
+        }
+
+        for (sym, doc) in self.docs().iter() {
+            gram.add_doc(sym, doc);
+        }
+
+        Ok(gram.without_unreachable())
+    }
+
+    /// 格雷巴赫范式: 产生式都是`A -> a B1 ... Bn`， 只有开始符号可以推出ε。
+    /// 在乔姆斯基范式的基础上， 按规则的顺序消除左递归(引入`A_i -> α | α A_i`)，
+    /// 然后反复把开头的非终结符代换成它的各个分支
+    pub fn to_gnf(&self) -> Result<Gram, Box<dyn Error>> {
+        let cnf = self.to_cnf()?;
+        let mut fresh = FreshNames::new(&cnf);
+        let order = cnf.nonterminals().cloned().collect_vec();
+        let mut rules: IndexMap<GramSym, Vec<GramProd>> = order
+            .iter()
+            .map(|sym| (sym.clone(), cnf.alts_of(sym).cloned().collect()))
+            .collect();
+
+        for (i, sym) in order.iter().enumerate() {
+            // 开头是排在前面的非终结符的， 代换掉
+            loop {
+                let prods = rules[sym].clone();
+                let mut changed = false;
+                let mut new_prods = vec![];
+
+                for prod in prods.iter() {
+                    match leading_nonterminal(prod) {
+                        Some(first) if order[..i].contains(first) => {
+                            for sub in rules[first].iter() {
+                                new_prods.push(substitute(sym, prod, sub));
+                            }
+                            changed = true;
+                        },
+                        _ => new_prods.push(prod.clone()),
+                    }
+                }
+
+                rules.insert(sym.clone(), new_prods.into_iter().unique().collect());
+                if !changed {
+                    break;
+                }
+            }
+
+            // 直接左递归: A -> A α | β  =>  A -> β | β Z, Z -> α | α Z
+            let (recursive, others): (Vec<GramProd>, Vec<GramProd>) = rules[sym]
+                .iter()
+                .cloned()
+                .partition(|prod| leading_nonterminal(prod) == Some(sym));
+
+            if recursive.is_empty() {
+                continue;
+            }
+
+            let tail = fresh.sym(&sym.name());
+            let mut prods = vec![];
+            let mut tail_prods = vec![];
+
+            for prod in others.iter() {
+                let beta = prod.rhstr.get_normal().cloned().unwrap_or_default();
+
+                prods.push(prod.clone());
+                prods.push(derived(sym, [beta, vec![tail.clone()]].concat(), prod));
+            }
+            for prod in recursive.iter() {
+                let alpha = prod.rhstr.get_normal().unwrap()[1..].to_vec();
+                if alpha.is_empty() {
+                    continue;
+                }
+
+                let origin = SymOrigin::Tail(sym.clone());
+                tail_prods.push(mark(derived(&tail, alpha.clone(), prod), origin.clone()));
+                tail_prods.push(mark(derived(&tail, [alpha, vec![tail.clone()]].concat(), prod), origin));
+            }
+
+            rules.insert(sym.clone(), prods);
+            rules.insert(tail, tail_prods);
+        }
+
+        // 已经没有左递归了， 反复代换一定能结束
+        let limit = 4 * rules.len() + 4;
+        for round in 0.. {
+            let pending = rules
+                .values()
+                .flatten()
+                .any(|prod| leading_nonterminal(prod).is_some());
+
+            if !pending {
+                break;
+            }
+            if round >= limit {
+                return Err(Trap::new_box_err("failed to convert into Greibach normal form"));
+            }
+
+            let snapshot = rules.clone();
+            for (sym, prods) in rules.iter_mut() {
+                *prods = prods
+                    .iter()
+                    .flat_map(|prod| match leading_nonterminal(prod) {
+                        Some(first) => snapshot[first].iter().map(|sub| substitute(sym, prod, sub)).collect_vec(),
+                        None => vec![prod.clone()],
+                    })
+                    .unique()
+                    .collect();
+            }
+        }
+
+        let mut gram = Gram::new(self.name());
+        for prod in rules.into_iter().flat_map(|(_, prods)| prods) {
+            gram.insert_prod(prod);
+        }
+        for (sym, doc) in self.docs().iter() {
+            gram.add_doc(sym, doc);
+        }
+
+        Ok(gram.without_unreachable())
+    }
+
+    fn without_error_prods(&self) -> Gram {
+        let mut gram = Gram::new(self.name());
+
+        for prod in self.productions().filter(|prod| !prod.is_error_prod()) {
+            gram.insert_prod(prod.clone());
+        }
+        for (sym, doc) in self.docs().iter() {
+            gram.add_doc(sym, doc);
+        }
+
+        gram
+    }
+
+    /// 把`A -> B`换成B的(非单)产生式
+    fn without_unit_prods(&self) -> Gram {
+        let mut gram = Gram::new(self.name());
+
+        for sym in self.nonterminals() {
+            let mut units = IndexSet::new();
+            units.insert(sym.clone());
+
+            let mut i = 0;
+            while i < units.len() {
+                let cur = units[i].clone();
+
+                for prod in self.alts_of(&cur) {
+                    if let Some(unit) = unit_target(prod) {
+                        units.insert(unit.clone());
+                    }
+                }
+                i += 1;
+            }
+
+            for unit in units.iter() {
+                for prod in self.alts_of(unit).filter(|prod| unit_target(prod).is_none()) {
+                    gram.insert_prod(derived(sym, prod.rhstr.get_normal().cloned().unwrap_or_default(), prod));
+                }
+            }
+        }
+
+        for (sym, doc) in self.docs().iter() {
+            gram.add_doc(sym, doc);
+        }
+
+        gram
+    }
+
+    fn without_unreachable(&self) -> Gram {
+        let start_sym = match self.start_sym() {
+            Some(start_sym) => start_sym,
+            None => return self.clone(),
+        };
+
+        let mut reachable = self.reachable_from(start_sym);
+        reachable.insert(start_sym.clone());
+
+        let mut gram = Gram::new(self.name());
+        for prod in self.productions().filter(|prod| reachable.contains(&prod.lfsym)) {
+            gram.insert_prod(prod.clone());
+        }
+        for (sym, doc) in self.docs().iter().filter(|(sym, _)| reachable.contains(*sym)) {
+            gram.add_doc(sym, doc);
+        }
+
+        gram
+    }
+}
+
+
+/// 分配不重名的新非终结符`base_0`, `base_1`, ...
+struct FreshNames {
+    used: IndexSet<String>,
+}
+
+impl FreshNames {
+    fn new(gram: &Gram) -> Self {
+        Self {
+            used: gram.symbols().map(|sym| sym.name().to_string()).collect(),
+        }
+    }
+
+    fn sym(&mut self, base: &str) -> GramSym {
+        let name = (0..)
+            .map(|i| format!("{}_{}", base, i))
+            .find(|name| !self.used.contains(name))
+            .unwrap();
+
+        self.used.insert(name.clone());
+
+        GramSym::NonTerminal(name)
+    }
+}
+
+/// 改写自`from`的产生式， 保留注解， 并记下最初的来源
+fn derived(lfsym: &GramSym, rhs: Vec<GramSym>, from: &GramProd) -> GramProd {
+    let rhstr = if rhs.is_empty() {
+        GramSymStr::Epsilon
+    } else {
+        GramSymStr::Str(rhs)
+    };
+
+    let mut prod = GramProd::new(lfsym.clone(), rhstr);
+    prod.attrs = from.attrs.clone();
+
+    // 生成的符号是什么只对它自己的产生式有意义
+    if lfsym != &from.lfsym {
+        prod.attrs.shift_remove(SYNTHETIC_ATTR);
+    }
+
+    if prod != *from && !prod.has_attr(ORIGIN_ATTR) {
+        prod.attrs.insert(ORIGIN_ATTR.to_string(), from.to_string());
+    }
+
+    prod
+}
+
+fn mark(mut prod: GramProd, origin: SymOrigin) -> GramProd {
+    prod.attrs.insert(SYNTHETIC_ATTR.to_string(), origin.to_string());
+    prod
+}
+
+/// 拆分`from`得到的新符号的产生式
+fn part(prod: GramProd, from: &GramProd) -> GramProd {
+    if prod.lfsym == from.lfsym {
+        prod
+    } else {
+        mark(prod, SymOrigin::Part(from.lfsym.clone()))
+    }
+}
+
+/// `A -> B γ`中的B， 保留`A -> B γ`的注解， 用`B -> δ`代换得到`A -> δ γ`
+fn substitute(lfsym: &GramSym, prod: &GramProd, sub: &GramProd) -> GramProd {
+    let rest = &prod.rhstr.get_normal().unwrap()[1..];
+    let delta = sub.rhstr.get_normal().cloned().unwrap_or_default();
+
+    derived(lfsym, [delta, rest.to_vec()].concat(), prod)
+}
+
+fn leading_nonterminal(prod: &GramProd) -> Option<&GramSym> {
+    prod.rhstr.get_normal()?.first().filter(|sym| sym.is_nonterminal())
+}
+
+fn unit_target(prod: &GramProd) -> Option<&GramSym> {
+    match prod.rhstr.get_normal() {
+        Some(normal_str) if normal_str.len() == 1 && normal_str[0].is_nonterminal() => Some(&normal_str[0]),
+        _ => None,
+    }
+}
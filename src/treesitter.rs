@@ -0,0 +1,158 @@
+//! Tree-sitter Export: 把AST转成tree-sitter的S表达式， 从语法生成`node-types.json`，
+//! 给已经围绕tree-sitter的树构建的编辑器和工具用
+//!
+//! 非终结符和终结符都是具名节点， 标点之类的终结符可以声明为匿名的， 匿名节点不出现在S表达式里。
+//! 错误恢复插入的`error`节点写作`(ERROR)`， 补全的token写作`(MISSING name)`
+//!
+//! ```ignore
+//! let export = TreeSitterExport::new().with_anonymous(&["eq", "semi", "lparen", "rparen"]);
+//!
+//! println!("{}", export.sexp(&root.borrow()));
+//! // (Prog (Stmts (Stmt (id) (Expr (intlit)))))
+//! fs::write("node-types.json", export.node_types(&gram))?;
+//! ```
+
+use std::borrow::Cow;
+
+use indexmap::IndexSet;
+use itertools::Itertools;
+
+use crate::{
+    gram::{FstSetSym, Gram, GramSym, GramSymStr},
+    parser::{ASTNode, AST},
+    trace::json_str,
+};
+
+
+#[derive(Debug, Clone, Default)]
+pub struct TreeSitterExport {
+    /// 匿名的终结符(tree-sitter里的字符串字面量)
+    pub anonymous: IndexSet<String>,
+}
+
+impl TreeSitterExport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_anonymous(mut self, names: &[&str]) -> Self {
+        self.anonymous.extend(names.iter().map(|name| name.to_string()));
+        self
+    }
+
+    /// 同tree-sitter的`Node::to_sexp`， 只有具名节点
+    pub fn sexp(&self, root: &AST) -> String {
+        enum Item {
+            Node(ASTNode),
+            Close,
+        }
+
+        let mut out = format!("({}", root.sym().name());
+        let mut stack = vec![Item::Close];
+        stack.extend(root.elems_vec().into_iter().rev().map(|(_, node)| Item::Node(node.clone())));
+
+        while let Some(item) = stack.pop() {
+            match item {
+                Item::Node(ASTNode::Leaf(token)) => {
+                    if token.to_gram_sym().is_error() {
+                        out.push_str(" (ERROR)");
+                    }
+                    else if !self.anonymous.contains(token.name()) {
+                        if token.is_synthesized() {
+                            out.push_str(&format!(" (MISSING {})", token.name()));
+                        }
+                        else {
+                            out.push_str(&format!(" ({})", token.name()));
+                        }
+                    }
+                }
+                Item::Node(ASTNode::Tree(tree)) => {
+                    let tree = tree.as_ref().borrow();
+                    out.push_str(&format!(" ({}", tree.sym().name()));
+
+                    stack.push(Item::Close);
+                    stack.extend(tree.elems_vec().into_iter().rev().map(|(_, node)| Item::Node(node.clone())));
+                }
+                Item::Close => out.push(')'),
+            }
+        }
+
+        out
+    }
+
+    /// `node-types.json`的内容， 非终结符列出可能的子节点类型，
+    /// 能推导出ε的子节点不在树里， 所以不算作必有的子节点
+    pub fn node_types(&self, gram: &Gram) -> String {
+        let fstsets = gram.first_sets();
+        let nullable = |sym: &GramSym| {
+            fstsets.get(sym).is_some_and(|set| set.contains(&FstSetSym::Epsilon))
+        };
+
+        let mut entries = vec![];
+
+        for (lfsym, prods) in gram.derivation_tree() {
+            let mut types: IndexSet<Cow<str>> = IndexSet::new();
+            let mut multiple = false;
+            let mut required = true;
+
+            for prod in prods.iter() {
+                let children = match &prod.rhstr {
+                    GramSymStr::Str(normal_str) => normal_str
+                        .iter()
+                        .filter(|sym| self.is_node(sym) && !self.anonymous.contains(sym.token_name().as_ref()))
+                        .collect_vec(),
+                    GramSymStr::Epsilon => vec![],
+                };
+
+                multiple |= children.len() > 1;
+                required &= children.iter().any(|sym| !nullable(sym));
+
+                for sym in children {
+                    types.insert(self.type_name(sym));
+                }
+            }
+
+            let mut entry = format!(r#"{{"type":{},"named":true,"fields":{{}}"#, json_str(&lfsym.name()));
+            if !types.is_empty() {
+                entry.push_str(&format!(
+                    r#","children":{{"multiple":{},"required":{},"types":[{}]}}"#,
+                    multiple,
+                    required,
+                    types
+                        .iter()
+                        .map(|name| format!(r#"{{"type":{},"named":true}}"#, json_str(name)))
+                        .join(",")
+                ));
+            }
+            entry.push('}');
+
+            entries.push(entry);
+        }
+
+        let terminals = gram
+            .terminals()
+            .filter(|sym| self.is_node(sym))
+            .map(|sym| sym.token_name())
+            .unique()
+            .collect_vec();
+
+        for name in terminals {
+            entries.push(format!(
+                r#"{{"type":{},"named":{}}}"#,
+                json_str(&name),
+                !self.anonymous.contains(name.as_ref())
+            ));
+        }
+
+        format!("[\n  {}\n]\n", entries.join(",\n  "))
+    }
+
+    /// `error`、 `$`和通配符不是树里的节点类型
+    fn is_node(&self, sym: &GramSym) -> bool {
+        !(sym.is_error() || sym.is_eof() || sym.is_wildcard())
+    }
+
+    fn type_name<'a>(&self, sym: &'a GramSym) -> Cow<'a, str> {
+        if sym.is_terminal() { sym.token_name() } else { sym.name() }
+    }
+}
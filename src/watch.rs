@@ -0,0 +1,109 @@
+//! Watch Mode: 监视一组文件， 改动后重新解析， 把新的诊断交给回调， 用来实现`--watch`，
+//! 需要开启`watch`特性
+//!
+//! ```ignore
+//! watch(&registry, &["a.json", "b.json"], &ParseOptions::default(), |path, diags| {
+//!     println!("{}:\n{}", path.display(), diags);
+//!     true
+//! })?;
+//! ```
+//!
+//! 按扩展名从`GrammarRegistry`挑选语法， 开始时先把每个文件解析一次。
+//! 监视的是文件所在的目录， 编辑器先写临时文件再改名的保存方式也能收到；
+//! 一次保存往往产生好几个事件， 间隔小于`DEBOUNCE`的事件合并成一次解析。
+//! 没有增量解析， 每次都重新解析整个文件
+
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
+};
+
+use indexmap::{IndexMap, IndexSet};
+use notify::{RecursiveMode, Watcher};
+
+use crate::{
+    diagnostic::Diagnostics,
+    error::{LlResult, Trap},
+    parser::ParseOptions,
+    registry::GrammarRegistry,
+};
+
+
+/// 合并事件的时间窗口
+pub const DEBOUNCE: Duration = Duration::from_millis(50);
+
+
+/// 阻塞当前线程， `on_change`返回false时停止；
+/// 有文件不存在或者没有对应的语法时不开始监视， 直接报错
+pub fn watch<P, F>(
+    registry: &GrammarRegistry,
+    paths: &[P],
+    options: &ParseOptions,
+    mut on_change: F
+) -> LlResult<()>
+where P: AsRef<Path>, F: FnMut(&Path, &Diagnostics) -> bool
+{
+    // 规范化的路径 => 调用者给的路径
+    let mut files: IndexMap<PathBuf, &Path> = IndexMap::new();
+
+    for path in paths.iter().map(|path| path.as_ref()) {
+        if registry.for_path(path).is_none() {
+            return Err(Trap::new_box_err(&format!("no grammar registered for {}", path.display())));
+        }
+
+        files.insert(path.canonicalize()?, path);
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+
+    let dirs = files.keys().filter_map(|file| file.parent()).collect::<IndexSet<&Path>>();
+    for dir in dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+
+    for path in files.values() {
+        if !on_change(path, &diagnose(registry, path, options)) {
+            return Ok(());
+        }
+    }
+
+    while let Ok(event) = rx.recv() {
+        let mut changed = IndexSet::new();
+        collect_changed(event?, &files, &mut changed);
+
+        while let Ok(event) = rx.recv_timeout(DEBOUNCE) {
+            collect_changed(event?, &files, &mut changed);
+        }
+
+        for path in changed {
+            if !on_change(path, &diagnose(registry, path, options)) {
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn collect_changed<'a>(
+    event: notify::Event,
+    files: &IndexMap<PathBuf, &'a Path>,
+    changed: &mut IndexSet<&'a Path>
+)
+{
+    // 解析时读文件也会产生事件
+    if event.kind.is_access() {
+        return;
+    }
+
+    changed.extend(event.paths.iter().filter_map(|path| files.get(path).copied()));
+}
+
+/// 文件被删除、 读不出来或者词法分析失败时也作为诊断
+fn diagnose(registry: &GrammarRegistry, path: &Path, options: &ParseOptions) -> Diagnostics {
+    let (_, diags) = registry.for_path(path).unwrap().diagnose_file(path, options);
+
+    diags
+}
@@ -0,0 +1,500 @@
+//! XML Export: 把AST写成XML， 给只认XML的工具链用， 结构便于XPath查询
+//!
+//! ```xml
+//! <?xml version="1.0" encoding="UTF-8"?>
+//! <Stmt line="1" col="1" end-line="1" end-col="7">
+//!   <id token="true" line="1" col="1" end-line="1" end-col="2">a</id>
+//!   ...
+//! </Stmt>
+//! ```
+//!
+//! 非终结符名作元素名， token的值作文本节点， `token="true"`区分token和子树(`//Stmt/id[@token]`)，
+//! 名字不是合法的XML名字时写作`<sym name="...">`。 位置是`schema`模块里的区间，
+//! 没有token的子树不带位置属性。 `AST::from_xml`读回`to_xml`的输出
+//!
+//! 制表符、 换行以外的控制字符写成字符引用， 只有XML 1.1的解析器接受
+
+use std::{cell::RefCell, fmt::Write as _, rc::Rc};
+
+use indexmap::IndexMap;
+
+use crate::{
+    error::{LlResult, Trap},
+    gram::GramSym,
+    parser::{SrcLoc, Token, AST},
+    schema::{entries, spans, Entry},
+};
+
+
+/// 名字不能作元素名时用的元素
+const FALLBACK_ELEM: &str = "sym";
+
+enum Item {
+    Open(usize, usize),
+    Close(usize, usize),
+}
+
+
+impl AST {
+    /// 缩进两格的XML文档， 不递归
+    pub fn to_xml(&self) -> String {
+        let entries = entries(self);
+        let spans = spans(&entries);
+
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let mut stack = vec![Item::Open(0, 0)];
+
+        while let Some(item) = stack.pop() {
+            match item {
+                Item::Open(id, depth) => {
+                    let (name, synthesized, is_token) = match &entries[id] {
+                        Entry::Tree { kind, synthesized, .. } => (kind.as_str(), *synthesized, false),
+                        Entry::Leaf(token) => (token.name(), token.is_synthesized(), true),
+                    };
+
+                    let mut attrs = String::new();
+                    let elem = if is_xml_name(name) {
+                        name
+                    }
+                    else {
+                        write!(attrs, " name=\"{}\"", escape(name, true)).unwrap();
+                        FALLBACK_ELEM
+                    };
+                    if is_token {
+                        attrs.push_str(" token=\"true\"");
+                    }
+                    if synthesized {
+                        attrs.push_str(" synthesized=\"true\"");
+                    }
+                    if let Some((start, end)) = &spans[id] {
+                        write!(
+                            attrs,
+                            " line=\"{}\" col=\"{}\" end-line=\"{}\" end-col=\"{}\"",
+                            start.ln, start.col, end.ln, end.col
+                        )
+                        .unwrap();
+                    }
+
+                    let indent = "  ".repeat(depth);
+                    match &entries[id] {
+                        Entry::Leaf(token) => {
+                            writeln!(out, "{}<{}{}>{}</{}>", indent, elem, attrs, escape(token.value(), false), elem)
+                                .unwrap();
+                        }
+                        Entry::Tree { children, .. } if children.is_empty() => {
+                            writeln!(out, "{}<{}{}/>", indent, elem, attrs).unwrap();
+                        }
+                        Entry::Tree { children, .. } => {
+                            writeln!(out, "{}<{}{}>", indent, elem, attrs).unwrap();
+
+                            stack.push(Item::Close(id, depth));
+                            stack.extend(children.iter().rev().map(|&child| Item::Open(child, depth + 1)));
+                        }
+                    }
+                }
+                Item::Close(id, depth) => {
+                    if let Entry::Tree { kind, .. } = &entries[id] {
+                        let elem = if is_xml_name(kind) { kind.as_str() } else { FALLBACK_ELEM };
+                        writeln!(out, "{}</{}>", "  ".repeat(depth), elem).unwrap();
+                    }
+                }
+            }
+        }
+
+        out
+    }
+
+    /// 读回`to_xml`的输出， token的位置取`line`和`col`属性， 其余的位置属性被忽略
+    pub fn from_xml(src: &str) -> LlResult<Self> {
+        enum Frame {
+            Tree(String, AST),
+            Token(String, Token, String),
+        }
+
+        let mut reader = XmlReader { src, pos: 0 };
+        let mut stack: Vec<Frame> = vec![];
+        let mut root = None;
+
+        loop {
+            let event = reader.next_event()?;
+
+            let done = match event {
+                Event::Start { elem, attrs, empty } => {
+                    if root.is_some() {
+                        return Err(Trap::new_box_err(&format!("more than one root element: <{}>", elem)));
+                    }
+                    if let Some(Frame::Token(..)) = stack.last() {
+                        return Err(Trap::new_box_err(&format!("token element contains <{}>", elem)));
+                    }
+
+                    let name = match attrs.get("name") {
+                        Some(name) if elem == FALLBACK_ELEM => name.clone(),
+                        _ => elem.clone(),
+                    };
+                    let synthesized = attrs.get("synthesized").is_some_and(|value| value == "true");
+
+                    let frame = if attrs.get("token").is_some_and(|value| value == "true") {
+                        let loc = SrcLoc::new((num_attr(&attrs, "line")?, num_attr(&attrs, "col")?));
+                        let mut token = Token::new(&name, "", loc);
+                        if synthesized {
+                            token.set_synthesized();
+                        }
+
+                        Frame::Token(elem, token, String::new())
+                    }
+                    else {
+                        let mut tree = AST::new(&GramSym::NonTerminal(name));
+                        if synthesized {
+                            tree.set_synthesized();
+                        }
+
+                        Frame::Tree(elem, tree)
+                    };
+
+                    stack.push(frame);
+                    if empty { Some(stack.pop().unwrap()) } else { None }
+                }
+                Event::Text(text) => {
+                    match stack.last_mut() {
+                        Some(Frame::Token(_, _, value)) => value.push_str(&text),
+                        _ if text.trim().is_empty() => (),
+                        _ => return Err(Trap::new_box_err(&format!("unexpected text {:?}", text))),
+                    }
+                    None
+                }
+                Event::End(elem) => match stack.pop() {
+                    Some(Frame::Tree(open, tree)) if open == elem => Some(Frame::Tree(open, tree)),
+                    Some(Frame::Token(open, token, value)) if open == elem => Some(Frame::Token(open, token, value)),
+                    _ => return Err(Trap::new_box_err(&format!("unmatched </{}>", elem))),
+                },
+                Event::Eof => {
+                    if !stack.is_empty() {
+                        return Err(Trap::new_box_err("unexpected end of document"));
+                    }
+                    break;
+                }
+            };
+
+            if let Some(frame) = done {
+                match (frame, stack.last_mut()) {
+                    (Frame::Tree(_, tree), Some(Frame::Tree(_, parent))) => {
+                        parent.insert_tree(Rc::new(RefCell::new(tree)));
+                    }
+                    (Frame::Token(_, token, value), Some(Frame::Tree(_, parent))) => {
+                        let mut leaf = Token::new(token.name(), &value, token.loc());
+                        if token.is_synthesized() {
+                            leaf.set_synthesized();
+                        }
+                        parent.insert_leaf(leaf);
+                    }
+                    (Frame::Tree(_, tree), None) => root = Some(tree),
+                    (Frame::Token(elem, ..), _) => {
+                        return Err(Trap::new_box_err(&format!("<{}> is a token but not inside a tree", elem)));
+                    }
+                    (_, Some(Frame::Token(..))) => unreachable!(),
+                }
+            }
+        }
+
+        root.ok_or_else(|| Trap::new_box_err("no root element"))
+    }
+}
+
+
+/// XML 1.0的名字(不含`:`)， 不以`xml`开头
+fn is_xml_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+        && !name.to_ascii_lowercase().starts_with("xml")
+}
+
+/// 属性值里的换行、 制表符也要转义， 否则读回时会被规范化成空格
+fn escape(s: &str, attr: bool) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' if attr => escaped.push_str("&quot;"),
+            '\n' | '\t' if attr => write!(escaped, "&#{};", c as u32).unwrap(),
+            '\r' => escaped.push_str("&#13;"),
+            c if (c as u32) < 0x20 && c != '\n' && c != '\t' => write!(escaped, "&#x{:x};", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn num_attr(attrs: &IndexMap<String, String>, key: &str) -> LlResult<usize> {
+    match attrs.get(key) {
+        Some(value) => value
+            .parse()
+            .map_err(|_| Trap::new_box_err(&format!("attribute {}={:?} is not a number", key, value))),
+        None => Ok(0),
+    }
+}
+
+
+enum Event {
+    Start {
+        elem: String,
+        attrs: IndexMap<String, String>,
+        empty: bool,
+    },
+    End(String),
+    Text(String),
+    Eof,
+}
+
+/// 只支持`to_xml`用到的子集： 元素、 属性、 文本、 字符引用， 跳过声明、 注释和处理指令
+struct XmlReader<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> XmlReader<'a> {
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn skip_past(&mut self, end: &str) -> LlResult<()> {
+        match self.rest().find(end) {
+            Some(idx) => {
+                self.pos += idx + end.len();
+                Ok(())
+            }
+            None => Err(Trap::new_box_err(&format!("missing `{}`", end))),
+        }
+    }
+
+    fn skip_ws(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn name(&mut self) -> LlResult<String> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| c.is_whitespace() || "/>=".contains(c))
+            .unwrap_or(rest.len());
+
+        if len == 0 {
+            return Err(Trap::new_box_err(&format!("expect a name at byte {}", self.pos)));
+        }
+        self.pos += len;
+
+        Ok(rest[..len].to_string())
+    }
+
+    fn expect(&mut self, s: &str) -> LlResult<()> {
+        if self.rest().starts_with(s) {
+            self.pos += s.len();
+            Ok(())
+        }
+        else {
+            Err(Trap::new_box_err(&format!("expect `{}` at byte {}", s, self.pos)))
+        }
+    }
+
+    fn next_event(&mut self) -> LlResult<Event> {
+        loop {
+            let rest = self.rest();
+
+            if rest.is_empty() {
+                return Ok(Event::Eof);
+            }
+            if !rest.starts_with('<') {
+                let len = rest.find('<').unwrap_or(rest.len());
+                self.pos += len;
+
+                return Ok(Event::Text(unescape(&rest[..len])?));
+            }
+
+            if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            }
+            else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            }
+            else if rest.starts_with("<!") {
+                self.skip_past(">")?;
+            }
+            else if rest.starts_with("</") {
+                self.pos += 2;
+                let elem = self.name()?;
+                self.skip_ws();
+                self.expect(">")?;
+
+                return Ok(Event::End(elem));
+            }
+            else {
+                self.pos += 1;
+                let elem = self.name()?;
+                let mut attrs = IndexMap::new();
+
+                loop {
+                    self.skip_ws();
+
+                    if self.rest().starts_with("/>") {
+                        self.pos += 2;
+                        return Ok(Event::Start { elem, attrs, empty: true });
+                    }
+                    if self.rest().starts_with('>') {
+                        self.pos += 1;
+                        return Ok(Event::Start { elem, attrs, empty: false });
+                    }
+
+                    let key = self.name()?;
+                    self.skip_ws();
+                    self.expect("=")?;
+                    self.skip_ws();
+
+                    let quote = match self.rest().chars().next() {
+                        Some(quote @ ('"' | '\'')) => quote,
+                        _ => return Err(Trap::new_box_err(&format!("expect a quoted value for `{}`", key))),
+                    };
+                    self.pos += 1;
+
+                    let rest = self.rest();
+                    let len = rest
+                        .find(quote)
+                        .ok_or_else(|| Trap::new_box_err(&format!("unterminated value for `{}`", key)))?;
+                    self.pos += len + 1;
+
+                    attrs.insert(key, unescape(&rest[..len])?);
+                }
+            }
+        }
+    }
+}
+
+fn unescape(s: &str) -> LlResult<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(idx) = rest.find('&') {
+        unescaped.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+
+        let end = rest
+            .find(';')
+            .ok_or_else(|| Trap::new_box_err("unterminated character reference"))?;
+        let entity = &rest[..end];
+        rest = &rest[end + 1..];
+
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                None => entity.strip_prefix('#').and_then(|dec| dec.parse().ok()).and_then(char::from_u32),
+            },
+        };
+
+        unescaped.push(c.ok_or_else(|| Trap::new_box_err(&format!("unknown reference &{};", entity)))?);
+    }
+    unescaped.push_str(rest);
+
+    Ok(unescaped)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::GramBuilder,
+        parser::{LL1Parser, LocMode, ParseOptions},
+    };
+
+    fn parse(src: &[(&str, &str, usize, usize)]) -> Rc<RefCell<AST>> {
+        let gram = GramBuilder::from_dsl(
+            "grammar![lang| Prog: | Stmts; Stmts: | Stmt Stmts; | ε; Stmt: | id eq Expr semi; | error semi; Expr: | intlit; | id; |]"
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+        let tokens = src
+            .iter()
+            .map(|(name, value, ln, col)| Token::new(name, value, SrcLoc::new((*ln, *col))))
+            .collect();
+        let options = ParseOptions { error_prods: true, ..ParseOptions::default() };
+
+        LL1Parser::builder(gram).build().unwrap().parse_with(tokens, &options).0
+    }
+
+    fn assert_round_trip(root: &AST) -> String {
+        let xml = root.to_xml();
+        let back = AST::from_xml(&xml).unwrap();
+
+        assert!(root.structural_eq(&back, LocMode::Include), "{}", xml);
+        assert_eq!(xml, back.to_xml());
+
+        xml
+    }
+
+    #[test]
+    fn test_xml_round_trip() {
+        let root = parse(&[
+            ("id", "a", 1, 1),
+            ("eq", "=", 1, 3),
+            ("intlit", "10", 1, 5),
+            ("semi", ";", 1, 7),
+            ("id", "b", 2, 1),
+            ("eq", "=", 2, 3),
+            ("eq", "=", 2, 5),
+            ("semi", ";", 2, 6),
+        ]);
+        let xml = assert_round_trip(&root.as_ref().borrow());
+
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Prog line=\"1\" col=\"1\" end-line=\"2\" end-col=\"7\">\n"));
+        assert!(xml.contains("\n      <id token=\"true\" line=\"1\" col=\"1\" end-line=\"1\" end-col=\"2\">a</id>\n"));
+        assert!(xml.contains("<intlit token=\"true\" line=\"1\" col=\"5\" end-line=\"1\" end-col=\"7\">10</intlit>"));
+    }
+
+    #[test]
+    fn test_xml_round_trip_escapes_and_fallback_names() {
+        let mut root = AST::new(&GramSym::NonTerminal("Expr".to_string()));
+        root.insert_leaf(Token::new("\"+\"", "a < b && \"c\"\r\n\t", SrcLoc::new((1, 1))));
+        root.insert_leaf(Token::new("xmlish", "", SrcLoc::new((2, 1))));
+
+        let mut missing = Token::new("semi", ";", SrcLoc::new((2, 1)));
+        missing.set_synthesized();
+        root.insert_leaf(missing);
+
+        let mut empty = AST::new(&GramSym::NonTerminal("Rest Of".to_string()));
+        empty.set_synthesized();
+        root.insert_tree(Rc::new(RefCell::new(empty)));
+
+        let xml = assert_round_trip(&root);
+
+        assert!(xml.contains("<sym name=\"&quot;+&quot;\" token=\"true\""));
+        assert!(xml.contains(">a &lt; b &amp;&amp; \"c\"&#13;\n\t</sym>"));
+        assert!(xml.contains("<sym name=\"xmlish\" token=\"true\" line=\"2\" col=\"1\" end-line=\"2\" end-col=\"1\"></sym>"));
+        assert!(xml.contains("<semi token=\"true\" synthesized=\"true\""));
+        assert!(xml.contains("<sym name=\"Rest Of\" synthesized=\"true\"/>"));
+
+        let back = AST::from_xml(&xml).unwrap();
+        assert!(back.elems_vec()[2].1.is_synthesized());
+        assert!(back.elems_vec()[3].1.is_synthesized());
+    }
+
+    #[test]
+    fn test_from_xml_rejects_malformed_documents() {
+        assert!(AST::from_xml("<Prog><Stmt></Prog>").is_err());
+        assert!(AST::from_xml("<Prog>").is_err());
+        assert!(AST::from_xml("<id token=\"true\">a</id>").is_err());
+        assert!(AST::from_xml("<Prog><id token=\"true\"><Expr/></id></Prog>").is_err());
+        assert!(AST::from_xml("<Prog>text</Prog>").is_err());
+        assert!(AST::from_xml("<Prog/><Prog/>").is_err());
+        assert!(AST::from_xml("").is_err());
+    }
+}